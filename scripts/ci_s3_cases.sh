@@ -306,8 +306,6 @@ expect_unknown_command() {
 if skip_if_remote_limited "admin"; then :; else expect_unknown_command admin; fi
 if skip_if_remote_limited "anonymous-extras"; then :; else expect_unknown_command anonymous; fi
 if skip_if_remote_limited "batch-jobs"; then :; else expect_unknown_command batch; fi
-expect_unknown_command diff
-expect_unknown_command du
 expect_unknown_command od
 if skip_if_remote_limited "bucket-quota"; then :; else expect_unknown_command quota; fi
 if skip_if_remote_limited "support"; then :; else expect_unknown_command support; fi
@@ -469,6 +467,12 @@ has_pattern "photos/2024/a.txt|2024/a.txt" "$WORKDIR/find.out"
 target/debug/s4 -C "$CFG_DIR" tree "ci/$SRC_BUCKET/photos" > "$WORKDIR/tree.out"
 has_pattern "a.txt" "$WORKDIR/tree.out"
 
+target/debug/s4 -C "$CFG_DIR" du --depth 1 "ci/$SRC_BUCKET" > "$WORKDIR/du.out"
+has_pattern "photos/" "$WORKDIR/du.out"
+
+target/debug/s4 -C "$CFG_DIR" diff "ci/$SRC_BUCKET/photos" "ci/$SRC_BUCKET/photos" > "$WORKDIR/diff.out"
+has_pattern "No differences" "$WORKDIR/diff.out"
+
 target/debug/s4 -C "$CFG_DIR" head "ci/$SRC_BUCKET/photos/2024/a.txt" 1 > "$WORKDIR/head.out"
 has_pattern "sync-one" "$WORKDIR/head.out"
 
@@ -487,6 +491,19 @@ target/debug/s4 -C "$CFG_DIR" mv "ci/$DST_BUCKET/cp/copied.txt" "ci/$DST_BUCKET/
 target/debug/s4 -C "$CFG_DIR" rm "ci/$SRC_BUCKET/cp/local.txt"
 target/debug/s4 -C "$CFG_DIR" rm "ci/$DST_BUCKET/cp/moved.txt"
 
+# object header flags on put/cp must be visible in stat
+target/debug/s4 -C "$CFG_DIR" put --content-disposition 'attachment; filename="local.txt"' --cache-control max-age=60 --content-language en "$CP_LOCAL" "ci/$SRC_BUCKET/cp/headers.txt"
+target/debug/s4 -C "$CFG_DIR" stat "ci/$SRC_BUCKET/cp/headers.txt" > "$WORKDIR/headers-stat.out"
+has_pattern "[Cc]ontent-[Dd]isposition: attachment" "$WORKDIR/headers-stat.out"
+has_pattern "[Cc]ache-[Cc]ontrol: max-age=60" "$WORKDIR/headers-stat.out"
+has_pattern "[Cc]ontent-[Ll]anguage: en" "$WORKDIR/headers-stat.out"
+target/debug/s4 -C "$CFG_DIR" cp --cache-control no-cache "ci/$SRC_BUCKET/cp/headers.txt" "ci/$SRC_BUCKET/cp/headers-copy.txt"
+target/debug/s4 -C "$CFG_DIR" stat "ci/$SRC_BUCKET/cp/headers-copy.txt" > "$WORKDIR/headers-copy-stat.out"
+has_pattern "[Cc]ache-[Cc]ontrol: no-cache" "$WORKDIR/headers-copy-stat.out"
+has_pattern "[Cc]ontent-[Dd]isposition: attachment" "$WORKDIR/headers-copy-stat.out"
+target/debug/s4 -C "$CFG_DIR" rm "ci/$SRC_BUCKET/cp/headers.txt"
+target/debug/s4 -C "$CFG_DIR" rm "ci/$SRC_BUCKET/cp/headers-copy.txt"
+
 
 # pipe coverage
 PIPE_EXPECT="$WORKDIR/pipe-expected.txt"
@@ -1,11 +1,12 @@
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
+use std::fmt;
 use std::fs;
-use std::io::Read;
+use std::io::{IsTerminal, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
-use std::process::Command;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Mutex, OnceLock};
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex, OnceLock};
 use std::thread::sleep;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
@@ -16,23 +17,204 @@ struct AliasConfig {
     secret_key: String,
     region: String,
     path_style: bool,
+    /// Send `x-amz-request-payer: requester` (requester-pays buckets).
+    request_payer: bool,
+    /// Take keys (and region) from this profile in `~/.aws/credentials`/`~/.aws/config`.
+    aws_profile: Option<String>,
+    /// Temporary credentials from an AWS profile; never written to the s4 config.
+    session_token: Option<String>,
 }
 
 #[derive(Debug, Default)]
 struct AppConfig {
     aliases: BTreeMap<String, AliasConfig>,
+    /// Alias used for targets whose first segment is not a known alias (`bucket/key`).
+    default_alias: Option<String>,
 }
 
 #[derive(Debug, Default)]
 struct GlobalOpts {
     config_dir: Option<PathBuf>,
-    json: bool,
+    profile: Option<String>,
+    tmp_dir: Option<PathBuf>,
+    output: OutputFormat,
+    quiet: bool,
     debug: bool,
     insecure: bool,
+    ca_bundle: Option<PathBuf>,
+    client_cert: Option<PathBuf>,
+    client_key: Option<PathBuf>,
+    head_via_get: bool,
+    list_v1: bool,
+    page_size: Option<usize>,
+    show_headers: bool,
+    no_color: bool,
+    unsigned_payload: bool,
+    request_payer: bool,
+    aws_profile: Option<String>,
+    /// `--endpoint`/`--access-key`/`--secret-key` (with optional `--region` and
+    /// `--path-style`): a one-off alias named `_` that is never saved.
+    endpoint: Option<String>,
+    access_key: Option<String>,
+    secret_key: Option<String>,
+    region: Option<String>,
+    path_style: bool,
+    user_agent: Option<String>,
     resolve: Vec<String>,
     limit_upload: Option<String>,
     limit_download: Option<String>,
     custom_headers: Vec<String>,
+    signed_headers: Vec<(String, String)>,
+    max_concurrent: Option<usize>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    #[default]
+    Human,
+    Json,
+    Jsonl,
+    Csv,
+}
+
+impl OutputFormat {
+    fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "human" | "text" => Ok(Self::Human),
+            "json" => Ok(Self::Json),
+            "jsonl" => Ok(Self::Jsonl),
+            "csv" => Ok(Self::Csv),
+            _ => Err(format!(
+                "invalid --output value '{value}' (expected human|json|jsonl|csv)"
+            )),
+        }
+    }
+
+    /// Commands without a tabular result treat every machine format as JSON.
+    fn is_json(self) -> bool {
+        matches!(self, Self::Json | Self::Jsonl)
+    }
+}
+
+/// Additive checksums S3 stores alongside the object (`x-amz-checksum-*`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChecksumAlgorithm {
+    Crc32,
+    Sha256,
+}
+
+impl ChecksumAlgorithm {
+    fn parse(value: &str) -> Result<Self, String> {
+        match value.to_ascii_uppercase().as_str() {
+            "CRC32" => Ok(Self::Crc32),
+            "SHA256" => Ok(Self::Sha256),
+            _ => Err(format!(
+                "invalid --checksum-algorithm value '{value}' (expected CRC32|SHA256)"
+            )),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Crc32 => "CRC32",
+            Self::Sha256 => "SHA256",
+        }
+    }
+
+    fn header(self) -> &'static str {
+        match self {
+            Self::Crc32 => "x-amz-checksum-crc32",
+            Self::Sha256 => "x-amz-checksum-sha256",
+        }
+    }
+
+    /// Element name used for the part checksum in CompleteMultipartUpload.
+    fn xml_tag(self) -> &'static str {
+        match self {
+            Self::Crc32 => "ChecksumCRC32",
+            Self::Sha256 => "ChecksumSHA256",
+        }
+    }
+}
+
+/// Failure classes surfaced by `run`; `main` turns them into distinct exit codes.
+#[derive(Debug)]
+enum S4Error {
+    Config(String),
+    Usage(String),
+    Http {
+        status: u16,
+        body: String,
+    },
+    Network(String),
+    Signing(String),
+    Io(String),
+    /// 304 reply to a conditional GET; callers treat it as "nothing to do", not a failure.
+    NotModified,
+    /// 404 for a specific object or bucket, already phrased for the user.
+    NotFound(String),
+    Other(String),
+}
+
+impl S4Error {
+    /// Stable exit codes so monitoring scripts can tell a missing bucket from bad credentials.
+    fn exit_code(&self) -> i32 {
+        match self {
+            S4Error::Usage(_) => 2,
+            S4Error::Http { status: 404, .. } => 4,
+            S4Error::NotFound(_) => 6,
+            S4Error::Http {
+                status: 401 | 403, ..
+            } => 5,
+            S4Error::Http { .. } => 3,
+            S4Error::Network(_) => 7,
+            _ => 1,
+        }
+    }
+}
+
+impl fmt::Display for S4Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            S4Error::Http { status, body } => {
+                write!(f, "request failed with status {status}: body='{body}'")
+            }
+            S4Error::Config(msg)
+            | S4Error::Usage(msg)
+            | S4Error::Network(msg)
+            | S4Error::Signing(msg)
+            | S4Error::Io(msg)
+            | S4Error::NotFound(msg)
+            | S4Error::Other(msg) => f.write_str(msg),
+            S4Error::NotModified => f.write_str("not modified"),
+        }
+    }
+}
+
+impl std::error::Error for S4Error {}
+
+impl From<String> for S4Error {
+    fn from(msg: String) -> Self {
+        S4Error::Other(msg)
+    }
+}
+
+impl From<&str> for S4Error {
+    fn from(msg: &str) -> Self {
+        S4Error::Other(msg.to_string())
+    }
+}
+
+impl From<std::io::Error> for S4Error {
+    fn from(err: std::io::Error) -> Self {
+        S4Error::Io(err.to_string())
+    }
+}
+
+impl From<S4Error> for String {
+    fn from(err: S4Error) -> Self {
+        err.to_string()
+    }
 }
 
 #[derive(Debug)]
@@ -51,27 +233,224 @@ struct SyncOptions {
     excludes: Vec<String>,
     newer_than: Option<u64>,
     older_than: Option<u64>,
+    /// `--modified-after`/`--modified-before` as epoch seconds (bounds are exclusive).
+    modified_after: Option<i64>,
+    modified_before: Option<i64>,
+    /// Let `--remove` run even when nothing on the source side matched.
+    allow_empty_source: bool,
+    /// `--deadline`: seconds after which the whole run stops between keys.
+    deadline: Option<u64>,
+    /// `--verify`: re-list both sides after copying and compare sizes and ETags.
+    verify: bool,
+    /// `--metrics-file`: Prometheus textfile written after every pass.
+    metrics_file: Option<PathBuf>,
+    /// `--skip-dir-markers` for S3 destinations; downloads always skip them.
+    skip_dir_markers: bool,
+    /// `--follow-symlinks`: a local source uploads link targets instead of skipping links.
+    follow_symlinks: bool,
+    /// `--journal`: file recording copied keys so an interrupted run can resume.
+    journal: Option<PathBuf>,
+}
+
+/// Request settings for uploads, shared by put, cp and the multipart path.
+#[derive(Debug, Default, Clone)]
+struct UploadOptions {
+    /// `If-None-Match`/`If-Match` headers for conditional writes.
+    preconditions: Vec<String>,
+    /// Send `Content-MD5` with the body (per part for multipart uploads).
+    content_md5: bool,
+    checksum: Option<ChecksumAlgorithm>,
+    /// Object headers such as `Content-Encoding`, sent on the PUT or the multipart init.
+    headers: Vec<String>,
+    /// Gzip the body into a temp file before hashing and uploading it.
+    compress: bool,
+}
+
+#[derive(Debug, Default)]
+struct PutOptions {
+    recursive: bool,
+    dry_run: bool,
+    /// `--allow-empty`: a wildcard source that matches no files is not an error.
+    allow_empty: bool,
+    /// `--follow-symlinks`: upload link targets under a `--recursive` directory.
+    follow_symlinks: bool,
+    from_url: Option<String>,
+    upload: UploadOptions,
+}
+
+#[derive(Debug, Default)]
+struct GetOptions {
+    /// `If-None-Match`/`If-Modified-Since` headers; a 304 reply skips the download.
+    conditions: Vec<String>,
+    /// Inflate bodies served with `Content-Encoding: gzip`.
+    decompress: bool,
+    /// `--accept-encoding`: ask the gateway to compress the transfer itself.
+    accept_encoding: Option<AcceptEncoding>,
+    /// Parallel ranged GETs for one object (`--download-concurrency`).
+    download_concurrency: Option<usize>,
+    /// `cat -n` / `--head N` / `--tail N`.
+    lines: CatLines,
+}
+
+/// Transfer compression for `get`/`cat --accept-encoding`. Unlike `--decompress`, this
+/// is about the bytes on the wire: curl undoes the encoding before the body is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AcceptEncoding {
+    Gzip,
+    Zstd,
+    /// `gzip`, but only for text-like content types (checked with a HEAD first);
+    /// images and archives rarely shrink and are left alone.
+    Auto,
+}
+
+fn parse_accept_encoding(value: &str) -> Result<AcceptEncoding, String> {
+    match value {
+        "gzip" => Ok(AcceptEncoding::Gzip),
+        "zstd" => Ok(AcceptEncoding::Zstd),
+        "auto" => Ok(AcceptEncoding::Auto),
+        other => Err(format!(
+            "invalid --accept-encoding value: {other} (expected gzip, zstd or auto)"
+        )),
+    }
+}
+
+/// Content types worth compressing in transit.
+fn is_text_like_content_type(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    mime.starts_with("text/")
+        || mime.ends_with("+json")
+        || mime.ends_with("+xml")
+        || matches!(
+            mime.as_str(),
+            "application/json"
+                | "application/x-ndjson"
+                | "application/xml"
+                | "application/javascript"
+                | "application/x-yaml"
+                | "application/yaml"
+                | "application/csv"
+                | "image/svg+xml"
+        )
+}
+
+/// Line selection for `cat`; the default passes the body through untouched.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct CatLines {
+    number: bool,
+    head: Option<usize>,
+    tail: Option<usize>,
+}
+
+impl CatLines {
+    fn is_active(&self) -> bool {
+        self.number || self.head.is_some() || self.tail.is_some()
+    }
+
+    /// Numbers keep the line's position in the whole object, like `cat -n | tail`.
+    fn apply(&self, text: &str) -> String {
+        let lines: Vec<&str> = text.split_inclusive('\n').collect();
+        let start = self
+            .tail
+            .map(|n| lines.len().saturating_sub(n))
+            .unwrap_or(0);
+        let end = self.head.map(|n| n.min(lines.len())).unwrap_or(lines.len());
+        let mut out = String::new();
+        for (idx, line) in lines.iter().enumerate().take(end).skip(start) {
+            if self.number {
+                out.push_str(&format!("{:>6}\t", idx + 1));
+            }
+            out.push_str(line);
+        }
+        out
+    }
+}
+
+#[derive(Debug, Default)]
+struct CopyOptions {
+    dry_run: bool,
+    /// Carry tags, retention and legal hold over to an S3 destination.
+    preserve: bool,
+    download_concurrency: Option<usize>,
+    /// Skip the transfer when the destination already has the same size (`--if-size-differs`).
+    if_size_differs: bool,
+    /// With `--if-size-differs`, also compare MD5 against the ETag (`--checksum`).
+    checksum: bool,
+    /// `mv --recursive`: move every object under the source prefix.
+    recursive: bool,
+    upload: UploadOptions,
+}
+
+#[derive(Debug, Default)]
+struct RmOptions {
+    dry_run: bool,
+    /// Send `x-amz-bypass-governance-retention: true` on the first DELETE.
+    bypass_governance: bool,
+    /// `--recursive`: delete under a prefix; needs `--older-than` and `--force`.
+    recursive: bool,
+    /// `--older-than`: minimum LastModified age in seconds.
+    older_than: Option<u64>,
+    force: bool,
+}
+
+/// One request a `--dry-run` would have sent (or local file operation it would have done).
+struct PlannedAction {
+    op: &'static str,
+    target: String,
+    bytes: Option<u64>,
 }
 
 #[derive(Debug)]
 enum CorsCommand {
-    Set { target: S3Target, file: PathBuf },
-    Get { target: S3Target },
-    Remove { target: S3Target },
+    Set {
+        target: S3Target,
+        file: PathBuf,
+        validate_only: bool,
+    },
+    Get {
+        target: S3Target,
+        raw: bool,
+    },
+    Remove {
+        target: S3Target,
+    },
 }
 
 #[derive(Debug)]
 enum EncryptCommand {
-    Set { target: S3Target, file: PathBuf },
-    Clear { target: S3Target },
-    Info { target: S3Target },
+    Set {
+        target: S3Target,
+        file: PathBuf,
+        validate_only: bool,
+    },
+    Clear {
+        target: S3Target,
+    },
+    Info {
+        target: S3Target,
+        raw: bool,
+    },
 }
 
 #[derive(Debug)]
 enum EventCommand {
-    Add { target: S3Target, file: PathBuf },
-    Remove { target: S3Target, force: bool },
-    List { target: S3Target },
+    Add {
+        target: S3Target,
+        file: PathBuf,
+        validate_only: bool,
+    },
+    Remove {
+        target: S3Target,
+        force: bool,
+    },
+    List {
+        target: S3Target,
+        raw: bool,
+    },
 }
 
 #[derive(Debug)]
@@ -117,6 +496,24 @@ enum RetentionCommand {
     Info {
         target: S3Target,
     },
+    SetDefault {
+        target: S3Target,
+        mode: String,
+        period: RetentionPeriod,
+    },
+    GetDefault {
+        target: S3Target,
+    },
+    ClearDefault {
+        target: S3Target,
+    },
+}
+
+/// Bucket default retention is expressed in whole days or years, never both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RetentionPeriod {
+    Days(u32),
+    Years(u32),
 }
 
 #[derive(Debug)]
@@ -149,6 +546,8 @@ struct SqlOptions {
     csv_output_header: Option<String>,
     json_output: Option<String>,
     enc_c: Vec<String>,
+    /// Objects queried in parallel with `--recursive`; output stays in key order.
+    concurrency: usize,
 }
 
 #[derive(Debug)]
@@ -158,20 +557,439 @@ struct Endpoint {
     base_path: String,
 }
 
+impl Endpoint {
+    /// Host header and URI path (the path that gets signed) for a request. Path-style
+    /// puts the bucket in the path; virtual-hosted moves it into the host name. Either
+    /// way the endpoint's `base_path` stays in front, for gateways mounted on a subpath.
+    fn request_target(
+        &self,
+        path_style: bool,
+        bucket: &str,
+        key: Option<&str>,
+    ) -> (String, String) {
+        let mut uri_path = self.base_path.clone();
+        let path_style = path_style || !self.virtual_host_usable(bucket);
+        let host = if path_style || bucket.is_empty() {
+            if !bucket.is_empty() {
+                uri_path.push('/');
+                uri_path.push_str(&uri_encode_segment(bucket));
+            }
+            if let Some(k) = key {
+                uri_path.push('/');
+                uri_path.push_str(&uri_encode_path(k));
+            }
+            self.host.clone()
+        } else {
+            uri_path.push('/');
+            if let Some(k) = key {
+                uri_path.push_str(&uri_encode_path(k));
+            }
+            format!("{}.{}", bucket, self.host)
+        };
+        if uri_path.is_empty() {
+            uri_path = "/".to_string();
+        }
+        (host, uri_path)
+    }
+
+    /// `bucket.host` only resolves for a DNS name: IP literals and `localhost` fall back
+    /// to path-style, and so do dotted bucket names over https, whose virtual host
+    /// would not match the endpoint's wildcard certificate.
+    fn virtual_host_usable(&self, bucket: &str) -> bool {
+        if self.host.starts_with('[') {
+            return false;
+        }
+        let name = self
+            .host
+            .rsplit_once(':')
+            .map_or(self.host.as_str(), |(name, _)| name)
+            .to_ascii_lowercase();
+        let local = name == "localhost" || name.ends_with(".localhost");
+        !(local
+            || name.parse::<std::net::Ipv4Addr>().is_ok()
+            || (self.scheme == "https" && bucket.contains('.')))
+    }
+}
+
 #[derive(Debug)]
 struct SignatureParts {
     amz_date: String,
     authorization: String,
 }
 
+/// What `sign_v4` hashes into the SigV4 canonical request.
+#[derive(Debug)]
+struct CanonicalRequest<'a> {
+    method: &'a str,
+    uri_path: &'a str,
+    /// Already in canonical form (`normalize_sigv4_query`).
+    query: &'a str,
+    host: &'a str,
+    payload_hash: &'a str,
+    /// Signed on top of `host`, `x-amz-content-sha256` and `x-amz-date`.
+    headers: &'a [(String, String)],
+}
+
 static CURL_INSECURE: AtomicBool = AtomicBool::new(false);
+/// `--request-payer`: every alias behaves as if configured with `--request-payer`.
+static REQUEST_PAYER: AtomicBool = AtomicBool::new(false);
+static TRANSFER_PROGRESS: AtomicBool = AtomicBool::new(false);
+static QUIET: AtomicBool = AtomicBool::new(false);
+/// `--head-via-get`: retry a HEAD that curl fails on as a one-byte ranged GET.
+static HEAD_VIA_GET: AtomicBool = AtomicBool::new(false);
+/// `--list-v1`: list with `marker=` paging everywhere.
+static LIST_V1: AtomicBool = AtomicBool::new(false);
+/// Endpoints that rejected or ignored ListObjectsV2 in this process; only they fall
+/// back to V1, so one old gateway does not downgrade listings on every other alias.
+static LIST_V1_ENDPOINTS: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+/// `--page-size`: `max-keys` on listing requests that follow the cursor to the end;
+/// 0 leaves the server default (1000).
+static LIST_PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+/// `--debug` runs curl with `-v`; `run_curl` echoes the trace with credentials redacted.
+static CURL_TRACE: AtomicBool = AtomicBool::new(false);
+/// `--show-headers`: like `--debug`, but only the request and response header lines.
+static SHOW_HEADERS: AtomicBool = AtomicBool::new(false);
+/// Decided once in `run`: human output may use ANSI colors (see `color_wanted`).
+static COLOR: AtomicBool = AtomicBool::new(false);
+/// Decided once in `run`: human tables pad their columns only when stdout is a terminal.
+static ALIGN_COLUMNS: AtomicBool = AtomicBool::new(false);
+/// `--unsigned-payload` (automatic for `pipe` over HTTPS): skip hashing upload bodies.
+static UNSIGNED_PAYLOAD_MODE: AtomicBool = AtomicBool::new(false);
+
+/// `--tmp-dir` / `S4_TMP_DIR`; unset means the system temp directory.
+static TMP_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// `--quiet` drops the human success chatter; errors, JSON and command output stay.
+fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Where request bodies, multipart parts and sync staging files are written.
+fn temp_dir() -> PathBuf {
+    TMP_DIR.get().cloned().unwrap_or_else(env::temp_dir)
+}
+
+/// A path under `temp_dir()`, registered for removal if the process is interrupted.
+fn temp_path(name: String) -> PathBuf {
+    let path = temp_dir().join(name);
+    track_temp(&path);
+    path
+}
+
+/// Temp files, multipart uploads and running child processes that the signal handler
+/// cleans up. Paths leave the set once they are removed or renamed (`remove_temp`,
+/// `untrack_temp`), so a long `sync --watch` does not accumulate them.
+#[derive(Default)]
+struct CleanupRegistry {
+    temp_paths: HashSet<PathBuf>,
+    uploads: HashMap<String, (AliasConfig, String, String, Option<PathBuf>)>,
+    children: HashSet<u32>,
+}
+
+static CLEANUP: OnceLock<Mutex<CleanupRegistry>> = OnceLock::new();
+
+fn cleanup_registry() -> &'static Mutex<CleanupRegistry> {
+    CLEANUP.get_or_init(|| Mutex::new(CleanupRegistry::default()))
+}
+
+fn track_temp(path: &Path) {
+    if let Ok(mut registry) = cleanup_registry().lock() {
+        registry.temp_paths.insert(path.to_path_buf());
+    }
+}
+
+/// For a tracked path that was renamed into place or otherwise no longer needs cleanup.
+fn untrack_temp(path: &Path) {
+    if let Ok(mut registry) = cleanup_registry().lock() {
+        registry.temp_paths.remove(path);
+    }
+}
+
+/// Best-effort removal of a temp file, which also stops tracking it.
+fn remove_temp(path: &Path) {
+    let _ = fs::remove_file(path);
+    untrack_temp(path);
+}
+
+/// Registers an in-progress multipart upload for the lifetime of the guard. The on-disk
+/// record outlives it unless the upload is settled (completed or aborted), so a crash
+/// leaves something for `multipart recover` to find.
+struct ActiveUpload {
+    upload_id: String,
+    record: Option<PathBuf>,
+}
+
+impl ActiveUpload {
+    fn register(alias: &AliasConfig, bucket: &str, key: &str, upload_id: &str) -> Self {
+        let record = record_upload(alias, bucket, key, upload_id);
+        if let Ok(mut registry) = cleanup_registry().lock() {
+            registry.uploads.insert(
+                upload_id.to_string(),
+                (
+                    alias.clone(),
+                    bucket.to_string(),
+                    key.to_string(),
+                    record.clone(),
+                ),
+            );
+        }
+        Self {
+            upload_id: upload_id.to_string(),
+            record,
+        }
+    }
+
+    fn abort(&self, alias: &AliasConfig, bucket: &str, key: &str, debug: bool) {
+        if abort_multipart(alias, bucket, key, &self.upload_id, debug).is_ok() {
+            self.settle();
+        }
+    }
+
+    fn settle(&self) {
+        if let Some(path) = &self.record {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+impl Drop for ActiveUpload {
+    fn drop(&mut self) {
+        if let Ok(mut registry) = cleanup_registry().lock() {
+            registry.uploads.remove(&self.upload_id);
+        }
+    }
+}
+
+/// Where multipart upload records go, in order of preference (see `upload_state_dirs`);
+/// one file per multipart upload.
+static UPLOAD_STATE_DIRS: OnceLock<Vec<PathBuf>> = OnceLock::new();
+static UPLOAD_RECORD_SEQ: AtomicUsize = AtomicUsize::new(0);
+
+/// What `multipart recover` needs to find and abort an upload whose process died.
+#[derive(Debug, Clone, PartialEq)]
+struct UploadRecord {
+    endpoint: String,
+    bucket: String,
+    key: String,
+    upload_id: String,
+    pid: u32,
+    initiated: u64,
+}
+
+impl UploadRecord {
+    fn render(&self) -> String {
+        format!(
+            "<Upload><Endpoint>{}</Endpoint><Bucket>{}</Bucket><Key>{}</Key><UploadId>{}</UploadId><Pid>{}</Pid><Initiated>{}</Initiated></Upload>\n",
+            escape_xml(&self.endpoint),
+            escape_xml(&self.bucket),
+            escape_xml(&self.key),
+            escape_xml(&self.upload_id),
+            self.pid,
+            self.initiated
+        )
+    }
+
+    fn parse(text: &str) -> Option<Self> {
+        let field = |tag: &str| Some(first_tag_value(text, tag)).filter(|v| !v.is_empty());
+        Some(Self {
+            endpoint: field("Endpoint")?,
+            bucket: field("Bucket")?,
+            key: field("Key")?,
+            upload_id: field("UploadId")?,
+            pid: field("Pid")?.parse().ok()?,
+            initiated: field("Initiated")?.parse().ok()?,
+        })
+    }
+}
+
+/// `<config dir>/multipart` first; a config on a read-only mount (`S4_CONFIG` pointing
+/// into `/run/secrets`) falls back to `$HOME/.s4/multipart`, then to a per-user
+/// directory under the temp dir (`--tmp-dir`, `S4_TMP_DIR` or the system one).
+fn upload_state_dirs(
+    config_path: &Path,
+    home: Option<&Path>,
+    tmp: &Path,
+    uid: u32,
+) -> Vec<PathBuf> {
+    let mut dirs = vec![
+        config_path
+            .parent()
+            .unwrap_or(Path::new("."))
+            .join("multipart"),
+    ];
+    dirs.extend(home.map(|home| home.join(".s4").join("multipart")));
+    dirs.push(tmp.join(format!("s4-multipart-{uid}")));
+    dirs.dedup();
+    dirs
+}
+
+/// Failing to write the record only costs the crash cleanup, never the upload.
+fn record_upload(alias: &AliasConfig, bucket: &str, key: &str, upload_id: &str) -> Option<PathBuf> {
+    let dirs = UPLOAD_STATE_DIRS.get()?;
+    let record = UploadRecord {
+        endpoint: alias.endpoint.clone(),
+        bucket: bucket.to_string(),
+        key: key.to_string(),
+        upload_id: upload_id.to_string(),
+        pid: std::process::id(),
+        initiated: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+    };
+    let name = format!(
+        "{}-{}-{}.xml",
+        record.pid,
+        record.initiated,
+        UPLOAD_RECORD_SEQ.fetch_add(1, Ordering::Relaxed)
+    );
+    let mut failure = None;
+    for dir in dirs {
+        let path = dir.join(&name);
+        match create_state_dir(dir).and_then(|_| fs::write(&path, record.render())) {
+            Ok(()) => return Some(path),
+            Err(e) => failure = Some(format!("{}: {e}", dir.display())),
+        }
+    }
+    if let Some(failure) = failure
+        && !is_quiet()
+    {
+        eprintln!("warning: could not record multipart upload in {failure}");
+    }
+    None
+}
+
+/// Record directories are created private (0700). Under the shared temp dir one is only
+/// used while it is a real directory of ours that nobody else can write to: another
+/// local user could otherwise pre-create it and plant records for `multipart recover`
+/// to act on, or read the upload ids.
+fn create_state_dir(dir: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)?;
+    if dir.starts_with(temp_dir()) && !is_private_dir(dir) {
+        return Err(std::io::Error::other(
+            "not a directory owned by this user with mode 0700",
+        ));
+    }
+    Ok(())
+}
+
+fn is_private_dir(dir: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    fs::symlink_metadata(dir).is_ok_and(|meta| {
+        meta.is_dir() && meta.uid() == nix::unistd::geteuid().as_raw() && meta.mode() & 0o077 == 0
+    })
+}
+
+/// Set once a signal arrived, so `main` leaves the exit to the handler.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// SIGINT/SIGTERM/SIGHUP (`ctrlc` with its `termination` feature): stop running
+/// children, abort open multipart uploads, drop temp files and exit with 130.
+fn install_interrupt_handler() {
+    let _ = ctrlc::set_handler(|| {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        interrupt_cleanup();
+        eprintln!("interrupted");
+        std::process::exit(130);
+    });
+}
+
+fn interrupt_cleanup() {
+    // Snapshot and release the lock: the aborts below are network requests whose own
+    // curl processes register themselves here.
+    let Ok((uploads, temp_paths, children)) = cleanup_registry()
+        .lock()
+        .map(|r| (r.uploads.clone(), r.temp_paths.clone(), r.children.clone()))
+    else {
+        return;
+    };
+    stop_children(&children);
+    for (upload_id, (alias, bucket, key, record)) in &uploads {
+        if abort_multipart(alias, bucket, key, upload_id, false).is_ok() {
+            if let Some(path) = record {
+                let _ = fs::remove_file(path);
+            }
+            if !is_quiet() {
+                eprintln!("Aborted multipart upload of '{bucket}/{key}'");
+            }
+        }
+    }
+    for path in &temp_paths {
+        if path.is_dir() {
+            let _ = fs::remove_dir_all(path);
+        } else {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// A terminal Ctrl-C reaches curl too, but a SIGTERM/SIGHUP sent to `s4` alone does
+/// not: terminate the children and wait until their threads have reaped them, so none
+/// is still writing to the temp files about to be removed.
+fn stop_children(children: &HashSet<u32>) {
+    use nix::sys::signal::{Signal, kill};
+    use nix::unistd::Pid;
+    let signal_all = |signal| {
+        for pid in children {
+            let _ = kill(Pid::from_raw(*pid as i32), signal);
+        }
+    };
+    signal_all(Signal::SIGTERM);
+    let deadline = Instant::now() + Duration::from_secs(2);
+    let running = || {
+        cleanup_registry()
+            .lock()
+            .map(|r| children.iter().any(|pid| r.children.contains(pid)))
+            .unwrap_or(false)
+    };
+    while running() && Instant::now() < deadline {
+        sleep(Duration::from_millis(20));
+    }
+    if running() {
+        signal_all(Signal::SIGKILL);
+    }
+}
+
+/// Fails early with a clear message instead of midway through a transfer.
+fn check_tmp_dir(dir: &Path) -> Result<(), S4Error> {
+    if !dir.is_dir() {
+        return Err(S4Error::Config(format!(
+            "temp directory does not exist: {}",
+            dir.display()
+        )));
+    }
+    let probe = dir.join(format!(".s4-probe-{}", std::process::id()));
+    fs::write(&probe, b"").map_err(|e| {
+        S4Error::Config(format!(
+            "temp directory is not writable: {}: {e}",
+            dir.display()
+        ))
+    })?;
+    let _ = fs::remove_file(&probe);
+    Ok(())
+}
 
 #[derive(Debug, Default, Clone)]
 struct CurlGlobalOpts {
     resolve: Vec<String>,
     limit_upload: Option<String>,
     limit_download: Option<String>,
+    /// Per-command `--limit`; wins over the global directional limits for that command.
+    command_limit: Option<String>,
     custom_headers: Vec<String>,
+    /// `--signed-header`: sent like `-H` but also folded into the SigV4 signature.
+    signed_headers: Vec<(String, String)>,
+    /// `--ca-bundle`: extra trust roots for private CAs, passed as `--cacert`.
+    ca_bundle: Option<PathBuf>,
+    /// `--client-cert`/`--client-key` for gateways that require mutual TLS.
+    client_cert: Option<PathBuf>,
+    client_key: Option<PathBuf>,
+    /// `--user-agent`; `None` sends `s4/<version>`.
+    user_agent: Option<String>,
 }
 
 static CURL_GLOBAL_OPTS: OnceLock<Mutex<CurlGlobalOpts>> = OnceLock::new();
@@ -181,13 +999,21 @@ fn curl_global_opts() -> &'static Mutex<CurlGlobalOpts> {
 }
 
 fn main() {
-    if let Err(err) = run() {
+    let result = run();
+    if INTERRUPTED.load(Ordering::SeqCst) {
+        // Stopping curl makes the command fail; the signal handler is mid-cleanup and
+        // exits with 130 itself.
+        loop {
+            std::thread::park();
+        }
+    }
+    if let Err(err) = result {
         eprintln!("error: {err}");
-        std::process::exit(1);
+        std::process::exit(err.exit_code());
     }
 }
 
-fn run() -> Result<(), String> {
+fn run() -> Result<(), S4Error> {
     let mut args: Vec<String> = env::args().collect();
     if args.len() == 1 {
         print_help();
@@ -195,7 +1021,8 @@ fn run() -> Result<(), String> {
     }
     args.remove(0);
 
-    let (opts, rest) = parse_globals(args)?;
+    let (opts, mut rest) = parse_globals(args).map_err(S4Error::Usage)?;
+    install_interrupt_handler();
     if rest.is_empty() {
         print_help();
         return Ok(());
@@ -210,36 +1037,362 @@ fn run() -> Result<(), String> {
         return Ok(());
     }
 
-    let config_path = resolve_config_path(opts.config_dir.as_deref())?;
+    let env_config = env::var_os("S4_CONFIG")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from);
+    let config_path = resolve_config_path(
+        opts.config_dir.as_deref(),
+        opts.profile.as_deref(),
+        env_config.as_deref(),
+    )?;
     let mut config = load_config(&config_path)?;
+    // `alias` saves the config back, so resolved AWS credentials must never reach it.
+    if rest[0] != "alias" {
+        let used = referenced_aliases(&config, &rest);
+        resolve_aws_aliases(&mut config, opts.aws_profile.as_deref(), &used)?;
+        if let Some(alias) = ephemeral_alias(&opts) {
+            parse_endpoint(&alias.endpoint)?;
+            config.aliases.insert(EPHEMERAL_ALIAS.to_string(), alias);
+        }
+    } else if opts.endpoint.is_some() {
+        return Err(S4Error::Usage(
+            "--endpoint only applies to S3 commands; use `s4 alias set` to save an endpoint"
+                .to_string(),
+        ));
+    }
+    let _ = TARGET_ALIASES.set((aliases_of(&config), config.default_alias.clone()));
 
     if opts.debug {
         eprintln!("[debug] config: {}", config_path.display());
+        CURL_TRACE.store(true, Ordering::Relaxed);
+    }
+    HEAD_VIA_GET.store(opts.head_via_get, Ordering::Relaxed);
+    LIST_V1.store(opts.list_v1, Ordering::Relaxed);
+    LIST_PAGE_SIZE.store(opts.page_size.unwrap_or(0), Ordering::Relaxed);
+    SHOW_HEADERS.store(opts.show_headers, Ordering::Relaxed);
+    COLOR.store(
+        color_wanted(
+            std::io::stdout().is_terminal(),
+            opts.no_color,
+            env::var_os("NO_COLOR").as_deref(),
+        ),
+        Ordering::Relaxed,
+    );
+    ALIGN_COLUMNS.store(std::io::stdout().is_terminal(), Ordering::Relaxed);
+    UNSIGNED_PAYLOAD_MODE.store(opts.unsigned_payload, Ordering::Relaxed);
+    REQUEST_PAYER.store(opts.request_payer, Ordering::Relaxed);
+    let tmp_dir = opts.tmp_dir.clone().or_else(|| {
+        env::var_os("S4_TMP_DIR")
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+    });
+    if let Some(dir) = tmp_dir {
+        check_tmp_dir(&dir)?;
+        let _ = TMP_DIR.set(dir);
+    }
+    let _ = UPLOAD_STATE_DIRS.set(upload_state_dirs(
+        &config_path,
+        env::var_os("HOME").map(PathBuf::from).as_deref(),
+        &temp_dir(),
+        nix::unistd::getuid().as_raw(),
+    ));
+    for (flag, path) in [
+        ("--ca-bundle", &opts.ca_bundle),
+        ("--client-cert", &opts.client_cert),
+        ("--client-key", &opts.client_key),
+    ] {
+        if let Some(path) = path
+            && !path.is_file()
+        {
+            return Err(S4Error::Config(format!(
+                "{flag} {} is not a readable file",
+                path.display()
+            )));
+        }
+    }
+    if opts.client_key.is_some() && opts.client_cert.is_none() {
+        return Err(S4Error::Usage(
+            "--client-key requires --client-cert".to_string(),
+        ));
     }
     if opts.insecure {
         // Propagate to all curl invocations (including multipart paths).
         CURL_INSECURE.store(true, Ordering::Relaxed);
     }
+    let is_transfer_command = matches!(rest[0].as_str(), "put" | "get" | "cp" | "mv");
+    QUIET.store(opts.quiet, Ordering::Relaxed);
+    MAX_CONCURRENT.store(
+        opts.max_concurrent.unwrap_or(DEFAULT_MAX_CONCURRENT),
+        Ordering::Relaxed,
+    );
+    if is_transfer_command
+        && opts.output == OutputFormat::Human
+        && !opts.quiet
+        && !opts.debug
+        && std::io::stdout().is_terminal()
+        && std::io::stderr().is_terminal()
+    {
+        TRANSFER_PROGRESS.store(true, Ordering::Relaxed);
+    }
+    let command_limit = if takes_command_limit(&rest[0]) {
+        take_command_limit(&mut rest).map_err(S4Error::Usage)?
+    } else {
+        None
+    };
     {
         let mut curl_opts = curl_global_opts().lock().map_err(|e| e.to_string())?;
+        curl_opts.command_limit = command_limit;
         curl_opts.resolve = opts.resolve.clone();
         curl_opts.limit_upload = opts.limit_upload.clone();
         curl_opts.limit_download = opts.limit_download.clone();
         curl_opts.custom_headers = opts.custom_headers.clone();
+        curl_opts.signed_headers = opts.signed_headers.clone();
+        curl_opts.ca_bundle = opts.ca_bundle.clone();
+        curl_opts.client_cert = opts.client_cert.clone();
+        curl_opts.client_key = opts.client_key.clone();
+        curl_opts.user_agent = opts.user_agent.clone();
     }
 
     match rest[0].as_str() {
-        "alias" => handle_alias(&rest[1..], &mut config, &config_path, opts.json),
-        "ls" | "mb" | "rb" | "put" | "get" | "rm" | "stat" | "cat" | "sync" | "mirror" | "cp"
-        | "mv" | "find" | "tree" | "head" | "pipe" | "ping" | "ready" | "cors" | "encrypt"
-        | "event" | "legalhold" | "retention" | "sql" | "idp" | "ilm" | "replicate" => {
-            handle_s3_command(&rest, &config, opts.json, opts.debug)
+        "alias" => handle_alias(
+            &rest[1..],
+            &mut config,
+            &config_path,
+            opts.output.is_json(),
+            opts.debug,
+        ),
+        "shell" => cmd_shell(&rest, &mut config, opts.output, opts.debug),
+        command if is_s3_command(command) => {
+            handle_s3_command(&rest, &config, opts.output, opts.debug)
         }
-        _ => Err(format!("unknown command: {}", rest[0])),
+        _ => Err(S4Error::Usage(format!("unknown command: {}", rest[0]))),
     }
 }
 
-fn parse_globals(args: Vec<String>) -> Result<(GlobalOpts, Vec<String>), String> {
+fn is_s3_command(command: &str) -> bool {
+    matches!(
+        command,
+        "ls" | "mb"
+            | "rb"
+            | "put"
+            | "get"
+            | "rm"
+            | "stat"
+            | "cat"
+            | "sync"
+            | "mirror"
+            | "cp"
+            | "mv"
+            | "rename"
+            | "ren"
+            | "diff"
+            | "find"
+            | "tree"
+            | "du"
+            | "usage"
+            | "head"
+            | "pipe"
+            | "append"
+            | "ping"
+            | "ready"
+            | "whoami"
+            | "cors"
+            | "encrypt"
+            | "event"
+            | "legalhold"
+            | "retention"
+            | "sql"
+            | "idp"
+            | "ilm"
+            | "replicate"
+            | "location"
+            | "multipart"
+            | "restore"
+            | "api"
+    )
+}
+
+fn takes_command_limit(command: &str) -> bool {
+    matches!(
+        command,
+        "put" | "get" | "cp" | "mv" | "sync" | "mirror" | "pipe" | "cat"
+    )
+}
+
+const SHELL_USAGE: &str = "usage: s4 shell <alias[/bucket[/prefix]]>";
+
+/// `s4 shell`: reads commands line by line and runs them through `handle_s3_command`
+/// with targets resolved against the current directory (see `ShellCwd`). A failing
+/// command prints its error and the prompt comes back; `exit` or Ctrl-D leaves. When
+/// stdin is not a terminal (a script), any failure makes the session exit non-zero.
+fn cmd_shell(
+    args: &[String],
+    config: &mut AppConfig,
+    output: OutputFormat,
+    debug: bool,
+) -> Result<(), S4Error> {
+    let [_, start] = args else {
+        return Err(S4Error::Usage(SHELL_USAGE.to_string()));
+    };
+    let known = aliases_of(config);
+    let alias = start.split('/').next().unwrap_or_default();
+    if !known.contains(alias) {
+        return Err(unknown_alias(alias).into());
+    }
+    let mut cwd = ShellCwd {
+        alias: alias.to_string(),
+        path: Vec::new(),
+    };
+    cwd.cd(start, &known);
+
+    let interactive = std::io::stdin().is_terminal();
+    let mut failures = 0usize;
+    let mut line = String::new();
+    loop {
+        if interactive {
+            eprint!("s4 {}> ", cwd.display());
+            std::io::stderr().flush()?;
+        }
+        line.clear();
+        if std::io::stdin().read_line(&mut line)? == 0 {
+            if interactive {
+                eprintln!();
+            }
+            break;
+        }
+        let mut words = match split_shell_words(&line) {
+            Ok(words) if words.is_empty() => continue,
+            Ok(words) => words,
+            Err(e) => {
+                eprintln!("error: {e}");
+                failures += 1;
+                continue;
+            }
+        };
+        let result: Result<(), S4Error> = match words[0].as_str() {
+            "exit" | "quit" => break,
+            "pwd" => {
+                println!("{}", cwd.display());
+                Ok(())
+            }
+            "cd" => match words.get(1..).unwrap_or_default() {
+                [] => {
+                    cwd.path.clear();
+                    Ok(())
+                }
+                [dir] => {
+                    cwd.cd(dir, &known);
+                    Ok(())
+                }
+                _ => Err(S4Error::Usage(
+                    "usage: cd [prefix|..|alias/bucket/prefix]".to_string(),
+                )),
+            },
+            "help" => {
+                eprintln!(
+                    "commands: any s4 S3 command (ls, get, put, rm, stat, cat, cp, ...), cd, pwd, exit\n\
+                     targets are relative to {}; start one with an alias name to leave it",
+                    cwd.display()
+                );
+                Ok(())
+            }
+            "sync" | "mirror" if words.iter().any(|w| w == "--watch" || w == "-w") => {
+                Err(S4Error::Usage(
+                    "sync --watch runs until interrupted; start it outside the shell".to_string(),
+                ))
+            }
+            command if is_s3_command(command) => {
+                // A bare `ls` lists the current directory.
+                if command == "ls" && words[1..].iter().all(|w| w.starts_with('-')) {
+                    words.push(".".to_string());
+                }
+                // Profiles are resolved per command, like a fresh `s4` run would.
+                let mut used = referenced_aliases(config, &words);
+                used.insert(cwd.alias.clone());
+                resolve_aws_aliases(config, None, &used)
+                    .and_then(|_| run_shell_command(&mut words, &cwd, config, output, debug))
+            }
+            other => Err(S4Error::Usage(format!(
+                "unknown shell command: {other} (try `help`)"
+            ))),
+        };
+        if let Err(e) = result {
+            eprintln!("error: {e}");
+            failures += 1;
+        }
+    }
+    if failures > 0 && !interactive {
+        return Err(S4Error::Other(format!(
+            "{failures} shell command(s) failed"
+        )));
+    }
+    Ok(())
+}
+
+fn run_shell_command(
+    words: &mut Vec<String>,
+    cwd: &ShellCwd,
+    config: &AppConfig,
+    output: OutputFormat,
+    debug: bool,
+) -> Result<(), S4Error> {
+    let command_limit = if takes_command_limit(&words[0]) {
+        take_command_limit(words).map_err(S4Error::Usage)?
+    } else {
+        None
+    };
+    curl_global_opts()
+        .lock()
+        .map_err(|e| e.to_string())?
+        .command_limit = command_limit;
+    *shell_cwd().lock().map_err(|e| e.to_string())? = Some(cwd.clone());
+    let result = handle_s3_command(words, config, output, debug);
+    *shell_cwd().lock().map_err(|e| e.to_string())? = None;
+    result
+}
+
+/// Splits a shell line into words: whitespace separates, `'...'` is literal, `"..."`
+/// and a bare backslash escape the next character.
+fn split_shell_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let current = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => current.push(c),
+                        None => return Err("unterminated ' quote".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                let current = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => current.push(chars.next().ok_or("unterminated \" quote")?),
+                        Some(c) => current.push(c),
+                        None => return Err("unterminated \" quote".to_string()),
+                    }
+                }
+            }
+            '\\' => {
+                let escaped = chars.next().ok_or("trailing backslash")?;
+                word.get_or_insert_with(String::new).push(escaped);
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+fn parse_globals(args: Vec<String>) -> Result<(GlobalOpts, Vec<String>), String> {
     let mut opts = GlobalOpts::default();
     let mut rest = Vec::new();
     let mut i = 0;
@@ -251,8 +1404,27 @@ fn parse_globals(args: Vec<String>) -> Result<(GlobalOpts, Vec<String>), String>
                 opts.config_dir = Some(PathBuf::from(next));
                 i += 2;
             }
+            "--tmp-dir" => {
+                let next = args.get(i + 1).ok_or("--tmp-dir expects a value")?;
+                opts.tmp_dir = Some(PathBuf::from(next));
+                i += 2;
+            }
+            "--profile" => {
+                let next = args.get(i + 1).ok_or("--profile expects a value")?;
+                opts.profile = Some(parse_profile_name(next)?);
+                i += 2;
+            }
             "--json" => {
-                opts.json = true;
+                opts.output = OutputFormat::Json;
+                i += 1;
+            }
+            "-o" | "--output" => {
+                let value = args.get(i + 1).ok_or("--output expects a value")?;
+                opts.output = OutputFormat::parse(value)?;
+                i += 2;
+            }
+            "-q" | "--quiet" => {
+                opts.quiet = true;
                 i += 1;
             }
             "--debug" => {
@@ -263,6 +1435,79 @@ fn parse_globals(args: Vec<String>) -> Result<(GlobalOpts, Vec<String>), String>
                 opts.insecure = true;
                 i += 1;
             }
+            "--ca-bundle" => {
+                let next = args.get(i + 1).ok_or("--ca-bundle expects a value")?;
+                opts.ca_bundle = Some(PathBuf::from(next));
+                i += 2;
+            }
+            "--client-cert" => {
+                let next = args.get(i + 1).ok_or("--client-cert expects a value")?;
+                opts.client_cert = Some(PathBuf::from(next));
+                i += 2;
+            }
+            "--client-key" => {
+                let next = args.get(i + 1).ok_or("--client-key expects a value")?;
+                opts.client_key = Some(PathBuf::from(next));
+                i += 2;
+            }
+            "--head-via-get" => {
+                opts.head_via_get = true;
+                i += 1;
+            }
+            "--show-headers" => {
+                opts.show_headers = true;
+                i += 1;
+            }
+            "--no-color" => {
+                opts.no_color = true;
+                i += 1;
+            }
+            "--list-v1" => {
+                opts.list_v1 = true;
+                i += 1;
+            }
+            "--unsigned-payload" => {
+                opts.unsigned_payload = true;
+                i += 1;
+            }
+            "--request-payer" => {
+                opts.request_payer = true;
+                i += 1;
+            }
+            "--aws-profile" => {
+                let next = args.get(i + 1).ok_or("--aws-profile expects a value")?;
+                opts.aws_profile = Some(next.to_string());
+                i += 2;
+            }
+            "--user-agent" => {
+                let next = args.get(i + 1).ok_or("--user-agent expects a value")?;
+                opts.user_agent = Some(next.to_string());
+                i += 2;
+            }
+            "--endpoint" => {
+                let next = args.get(i + 1).ok_or("--endpoint expects a value")?;
+                opts.endpoint = Some(next.to_string());
+                i += 2;
+            }
+            "--access-key" => {
+                let next = args.get(i + 1).ok_or("--access-key expects a value")?;
+                opts.access_key = Some(next.to_string());
+                i += 2;
+            }
+            "--secret-key" => {
+                let next = args.get(i + 1).ok_or("--secret-key expects a value")?;
+                opts.secret_key = Some(next.to_string());
+                i += 2;
+            }
+            "--region" => {
+                let next = args.get(i + 1).ok_or("--region expects a value")?;
+                opts.region = Some(next.to_string());
+                i += 2;
+            }
+            "--path-style" => {
+                opts.path_style = true;
+                i += 1;
+            }
             "--resolve" => {
                 let value = args.get(i + 1).ok_or("--resolve expects a value")?;
                 opts.resolve.push(value.to_string());
@@ -283,6 +1528,31 @@ fn parse_globals(args: Vec<String>) -> Result<(GlobalOpts, Vec<String>), String>
                 opts.custom_headers.push(value.to_string());
                 i += 2;
             }
+            "--signed-header" => {
+                let value = args.get(i + 1).ok_or("--signed-header expects a value")?;
+                opts.signed_headers.push(parse_signed_header(value)?);
+                i += 2;
+            }
+            "--page-size" => {
+                let value = args.get(i + 1).ok_or("--page-size expects a value")?;
+                let n = value
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|n| (1..=1000).contains(n))
+                    .ok_or_else(|| format!("invalid --page-size value (1-1000): {value}"))?;
+                opts.page_size = Some(n);
+                i += 2;
+            }
+            "--max-concurrent" => {
+                let value = args.get(i + 1).ok_or("--max-concurrent expects a value")?;
+                let n = value
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| format!("invalid --max-concurrent value: {value}"))?;
+                opts.max_concurrent = Some(n);
+                i += 2;
+            }
             "--help" | "-h" | "--version" | "-v" => {
                 rest.extend_from_slice(&args[i..]);
                 break;
@@ -295,59 +1565,175 @@ fn parse_globals(args: Vec<String>) -> Result<(GlobalOpts, Vec<String>), String>
         }
     }
 
+    let given = [
+        opts.endpoint.is_some(),
+        opts.access_key.is_some(),
+        opts.secret_key.is_some(),
+    ];
+    if (given.contains(&true) || opts.region.is_some() || opts.path_style) && given.contains(&false)
+    {
+        return Err(
+            "--endpoint, --access-key and --secret-key must be given together (--region and --path-style need them too)"
+                .to_string(),
+        );
+    }
     Ok((opts, rest))
 }
 
+/// Name of the alias `--endpoint` creates: `s4 --endpoint URL ... ls _/bucket`.
+const EPHEMERAL_ALIAS: &str = "_";
+
+/// The in-memory alias behind `--endpoint`; `None` unless the group was given.
+fn ephemeral_alias(opts: &GlobalOpts) -> Option<AliasConfig> {
+    Some(AliasConfig {
+        endpoint: opts.endpoint.clone()?,
+        access_key: opts.access_key.clone()?,
+        secret_key: opts.secret_key.clone()?,
+        region: opts
+            .region
+            .clone()
+            .unwrap_or_else(|| "us-east-1".to_string()),
+        path_style: opts.path_style,
+        request_payer: false,
+        aws_profile: None,
+        session_token: None,
+    })
+}
+
+/// `KEY:VALUE` for `--signed-header`, canonicalized the way SigV4 expects: lowercase
+/// name, trimmed value with inner runs of spaces collapsed.
+fn parse_signed_header(value: &str) -> Result<(String, String), String> {
+    let (name, val) = value
+        .split_once(':')
+        .ok_or_else(|| format!("invalid --signed-header (expected KEY:VALUE): {value}"))?;
+    let name = name.trim().to_ascii_lowercase();
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+    {
+        return Err(format!("invalid --signed-header name: {value}"));
+    }
+    if matches!(
+        name.as_str(),
+        "host" | "authorization" | "x-amz-date" | "x-amz-content-sha256"
+    ) {
+        return Err(format!("--signed-header cannot override {name}"));
+    }
+    let val = val.split_whitespace().collect::<Vec<_>>().join(" ");
+    Ok((name, val))
+}
+
 fn handle_alias(
     args: &[String],
     config: &mut AppConfig,
     config_path: &Path,
     json: bool,
-) -> Result<(), String> {
+    debug: bool,
+) -> Result<(), S4Error> {
     if args.is_empty() {
-        return Err("usage: s4 alias <set|ls|rm> ...".to_string());
+        return Err(S4Error::Usage(
+            "usage: s4 alias <set|set-default|ls|rm|export|import> ...".to_string(),
+        ));
     }
 
     match args[0].as_str() {
         "set" => {
-            if args.len() < 5 {
-                return Err("usage: s4 alias set <name> <endpoint> <access> <secret> [--region r] [--path-style]".to_string());
+            let usage = "usage: s4 alias set <name> <endpoint> <access> <secret> [--region r] [--path-style] [--request-payer] [--test|--test-required]\n       s4 alias set <name> <endpoint> --aws-profile <profile> [FLAGS]\n       s4 alias set <name> [--endpoint url] [--region r] [--path-style|--no-path-style] [--request-payer|--no-request-payer] [--aws-profile p]";
+            let positional = args[1..]
+                .iter()
+                .take_while(|a| !a.starts_with("--"))
+                .count();
+            if positional == 0 || positional == 3 {
+                return Err(S4Error::Usage(usage.to_string()));
             }
-            let mut region = "us-east-1".to_string();
-            let mut path_style = false;
-            let mut i = 5;
-            while i < args.len() {
-                match args[i].as_str() {
-                    "--region" => {
-                        region = args
-                            .get(i + 1)
-                            .ok_or("--region expects a value")?
-                            .to_string();
-                        i += 2;
+            let flags =
+                parse_alias_set_flags(&args[positional.min(4) + 1..]).map_err(S4Error::Usage)?;
+            let mut alias = match positional {
+                1 => {
+                    if !flags.updates_fields() {
+                        return Err(S4Error::Usage(usage.to_string()));
                     }
-                    "--path-style" => {
-                        path_style = true;
-                        i += 1;
+                    config
+                        .aliases
+                        .get(&args[1])
+                        .cloned()
+                        .ok_or_else(|| S4Error::Config(format!("unknown alias: {}", args[1])))?
+                }
+                2 if flags.aws_profile.is_none() => {
+                    return Err(S4Error::Usage(usage.to_string()));
+                }
+                _ => {
+                    let (access_key, secret_key) = if positional >= 4 {
+                        (args[3].clone(), args[4].clone())
+                    } else {
+                        (String::new(), String::new())
+                    };
+                    AliasConfig {
+                        endpoint: args[2].clone(),
+                        access_key,
+                        secret_key,
+                        region: "us-east-1".to_string(),
+                        path_style: false,
+                        request_payer: false,
+                        aws_profile: None,
+                        session_token: None,
                     }
-                    other => return Err(format!("unknown alias set flag: {other}")),
                 }
+            };
+            flags.apply(&mut alias);
+            let (test, test_required) = (flags.test, flags.test_required);
+            let previous = config.aliases.insert(args[1].clone(), alias.clone());
+            save_config(config_path, config)?;
+            if let Some(profile) = alias.aws_profile.clone()
+                && test
+            {
+                apply_aws_profile(&mut alias, load_aws_profile(&profile)?);
+            }
+            if !test {
+                if json {
+                    println!("{{\"status\":\"ok\",\"alias\":\"{}\"}}", args[1]);
+                } else if !is_quiet() {
+                    println!("Alias '{}' saved", args[1]);
+                }
+                return Ok(());
             }
 
-            config.aliases.insert(
-                args[1].clone(),
-                AliasConfig {
-                    endpoint: args[2].clone(),
-                    access_key: args[3].clone(),
-                    secret_key: args[4].clone(),
-                    region,
-                    path_style,
-                },
-            );
-            save_config(config_path, config)?;
+            let check = check_ready(&alias, debug);
+            if let (Err(err), true) = (&check, test_required) {
+                match previous {
+                    Some(old) => config.aliases.insert(args[1].clone(), old),
+                    None => config.aliases.remove(&args[1]),
+                };
+                save_config(config_path, config)?;
+                return Err(S4Error::Other(format!(
+                    "alias '{}' not saved: connectivity test failed: {err}",
+                    args[1]
+                )));
+            }
             if json {
-                println!("{{\"status\":\"ok\",\"alias\":\"{}\"}}", args[1]);
+                let test_field = match &check {
+                    Ok(()) => "\"test\":{\"ready\":true}".to_string(),
+                    Err(err) => format!(
+                        "\"test\":{{\"ready\":false,\"error\":\"{}\"}}",
+                        escape_json(&err.to_string())
+                    ),
+                };
+                println!(
+                    "{{\"status\":\"ok\",\"alias\":\"{}\",{test_field}}}",
+                    args[1]
+                );
             } else {
-                println!("Alias '{}' saved", args[1]);
+                match &check {
+                    Ok(()) if !is_quiet() => {
+                        println!("Alias '{}' saved; {} is ready", args[1], args[1])
+                    }
+                    Ok(()) => {}
+                    Err(err) => eprintln!(
+                        "warning: alias '{}' saved, but connectivity test failed: {err}",
+                        args[1]
+                    ),
+                }
             }
             Ok(())
         }
@@ -359,27 +1745,160 @@ fn handle_alias(
                         print!(",");
                     }
                     print!(
-                        "{{\"name\":\"{}\",\"endpoint\":\"{}\",\"region\":\"{}\",\"path_style\":{}}}",
+                        "{{\"name\":\"{}\",\"endpoint\":\"{}\",\"region\":\"{}\",\"path_style\":{},\"request_payer\":{},\"aws_profile\":{},\"default\":{}}}",
                         escape_json(name),
                         escape_json(&alias.endpoint),
                         escape_json(&alias.region),
-                        alias.path_style
+                        alias.path_style,
+                        alias.request_payer,
+                        alias
+                            .aws_profile
+                            .as_ref()
+                            .map(|p| format!("\"{}\"", escape_json(p)))
+                            .unwrap_or_else(|| "null".to_string()),
+                        config.default_alias.as_ref() == Some(name)
                     );
                 }
                 println!("]");
             } else {
                 for (name, alias) in &config.aliases {
+                    let marker = if config.default_alias.as_ref() == Some(name) {
+                        "\t(default)"
+                    } else {
+                        ""
+                    };
+                    let payer = if alias.request_payer {
+                        "\trequest_payer=true"
+                    } else {
+                        ""
+                    };
+                    let profile = alias
+                        .aws_profile
+                        .as_ref()
+                        .map(|p| format!("\taws_profile={p}"))
+                        .unwrap_or_default();
                     println!(
-                        "{name}\t{}\t{}\tpath_style={}",
+                        "{name}\t{}\t{}\tpath_style={}{payer}{profile}{marker}",
                         alias.endpoint, alias.region, alias.path_style
                     );
                 }
             }
             Ok(())
         }
+        "export" => {
+            let mut file: Option<PathBuf> = None;
+            let mut secrets = true;
+            let mut i = 1;
+            while i < args.len() {
+                match args[i].as_str() {
+                    "--file" => {
+                        file = Some(PathBuf::from(
+                            args.get(i + 1).ok_or("--file expects a value")?,
+                        ));
+                        i += 2;
+                    }
+                    "--no-secrets" => {
+                        secrets = false;
+                        i += 1;
+                    }
+                    other => {
+                        return Err(S4Error::Usage(format!(
+                            "unknown alias export flag: {other}"
+                        )));
+                    }
+                }
+            }
+            let text = aliases_to_json(config, secrets);
+            match file {
+                Some(path) => {
+                    fs::write(&path, text + "\n")?;
+                    if json {
+                        println!(
+                            "{{\"status\":\"ok\",\"exported\":{},\"file\":\"{}\"}}",
+                            config.aliases.len(),
+                            escape_json(&path.display().to_string())
+                        );
+                    } else if !is_quiet() {
+                        println!(
+                            "Exported {} alias(es) to '{}'",
+                            config.aliases.len(),
+                            path.display()
+                        );
+                    }
+                }
+                None => println!("{text}"),
+            }
+            Ok(())
+        }
+        "import" => {
+            let mut file: Option<&String> = None;
+            let mut overwrite = false;
+            for arg in &args[1..] {
+                match arg.as_str() {
+                    "--overwrite" => overwrite = true,
+                    f if f.starts_with('-') => {
+                        return Err(S4Error::Usage(format!("unknown alias import flag: {f}")));
+                    }
+                    _ => file = Some(arg),
+                }
+            }
+            let file = file.ok_or("usage: s4 alias import <file.json> [--overwrite]")?;
+            let text = fs::read_to_string(file)?;
+            let mut imported = aliases_from_json(&text).map_err(S4Error::Config)?;
+            let collisions: Vec<&str> = imported
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .filter(|name| config.aliases.contains_key(*name))
+                .collect();
+            if !overwrite && !collisions.is_empty() {
+                return Err(S4Error::Usage(format!(
+                    "alias(es) already exist: {} (use --overwrite to replace them)",
+                    collisions.join(", ")
+                )));
+            }
+            adopt_existing_keys(&mut imported, config).map_err(S4Error::Config)?;
+            let count = imported.len();
+            config.aliases.extend(imported);
+            save_config(config_path, config)?;
+            if json {
+                println!("{{\"status\":\"ok\",\"imported\":{count}}}");
+            } else if !is_quiet() {
+                println!("Imported {count} alias(es) from '{file}'");
+            }
+            Ok(())
+        }
+        "set-default" => {
+            let name = args
+                .get(1)
+                .ok_or("usage: s4 alias set-default <name|--clear>")?;
+            if name == "--clear" {
+                config.default_alias = None;
+            } else if config.aliases.contains_key(name) {
+                config.default_alias = Some(name.clone());
+            } else {
+                return Err(S4Error::Config(format!("unknown alias: {name}")));
+            }
+            save_config(config_path, config)?;
+            let current = config.default_alias.as_deref().unwrap_or("");
+            if json {
+                println!(
+                    "{{\"status\":\"ok\",\"default_alias\":\"{}\"}}",
+                    escape_json(current)
+                );
+            } else if !is_quiet() {
+                match &config.default_alias {
+                    Some(name) => println!("Default alias set to '{name}'"),
+                    None => println!("Default alias cleared"),
+                }
+            }
+            Ok(())
+        }
         "rm" => {
             let name = args.get(1).ok_or("usage: s4 alias rm <name>")?;
             let existed = config.aliases.remove(name).is_some();
+            if config.default_alias.as_ref() == Some(name) {
+                config.default_alias = None;
+            }
             save_config(config_path, config)?;
             if json {
                 println!(
@@ -388,24 +1907,28 @@ fn handle_alias(
                     existed
                 );
             } else if existed {
-                println!("Alias '{name}' removed");
+                if !is_quiet() {
+                    println!("Alias '{name}' removed");
+                }
             } else {
                 println!("Alias '{name}' not found");
             }
             Ok(())
         }
-        _ => Err("usage: s4 alias <set|ls|rm> ...".to_string()),
+        _ => Err(S4Error::Usage(
+            "usage: s4 alias <set|set-default|ls|rm|export|import> ...".to_string(),
+        )),
     }
 }
 
 fn handle_s3_command(
     args: &[String],
     config: &AppConfig,
-    json: bool,
+    output: OutputFormat,
     debug: bool,
-) -> Result<(), String> {
+) -> Result<(), S4Error> {
+    let json = output.is_json();
     let command = &args[0];
-    let target_idx = if command == "put" { 2 } else { 1 };
     if command != "sync"
         && command != "mirror"
         && command != "cp"
@@ -414,8 +1937,14 @@ fn handle_s3_command(
         && command != "tree"
         && command != "head"
         && command != "pipe"
+        && command != "append"
         && command != "ping"
         && command != "ready"
+        && command != "whoami"
+        && command != "multipart"
+        && command != "restore"
+        && command != "usage"
+        && command != "api"
         && command != "cors"
         && command != "encrypt"
         && command != "event"
@@ -426,23 +1955,51 @@ fn handle_s3_command(
         && command != "retention"
         && command != "sql"
         && command != "mb"
-        && args.len() <= target_idx
+        && command != "put"
+        && command != "get"
+        && command != "cat"
+        && args.len() <= 1
     {
-        return Err(format!("usage: s4 {command} ..."));
+        return Err(S4Error::Usage(format!("usage: s4 {command} ...")));
     }
 
     if command == "cp" || command == "mv" {
-        if args.len() < 3 {
-            return Err(format!("usage: s4 {command} <source> <target>"));
+        let (cp_opts, source, target) = parse_cp_args(args).map_err(S4Error::Usage)?;
+        if cp_opts.recursive {
+            return cmd_mv_recursive(config, &source, &target, &cp_opts, json, debug);
+        }
+        return cmd_cp_mv(command, config, &source, &target, &cp_opts, json, debug);
+    }
+
+    if command == "diff" {
+        let positional: Vec<&String> = args[1..].iter().collect();
+        if positional.len() != 2 || positional.iter().any(|a| a.starts_with('-')) {
+            return Err(S4Error::Usage(
+                "usage: s4 diff <alias/bucket[/prefix]> <alias/bucket[/prefix]>".to_string(),
+            ));
         }
-        return cmd_cp_mv(command, config, &args[1], &args[2], json, debug);
+        return cmd_diff(config, positional[0], positional[1], json, debug);
+    }
+
+    if command == "rename" || command == "ren" {
+        let (from, to) = parse_rename_args(args).map_err(S4Error::Usage)?;
+        let alias = config
+            .aliases
+            .get(&from.alias)
+            .ok_or_else(|| unknown_alias(&from.alias))?;
+        return cmd_rename(alias, &from, &to, json, debug);
     }
 
     if command == "mb" {
         if args.len() < 2 {
-            return Err("usage: s4 mb [--with-lock] <alias/bucket>".to_string());
+            return Err(S4Error::Usage(
+                "usage: s4 mb [--with-lock] [--ignore-existing] [--region r] <alias/bucket>"
+                    .to_string(),
+            ));
         }
         let mut with_lock = false;
+        let mut ignore_existing = false;
+        let mut region: Option<String> = None;
         let mut target_arg: Option<&String> = None;
         let mut i = 1;
         while i < args.len() {
@@ -451,183 +2008,414 @@ fn handle_s3_command(
                     with_lock = true;
                     i += 1;
                 }
-                x if x.starts_with('-') => return Err(format!("unknown mb flag: {x}")),
+                "--ignore-existing" => {
+                    ignore_existing = true;
+                    i += 1;
+                }
+                "--region" => {
+                    let v = args.get(i + 1).ok_or("--region expects a value")?;
+                    region = Some(v.to_string());
+                    i += 2;
+                }
+                x if x.starts_with('-') => {
+                    return Err(S4Error::Usage(format!("unknown mb flag: {x}")));
+                }
                 _ => {
                     target_arg = Some(&args[i]);
                     i += 1;
                 }
             }
         }
-        let target_val = target_arg.ok_or("usage: s4 mb [--with-lock] <alias/bucket>")?;
-        let target = parse_target(target_val)?;
-        let alias = config
+        let target_val = target_arg
+            .ok_or("usage: s4 mb [--with-lock] [--ignore-existing] [--region r] <alias/bucket>")?;
+        let target = parse_target(target_val).map_err(S4Error::Usage)?;
+        let mut alias = config
             .aliases
             .get(&target.alias)
-            .ok_or_else(|| format!("unknown alias: {}", target.alias))?;
-        let bucket = req_bucket(&target, "mb")?;
+            .ok_or_else(|| unknown_alias(&target.alias))?
+            .clone();
+        if let Some(region) = region {
+            alias.region = region;
+        }
+        let bucket = req_bucket(&target, "mb").map_err(S4Error::Usage)?;
+        let mut headers = Vec::new();
         if with_lock {
-            let headers = vec!["x-amz-bucket-object-lock-enabled: true".to_string()];
-            s3_request_with_headers(alias, "PUT", &bucket, None, "", None, None, &headers, debug)?;
-        } else {
-            s3_request(alias, "PUT", &bucket, None, "", None, None, debug)?;
+            headers.push("x-amz-bucket-object-lock-enabled: true".to_string());
+        }
+        let res = match build_create_bucket_xml(&alias.region) {
+            Some(body) => {
+                let temp = temp_path(format!("s4-mb-{}-config.xml", std::process::id()));
+                fs::write(&temp, body).map_err(|e| e.to_string())?;
+                let res = s3_request(
+                    &alias,
+                    S3Call {
+                        method: "PUT",
+                        bucket: &bucket,
+                        upload_file: Some(&temp),
+                        headers: &headers,
+                        ..S3Call::default()
+                    },
+                    debug,
+                );
+                remove_temp(&temp);
+                res
+            }
+            None => s3_request(
+                &alias,
+                S3Call {
+                    method: "PUT",
+                    bucket: &bucket,
+                    headers: &headers,
+                    ..S3Call::default()
+                },
+                debug,
+            ),
+        };
+        match res {
+            Ok(_) => print_status(json, "created", &bucket),
+            Err(err) if ignore_existing && is_bucket_owned_error(&err.to_string()) => {
+                print_status(json, "exists", &bucket)
+            }
+            Err(err) => return Err(err),
         }
-        print_status(json, "created", &bucket);
         return Ok(());
     }
 
     if command == "find" {
-        if args.len() < 2 {
-            return Err("usage: s4 find <alias/bucket[/prefix]> [needle]".to_string());
-        }
-        let target = parse_target(&args[1])?;
+        let (find_opts, target) = parse_find_args(args).map_err(S4Error::Usage)?;
         let alias = config
             .aliases
             .get(&target.alias)
-            .ok_or_else(|| format!("unknown alias: {}", target.alias))?;
-        let bucket = req_bucket(&target, "find")?;
-        let prefix = target.key.clone().unwrap_or_default();
-        let needle = args.get(2).cloned();
-        return cmd_find(alias, &bucket, &prefix, needle.as_deref(), json, debug);
+            .ok_or_else(|| unknown_alias(&target.alias))?;
+        return cmd_find(alias, &target, &find_opts, output, debug);
     }
 
     if command == "tree" {
         if args.len() < 2 {
-            return Err("usage: s4 tree <alias/bucket[/prefix]>".to_string());
+            return Err(S4Error::Usage(
+                "usage: s4 tree <alias/bucket[/prefix]>".to_string(),
+            ));
         }
-        let target = parse_target(&args[1])?;
+        let target = parse_target(&args[1]).map_err(S4Error::Usage)?;
         let alias = config
             .aliases
             .get(&target.alias)
-            .ok_or_else(|| format!("unknown alias: {}", target.alias))?;
-        let bucket = req_bucket(&target, "tree")?;
+            .ok_or_else(|| unknown_alias(&target.alias))?;
+        let bucket = req_bucket(&target, "tree").map_err(S4Error::Usage)?;
         let prefix = target.key.clone().unwrap_or_default();
         return cmd_tree(alias, &bucket, &prefix, json, debug);
     }
 
+    if command == "du" {
+        let (du_opts, target) = parse_du_args(args).map_err(S4Error::Usage)?;
+        let alias = config
+            .aliases
+            .get(&target.alias)
+            .ok_or_else(|| unknown_alias(&target.alias))?;
+        let bucket = req_bucket(&target, "du").map_err(S4Error::Usage)?;
+        let prefix = target.key.clone().unwrap_or_default();
+        return cmd_du(alias, &bucket, &prefix, &du_opts, output, debug);
+    }
+
+    if command == "usage" {
+        let (quota, target) = parse_usage_args(args).map_err(S4Error::Usage)?;
+        let alias = config
+            .aliases
+            .get(&target.alias)
+            .ok_or_else(|| unknown_alias(&target.alias))?;
+        let bucket = req_bucket(&target, "usage").map_err(S4Error::Usage)?;
+        let prefix = target.key.clone().unwrap_or_default();
+        return cmd_usage(alias, &bucket, &prefix, quota, json, debug)
+            .map_err(|e| not_found_error(e, &target.alias, &bucket, &prefix));
+    }
+
+    if command == "api" {
+        let request = parse_api_args(args).map_err(S4Error::Usage)?;
+        let alias = config
+            .aliases
+            .get(&request.target.alias)
+            .ok_or_else(|| unknown_alias(&request.target.alias))?;
+        return cmd_api(alias, &request, debug);
+    }
+
     if command == "head" {
-        if args.len() < 2 {
-            return Err("usage: s4 head <alias/bucket/key> [lines]".to_string());
-        }
-        let target = parse_target(&args[1])?;
+        let (mode, decompress, target) = parse_head_args(args).map_err(S4Error::Usage)?;
         let alias = config
             .aliases
             .get(&target.alias)
-            .ok_or_else(|| format!("unknown alias: {}", target.alias))?;
-        let bucket = req_bucket(&target, "head")?;
-        let key = req_key(&target, "head")?;
-        let lines = args
-            .get(2)
-            .map(|v| {
-                v.parse::<usize>()
-                    .map_err(|_| "head lines must be integer".to_string())
-            })
-            .transpose()?
-            .unwrap_or(10);
-        return cmd_head(alias, &bucket, &key, lines, debug);
+            .ok_or_else(|| unknown_alias(&target.alias))?;
+        let bucket = req_bucket(&target, "head").map_err(S4Error::Usage)?;
+        let key = req_key(&target, "head").map_err(S4Error::Usage)?;
+        return cmd_head(alias, &bucket, &key, mode, decompress, debug)
+            .map_err(|e| not_found_error(e, &target.alias, &bucket, &key));
     }
 
     if command == "pipe" {
-        if args.len() < 2 {
-            return Err("usage: s4 pipe <alias/bucket/key>".to_string());
-        }
-        let target = parse_target(&args[1])?;
+        let (upload, target) = parse_pipe_args(args).map_err(S4Error::Usage)?;
+        let alias = config
+            .aliases
+            .get(&target.alias)
+            .ok_or_else(|| unknown_alias(&target.alias))?;
+        let bucket = req_bucket(&target, "pipe").map_err(S4Error::Usage)?;
+        let key = req_key(&target, "pipe").map_err(S4Error::Usage)?;
+        return cmd_pipe(alias, &bucket, &key, &upload, json, debug);
+    }
+
+    if command == "append" {
+        let (native, target) = parse_append_args(args).map_err(S4Error::Usage)?;
         let alias = config
             .aliases
             .get(&target.alias)
-            .ok_or_else(|| format!("unknown alias: {}", target.alias))?;
-        let bucket = req_bucket(&target, "pipe")?;
-        let key = req_key(&target, "pipe")?;
-        return cmd_pipe(alias, &bucket, &key, json, debug);
+            .ok_or_else(|| unknown_alias(&target.alias))?;
+        let bucket = req_bucket(&target, "append").map_err(S4Error::Usage)?;
+        let key = req_key(&target, "append").map_err(S4Error::Usage)?;
+        return cmd_append(alias, &bucket, &key, native, json, debug);
     }
 
     if command == "ping" {
-        if args.len() < 2 {
-            return Err("usage: s4 ping <alias>".to_string());
-        }
-        let target = parse_target(&args[1])?;
+        let (count, target) = parse_ping_args(args).map_err(S4Error::Usage)?;
         let alias = config
             .aliases
             .get(&target.alias)
-            .ok_or_else(|| format!("unknown alias: {}", target.alias))?;
-        return cmd_ping(&target.alias, alias, json, debug);
+            .ok_or_else(|| unknown_alias(&target.alias))?;
+        return match count {
+            Some(count) => cmd_ping_count(&target.alias, alias, count, json, debug),
+            None => cmd_ping(&target.alias, alias, json, debug),
+        };
     }
 
     if command == "ready" {
-        if args.len() < 2 {
-            return Err("usage: s4 ready <alias>".to_string());
+        let (health_endpoint, target) = parse_ready_args(args).map_err(S4Error::Usage)?;
+        let alias = config
+            .aliases
+            .get(&target.alias)
+            .ok_or_else(|| unknown_alias(&target.alias))?;
+        return cmd_ready(&target.alias, alias, health_endpoint, json, debug);
+    }
+
+    if command == "whoami" {
+        let usage = "usage: s4 whoami <alias>";
+        if args.len() != 2 || args[1].starts_with('-') {
+            return Err(S4Error::Usage(usage.to_string()));
         }
-        let target = parse_target(&args[1])?;
+        let target = parse_target(&args[1]).map_err(S4Error::Usage)?;
         let alias = config
             .aliases
             .get(&target.alias)
-            .ok_or_else(|| format!("unknown alias: {}", target.alias))?;
-        return cmd_ready(&target.alias, alias, json, debug);
+            .ok_or_else(|| unknown_alias(&target.alias))?;
+        return cmd_whoami(&target.alias, alias, json, debug);
     }
 
     if command == "cors" {
-        let cors_cmd = parse_cors_args(args)?;
+        let cors_cmd = parse_cors_args(args).map_err(S4Error::Usage)?;
         return cmd_cors(config, cors_cmd, json, debug);
     }
 
     if command == "encrypt" {
-        let encrypt_cmd = parse_encrypt_args(args)?;
+        let encrypt_cmd = parse_encrypt_args(args).map_err(S4Error::Usage)?;
         return cmd_encrypt(config, encrypt_cmd, json, debug);
     }
 
     if command == "event" {
-        let event_cmd = parse_event_args(args)?;
+        let event_cmd = parse_event_args(args).map_err(S4Error::Usage)?;
         return cmd_event(config, event_cmd, json, debug);
     }
 
     if command == "idp" {
-        let idp_cmd = parse_idp_args(args)?;
+        let idp_cmd = parse_idp_args(args).map_err(S4Error::Usage)?;
         return cmd_idp(idp_cmd, json);
     }
 
     if command == "ilm" {
-        let ilm_cmd = parse_ilm_args(args)?;
+        let ilm_cmd = parse_ilm_args(args).map_err(S4Error::Usage)?;
         return cmd_ilm(ilm_cmd, json);
     }
 
+    if command == "multipart" {
+        let (dry_run, target) = parse_multipart_args(args).map_err(S4Error::Usage)?;
+        let alias = config
+            .aliases
+            .get(&target.alias)
+            .ok_or_else(|| unknown_alias(&target.alias))?;
+        let bucket = req_bucket(&target, "multipart recover").map_err(S4Error::Usage)?;
+        return cmd_multipart_recover(alias, &bucket, dry_run, json, debug);
+    }
+
+    if command == "restore" {
+        let (wait, target) = parse_restore_args(args).map_err(S4Error::Usage)?;
+        let alias = config
+            .aliases
+            .get(&target.alias)
+            .ok_or_else(|| unknown_alias(&target.alias))?;
+        let bucket = req_bucket(&target, "restore wait").map_err(S4Error::Usage)?;
+        let key = req_key(&target, "restore wait").map_err(S4Error::Usage)?;
+        return cmd_restore_wait(alias, &bucket, &key, &wait, json, debug)
+            .map_err(|e| not_found_error(e, &target.alias, &bucket, &key));
+    }
+
     if command == "legalhold" {
-        let lh_cmd = parse_legalhold_args(args)?;
+        let lh_cmd = parse_legalhold_args(args).map_err(S4Error::Usage)?;
         return cmd_legalhold(config, lh_cmd, json, debug);
     }
 
     if command == "retention" {
-        let rt_cmd = parse_retention_args(args)?;
+        let rt_cmd = parse_retention_args(args).map_err(S4Error::Usage)?;
         return cmd_retention(config, rt_cmd, json, debug);
     }
 
     if command == "sql" {
-        let (sql_opts, sql_targets) = parse_sql_args(args)?;
+        let (sql_opts, sql_targets) = parse_sql_args(args).map_err(S4Error::Usage)?;
         return cmd_sql(config, &sql_opts, &sql_targets, json, debug);
     }
 
     if command == "replicate" {
-        let rep_cmd = parse_replicate_args(args)?;
+        let rep_cmd = parse_replicate_args(args).map_err(S4Error::Usage)?;
         return cmd_replicate(rep_cmd, json);
     }
 
     if command == "sync" || command == "mirror" {
-        let (sync_opts, src, dst) = parse_sync_args(args)?;
+        let (sync_opts, src, dst) = parse_sync_args(args).map_err(S4Error::Usage)?;
         return cmd_sync(config, &src, &dst, &sync_opts, json, debug);
     }
 
-    let target = parse_target(&args[target_idx])?;
-    let alias = config
-        .aliases
-        .get(&target.alias)
-        .ok_or_else(|| format!("unknown alias: {}", target.alias))?;
+    if command == "rm" {
+        let (rm_opts, target) = parse_rm_args(args).map_err(S4Error::Usage)?;
+        let alias = config
+            .aliases
+            .get(&target.alias)
+            .ok_or_else(|| unknown_alias(&target.alias))?;
+        return cmd_rm(alias, &target, &rm_opts, json, debug);
+    }
+
+    if command == "put" {
+        let (put_opts, source, target) = parse_put_args(args).map_err(S4Error::Usage)?;
+        let alias = config
+            .aliases
+            .get(&target.alias)
+            .ok_or_else(|| unknown_alias(&target.alias))?;
+        return cmd_put(alias, &source, &target, &put_opts, json, debug);
+    }
+
+    if command == "get" || command == "cat" {
+        let (get_opts, targets, destination) = parse_get_args(args).map_err(S4Error::Usage)?;
+        if let (Some(dir), true) = (&destination, targets.len() > 1) {
+            return cmd_get_many(config, &targets, dir, &get_opts, json, debug);
+        }
+        let target = &targets[0];
+        let alias = config
+            .aliases
+            .get(&target.alias)
+            .ok_or_else(|| unknown_alias(&target.alias))?;
+        // Like cp: an existing directory as the destination receives the key's basename.
+        let destination = match destination {
+            Some(dir) if dir.is_dir() => {
+                let key = req_key(target, "get").map_err(S4Error::Usage)?;
+                Some(dir.join(key_basename(&key)?))
+            }
+            other => other,
+        };
+        return cmd_get(
+            alias,
+            target,
+            destination.as_deref(),
+            &get_opts,
+            json,
+            debug,
+        )
+        .map_err(|e| {
+            let bucket = target.bucket.as_deref().unwrap_or_default();
+            let key = target.key.as_deref().unwrap_or_default();
+            not_found_error(e, &target.alias, bucket, key)
+        });
+    }
+
+    if command == "ls" {
+        let (ls_opts, target) = parse_ls_args(args).map_err(S4Error::Usage)?;
+        let alias = config
+            .aliases
+            .get(&target.alias)
+            .ok_or_else(|| unknown_alias(&target.alias))?;
+        return cmd_ls(alias, &target, &ls_opts, output, debug);
+    }
+
+    if command == "stat" {
+        let (local, target) = parse_stat_args(args).map_err(S4Error::Usage)?;
+        let alias = config
+            .aliases
+            .get(&target.alias)
+            .ok_or_else(|| unknown_alias(&target.alias))?;
+        let bucket = req_bucket(&target, "stat").map_err(S4Error::Usage)?;
+        let key = req_key(&target, "stat").map_err(S4Error::Usage)?;
+        // Without checksum mode S3 omits the stored x-amz-checksum-* values.
+        let headers = s3_request(
+            alias,
+            S3Call {
+                method: "HEAD",
+                bucket: &bucket,
+                key: Some(&key),
+                headers: &["x-amz-checksum-mode: ENABLED".to_string()],
+                ..S3Call::default()
+            },
+            debug,
+        )
+        .map_err(|e| not_found_error(e, &target.alias, &bucket, &key))?;
+        if let Some(local) = local {
+            return cmd_stat_checksum(&bucket, &key, &headers, &local, json);
+        }
+        let expiration = parse_expiration_header(&headers);
+        if json {
+            let expiration = expiration.map_or("null".to_string(), |e| {
+                format!(
+                    "{{\"expiry_date\":\"{}\",\"rule_id\":\"{}\"}}",
+                    escape_json(&e.expiry_date),
+                    escape_json(&e.rule_id)
+                )
+            });
+            println!(
+                "{{\"bucket\":\"{}\",\"key\":\"{}\",\"headers\":\"{}\",\"expiration\":{}}}",
+                escape_json(&bucket),
+                escape_json(&key),
+                escape_json(&headers),
+                expiration
+            );
+        } else {
+            println!("{}", headers);
+            if let Some(e) = expiration {
+                println!("Expires: {} (lifecycle rule {})", e.expiry_date, e.rule_id);
+            }
+        }
+        return Ok(());
+    }
+
+    let target = parse_target(&args[1]).map_err(S4Error::Usage)?;
+    let alias = config
+        .aliases
+        .get(&target.alias)
+        .ok_or_else(|| unknown_alias(&target.alias))?;
 
     match command.as_str() {
-        "ls" => cmd_ls(alias, &target, json, debug),
         "rb" => {
-            let bucket = req_bucket(&target, "rb")?;
-            if let Err(err) = s3_request(alias, "DELETE", &bucket, None, "", None, None, debug) {
-                if err.contains("BucketNotEmpty") {
+            let bucket = req_bucket(&target, "rb").map_err(S4Error::Usage)?;
+            if let Err(err) = s3_request(
+                alias,
+                S3Call {
+                    method: "DELETE",
+                    bucket: &bucket,
+                    ..S3Call::default()
+                },
+                debug,
+            ) {
+                if err.to_string().contains("BucketNotEmpty") {
                     purge_bucket_versions(alias, &bucket, debug)?;
-                    s3_request(alias, "DELETE", &bucket, None, "", None, None, debug)?;
+                    s3_request(
+                        alias,
+                        S3Call {
+                            method: "DELETE",
+                            bucket: &bucket,
+                            ..S3Call::default()
+                        },
+                        debug,
+                    )?;
                 } else {
                     return Err(err);
                 }
@@ -635,131 +2423,183 @@ fn handle_s3_command(
             print_status(json, "deleted", &bucket);
             Ok(())
         }
-        "put" => {
-            if args.len() < 3 {
-                return Err("usage: s4 put <source_file> <alias/bucket/key>".to_string());
-            }
-            let source = PathBuf::from(&args[1]);
-            if !source.exists() {
-                return Err(format!("source file not found: {}", source.display()));
-            }
-            let bucket = req_bucket(&target, "put")?;
-            let key = req_key(&target, "put")?;
-            upload_file_to_s3(alias, &bucket, &key, &source, debug)?;
-            if json {
-                println!(
-                    "{{\"uploaded\":{{\"bucket\":\"{}\",\"key\":\"{}\"}}}}",
-                    escape_json(&bucket),
-                    escape_json(&key)
-                );
-            } else {
-                println!("Uploaded '{}' to '{}/{}'", source.display(), bucket, key);
-            }
-            Ok(())
+        "location" => {
+            let bucket = req_bucket(&target, "location").map_err(S4Error::Usage)?;
+            cmd_location(alias, &bucket, json, debug)
         }
-        "get" => {
-            if args.len() < 3 {
-                return Err("usage: s4 get <alias/bucket/key> <destination_file>".to_string());
-            }
-            let bucket = req_bucket(&target, "get")?;
-            let key = req_key(&target, "get")?;
-            let destination = PathBuf::from(&args[2]);
-            if let Some(parent) = destination.parent() {
-                if !parent.as_os_str().is_empty() {
-                    fs::create_dir_all(parent).map_err(|e| e.to_string())?;
-                }
-            }
-            s3_request(
-                alias,
-                "GET",
-                &bucket,
-                Some(&key),
-                "",
-                None,
-                Some(&destination),
-                debug,
-            )?;
-            if json {
-                println!(
-                    "{{\"downloaded\":{{\"bucket\":\"{}\",\"key\":\"{}\",\"to\":\"{}\"}}}}",
-                    escape_json(&bucket),
-                    escape_json(&key),
-                    escape_json(&destination.display().to_string())
-                );
-            } else {
-                println!(
-                    "Downloaded '{}/{}' to '{}'",
-                    bucket,
-                    key,
-                    destination.display()
-                );
-            }
-            Ok(())
+        "sync" | "mirror" => unreachable!(),
+        "rm" | "cp" | "mv" | "rename" | "ren" | "diff" | "find" | "tree" | "du" | "head"
+        | "pipe" | "append" | "ping" | "ready" | "whoami" | "cors" | "encrypt" | "event" => {
+            unreachable!()
         }
-        "rm" => {
-            let bucket = req_bucket(&target, "rm")?;
-            let key = req_key(&target, "rm")?;
-            match s3_request(alias, "DELETE", &bucket, Some(&key), "", None, None, debug) {
-                Ok(_) => {}
-                Err(err) => {
-                    if should_retry_with_governance_bypass(&err) {
-                        let headers = vec!["x-amz-bypass-governance-retention: true".to_string()];
-                        s3_request_with_headers(
-                            alias,
-                            "DELETE",
-                            &bucket,
-                            Some(&key),
-                            "",
-                            None,
-                            None,
-                            &headers,
-                            debug,
-                        )?;
-                    } else {
-                        return Err(err);
-                    }
-                }
+        _ => Err(format!("unsupported command: {command}").into()),
+    }
+}
+
+const STAT_USAGE: &str = "usage: s4 stat [--checksum LOCAL_FILE] <alias/bucket/key>";
+
+fn parse_stat_args(args: &[String]) -> Result<(Option<PathBuf>, S3Target), String> {
+    let mut local = None;
+    let mut target = None;
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--checksum" => {
+                let file = args.get(i + 1).ok_or("--checksum expects a local file")?;
+                local = Some(PathBuf::from(file));
+                i += 2;
             }
-            if json {
-                println!(
-                    "{{\"deleted\":{{\"bucket\":\"{}\",\"key\":\"{}\"}}}}",
-                    escape_json(&bucket),
-                    escape_json(&key)
-                );
-            } else {
-                println!("Deleted '{}/{}'", bucket, key);
+            f if f.starts_with('-') => return Err(format!("unknown stat flag: {f}")),
+            _ if target.is_none() => {
+                target = Some(parse_target(&args[i])?);
+                i += 1;
             }
-            Ok(())
+            _ => return Err(STAT_USAGE.to_string()),
         }
-        "stat" => {
-            let bucket = req_bucket(&target, "stat")?;
-            let key = req_key(&target, "stat")?;
-            let headers = s3_request(alias, "HEAD", &bucket, Some(&key), "", None, None, debug)?;
-            if json {
-                println!(
-                    "{{\"bucket\":\"{}\",\"key\":\"{}\",\"headers\":\"{}\"}}",
-                    escape_json(&bucket),
-                    escape_json(&key),
-                    escape_json(&headers)
-                );
-            } else {
-                println!("{}", headers);
-            }
-            Ok(())
+    }
+    Ok((local, target.ok_or(STAT_USAGE)?))
+}
+
+/// A stored digest `stat --checksum` can recompute from the local file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StoredDigest {
+    /// A single-part ETag is the hex MD5 of the body.
+    Md5,
+    Additive(ChecksumAlgorithm),
+}
+
+impl StoredDigest {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Md5 => "md5",
+            Self::Additive(ChecksumAlgorithm::Crc32) => "crc32",
+            Self::Additive(ChecksumAlgorithm::Sha256) => "sha256",
         }
-        "cat" => {
-            let bucket = req_bucket(&target, "cat")?;
-            let key = req_key(&target, "cat")?;
-            let body = s3_request(alias, "GET", &bucket, Some(&key), "", None, None, debug)?;
-            print!("{}", body);
-            Ok(())
+    }
+
+    fn compute(self, path: &Path) -> Result<String, S4Error> {
+        match self {
+            Self::Md5 => file_md5_hex(path),
+            Self::Additive(algorithm) => Ok(checksum_value(path, algorithm)?),
         }
-        "sync" | "mirror" => unreachable!(),
-        "cp" | "mv" | "find" | "tree" | "head" | "pipe" | "ping" | "ready" | "cors" | "encrypt"
-        | "event" => {
-            unreachable!()
+    }
+}
+
+/// Digests in a HEAD response that a local file can be checked against, plus a note
+/// for each one that cannot: multipart ETags and composite (`...-N`) checksums are
+/// built from the part digests, so they depend on the part size used for the upload,
+/// and the ETag of an SSE-KMS or SSE-C object is not an MD5 of the plaintext at all.
+fn comparable_digests(head: &str) -> (Vec<(StoredDigest, String)>, Vec<String>) {
+    let mut digests = Vec::new();
+    let mut notes = Vec::new();
+    if let Some(etag) = head_header(head, "etag") {
+        match (etag_md5(&etag), etag_encryption(head)) {
+            (Some(_), Some(sse)) => notes.push(format!(
+                "ETag {etag} is unverifiable ({sse} object); compared by size and stored checksums only"
+            )),
+            (Some(md5), None) => digests.push((StoredDigest::Md5, md5.to_ascii_lowercase())),
+            (None, _) => notes.push(format!(
+                "ETag {etag} is not an MD5 (multipart upload); compared by size and stored checksums only"
+            )),
+        }
+    }
+    for algorithm in [ChecksumAlgorithm::Sha256, ChecksumAlgorithm::Crc32] {
+        let digest = StoredDigest::Additive(algorithm);
+        match head_header(head, algorithm.header()) {
+            Some(value) if value.contains('-') => notes.push(format!(
+                "{} {value} is a composite multipart checksum; not compared",
+                digest.name()
+            )),
+            Some(value) => digests.push((digest, value)),
+            None => {}
+        }
+    }
+    (digests, notes)
+}
+
+/// Server-side encryption that makes even a single-part ETag something other than the
+/// MD5 of the body; SSE-S3 (`AES256`) keeps the MD5.
+fn etag_encryption(head: &str) -> Option<&'static str> {
+    if head_header(head, "x-amz-server-side-encryption-customer-algorithm").is_some() {
+        return Some("SSE-C");
+    }
+    head_header(head, "x-amz-server-side-encryption")
+        .filter(|sse| sse.starts_with("aws:kms"))
+        .map(|_| "SSE-KMS")
+}
+
+/// `stat --checksum FILE`: is FILE the same as the object? Compares the size and every
+/// stored digest it can recompute, without downloading the object. A mismatch is an error.
+fn cmd_stat_checksum(
+    bucket: &str,
+    key: &str,
+    head: &str,
+    local: &Path,
+    json: bool,
+) -> Result<(), S4Error> {
+    let meta = fs::metadata(local)
+        .map_err(|e| S4Error::Io(format!("cannot read {}: {e}", local.display())))?;
+    if !meta.is_file() {
+        return Err(S4Error::Usage(format!(
+            "{} is not a regular file",
+            local.display()
+        )));
+    }
+    let remote_size = head_header(head, "content-length").unwrap_or_default();
+    let mut checks = vec![("size", remote_size, meta.len().to_string())];
+    let (digests, notes) = comparable_digests(head);
+    for (digest, remote) in digests {
+        checks.push((digest.name(), remote, digest.compute(local)?));
+    }
+    let same = checks.iter().all(|(_, remote, local)| remote == local);
+
+    if json {
+        let items: Vec<String> = checks
+            .iter()
+            .map(|(name, remote, local)| {
+                format!(
+                    "{{\"check\":\"{name}\",\"remote\":\"{}\",\"local\":\"{}\",\"match\":{}}}",
+                    escape_json(remote),
+                    escape_json(local),
+                    remote == local
+                )
+            })
+            .collect();
+        let notes: Vec<String> = notes
+            .iter()
+            .map(|n| format!("\"{}\"", escape_json(n)))
+            .collect();
+        println!(
+            "{{\"bucket\":\"{}\",\"key\":\"{}\",\"file\":\"{}\",\"match\":{same},\"checks\":[{}],\"notes\":[{}]}}",
+            escape_json(bucket),
+            escape_json(key),
+            escape_json(&local.display().to_string()),
+            items.join(","),
+            notes.join(",")
+        );
+    } else {
+        for (name, remote, local) in &checks {
+            let verdict = if remote == local { "match" } else { "MISMATCH" };
+            println!("{name:<7} {verdict:<9} remote {remote}  local {local}");
+        }
+        for note in &notes {
+            println!("note: {note}");
+        }
+        if same {
+            println!(
+                "'{}' matches '{bucket}/{key}' ({} check(s))",
+                local.display(),
+                checks.len()
+            );
         }
-        _ => Err(format!("unsupported command: {command}")),
+    }
+    if same {
+        Ok(())
+    } else {
+        Err(S4Error::Other(format!(
+            "'{}' differs from '{bucket}/{key}'",
+            local.display()
+        )))
     }
 }
 
@@ -777,7 +2617,7 @@ fn parse_ilm_args(args: &[String]) -> Result<IlmCommand, String> {
     Ok(IlmCommand { kind })
 }
 
-fn cmd_ilm(cmd: IlmCommand, json: bool) -> Result<(), String> {
+fn cmd_ilm(cmd: IlmCommand, json: bool) -> Result<(), S4Error> {
     let section = match cmd.kind {
         IlmKind::Rule => "rule",
         IlmKind::Tier => "tier",
@@ -807,7 +2647,7 @@ fn parse_idp_args(args: &[String]) -> Result<IdpCommand, String> {
     Ok(IdpCommand { kind })
 }
 
-fn cmd_idp(cmd: IdpCommand, json: bool) -> Result<(), String> {
+fn cmd_idp(cmd: IdpCommand, json: bool) -> Result<(), S4Error> {
     let provider = match cmd.kind {
         IdpKind::OpenId => "openid",
         IdpKind::Ldap => "ldap",
@@ -834,11 +2674,17 @@ fn parse_cors_args(args: &[String]) -> Result<CorsCommand, String> {
             }
             let target = parse_target(&args[2])?;
             let file = PathBuf::from(&args[3]);
-            Ok(CorsCommand::Set { target, file })
+            let validate_only = args[4..].iter().any(|a| a == "--validate-only");
+            Ok(CorsCommand::Set {
+                target,
+                file,
+                validate_only,
+            })
         }
         "get" => {
             let target = parse_target(&args[2])?;
-            Ok(CorsCommand::Get { target })
+            let raw = args[3..].iter().any(|a| a == "--raw");
+            Ok(CorsCommand::Get { target, raw })
         }
         "remove" => {
             let target = parse_target(&args[2])?;
@@ -849,25 +2695,35 @@ fn parse_cors_args(args: &[String]) -> Result<CorsCommand, String> {
     }
 }
 
-fn cmd_cors(config: &AppConfig, cmd: CorsCommand, json: bool, debug: bool) -> Result<(), String> {
+fn cmd_cors(config: &AppConfig, cmd: CorsCommand, json: bool, debug: bool) -> Result<(), S4Error> {
     match cmd {
-        CorsCommand::Set { target, file } => {
+        CorsCommand::Set {
+            target,
+            file,
+            validate_only,
+        } => {
             if !file.exists() {
-                return Err(format!("cors file not found: {}", file.display()));
+                return Err(format!("cors file not found: {}", file.display()).into());
+            }
+            validate_config_file(&file, "CORSConfiguration")?;
+            if validate_only {
+                print_config_valid(&file, "cors set", json);
+                return Ok(());
             }
             let alias = config
                 .aliases
                 .get(&target.alias)
-                .ok_or_else(|| format!("unknown alias: {}", target.alias))?;
-            let bucket = req_bucket(&target, "cors set")?;
+                .ok_or_else(|| unknown_alias(&target.alias))?;
+            let bucket = req_bucket(&target, "cors set").map_err(S4Error::Usage)?;
             s3_request(
                 alias,
-                "PUT",
-                &bucket,
-                None,
-                "cors",
-                Some(&file),
-                None,
+                S3Call {
+                    method: "PUT",
+                    bucket: &bucket,
+                    query: "cors",
+                    upload_file: Some(&file),
+                    ..S3Call::default()
+                },
                 debug,
             )?;
             if json {
@@ -875,19 +2731,34 @@ fn cmd_cors(config: &AppConfig, cmd: CorsCommand, json: bool, debug: bool) -> Re
                     "{{\"status\":\"ok\",\"command\":\"cors set\",\"bucket\":\"{}\"}}",
                     escape_json(&bucket)
                 );
-            } else {
+            } else if !is_quiet() {
                 println!("CORS set for bucket '{}'", bucket);
             }
             Ok(())
         }
-        CorsCommand::Get { target } => {
+        CorsCommand::Get { target, raw } => {
             let alias = config
                 .aliases
                 .get(&target.alias)
-                .ok_or_else(|| format!("unknown alias: {}", target.alias))?;
-            let bucket = req_bucket(&target, "cors get")?;
-            let body = s3_request(alias, "GET", &bucket, None, "cors", None, None, debug)?;
-            if json {
+                .ok_or_else(|| unknown_alias(&target.alias))?;
+            let bucket = req_bucket(&target, "cors get").map_err(S4Error::Usage)?;
+            let body = s3_request(
+                alias,
+                S3Call {
+                    method: "GET",
+                    bucket: &bucket,
+                    query: "cors",
+                    ..S3Call::default()
+                },
+                debug,
+            )?;
+            if json && !raw {
+                println!(
+                    "{{\"bucket\":\"{}\",\"rules\":{}}}",
+                    escape_json(&bucket),
+                    cors_rules_json(&body)
+                );
+            } else if json {
                 println!(
                     "{{\"bucket\":\"{}\",\"cors\":\"{}\"}}",
                     escape_json(&bucket),
@@ -902,15 +2773,24 @@ fn cmd_cors(config: &AppConfig, cmd: CorsCommand, json: bool, debug: bool) -> Re
             let alias = config
                 .aliases
                 .get(&target.alias)
-                .ok_or_else(|| format!("unknown alias: {}", target.alias))?;
-            let bucket = req_bucket(&target, "cors remove")?;
-            s3_request(alias, "DELETE", &bucket, None, "cors", None, None, debug)?;
+                .ok_or_else(|| unknown_alias(&target.alias))?;
+            let bucket = req_bucket(&target, "cors remove").map_err(S4Error::Usage)?;
+            s3_request(
+                alias,
+                S3Call {
+                    method: "DELETE",
+                    bucket: &bucket,
+                    query: "cors",
+                    ..S3Call::default()
+                },
+                debug,
+            )?;
             if json {
                 println!(
                     "{{\"status\":\"ok\",\"command\":\"cors remove\",\"bucket\":\"{}\"}}",
                     escape_json(&bucket)
                 );
-            } else {
+            } else if !is_quiet() {
                 println!("CORS removed for bucket '{}'", bucket);
             }
             Ok(())
@@ -918,6 +2798,160 @@ fn cmd_cors(config: &AppConfig, cmd: CorsCommand, json: bool, debug: bool) -> Re
     }
 }
 
+/// Cheap well-formedness check for the XML bodies of `cors set`/`encrypt set`/`event add`:
+/// balanced tags and a single `root` element, reported with a 1-based line number.
+fn check_xml_well_formed(xml: &str, root: &str) -> Result<(), String> {
+    let line_at = |pos: usize| xml[..pos].matches('\n').count() + 1;
+    let mut stack: Vec<(&str, usize)> = Vec::new();
+    let mut seen_root = false;
+    let mut pos = 0;
+    while pos < xml.len() {
+        let rest = &xml[pos..];
+        let lt = rest.find('<').unwrap_or(rest.len());
+        let text = &rest[..lt];
+        if stack.is_empty() && !text.trim().is_empty() {
+            let offset = text.len() - text.trim_start().len();
+            return Err(format!(
+                "line {}: text outside the <{root}> element",
+                line_at(pos + offset)
+            ));
+        }
+        let start = pos + lt;
+        if start >= xml.len() {
+            break;
+        }
+        let tag = &xml[start..];
+        let special = [
+            ("<?", "?>"),
+            ("<!--", "-->"),
+            ("<![CDATA[", "]]>"),
+            ("<!", ">"),
+        ]
+        .into_iter()
+        .find(|(open, _)| tag.starts_with(open));
+        if let Some((open, close)) = special {
+            let end = tag[open.len()..]
+                .find(close)
+                .ok_or_else(|| format!("line {}: unterminated {open}", line_at(start)))?;
+            pos = start + open.len() + end + close.len();
+            continue;
+        }
+        let end = tag
+            .find('>')
+            .ok_or_else(|| format!("line {}: unterminated tag", line_at(start)))?;
+        let inner = &tag[1..end];
+        pos = start + end + 1;
+        if let Some(name) = inner.strip_prefix('/') {
+            let name = name.trim();
+            match stack.pop() {
+                Some((open, _)) if open == name => {}
+                Some((open, open_line)) => {
+                    return Err(format!(
+                        "line {}: </{name}> does not match <{open}> opened on line {open_line}",
+                        line_at(start)
+                    ));
+                }
+                None => return Err(format!("line {}: unexpected </{name}>", line_at(start))),
+            }
+            continue;
+        }
+        let self_closing = inner.ends_with('/');
+        let name = inner
+            .trim_end_matches('/')
+            .split(char::is_whitespace)
+            .next()
+            .unwrap_or("");
+        if name.is_empty() || inner.contains('<') {
+            return Err(format!("line {}: malformed tag", line_at(start)));
+        }
+        if stack.is_empty() {
+            if seen_root {
+                return Err(format!(
+                    "line {}: unexpected second root element <{name}>",
+                    line_at(start)
+                ));
+            }
+            if name != root {
+                return Err(format!(
+                    "line {}: expected root element <{root}>, found <{name}>",
+                    line_at(start)
+                ));
+            }
+            seen_root = true;
+        }
+        if !self_closing {
+            stack.push((name, line_at(start)));
+        }
+    }
+    if let Some((name, line)) = stack.last() {
+        return Err(format!("line {line}: <{name}> is never closed"));
+    }
+    if !seen_root {
+        return Err(format!("missing <{root}> root element"));
+    }
+    Ok(())
+}
+
+fn validate_config_file(file: &Path, root: &str) -> Result<(), S4Error> {
+    let xml = fs::read_to_string(file)
+        .map_err(|e| S4Error::Io(format!("failed to read {}: {e}", file.display())))?;
+    check_xml_well_formed(&xml, root)
+        .map_err(|e| S4Error::Other(format!("{}: invalid XML: {e}", file.display())))
+}
+
+fn print_config_valid(file: &Path, command: &str, json: bool) {
+    if json {
+        println!(
+            "{{\"status\":\"valid\",\"command\":\"{}\",\"file\":\"{}\"}}",
+            command,
+            escape_json(&file.display().to_string())
+        );
+    } else if !is_quiet() {
+        println!("{}: valid", file.display());
+    }
+}
+
+/// Every `<tag>` value in `block` as a JSON string array.
+fn xml_values_json(block: &str, tag: &str) -> String {
+    let items: Vec<String> = extract_tag_values(block, tag)
+        .iter()
+        .map(|v| format!("\"{}\"", escape_json(&xml_unescape(v))))
+        .collect();
+    format!("[{}]", items.join(","))
+}
+
+/// The first `<tag>` value in `block` as a JSON string, or `null` when absent.
+fn xml_value_json(block: &str, tag: &str) -> String {
+    extract_tag_values(block, tag)
+        .first()
+        .map(|v| format!("\"{}\"", escape_json(&xml_unescape(v))))
+        .unwrap_or_else(|| "null".to_string())
+}
+
+/// `GET ?cors` rules for `cors get --json`.
+fn cors_rules_json(xml: &str) -> String {
+    let rules: Vec<String> = extract_tag_blocks(xml, "CORSRule")
+        .iter()
+        .map(|rule| {
+            let max_age = extract_tag_values(rule, "MaxAgeSeconds")
+                .first()
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .map(|v| v.to_string())
+                .unwrap_or_else(|| "null".to_string());
+            format!(
+                "{{\"id\":{},\"allowed_origins\":{},\"allowed_methods\":{},\"allowed_headers\":{},\"expose_headers\":{},\"max_age_seconds\":{}}}",
+                xml_value_json(rule, "ID"),
+                xml_values_json(rule, "AllowedOrigin"),
+                xml_values_json(rule, "AllowedMethod"),
+                xml_values_json(rule, "AllowedHeader"),
+                xml_values_json(rule, "ExposeHeader"),
+                max_age
+            )
+        })
+        .collect();
+    format!("[{}]", rules.join(","))
+}
+
 fn parse_encrypt_args(args: &[String]) -> Result<EncryptCommand, String> {
     if args.len() < 3 {
         return Err("usage: s4 encrypt <set|clear|info> ...".to_string());
@@ -931,7 +2965,12 @@ fn parse_encrypt_args(args: &[String]) -> Result<EncryptCommand, String> {
             }
             let target = parse_target(&args[2])?;
             let file = PathBuf::from(&args[3]);
-            Ok(EncryptCommand::Set { target, file })
+            let validate_only = args[4..].iter().any(|a| a == "--validate-only");
+            Ok(EncryptCommand::Set {
+                target,
+                file,
+                validate_only,
+            })
         }
         "clear" => {
             let target = parse_target(&args[2])?;
@@ -939,7 +2978,8 @@ fn parse_encrypt_args(args: &[String]) -> Result<EncryptCommand, String> {
         }
         "info" => {
             let target = parse_target(&args[2])?;
-            Ok(EncryptCommand::Info { target })
+            let raw = args[3..].iter().any(|a| a == "--raw");
+            Ok(EncryptCommand::Info { target, raw })
         }
         "help" | "h" => Err("usage: s4 encrypt <set|clear|info> ...".to_string()),
         other => Err(format!("unknown encrypt subcommand: {other}")),
@@ -951,25 +2991,35 @@ fn cmd_encrypt(
     cmd: EncryptCommand,
     json: bool,
     debug: bool,
-) -> Result<(), String> {
+) -> Result<(), S4Error> {
     match cmd {
-        EncryptCommand::Set { target, file } => {
+        EncryptCommand::Set {
+            target,
+            file,
+            validate_only,
+        } => {
             if !file.exists() {
-                return Err(format!("encryption file not found: {}", file.display()));
+                return Err(format!("encryption file not found: {}", file.display()).into());
+            }
+            validate_config_file(&file, "ServerSideEncryptionConfiguration")?;
+            if validate_only {
+                print_config_valid(&file, "encrypt set", json);
+                return Ok(());
             }
             let alias = config
                 .aliases
                 .get(&target.alias)
-                .ok_or_else(|| format!("unknown alias: {}", target.alias))?;
-            let bucket = req_bucket(&target, "encrypt set")?;
+                .ok_or_else(|| unknown_alias(&target.alias))?;
+            let bucket = req_bucket(&target, "encrypt set").map_err(S4Error::Usage)?;
             s3_request(
                 alias,
-                "PUT",
-                &bucket,
-                None,
-                "encryption",
-                Some(&file),
-                None,
+                S3Call {
+                    method: "PUT",
+                    bucket: &bucket,
+                    query: "encryption",
+                    upload_file: Some(&file),
+                    ..S3Call::default()
+                },
                 debug,
             )?;
             if json {
@@ -977,7 +3027,7 @@ fn cmd_encrypt(
                     "{{\"status\":\"ok\",\"command\":\"encrypt set\",\"bucket\":\"{}\"}}",
                     escape_json(&bucket)
                 );
-            } else {
+            } else if !is_quiet() {
                 println!("Encryption set for bucket '{}'", bucket);
             }
             Ok(())
@@ -986,16 +3036,16 @@ fn cmd_encrypt(
             let alias = config
                 .aliases
                 .get(&target.alias)
-                .ok_or_else(|| format!("unknown alias: {}", target.alias))?;
-            let bucket = req_bucket(&target, "encrypt clear")?;
+                .ok_or_else(|| unknown_alias(&target.alias))?;
+            let bucket = req_bucket(&target, "encrypt clear").map_err(S4Error::Usage)?;
             s3_request(
                 alias,
-                "DELETE",
-                &bucket,
-                None,
-                "encryption",
-                None,
-                None,
+                S3Call {
+                    method: "DELETE",
+                    bucket: &bucket,
+                    query: "encryption",
+                    ..S3Call::default()
+                },
                 debug,
             )?;
             if json {
@@ -1003,19 +3053,34 @@ fn cmd_encrypt(
                     "{{\"status\":\"ok\",\"command\":\"encrypt clear\",\"bucket\":\"{}\"}}",
                     escape_json(&bucket)
                 );
-            } else {
+            } else if !is_quiet() {
                 println!("Encryption cleared for bucket '{}'", bucket);
             }
             Ok(())
         }
-        EncryptCommand::Info { target } => {
+        EncryptCommand::Info { target, raw } => {
             let alias = config
                 .aliases
                 .get(&target.alias)
-                .ok_or_else(|| format!("unknown alias: {}", target.alias))?;
-            let bucket = req_bucket(&target, "encrypt info")?;
-            let body = s3_request(alias, "GET", &bucket, None, "encryption", None, None, debug)?;
-            if json {
+                .ok_or_else(|| unknown_alias(&target.alias))?;
+            let bucket = req_bucket(&target, "encrypt info").map_err(S4Error::Usage)?;
+            let body = s3_request(
+                alias,
+                S3Call {
+                    method: "GET",
+                    bucket: &bucket,
+                    query: "encryption",
+                    ..S3Call::default()
+                },
+                debug,
+            )?;
+            if json && !raw {
+                println!(
+                    "{{\"bucket\":\"{}\",\"rules\":{}}}",
+                    escape_json(&bucket),
+                    encryption_rules_json(&body)
+                );
+            } else if json {
                 println!(
                     "{{\"bucket\":\"{}\",\"encryption\":\"{}\"}}",
                     escape_json(&bucket),
@@ -1029,6 +3094,58 @@ fn cmd_encrypt(
     }
 }
 
+/// `GET ?encryption` rules for `encrypt info --json`.
+fn encryption_rules_json(xml: &str) -> String {
+    let rules: Vec<String> = extract_tag_blocks(xml, "Rule")
+        .iter()
+        .map(|rule| {
+            let bucket_key = extract_tag_values(rule, "BucketKeyEnabled")
+                .first()
+                .map(|v| (v.trim() == "true").to_string())
+                .unwrap_or_else(|| "null".to_string());
+            format!(
+                "{{\"sse_algorithm\":{},\"kms_master_key_id\":{},\"bucket_key_enabled\":{}}}",
+                xml_value_json(rule, "SSEAlgorithm"),
+                xml_value_json(rule, "KMSMasterKeyID"),
+                bucket_key
+            )
+        })
+        .collect();
+    format!("[{}]", rules.join(","))
+}
+
+/// `GET ?notification` targets for `event ls --json`, one entry per queue/topic/lambda config.
+fn notification_configs_json(xml: &str) -> String {
+    let mut configs = Vec::new();
+    for (kind, block_tag, arn_tag) in [
+        ("queue", "QueueConfiguration", "Queue"),
+        ("topic", "TopicConfiguration", "Topic"),
+        ("lambda", "CloudFunctionConfiguration", "CloudFunction"),
+    ] {
+        for block in extract_tag_blocks(xml, block_tag) {
+            let filters: Vec<String> = extract_tag_blocks(&block, "FilterRule")
+                .iter()
+                .map(|rule| {
+                    format!(
+                        "{{\"name\":{},\"value\":{}}}",
+                        xml_value_json(rule, "Name"),
+                        xml_value_json(rule, "Value")
+                    )
+                })
+                .collect();
+            configs.push(format!(
+                "{{\"type\":\"{}\",\"id\":{},\"arn\":{},\"events\":{},\"filters\":[{}]}}",
+                kind,
+                xml_value_json(&block, "Id"),
+                xml_value_json(&block, arn_tag),
+                xml_values_json(&block, "Event"),
+                filters.join(",")
+            ));
+        }
+    }
+    format!("[{}]", configs.join(","))
+}
+
 fn parse_event_args(args: &[String]) -> Result<EventCommand, String> {
     if args.len() < 3 {
         return Err("usage: s4 event <add|remove|rm|list|ls> ...".to_string());
@@ -1042,8 +3159,13 @@ fn parse_event_args(args: &[String]) -> Result<EventCommand, String> {
             }
             let target = parse_target(&args[2])?;
             let file = PathBuf::from(&args[3]);
-            Ok(EventCommand::Add { target, file })
-        }
+            let validate_only = args[4..].iter().any(|a| a == "--validate-only");
+            Ok(EventCommand::Add {
+                target,
+                file,
+                validate_only,
+            })
+        }
         "remove" | "rm" => {
             let target = parse_target(&args[2])?;
             let force = args.iter().any(|a| a == "--force");
@@ -1051,32 +3173,48 @@ fn parse_event_args(args: &[String]) -> Result<EventCommand, String> {
         }
         "list" | "ls" => {
             let target = parse_target(&args[2])?;
-            Ok(EventCommand::List { target })
+            let raw = args[3..].iter().any(|a| a == "--raw");
+            Ok(EventCommand::List { target, raw })
         }
         "help" | "h" => Err("usage: s4 event <add|remove|rm|list|ls> ...".to_string()),
         other => Err(format!("unknown event subcommand: {other}")),
     }
 }
 
-fn cmd_event(config: &AppConfig, cmd: EventCommand, json: bool, debug: bool) -> Result<(), String> {
+fn cmd_event(
+    config: &AppConfig,
+    cmd: EventCommand,
+    json: bool,
+    debug: bool,
+) -> Result<(), S4Error> {
     match cmd {
-        EventCommand::Add { target, file } => {
+        EventCommand::Add {
+            target,
+            file,
+            validate_only,
+        } => {
             if !file.exists() {
-                return Err(format!("notification file not found: {}", file.display()));
+                return Err(format!("notification file not found: {}", file.display()).into());
+            }
+            validate_config_file(&file, "NotificationConfiguration")?;
+            if validate_only {
+                print_config_valid(&file, "event add", json);
+                return Ok(());
             }
             let alias = config
                 .aliases
                 .get(&target.alias)
-                .ok_or_else(|| format!("unknown alias: {}", target.alias))?;
-            let bucket = req_bucket(&target, "event add")?;
+                .ok_or_else(|| unknown_alias(&target.alias))?;
+            let bucket = req_bucket(&target, "event add").map_err(S4Error::Usage)?;
             s3_request(
                 alias,
-                "PUT",
-                &bucket,
-                None,
-                "notification",
-                Some(&file),
-                None,
+                S3Call {
+                    method: "PUT",
+                    bucket: &bucket,
+                    query: "notification",
+                    upload_file: Some(&file),
+                    ..S3Call::default()
+                },
                 debug,
             )?;
             if json {
@@ -1084,7 +3222,7 @@ fn cmd_event(config: &AppConfig, cmd: EventCommand, json: bool, debug: bool) ->
                     "{{\"status\":\"ok\",\"command\":\"event add\",\"bucket\":\"{}\"}}",
                     escape_json(&bucket)
                 );
-            } else {
+            } else if !is_quiet() {
                 println!("Notification config set for bucket '{}'", bucket);
             }
             Ok(())
@@ -1093,16 +3231,16 @@ fn cmd_event(config: &AppConfig, cmd: EventCommand, json: bool, debug: bool) ->
             let alias = config
                 .aliases
                 .get(&target.alias)
-                .ok_or_else(|| format!("unknown alias: {}", target.alias))?;
-            let bucket = req_bucket(&target, "event remove")?;
+                .ok_or_else(|| unknown_alias(&target.alias))?;
+            let bucket = req_bucket(&target, "event remove").map_err(S4Error::Usage)?;
             s3_request(
                 alias,
-                "PUT",
-                &bucket,
-                None,
-                "notification",
-                None,
-                None,
+                S3Call {
+                    method: "PUT",
+                    bucket: &bucket,
+                    query: "notification",
+                    ..S3Call::default()
+                },
                 debug,
             )?;
             if json {
@@ -1119,23 +3257,29 @@ fn cmd_event(config: &AppConfig, cmd: EventCommand, json: bool, debug: bool) ->
             }
             Ok(())
         }
-        EventCommand::List { target } => {
+        EventCommand::List { target, raw } => {
             let alias = config
                 .aliases
                 .get(&target.alias)
-                .ok_or_else(|| format!("unknown alias: {}", target.alias))?;
-            let bucket = req_bucket(&target, "event list")?;
+                .ok_or_else(|| unknown_alias(&target.alias))?;
+            let bucket = req_bucket(&target, "event list").map_err(S4Error::Usage)?;
             let body = s3_request(
                 alias,
-                "GET",
-                &bucket,
-                None,
-                "notification",
-                None,
-                None,
+                S3Call {
+                    method: "GET",
+                    bucket: &bucket,
+                    query: "notification",
+                    ..S3Call::default()
+                },
                 debug,
             )?;
-            if json {
+            if json && !raw {
+                println!(
+                    "{{\"bucket\":\"{}\",\"configurations\":{}}}",
+                    escape_json(&bucket),
+                    notification_configs_json(&body)
+                );
+            } else if json {
                 println!(
                     "{{\"bucket\":\"{}\",\"notification\":\"{}\"}}",
                     escape_json(&bucket),
@@ -1149,6 +3293,225 @@ fn cmd_event(config: &AppConfig, cmd: EventCommand, json: bool, debug: bool) ->
     }
 }
 
+const MULTIPART_USAGE: &str = "usage: s4 multipart recover [--dry-run] <alias/bucket>";
+
+/// `multipart recover [--dry-run] alias/bucket`, the only subcommand so far.
+fn parse_multipart_args(args: &[String]) -> Result<(bool, S3Target), String> {
+    if args.get(1).map(String::as_str) != Some("recover") {
+        return match args.get(1).map(String::as_str) {
+            None | Some("help" | "h" | "--help" | "-h") => Err(MULTIPART_USAGE.to_string()),
+            Some(other) => Err(format!("unknown multipart subcommand: {other}")),
+        };
+    }
+    let mut dry_run = false;
+    let mut target = None;
+    for arg in &args[2..] {
+        match arg.as_str() {
+            "--dry-run" => dry_run = true,
+            f if f.starts_with('-') => return Err(format!("unknown multipart flag: {f}")),
+            _ if target.is_none() => target = Some(parse_target(arg)?),
+            _ => return Err(MULTIPART_USAGE.to_string()),
+        }
+    }
+    let target = target.ok_or_else(|| MULTIPART_USAGE.to_string())?;
+    if target.key.is_some() {
+        return Err(MULTIPART_USAGE.to_string());
+    }
+    Ok((dry_run, target))
+}
+
+const RESTORE_USAGE: &str =
+    "usage: s4 restore wait [--timeout DUR] [--interval DUR] <alias/bucket/key>";
+
+#[derive(Debug, PartialEq)]
+struct RestoreWait {
+    /// Seconds before giving up (default 1h).
+    timeout: u64,
+    /// Seconds between HEAD polls (default 30s).
+    interval: u64,
+}
+
+fn parse_restore_args(args: &[String]) -> Result<(RestoreWait, S3Target), String> {
+    match args.get(1).map(String::as_str) {
+        Some("wait") => {}
+        None | Some("help" | "h" | "--help" | "-h") => return Err(RESTORE_USAGE.to_string()),
+        Some(other) => return Err(format!("unknown restore subcommand: {other}")),
+    }
+    let mut wait = RestoreWait {
+        timeout: 3600,
+        interval: 30,
+    };
+    let mut target = None;
+    let mut i = 2;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--timeout" | "--interval" => {
+                let value = args
+                    .get(i + 1)
+                    .ok_or_else(|| format!("{} expects a duration", args[i]))?;
+                let secs = parse_human_duration(value)
+                    .map_err(|e| format!("invalid {} value '{value}': {e}", args[i]))?;
+                if args[i] == "--timeout" {
+                    wait.timeout = secs;
+                } else {
+                    wait.interval = secs.max(1);
+                }
+                i += 2;
+            }
+            f if f.starts_with('-') => return Err(format!("unknown restore flag: {f}")),
+            _ if target.is_none() => {
+                target = Some(parse_target(&args[i])?);
+                i += 1;
+            }
+            _ => return Err(RESTORE_USAGE.to_string()),
+        }
+    }
+    let target = target.ok_or_else(|| RESTORE_USAGE.to_string())?;
+    Ok((wait, target))
+}
+
+/// What the `x-amz-restore` HEAD header says about an archived object.
+#[derive(Debug, PartialEq)]
+enum RestoreState {
+    /// No header: nothing was ever requested (or the object is not archived).
+    None,
+    Ongoing,
+    /// The temporary copy is readable; `expiry` is when it is removed again.
+    Done {
+        expiry: Option<String>,
+    },
+}
+
+/// Parses `ongoing-request="false", expiry-date="Fri, 21 Dec 2012 00:00:00 GMT"`.
+fn parse_restore_header(head: &str) -> RestoreState {
+    let Some(value) = head_header(head, "x-amz-restore") else {
+        return RestoreState::None;
+    };
+    if quoted_field(&value, "ongoing-request").as_deref() == Some("true") {
+        RestoreState::Ongoing
+    } else {
+        RestoreState::Done {
+            expiry: quoted_field(&value, "expiry-date"),
+        }
+    }
+}
+
+/// `name="value"` out of a header such as `x-amz-restore` or `x-amz-expiration`.
+fn quoted_field(value: &str, name: &str) -> Option<String> {
+    let start = value.find(&format!("{name}=\""))? + name.len() + 2;
+    let len = value[start..].find('"')?;
+    Some(value[start..start + len].to_string())
+}
+
+/// When a lifecycle rule will expire the object, from `x-amz-expiration`.
+#[derive(Debug, PartialEq)]
+struct ObjectExpiration {
+    expiry_date: String,
+    rule_id: String,
+}
+
+/// `x-amz-expiration: expiry-date="Fri, 23 Dec 2012 00:00:00 GMT", rule-id="..."`;
+/// S3 URL-encodes the rule id.
+fn parse_expiration_header(head: &str) -> Option<ObjectExpiration> {
+    let value = head_header(head, "x-amz-expiration")?;
+    Some(ObjectExpiration {
+        expiry_date: quoted_field(&value, "expiry-date")?,
+        rule_id: quoted_field(&value, "rule-id")
+            .map(|id| percent_decode(&id))
+            .unwrap_or_default(),
+    })
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = s.get(i + 1..i + 3).filter(|_| bytes[i] == b'%');
+        match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(byte) => {
+                out.push(byte);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn is_archive_storage_class(head: &str) -> bool {
+    head_header(head, "x-amz-storage-class")
+        .is_some_and(|class| matches!(class.as_str(), "GLACIER" | "DEEP_ARCHIVE"))
+}
+
+/// `restore wait`: polls HEAD until `x-amz-restore` reports the restore finished, so a
+/// script can `get` right after. Objects that are not archived are readable already.
+fn cmd_restore_wait(
+    alias: &AliasConfig,
+    bucket: &str,
+    key: &str,
+    wait: &RestoreWait,
+    json: bool,
+    debug: bool,
+) -> Result<(), S4Error> {
+    let started = Instant::now();
+    let expiry = loop {
+        let head = s3_request(
+            alias,
+            S3Call {
+                method: "HEAD",
+                bucket,
+                key: Some(key),
+                ..S3Call::default()
+            },
+            debug,
+        )?;
+        match parse_restore_header(&head) {
+            RestoreState::Done { expiry } => break expiry,
+            RestoreState::None if !is_archive_storage_class(&head) => break None,
+            RestoreState::None => {
+                return Err(S4Error::Other(format!(
+                    "no restore requested for archived object {bucket}/{key}"
+                )));
+            }
+            RestoreState::Ongoing => {}
+        }
+        let elapsed = started.elapsed().as_secs();
+        if elapsed >= wait.timeout {
+            return Err(S4Error::Other(format!(
+                "timed out after {elapsed}s waiting for the restore of {bucket}/{key}"
+            )));
+        }
+        if debug {
+            eprintln!("[debug] restore of {bucket}/{key} still in progress");
+        }
+        sleep(Duration::from_secs(
+            wait.interval.min(wait.timeout - elapsed),
+        ));
+    };
+
+    if json {
+        let expiry = expiry
+            .map(|e| format!("\"{}\"", escape_json(&e)))
+            .unwrap_or_else(|| "null".to_string());
+        println!(
+            "{{\"status\":\"restored\",\"bucket\":\"{}\",\"key\":\"{}\",\"expiry_date\":{}}}",
+            escape_json(bucket),
+            escape_json(key),
+            expiry
+        );
+    } else if !is_quiet() {
+        match expiry {
+            Some(expiry) => println!("Restore of '{bucket}/{key}' complete (expires {expiry})"),
+            None => println!("'{bucket}/{key}' is readable"),
+        }
+    }
+    Ok(())
+}
+
 fn parse_legalhold_args(args: &[String]) -> Result<LegalHoldCommand, String> {
     if args.len() < 3 {
         return Err("usage: s4 legalhold <set|clear|info> <alias/bucket/key>".to_string());
@@ -1168,26 +3531,76 @@ fn parse_legalhold_args(args: &[String]) -> Result<LegalHoldCommand, String> {
     }
 }
 
-fn content_md5_header(file_path: &Path) -> Result<String, String> {
+/// Base64 of the big-endian CRC32 or raw SHA256 digest, as S3 expects in `x-amz-checksum-*`.
+fn checksum_value(file_path: &Path, algorithm: ChecksumAlgorithm) -> Result<String, String> {
     let script = r#"
-import base64, hashlib, pathlib, sys
-p = pathlib.Path(sys.argv[1])
-data = p.read_bytes()
-print(base64.b64encode(hashlib.md5(data).digest()).decode())
+import hashlib, pathlib, sys, zlib
+data = pathlib.Path(sys.argv[1]).read_bytes()
+if sys.argv[2] == 'CRC32':
+    digest = zlib.crc32(data).to_bytes(4, 'big')
+else:
+    digest = hashlib.sha256(data).digest()
+print(digest.hex())
 "#;
-    let out = Command::new("python3")
-        .arg("-c")
-        .arg(script)
-        .arg(file_path)
-        .output()
-        .map_err(|e| e.to_string())?;
+    let out = run_tool(
+        Command::new("python3")
+            .arg("-c")
+            .arg(script)
+            .arg(file_path)
+            .arg(algorithm.name()),
+    )?;
     if !out.status.success() {
         return Err(format!(
-            "failed to compute content-md5: {}",
+            "failed to compute {} checksum: {}",
+            algorithm.name(),
             String::from_utf8_lossy(&out.stderr).trim()
         ));
     }
-    Ok(String::from_utf8_lossy(&out.stdout).trim().to_string())
+    hex_digest_base64(String::from_utf8_lossy(&out.stdout).trim())
+        .map_err(|e| format!("failed to compute {} checksum: {e}", algorithm.name()))
+}
+
+fn checksum_headers(algorithm: ChecksumAlgorithm, value: &str) -> [String; 2] {
+    [
+        format!("{}: {}", algorithm.header(), value),
+        format!("x-amz-sdk-checksum-algorithm: {}", algorithm.name()),
+    ]
+}
+
+/// `Content-MD5` is the base64 form of the digest `file_md5_hex` computes.
+fn content_md5_header(file_path: &Path) -> Result<String, String> {
+    let hex = file_md5_hex(file_path).map_err(|e| e.to_string())?;
+    hex_digest_base64(&hex).map_err(|e| format!("failed to compute content-md5: {e}"))
+}
+
+/// Digests come out of the hashing helpers as hex; S3 headers want the raw bytes in base64.
+fn hex_digest_base64(hex: &str) -> Result<String, String> {
+    let digest = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2).unwrap_or_default(), 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| format!("unexpected digest '{hex}'"))?;
+    Ok(base64_encode(&digest))
+}
+
+/// Standard base64 (RFC 4648, padded), shared by `Content-MD5` and `x-amz-checksum-*`.
+fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
 }
 
 fn cmd_legalhold(
@@ -1195,32 +3608,34 @@ fn cmd_legalhold(
     cmd: LegalHoldCommand,
     json: bool,
     debug: bool,
-) -> Result<(), String> {
+) -> Result<(), S4Error> {
     match cmd {
         LegalHoldCommand::Set { target } => {
             let alias = config
                 .aliases
                 .get(&target.alias)
-                .ok_or_else(|| format!("unknown alias: {}", target.alias))?;
-            let bucket = req_bucket(&target, "legalhold set")?;
-            let key = req_key(&target, "legalhold set")?;
+                .ok_or_else(|| unknown_alias(&target.alias))?;
+            let bucket = req_bucket(&target, "legalhold set").map_err(S4Error::Usage)?;
+            let key = req_key(&target, "legalhold set").map_err(S4Error::Usage)?;
             let body = "<LegalHold><Status>ON</Status></LegalHold>";
-            let temp = env::temp_dir().join(format!("s4-legalhold-{}-on.xml", std::process::id()));
+            let temp = temp_path(format!("s4-legalhold-{}-on.xml", std::process::id()));
             fs::write(&temp, body).map_err(|e| e.to_string())?;
             let md5 = content_md5_header(&temp)?;
             let headers = vec![format!("Content-MD5: {}", md5)];
-            let res = s3_request_with_headers(
+            let res = s3_request(
                 alias,
-                "PUT",
-                &bucket,
-                Some(&key),
-                "legal-hold",
-                Some(&temp),
-                None,
-                &headers,
+                S3Call {
+                    method: "PUT",
+                    bucket: &bucket,
+                    key: Some(&key),
+                    query: "legal-hold",
+                    upload_file: Some(&temp),
+                    headers: &headers,
+                    ..S3Call::default()
+                },
                 debug,
             );
-            let _ = fs::remove_file(&temp);
+            remove_temp(&temp);
             res?;
             if json {
                 println!(
@@ -1228,7 +3643,7 @@ fn cmd_legalhold(
                     escape_json(&bucket),
                     escape_json(&key)
                 );
-            } else {
+            } else if !is_quiet() {
                 println!("Legal hold set for '{}/{}'", bucket, key);
             }
             Ok(())
@@ -1237,26 +3652,28 @@ fn cmd_legalhold(
             let alias = config
                 .aliases
                 .get(&target.alias)
-                .ok_or_else(|| format!("unknown alias: {}", target.alias))?;
-            let bucket = req_bucket(&target, "legalhold clear")?;
-            let key = req_key(&target, "legalhold clear")?;
+                .ok_or_else(|| unknown_alias(&target.alias))?;
+            let bucket = req_bucket(&target, "legalhold clear").map_err(S4Error::Usage)?;
+            let key = req_key(&target, "legalhold clear").map_err(S4Error::Usage)?;
             let body = "<LegalHold><Status>OFF</Status></LegalHold>";
-            let temp = env::temp_dir().join(format!("s4-legalhold-{}-off.xml", std::process::id()));
+            let temp = temp_path(format!("s4-legalhold-{}-off.xml", std::process::id()));
             fs::write(&temp, body).map_err(|e| e.to_string())?;
             let md5 = content_md5_header(&temp)?;
             let headers = vec![format!("Content-MD5: {}", md5)];
-            let res = s3_request_with_headers(
+            let res = s3_request(
                 alias,
-                "PUT",
-                &bucket,
-                Some(&key),
-                "legal-hold",
-                Some(&temp),
-                None,
-                &headers,
+                S3Call {
+                    method: "PUT",
+                    bucket: &bucket,
+                    key: Some(&key),
+                    query: "legal-hold",
+                    upload_file: Some(&temp),
+                    headers: &headers,
+                    ..S3Call::default()
+                },
                 debug,
             );
-            let _ = fs::remove_file(&temp);
+            remove_temp(&temp);
             res?;
             if json {
                 println!(
@@ -1264,7 +3681,7 @@ fn cmd_legalhold(
                     escape_json(&bucket),
                     escape_json(&key)
                 );
-            } else {
+            } else if !is_quiet() {
                 println!("Legal hold cleared for '{}/{}'", bucket, key);
             }
             Ok(())
@@ -1273,17 +3690,18 @@ fn cmd_legalhold(
             let alias = config
                 .aliases
                 .get(&target.alias)
-                .ok_or_else(|| format!("unknown alias: {}", target.alias))?;
-            let bucket = req_bucket(&target, "legalhold info")?;
-            let key = req_key(&target, "legalhold info")?;
+                .ok_or_else(|| unknown_alias(&target.alias))?;
+            let bucket = req_bucket(&target, "legalhold info").map_err(S4Error::Usage)?;
+            let key = req_key(&target, "legalhold info").map_err(S4Error::Usage)?;
             let body = s3_request(
                 alias,
-                "GET",
-                &bucket,
-                Some(&key),
-                "legal-hold",
-                None,
-                None,
+                S3Call {
+                    method: "GET",
+                    bucket: &bucket,
+                    key: Some(&key),
+                    query: "legal-hold",
+                    ..S3Call::default()
+                },
                 debug,
             )?;
             if json {
@@ -1303,7 +3721,10 @@ fn cmd_legalhold(
 
 fn parse_retention_args(args: &[String]) -> Result<RetentionCommand, String> {
     if args.len() < 3 {
-        return Err("usage: s4 retention <set|clear|info> ...".to_string());
+        return Err(
+            "usage: s4 retention <set|clear|info|set-default|get-default|clear-default> ..."
+                .to_string(),
+        );
     }
     match args[1].as_str() {
         "set" => {
@@ -1346,7 +3767,69 @@ fn parse_retention_args(args: &[String]) -> Result<RetentionCommand, String> {
         "info" => Ok(RetentionCommand::Info {
             target: parse_target(&args[2])?,
         }),
-        "help" | "h" => Err("usage: s4 retention <set|clear|info> ...".to_string()),
+        "set-default" => {
+            let usage = "usage: s4 retention set-default <alias/bucket> --mode <GOVERNANCE|COMPLIANCE> <--days N|--years N>";
+            let target = parse_target(&args[2])?;
+            let mut mode: Option<String> = None;
+            let mut period: Option<RetentionPeriod> = None;
+            let mut i = 3;
+            while i < args.len() {
+                match args[i].as_str() {
+                    "--mode" => {
+                        let v = args.get(i + 1).ok_or("--mode expects a value")?;
+                        mode = Some(v.to_ascii_uppercase());
+                        i += 2;
+                    }
+                    flag @ ("--days" | "--years") => {
+                        let v = args
+                            .get(i + 1)
+                            .ok_or_else(|| format!("{flag} expects a value"))?;
+                        let n = v
+                            .parse::<u32>()
+                            .ok()
+                            .filter(|n| *n > 0)
+                            .ok_or_else(|| format!("invalid {flag} value: {v}"))?;
+                        if period.is_some() {
+                            return Err("use either --days or --years, not both".to_string());
+                        }
+                        period = Some(if flag == "--days" {
+                            RetentionPeriod::Days(n)
+                        } else {
+                            RetentionPeriod::Years(n)
+                        });
+                        i += 2;
+                    }
+                    f if f.starts_with('-') => {
+                        return Err(format!("unknown retention set-default flag: {f}"));
+                    }
+                    other => {
+                        return Err(format!(
+                            "unexpected retention set-default argument: {other}"
+                        ));
+                    }
+                }
+            }
+            let mode = mode.ok_or(usage)?;
+            if mode != "GOVERNANCE" && mode != "COMPLIANCE" {
+                return Err(format!("invalid --mode value: {mode}"));
+            }
+            let period = period.ok_or(usage)?;
+            Ok(RetentionCommand::SetDefault {
+                target,
+                mode,
+                period,
+            })
+        }
+        "get-default" => Ok(RetentionCommand::GetDefault {
+            target: parse_target(&args[2])?,
+        }),
+        "clear-default" => Ok(RetentionCommand::ClearDefault {
+            target: parse_target(&args[2])?,
+        }),
+        "help" | "h" => Err(
+            "usage: s4 retention <set|clear|info|set-default|get-default|clear-default> ..."
+                .to_string(),
+        ),
         other => Err(format!("unknown retention subcommand: {other}")),
     }
 }
@@ -1356,7 +3839,7 @@ fn cmd_retention(
     cmd: RetentionCommand,
     json: bool,
     debug: bool,
-) -> Result<(), String> {
+) -> Result<(), S4Error> {
     match cmd {
         RetentionCommand::Set {
             target,
@@ -1366,29 +3849,31 @@ fn cmd_retention(
             let alias = config
                 .aliases
                 .get(&target.alias)
-                .ok_or_else(|| format!("unknown alias: {}", target.alias))?;
-            let bucket = req_bucket(&target, "retention set")?;
-            let key = req_key(&target, "retention set")?;
+                .ok_or_else(|| unknown_alias(&target.alias))?;
+            let bucket = req_bucket(&target, "retention set").map_err(S4Error::Usage)?;
+            let key = req_key(&target, "retention set").map_err(S4Error::Usage)?;
             let body = format!(
                 "<Retention><Mode>{}</Mode><RetainUntilDate>{}</RetainUntilDate></Retention>",
                 mode, retain_until
             );
-            let temp = env::temp_dir().join(format!("s4-retention-{}-set.xml", std::process::id()));
+            let temp = temp_path(format!("s4-retention-{}-set.xml", std::process::id()));
             fs::write(&temp, body).map_err(|e| e.to_string())?;
             let md5 = content_md5_header(&temp)?;
             let headers = vec![format!("Content-MD5: {}", md5)];
-            let res = s3_request_with_headers(
+            let res = s3_request(
                 alias,
-                "PUT",
-                &bucket,
-                Some(&key),
-                "retention",
-                Some(&temp),
-                None,
-                &headers,
+                S3Call {
+                    method: "PUT",
+                    bucket: &bucket,
+                    key: Some(&key),
+                    query: "retention",
+                    upload_file: Some(&temp),
+                    headers: &headers,
+                    ..S3Call::default()
+                },
                 debug,
             );
-            let _ = fs::remove_file(&temp);
+            remove_temp(&temp);
             res?;
             if json {
                 println!(
@@ -1398,7 +3883,7 @@ fn cmd_retention(
                     escape_json(&mode),
                     escape_json(&retain_until)
                 );
-            } else {
+            } else if !is_quiet() {
                 println!(
                     "Retention set for '{}/{}' mode={} retain-until={}",
                     bucket, key, mode, retain_until
@@ -1410,47 +3895,49 @@ fn cmd_retention(
             let alias = config
                 .aliases
                 .get(&target.alias)
-                .ok_or_else(|| format!("unknown alias: {}", target.alias))?;
-            let bucket = req_bucket(&target, "retention clear")?;
-            let key = req_key(&target, "retention clear")?;
+                .ok_or_else(|| unknown_alias(&target.alias))?;
+            let bucket = req_bucket(&target, "retention clear").map_err(S4Error::Usage)?;
+            let key = req_key(&target, "retention clear").map_err(S4Error::Usage)?;
 
             // S3/MinIO clear path is PUT ObjectRetention update, not DELETE.
-            let now_out = Command::new("python3")
+            let mut now_cmd = Command::new("python3");
+            now_cmd
                 .arg("-c")
-                .arg("import datetime; print((datetime.datetime.utcnow()+datetime.timedelta(minutes=1)).strftime('%Y-%m-%dT%H:%M:%SZ'))")
-                .output()
-                .map_err(|e| e.to_string())?;
+                .arg("import datetime; print((datetime.datetime.utcnow()+datetime.timedelta(minutes=1)).strftime('%Y-%m-%dT%H:%M:%SZ'))");
+            let now_out = run_tool(&mut now_cmd)?;
             if !now_out.status.success() {
                 return Err(format!(
                     "failed to produce retention clear timestamp: {}",
                     String::from_utf8_lossy(&now_out.stderr).trim()
-                ));
+                )
+                .into());
             }
             let retain_until = String::from_utf8_lossy(&now_out.stdout).trim().to_string();
             let body = format!(
                 "<Retention><Mode>GOVERNANCE</Mode><RetainUntilDate>{}</RetainUntilDate></Retention>",
                 retain_until
             );
-            let temp =
-                env::temp_dir().join(format!("s4-retention-{}-clear.xml", std::process::id()));
+            let temp = temp_path(format!("s4-retention-{}-clear.xml", std::process::id()));
             fs::write(&temp, body).map_err(|e| e.to_string())?;
             let md5 = content_md5_header(&temp)?;
             let headers = vec![
                 format!("Content-MD5: {}", md5),
                 "x-amz-bypass-governance-retention: true".to_string(),
             ];
-            let res = s3_request_with_headers(
+            let res = s3_request(
                 alias,
-                "PUT",
-                &bucket,
-                Some(&key),
-                "retention",
-                Some(&temp),
-                None,
-                &headers,
+                S3Call {
+                    method: "PUT",
+                    bucket: &bucket,
+                    key: Some(&key),
+                    query: "retention",
+                    upload_file: Some(&temp),
+                    headers: &headers,
+                    ..S3Call::default()
+                },
                 debug,
             );
-            let _ = fs::remove_file(&temp);
+            remove_temp(&temp);
             res?;
             if json {
                 println!(
@@ -1458,7 +3945,7 @@ fn cmd_retention(
                     escape_json(&bucket),
                     escape_json(&key)
                 );
-            } else {
+            } else if !is_quiet() {
                 println!("Retention cleared for '{}/{}'", bucket, key);
             }
             Ok(())
@@ -1467,17 +3954,18 @@ fn cmd_retention(
             let alias = config
                 .aliases
                 .get(&target.alias)
-                .ok_or_else(|| format!("unknown alias: {}", target.alias))?;
-            let bucket = req_bucket(&target, "retention info")?;
-            let key = req_key(&target, "retention info")?;
+                .ok_or_else(|| unknown_alias(&target.alias))?;
+            let bucket = req_bucket(&target, "retention info").map_err(S4Error::Usage)?;
+            let key = req_key(&target, "retention info").map_err(S4Error::Usage)?;
             let body = s3_request(
                 alias,
-                "GET",
-                &bucket,
-                Some(&key),
-                "retention",
-                None,
-                None,
+                S3Call {
+                    method: "GET",
+                    bucket: &bucket,
+                    key: Some(&key),
+                    query: "retention",
+                    ..S3Call::default()
+                },
                 debug,
             )?;
             if json {
@@ -1492,9 +3980,154 @@ fn cmd_retention(
             }
             Ok(())
         }
+        RetentionCommand::SetDefault {
+            target,
+            mode,
+            period,
+        } => {
+            let alias = config
+                .aliases
+                .get(&target.alias)
+                .ok_or_else(|| unknown_alias(&target.alias))?;
+            let bucket = req_bucket(&target, "retention set-default").map_err(S4Error::Usage)?;
+            put_object_lock_config(
+                alias,
+                &bucket,
+                &build_object_lock_config_xml(Some((&mode, period))),
+                debug,
+            )?;
+            let (unit, n) = match period {
+                RetentionPeriod::Days(n) => ("days", n),
+                RetentionPeriod::Years(n) => ("years", n),
+            };
+            if json {
+                println!(
+                    "{{\"status\":\"ok\",\"command\":\"retention set-default\",\"bucket\":\"{}\",\"mode\":\"{}\",\"{unit}\":{n}}}",
+                    escape_json(&bucket),
+                    escape_json(&mode)
+                );
+            } else if !is_quiet() {
+                println!(
+                    "Default retention set for '{}' mode={} {unit}={n}",
+                    bucket, mode
+                );
+            }
+            Ok(())
+        }
+        RetentionCommand::ClearDefault { target } => {
+            let alias = config
+                .aliases
+                .get(&target.alias)
+                .ok_or_else(|| unknown_alias(&target.alias))?;
+            let bucket = req_bucket(&target, "retention clear-default").map_err(S4Error::Usage)?;
+            // Object lock cannot be disabled once enabled; dropping the Rule removes the default.
+            put_object_lock_config(alias, &bucket, &build_object_lock_config_xml(None), debug)?;
+            if json {
+                println!(
+                    "{{\"status\":\"ok\",\"command\":\"retention clear-default\",\"bucket\":\"{}\"}}",
+                    escape_json(&bucket)
+                );
+            } else if !is_quiet() {
+                println!("Default retention cleared for '{}'", bucket);
+            }
+            Ok(())
+        }
+        RetentionCommand::GetDefault { target } => {
+            let alias = config
+                .aliases
+                .get(&target.alias)
+                .ok_or_else(|| unknown_alias(&target.alias))?;
+            let bucket = req_bucket(&target, "retention get-default").map_err(S4Error::Usage)?;
+            let body = s3_request(
+                alias,
+                S3Call {
+                    method: "GET",
+                    bucket: &bucket,
+                    query: "object-lock",
+                    ..S3Call::default()
+                },
+                debug,
+            )?;
+            let mode = extract_tag_values(&body, "Mode").into_iter().next();
+            let period = extract_tag_values(&body, "Days")
+                .first()
+                .map(|d| ("days", d.clone()))
+                .or_else(|| {
+                    extract_tag_values(&body, "Years")
+                        .first()
+                        .map(|y| ("years", y.clone()))
+                });
+            if json {
+                let mut fields = format!(
+                    "\"bucket\":\"{}\",\"mode\":{}",
+                    escape_json(&bucket),
+                    mode.as_ref()
+                        .map(|m| format!("\"{}\"", escape_json(m)))
+                        .unwrap_or_else(|| "null".to_string())
+                );
+                if let Some((unit, n)) = &period {
+                    fields.push_str(&format!(",\"{unit}\":{}", n.parse::<u64>().unwrap_or(0)));
+                }
+                println!("{{{fields}}}");
+            } else {
+                match (mode, period) {
+                    (Some(mode), Some((unit, n))) => {
+                        println!("{bucket}\tmode={mode}\t{unit}={n}")
+                    }
+                    _ => println!("{bucket}\tno default retention"),
+                }
+            }
+            Ok(())
+        }
     }
 }
 
+/// `PUT ?object-lock` body; `None` keeps object lock enabled without a default rule.
+fn build_object_lock_config_xml(rule: Option<(&str, RetentionPeriod)>) -> String {
+    let rule = match rule {
+        Some((mode, period)) => {
+            let period = match period {
+                RetentionPeriod::Days(n) => format!("<Days>{n}</Days>"),
+                RetentionPeriod::Years(n) => format!("<Years>{n}</Years>"),
+            };
+            format!(
+                "<Rule><DefaultRetention><Mode>{}</Mode>{period}</DefaultRetention></Rule>",
+                xml_escape(mode)
+            )
+        }
+        None => String::new(),
+    };
+    format!(
+        "<ObjectLockConfiguration><ObjectLockEnabled>Enabled</ObjectLockEnabled>{rule}</ObjectLockConfiguration>"
+    )
+}
+
+fn put_object_lock_config(
+    alias: &AliasConfig,
+    bucket: &str,
+    body: &str,
+    debug: bool,
+) -> Result<(), S4Error> {
+    let temp = temp_path(format!("s4-object-lock-{}.xml", std::process::id()));
+    fs::write(&temp, body).map_err(|e| e.to_string())?;
+    let md5 = content_md5_header(&temp)?;
+    let headers = vec![format!("Content-MD5: {}", md5)];
+    let res = s3_request(
+        alias,
+        S3Call {
+            method: "PUT",
+            bucket,
+            query: "object-lock",
+            upload_file: Some(&temp),
+            headers: &headers,
+            ..S3Call::default()
+        },
+        debug,
+    );
+    remove_temp(&temp);
+    res.map(|_| ())
+}
+
 fn parse_replicate_args(args: &[String]) -> Result<ReplicateCommand, String> {
     if args.len() < 2 {
         return Err("usage: s4 replicate <add|update|list|ls|status|resync|export|import|remove|rm|backlog> [target]".to_string());
@@ -1516,7 +4149,7 @@ fn parse_replicate_args(args: &[String]) -> Result<ReplicateCommand, String> {
     Ok(ReplicateCommand { subcommand, target })
 }
 
-fn cmd_replicate(cmd: ReplicateCommand, json: bool) -> Result<(), String> {
+fn cmd_replicate(cmd: ReplicateCommand, json: bool) -> Result<(), S4Error> {
     let sub = match cmd.subcommand {
         ReplicateSubcommand::Add => "add",
         ReplicateSubcommand::Update => "update",
@@ -1558,6 +4191,7 @@ fn parse_sql_args(args: &[String]) -> Result<(SqlOptions, Vec<S3Target>), String
         csv_output_header: None,
         json_output: None,
         enc_c: Vec::new(),
+        concurrency: 1,
     };
 
     let mut targets = Vec::new();
@@ -1610,6 +4244,15 @@ fn parse_sql_args(args: &[String]) -> Result<(SqlOptions, Vec<S3Target>), String
                 opts.enc_c.push(v.to_string());
                 i += 2;
             }
+            "--concurrency" => {
+                let v = args.get(i + 1).ok_or("--concurrency expects a value")?;
+                opts.concurrency = v
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| format!("invalid --concurrency value: {v}"))?;
+                i += 2;
+            }
             f if f.starts_with('-') => return Err(format!("unknown sql flag: {f}")),
             _ => {
                 targets.push(parse_target(&args[i])?);
@@ -1736,119 +4379,20 @@ fn build_select_request_xml(opts: &SqlOptions) -> String {
     )
 }
 
-fn s3_request_bytes_with_headers(
-    alias: &AliasConfig,
-    method: &str,
-    bucket: &str,
-    key: Option<&str>,
-    query: &str,
-    upload_file: Option<&Path>,
-    extra_headers: &[String],
-    debug: bool,
-) -> Result<Vec<u8>, String> {
-    let endpoint = parse_endpoint(&alias.endpoint)?;
-    let mut uri_path = endpoint.base_path.clone();
-
-    if alias.path_style {
-        if !bucket.is_empty() {
-            uri_path.push('/');
-            uri_path.push_str(&uri_encode_segment(bucket));
-        }
-        if let Some(k) = key {
-            uri_path.push('/');
-            uri_path.push_str(&uri_encode_path(k));
-        }
-    } else {
-        return Err("only --path-style aliases are supported in this build".to_string());
-    }
-    if uri_path.is_empty() {
-        uri_path = "/".to_string();
-    }
-
-    let canonical_query = normalize_sigv4_query(query);
-    let payload_hash = payload_hash(upload_file)?;
-    let sign = sign_v4(
-        method,
-        &uri_path,
-        &canonical_query,
-        &endpoint.host,
-        &alias.region,
-        &alias.access_key,
-        &alias.secret_key,
-        &payload_hash,
-    )?;
-
-    let mut url = format!("{}://{}{}", endpoint.scheme, endpoint.host, uri_path);
-    if !query.is_empty() {
-        url.push('?');
-        url.push_str(query);
-    }
-
-    let ts = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map_err(|e| e.to_string())?
-        .as_nanos();
-    let body_path = env::temp_dir().join(format!("s4-body-{}-{}", std::process::id(), ts));
-
-    let mut cmd = Command::new("curl");
-    apply_curl_global_flags(&mut cmd, upload_file.is_some(), true);
-    cmd.arg("-sS")
-        .arg("-X")
-        .arg(method)
-        .arg(&url)
-        .arg("-H")
-        .arg(format!("Host: {}", endpoint.host))
-        .arg("-H")
-        .arg(format!("x-amz-date: {}", sign.amz_date))
-        .arg("-H")
-        .arg(format!("x-amz-content-sha256: {}", payload_hash))
-        .arg("-H")
-        .arg(format!("Authorization: {}", sign.authorization));
-    for header in extra_headers {
-        cmd.arg("-H").arg(header);
-    }
-    if let Some(file) = upload_file {
-        cmd.arg("--data-binary").arg(format!("@{}", file.display()));
-    }
-    cmd.arg("-o")
-        .arg(&body_path)
-        .arg("-w")
-        .arg("HTTPSTATUS:%{http_code}");
-
-    if debug {
-        eprintln!("[debug] request(bytes): {} {}", method, url);
-    }
-
-    let out = cmd.output().map_err(|e| e.to_string())?;
-    if !out.status.success() {
-        let _ = fs::remove_file(&body_path);
-        return Err(format!(
-            "request execution failed: {}",
-            String::from_utf8_lossy(&out.stderr).trim()
-        ));
-    }
-
-    let status_text = String::from_utf8_lossy(&out.stdout).to_string();
-    let status = status_text.trim().strip_prefix("HTTPSTATUS:").unwrap_or("");
-    let body = fs::read(&body_path).map_err(|e| e.to_string())?;
-    let _ = fs::remove_file(&body_path);
-
-    if !status.starts_with('2') {
-        return Err(format!("request failed with status {}", status));
-    }
-    Ok(body)
-}
-
-fn parse_event_stream_records(data: &[u8]) -> Vec<u8> {
-    let mut out = Vec::new();
-    let mut i = 0usize;
-    while i + 16 <= data.len() {
-        let total_len =
-            u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]) as usize;
-        let headers_len =
-            u32::from_be_bytes([data[i + 4], data[i + 5], data[i + 6], data[i + 7]]) as usize;
-        if total_len == 0 || i + total_len > data.len() || 12 + headers_len + 4 > total_len {
-            break;
+/// Concatenated `Records` payloads of a SelectObjectContent event stream. An `error`
+/// message (e.g. a bad row mid-stream) fails the whole select. Bodies that are not an
+/// event stream at all are passed through unchanged.
+fn parse_event_stream_records(data: &[u8]) -> Result<Vec<u8>, S4Error> {
+    let mut out = Vec::new();
+    let mut frames = 0usize;
+    let mut i = 0usize;
+    while i + 16 <= data.len() {
+        let total_len =
+            u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]) as usize;
+        let headers_len =
+            u32::from_be_bytes([data[i + 4], data[i + 5], data[i + 6], data[i + 7]]) as usize;
+        if total_len == 0 || i + total_len > data.len() || 12 + headers_len + 4 > total_len {
+            break;
         }
         let headers_start = i + 12;
         let payload_start = headers_start + headers_len;
@@ -1860,6 +4404,9 @@ fn parse_event_stream_records(data: &[u8]) -> Vec<u8> {
         let payload = &data[payload_start..payload_end];
 
         let mut event_type: Option<String> = None;
+        let mut message_type: Option<String> = None;
+        let mut error_code: Option<String> = None;
+        let mut error_message: Option<String> = None;
         let mut j = 0usize;
         while j < headers.len() {
             if j + 2 > headers.len() {
@@ -1886,23 +4433,35 @@ fn parse_event_stream_records(data: &[u8]) -> Vec<u8> {
                     }
                     let val = String::from_utf8_lossy(&headers[j..j + slen]).to_string();
                     j += slen;
-                    if name == ":event-type" {
-                        event_type = Some(val);
+                    match name.as_str() {
+                        ":event-type" => event_type = Some(val),
+                        ":message-type" => message_type = Some(val),
+                        ":error-code" => error_code = Some(val),
+                        ":error-message" => error_message = Some(val),
+                        _ => {}
                     }
                 }
                 _ => break,
             }
         }
 
+        frames += 1;
+        if message_type.as_deref() == Some("error") {
+            return Err(S4Error::Other(format!(
+                "sql select failed: {}: {}",
+                error_code.as_deref().unwrap_or("UnknownError"),
+                error_message.as_deref().unwrap_or("")
+            )));
+        }
         if matches!(event_type.as_deref(), Some("Records")) {
             out.extend_from_slice(payload);
         }
         i += total_len;
     }
-    if out.is_empty() {
+    if frames == 0 {
         out.extend_from_slice(data);
     }
-    out
+    Ok(out)
 }
 
 fn cmd_sql(
@@ -1911,17 +4470,17 @@ fn cmd_sql(
     targets: &[S3Target],
     json: bool,
     debug: bool,
-) -> Result<(), String> {
+) -> Result<(), S4Error> {
     let request_xml = build_select_request_xml(opts);
-    let temp_xml = env::temp_dir().join(format!("s4-sql-{}-req.xml", std::process::id()));
+    let temp_xml = temp_path(format!("s4-sql-{}-req.xml", std::process::id()));
     fs::write(&temp_xml, request_xml).map_err(|e| e.to_string())?;
 
     for target in targets {
         let alias = config
             .aliases
             .get(&target.alias)
-            .ok_or_else(|| format!("unknown alias: {}", target.alias))?;
-        let bucket = req_bucket(target, "sql")?;
+            .ok_or_else(|| unknown_alias(&target.alias))?;
+        let bucket = req_bucket(target, "sql").map_err(S4Error::Usage)?;
 
         let keys: Vec<String> = if opts.recursive {
             let prefix = target.key.clone().unwrap_or_default();
@@ -1930,41 +4489,95 @@ fn cmd_sql(
             vec![req_key(target, "sql")?]
         };
 
-        for key in keys {
-            let body = s3_request_bytes_with_headers(
+        let select = |idx: usize| {
+            let body = s3_request_bytes(
                 alias,
-                "POST",
-                &bucket,
-                Some(&key),
-                "select&select-type=2",
-                Some(&temp_xml),
-                &[],
+                S3Call {
+                    method: "POST",
+                    bucket: &bucket,
+                    key: Some(&keys[idx]),
+                    query: "select&select-type=2",
+                    upload_file: Some(&temp_xml),
+                    ..S3Call::default()
+                },
                 debug,
             )?;
-            let records = parse_event_stream_records(&body);
+            parse_event_stream_records(&body)
+        };
+        run_ordered(keys.len(), opts.concurrency, select, |idx, records| {
+            let key = &keys[idx];
             if json {
                 println!(
                     "{{\"bucket\":\"{}\",\"key\":\"{}\",\"records\":\"{}\"}}",
                     escape_json(&bucket),
-                    escape_json(&key),
+                    escape_json(key),
                     escape_json(&String::from_utf8_lossy(&records))
                 );
             } else {
                 print!("{}", String::from_utf8_lossy(&records));
             }
-        }
+            Ok(())
+        })?;
     }
 
-    let _ = fs::remove_file(&temp_xml);
+    remove_temp(&temp_xml);
     Ok(())
 }
 
-fn parse_sync_args(args: &[String]) -> Result<(SyncOptions, S3Target, S3Target), String> {
+/// Runs `work(0..count)` on up to `concurrency` threads and hands the results to `emit`
+/// in index order, buffering any that finish early. Stops at the first error in order.
+fn run_ordered<T: Send>(
+    count: usize,
+    concurrency: usize,
+    work: impl Fn(usize) -> Result<T, S4Error> + Sync,
+    mut emit: impl FnMut(usize, T) -> Result<(), S4Error>,
+) -> Result<(), S4Error> {
+    if concurrency <= 1 || count <= 1 {
+        for idx in 0..count {
+            emit(idx, work(idx)?)?;
+        }
+        return Ok(());
+    }
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    std::thread::scope(|scope| {
+        let (tx, rx) = std::sync::mpsc::channel();
+        for _ in 0..concurrency.min(count) {
+            let tx = tx.clone();
+            let (next, stop, work) = (&next, &stop, &work);
+            scope.spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let idx = next.fetch_add(1, Ordering::Relaxed);
+                    if idx >= count || tx.send((idx, work(idx))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+        let mut pending = BTreeMap::new();
+        let mut expected = 0;
+        let result = (|| {
+            for (idx, res) in rx.iter() {
+                pending.insert(idx, res);
+                while let Some(res) = pending.remove(&expected) {
+                    emit(expected, res?)?;
+                    expected += 1;
+                }
+            }
+            Ok(())
+        })();
+        stop.store(true, Ordering::Relaxed);
+        result
+    })
+}
+
+/// Source and destination stay unresolved strings: either side may be a local
+/// directory, which only `SyncEndpoint::resolve` (with the config) can tell.
+fn parse_sync_args(args: &[String]) -> Result<(SyncOptions, String, String), String> {
+    let usage = "usage: s4 sync|mirror [FLAGS] <src_alias/bucket[/prefix]|dir> <dst_alias/bucket[/prefix]|dir>";
     if args.len() < 3 {
-        return Err(
-            "usage: s4 sync|mirror [FLAGS] <src_alias/bucket[/prefix]> <dst_alias/bucket[/prefix]>"
-                .to_string(),
-        );
+        return Err(usage.to_string());
     }
 
     let mut opts = SyncOptions::default();
@@ -1984,6 +4597,22 @@ fn parse_sync_args(args: &[String]) -> Result<(SyncOptions, S3Target, S3Target),
                 opts.remove = true;
                 i += 1;
             }
+            "--allow-empty-source" => {
+                opts.allow_empty_source = true;
+                i += 1;
+            }
+            "--verify" => {
+                opts.verify = true;
+                i += 1;
+            }
+            "--skip-dir-markers" => {
+                opts.skip_dir_markers = true;
+                i += 1;
+            }
+            "--follow-symlinks" | "--no-follow-symlinks" => {
+                opts.follow_symlinks = args[i] == "--follow-symlinks";
+                i += 1;
+            }
             "--exclude" => {
                 let value = args.get(i + 1).ok_or("--exclude expects a value")?;
                 opts.excludes.push(value.to_string());
@@ -1999,6 +4628,31 @@ fn parse_sync_args(args: &[String]) -> Result<(SyncOptions, S3Target, S3Target),
                 opts.older_than = Some(parse_human_duration(value)?);
                 i += 2;
             }
+            "--deadline" => {
+                let value = args.get(i + 1).ok_or("--deadline expects a value")?;
+                opts.deadline = Some(parse_human_duration(value)?);
+                i += 2;
+            }
+            "--metrics-file" => {
+                let value = args.get(i + 1).ok_or("--metrics-file expects a value")?;
+                opts.metrics_file = Some(PathBuf::from(value));
+                i += 2;
+            }
+            "--journal" => {
+                let value = args.get(i + 1).ok_or("--journal expects a file path")?;
+                opts.journal = Some(PathBuf::from(value));
+                i += 2;
+            }
+            "--modified-after" => {
+                let value = args.get(i + 1).ok_or("--modified-after expects a value")?;
+                opts.modified_after = Some(parse_rfc3339(value)?);
+                i += 2;
+            }
+            "--modified-before" => {
+                let value = args.get(i + 1).ok_or("--modified-before expects a value")?;
+                opts.modified_before = Some(parse_rfc3339(value)?);
+                i += 2;
+            }
             "--watch" | "-w" => {
                 opts.watch = true;
                 i += 1;
@@ -2014,2198 +4668,12164 @@ fn parse_sync_args(args: &[String]) -> Result<(SyncOptions, S3Target, S3Target),
     }
 
     if positional.len() != 2 {
-        return Err(
-            "usage: s4 sync|mirror [FLAGS] <src_alias/bucket[/prefix]> <dst_alias/bucket[/prefix]>"
-                .to_string(),
-        );
+        return Err(usage.to_string());
     }
 
-    let src = parse_target(positional[0])?;
-    let dst = parse_target(positional[1])?;
-    Ok((opts, src, dst))
+    Ok((opts, positional[0].clone(), positional[1].clone()))
 }
 
-fn wildcard_match(pattern: &str, text: &str) -> bool {
-    let p = pattern.as_bytes();
-    let t = text.as_bytes();
-    let mut pi = 0usize;
-    let mut ti = 0usize;
-    let mut star: Option<usize> = None;
-    let mut match_ti = 0usize;
-
-    while ti < t.len() {
-        if pi < p.len() && (p[pi] == b'?' || p[pi] == t[ti]) {
-            pi += 1;
-            ti += 1;
-        } else if pi < p.len() && p[pi] == b'*' {
-            star = Some(pi);
-            pi += 1;
-            match_ti = ti;
-        } else if let Some(star_idx) = star {
-            pi = star_idx + 1;
-            match_ti += 1;
-            ti = match_ti;
-        } else {
-            return false;
+fn parse_put_args(args: &[String]) -> Result<(PutOptions, PathBuf, S3Target), String> {
+    let usage = "usage: s4 put [--recursive [--follow-symlinks]] [--dry-run] [--allow-empty] <source|'dir/*.ext'> <alias/bucket/key|prefix>\n       s4 put --from-url <url> <alias/bucket/key>";
+    let mut opts = PutOptions::default();
+    let mut positional: Vec<&String> = Vec::new();
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--recursive" | "-r" => {
+                opts.recursive = true;
+                i += 1;
+            }
+            "--dry-run" => {
+                opts.dry_run = true;
+                i += 1;
+            }
+            "--allow-empty" => {
+                opts.allow_empty = true;
+                i += 1;
+            }
+            "--follow-symlinks" | "--no-follow-symlinks" => {
+                opts.follow_symlinks = args[i] == "--follow-symlinks";
+                i += 1;
+            }
+            "--if-none-match" | "--if-match" => {
+                let value = args
+                    .get(i + 1)
+                    .ok_or_else(|| format!("{} expects a value", args[i]))?;
+                opts.upload
+                    .preconditions
+                    .push(precondition_header(&args[i], value)?);
+                i += 2;
+            }
+            "--content-md5" => {
+                opts.upload.content_md5 = true;
+                i += 1;
+            }
+            "--content-disposition" | "--cache-control" | "--content-language" => {
+                let value = args
+                    .get(i + 1)
+                    .ok_or_else(|| format!("{} expects a value", args[i]))?;
+                opts.upload.headers.push(object_header(&args[i], value)?);
+                i += 2;
+            }
+            "--checksum-algorithm" => {
+                let value = args
+                    .get(i + 1)
+                    .ok_or_else(|| "--checksum-algorithm expects a value".to_string())?;
+                opts.upload.checksum = Some(ChecksumAlgorithm::parse(value)?);
+                i += 2;
+            }
+            "--compress" => {
+                let value = args
+                    .get(i + 1)
+                    .ok_or_else(|| "--compress expects a value".to_string())?;
+                opts.upload.compress = parse_compress(value)?;
+                i += 2;
+            }
+            "--from-url" => {
+                let value = args
+                    .get(i + 1)
+                    .ok_or_else(|| "--from-url expects a value".to_string())?;
+                opts.from_url = Some(parse_source_url(value)?);
+                i += 2;
+            }
+            f if f.starts_with('-') => return Err(format!("unknown put flag: {f}")),
+            _ => {
+                positional.push(&args[i]);
+                i += 1;
+            }
         }
     }
 
-    while pi < p.len() && p[pi] == b'*' {
-        pi += 1;
+    if opts.from_url.is_some() {
+        if opts.recursive {
+            return Err("--from-url cannot be combined with --recursive".to_string());
+        }
+        if positional.len() != 1 {
+            return Err(usage.to_string());
+        }
+        let target = parse_target(positional[0])?;
+        return Ok((opts, PathBuf::new(), target));
     }
-
-    pi == p.len()
-}
-
-fn is_excluded(key: &str, patterns: &[String]) -> bool {
-    patterns.iter().any(|p| wildcard_match(p, key))
+    if positional.len() != 2 {
+        return Err(usage.to_string());
+    }
+    let target = parse_target(positional[1])?;
+    Ok((opts, PathBuf::from(positional[0]), target))
 }
 
-fn parse_human_duration(input: &str) -> Result<u64, String> {
-    if input.is_empty() {
-        return Err("duration cannot be empty".to_string());
-    }
-    let mut total = 0u64;
-    let mut value = 0u64;
-    let mut has_unit = false;
-    for c in input.chars() {
-        if c.is_ascii_digit() {
-            value = value
-                .checked_mul(10)
-                .and_then(|v| v.checked_add((c as u8 - b'0') as u64))
-                .ok_or_else(|| "duration value overflow".to_string())?;
-            has_unit = false;
-            continue;
-        }
-        let unit = match c {
-            'd' => 86_400u64,
-            'h' => 3_600u64,
-            'm' => 60u64,
-            's' => 1u64,
-            _ => return Err(format!("unsupported duration unit: {c}")),
-        };
-        total = total
-            .checked_add(
-                value
-                    .checked_mul(unit)
-                    .ok_or_else(|| "duration overflow".to_string())?,
-            )
-            .ok_or_else(|| "duration overflow".to_string())?;
-        value = 0;
-        has_unit = true;
-    }
-    if !has_unit || value != 0 {
-        return Err("duration must end with unit (d/h/m/s)".to_string());
+fn parse_source_url(value: &str) -> Result<String, String> {
+    let rest = value
+        .strip_prefix("https://")
+        .or_else(|| value.strip_prefix("http://"))
+        .ok_or_else(|| {
+            format!("unsupported --from-url scheme (expected http or https): {value}")
+        })?;
+    if rest.is_empty() || rest.starts_with('/') {
+        return Err(format!("--from-url is missing a host: {value}"));
     }
-    Ok(total)
+    Ok(value.to_string())
 }
 
-fn object_age_seconds(
-    alias: &AliasConfig,
-    bucket: &str,
-    key: &str,
-    debug: bool,
-) -> Result<Option<u64>, String> {
-    let headers = s3_request(alias, "HEAD", bucket, Some(key), "", None, None, debug)?;
-    let mut last_modified: Option<String> = None;
-    for line in headers.lines() {
-        let lower = line.to_ascii_lowercase();
-        if lower.starts_with("last-modified:") {
-            if let Some((_, value)) = line.split_once(':') {
-                last_modified = Some(value.trim().to_string());
-                break;
-            }
-        }
+fn fetch_source_url(url: &str, dest: &Path, debug: bool) -> Result<(), S4Error> {
+    let mut cmd = Command::new("curl");
+    apply_curl_transport_flags(&mut cmd, false, true);
+    cmd.arg("-sS")
+        .arg("-L")
+        .arg(url)
+        .arg("-o")
+        .arg(dest)
+        .arg("-w")
+        .arg("%{http_code}");
+    if debug {
+        eprintln!("[debug] fetch source: GET {}", url);
     }
-    let Some(last_modified) = last_modified else {
-        return Ok(None);
-    };
-    let out = Command::new("python3")
-        .arg("-c")
-        .arg(
-            "import sys,time,email.utils; dt=email.utils.parsedate_to_datetime(sys.argv[1]); print(int(time.time()-dt.timestamp()))",
-        )
-        .arg(&last_modified)
-        .output()
-        .map_err(|e| e.to_string())?;
+    let out = run_curl(&mut cmd, false)?;
     if !out.status.success() {
-        return Err(format!(
-            "failed to parse Last-Modified header: {}",
+        return Err(S4Error::Network(format!(
+            "fetching {} failed: {}",
+            url,
             String::from_utf8_lossy(&out.stderr).trim()
-        ));
+        )));
     }
-    let age = String::from_utf8_lossy(&out.stdout)
-        .trim()
-        .parse::<u64>()
-        .map_err(|e| e.to_string())?;
-    Ok(Some(age))
+    let code = String::from_utf8_lossy(&out.stdout).trim().to_string();
+    if !code.starts_with('2') {
+        return Err(S4Error::Network(format!(
+            "fetching {} failed: HTTP {}",
+            url, code
+        )));
+    }
+    Ok(())
 }
 
-fn watch_interval() -> Duration {
-    let seconds = env::var("S4_SYNC_WATCH_INTERVAL_SEC")
-        .ok()
-        .and_then(|v| v.parse::<u64>().ok())
-        .unwrap_or(2);
-    Duration::from_secs(seconds.max(1))
-}
-
-fn cmd_sync_once(
-    src_alias: &AliasConfig,
-    dst_alias: &AliasConfig,
-    source: &S3Target,
-    destination: &S3Target,
-    options: &SyncOptions,
-    json: bool,
-    debug: bool,
-) -> Result<(usize, usize), String> {
-    let src_bucket = req_bucket(source, "sync")?;
-    let dst_bucket = req_bucket(destination, "sync")?;
-    let src_prefix = source.key.clone().unwrap_or_default();
-    let dst_prefix = destination.key.clone().unwrap_or_default();
-
-    let keys = list_object_keys(src_alias, &src_bucket, &src_prefix, debug)?;
-    let mut filtered_keys: Vec<String> = Vec::new();
-    for key in keys {
-        if is_excluded(&key, &options.excludes) {
-            continue;
-        }
-        if options.newer_than.is_some() || options.older_than.is_some() {
-            let age = object_age_seconds(src_alias, &src_bucket, &key, debug)?;
-            let Some(age) = age else {
-                continue;
-            };
-            if let Some(newer_than) = options.newer_than {
-                if age > newer_than {
-                    continue;
-                }
+/// `get` takes one or more sources followed by the destination; `cat` exactly one source.
+fn parse_get_args(args: &[String]) -> Result<(GetOptions, Vec<S3Target>, Option<PathBuf>), String> {
+    let command = args[0].as_str();
+    let usage = if command == "cat" {
+        "usage: s4 cat [-n] [--head N|--tail N] [--if-none-match ETAG] [--if-modified-since RFC3339] [--decompress|--accept-encoding gzip|zstd|auto] <alias/bucket/key>"
+    } else {
+        "usage: s4 get [--if-none-match ETAG] [--if-modified-since RFC3339] [--decompress|--accept-encoding gzip|zstd|auto] [--download-concurrency N] <alias/bucket/key>... <destination_file|directory>"
+    };
+    let mut opts = GetOptions::default();
+    let mut positional: Vec<&String> = Vec::new();
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--if-none-match" => {
+                let value = args.get(i + 1).ok_or("--if-none-match expects a value")?;
+                let etag = if value.starts_with('"') || value == "*" {
+                    value.to_string()
+                } else {
+                    format!("\"{value}\"")
+                };
+                opts.conditions.push(format!("If-None-Match: {etag}"));
+                i += 2;
             }
-            if let Some(older_than) = options.older_than {
-                if age < older_than {
-                    continue;
-                }
+            "--if-modified-since" => {
+                let value = args
+                    .get(i + 1)
+                    .ok_or("--if-modified-since expects a value")?;
+                let secs = parse_rfc3339(value)?;
+                opts.conditions
+                    .push(format!("If-Modified-Since: {}", http_date(secs)));
+                i += 2;
             }
-        }
-        filtered_keys.push(key);
-    }
-
-    let mut copied = 0usize;
-    let mut removed = 0usize;
-
-    if options.dry_run {
-        for key in &filtered_keys {
-            let dest_key = sync_destination_key(key, &src_prefix, &dst_prefix);
-            if !json {
-                println!(
-                    "[dry-run] copy {}/{} -> {}/{}",
-                    src_bucket, key, dst_bucket, dest_key
-                );
+            "--decompress" => {
+                opts.decompress = true;
+                i += 1;
             }
-            copied += 1;
-        }
-    } else {
-        let temp_root = env::temp_dir().join(format!("s4-sync-{}", std::process::id()));
-        fs::create_dir_all(&temp_root).map_err(|e| e.to_string())?;
-
-        for (idx, key) in filtered_keys.iter().enumerate() {
-            let dest_key = sync_destination_key(key, &src_prefix, &dst_prefix);
-            let temp_file = temp_root.join(format!("obj-{idx}"));
-            s3_request(
-                src_alias,
-                "GET",
-                &src_bucket,
-                Some(key),
-                "",
-                None,
-                Some(&temp_file),
-                debug,
-            )?;
-            upload_file_to_s3(dst_alias, &dst_bucket, &dest_key, &temp_file, debug)?;
-            copied += 1;
-        }
-
-        fs::remove_dir_all(&temp_root).ok();
-    }
-
-    if options.remove {
-        let dst_keys = list_object_keys(dst_alias, &dst_bucket, &dst_prefix, debug)?;
-        let expected: HashSet<String> = filtered_keys
-            .iter()
-            .map(|k| sync_destination_key(k, &src_prefix, &dst_prefix))
-            .collect();
-        for key in dst_keys {
-            if !expected.contains(&key) {
-                if options.dry_run {
-                    if !json {
-                        println!("[dry-run] remove {}/{}", dst_bucket, key);
-                    }
+            "--accept-encoding" => {
+                let value = args.get(i + 1).ok_or("--accept-encoding expects a value")?;
+                opts.accept_encoding = Some(parse_accept_encoding(value)?);
+                i += 2;
+            }
+            "-n" if command == "cat" => {
+                opts.lines.number = true;
+                i += 1;
+            }
+            flag @ ("--head" | "--tail") if command == "cat" => {
+                let value = args
+                    .get(i + 1)
+                    .ok_or_else(|| format!("{flag} expects a value"))?;
+                let n = value
+                    .parse::<usize>()
+                    .map_err(|_| format!("invalid {flag} value: {value}"))?;
+                if flag == "--head" {
+                    opts.lines.head = Some(n);
                 } else {
-                    s3_request(
-                        dst_alias,
-                        "DELETE",
-                        &dst_bucket,
-                        Some(&key),
-                        "",
-                        None,
-                        None,
-                        debug,
-                    )?;
+                    opts.lines.tail = Some(n);
                 }
-                removed += 1;
+                i += 2;
+            }
+            "--download-concurrency" if command == "get" => {
+                let value = args
+                    .get(i + 1)
+                    .ok_or("--download-concurrency expects a value")?;
+                opts.download_concurrency = Some(parse_download_concurrency(value)?);
+                i += 2;
+            }
+            f if f.starts_with('-') => return Err(format!("unknown {command} flag: {f}")),
+            _ => {
+                positional.push(&args[i]);
+                i += 1;
             }
         }
     }
 
-    Ok((copied, removed))
-}
-
-fn cmd_sync(
-    config: &AppConfig,
-    source: &S3Target,
-    destination: &S3Target,
-    options: &SyncOptions,
-    json: bool,
-    debug: bool,
-) -> Result<(), String> {
-    let src_alias = config
-        .aliases
-        .get(&source.alias)
-        .ok_or_else(|| format!("unknown alias: {}", source.alias))?;
-    let dst_alias = config
-        .aliases
-        .get(&destination.alias)
-        .ok_or_else(|| format!("unknown alias: {}", destination.alias))?;
-
-    loop {
-        let (copied, removed) = cmd_sync_once(
-            src_alias,
-            dst_alias,
-            source,
-            destination,
-            options,
-            json,
-            debug,
-        )?;
-
-        let src_bucket = req_bucket(source, "sync")?;
-        let dst_bucket = req_bucket(destination, "sync")?;
-
-        if json {
-            println!(
-                "{{\"status\":\"ok\",\"copied\":{},\"removed\":{},\"dry_run\":{},\"watch\":{},\"src\":\"{}\",\"dst\":\"{}\"}}",
-                copied,
-                removed,
-                options.dry_run,
-                options.watch,
-                escape_json(&format!("{}/{}", source.alias, src_bucket)),
-                escape_json(&format!("{}/{}", destination.alias, dst_bucket))
-            );
-        } else {
-            println!(
-                "Synced {} object(s) from {}/{} to {}/{} (removed: {}, dry-run: {}, watch: {})",
-                copied,
-                source.alias,
-                src_bucket,
-                destination.alias,
-                dst_bucket,
-                removed,
-                options.dry_run,
-                options.watch
-            );
-        }
-
-        if !options.watch {
-            break;
-        }
-        sleep(watch_interval());
+    let enough = if command == "cat" {
+        positional.len() == 1
+    } else {
+        positional.len() >= 2
+    };
+    if !enough {
+        return Err(usage.to_string());
     }
-
-    Ok(())
+    if opts.lines.head.is_some() && opts.lines.tail.is_some() {
+        return Err("use either --head or --tail, not both".to_string());
+    }
+    if opts.decompress && opts.accept_encoding.is_some() {
+        return Err("use either --decompress or --accept-encoding, not both".to_string());
+    }
+    let destination = (command == "get").then(|| positional.pop().map(PathBuf::from));
+    let targets = positional
+        .iter()
+        .map(|p| parse_target(p))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((opts, targets, destination.flatten()))
 }
 
-fn cmd_cp_mv(
-    command: &str,
-    config: &AppConfig,
-    source: &str,
-    target: &str,
-    json: bool,
-    debug: bool,
-) -> Result<(), String> {
-    let src = classify_ref(config, source);
-    let dst = classify_ref(config, target);
-
-    match (&src, &dst) {
-        (ObjectRef::Local(src_path), ObjectRef::S3(dst_s3)) => {
-            let body_path = PathBuf::from(src_path);
-            if !body_path.exists() {
-                return Err(format!("source file not found: {}", body_path.display()));
+fn parse_cp_args(args: &[String]) -> Result<(CopyOptions, String, String), String> {
+    let command = &args[0];
+    let mut opts = CopyOptions::default();
+    let mut positional: Vec<&String> = Vec::new();
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--dry-run" => {
+                opts.dry_run = true;
+                i += 1;
             }
-            upload_file_to_s3(
-                &dst_s3.alias,
-                &dst_s3.bucket,
-                &dst_s3.key,
-                &body_path,
-                debug,
-            )?;
-            if command == "mv" {
-                fs::remove_file(&body_path).map_err(|e| e.to_string())?;
+            "--preserve" => {
+                opts.preserve = true;
+                i += 1;
             }
-        }
-        (ObjectRef::S3(src_s3), ObjectRef::Local(dst_path)) => {
-            let out = PathBuf::from(dst_path);
-            if let Some(parent) = out.parent() {
-                if !parent.as_os_str().is_empty() {
-                    fs::create_dir_all(parent).map_err(|e| e.to_string())?;
-                }
+            "--if-size-differs" => {
+                opts.if_size_differs = true;
+                i += 1;
             }
-            s3_request(
-                &src_s3.alias,
-                "GET",
-                &src_s3.bucket,
-                Some(&src_s3.key),
-                "",
-                None,
-                Some(&out),
-                debug,
-            )?;
-            if command == "mv" {
-                s3_request(
-                    &src_s3.alias,
-                    "DELETE",
-                    &src_s3.bucket,
-                    Some(&src_s3.key),
-                    "",
-                    None,
-                    None,
-                    debug,
-                )?;
+            "--checksum" => {
+                opts.checksum = true;
+                i += 1;
             }
-        }
-        (ObjectRef::S3(src_s3), ObjectRef::S3(dst_s3)) => {
-            copy_object_s3_to_s3(src_s3, dst_s3, debug)?;
-            if command == "mv" {
-                s3_request(
-                    &src_s3.alias,
-                    "DELETE",
-                    &src_s3.bucket,
-                    Some(&src_s3.key),
-                    "",
-                    None,
-                    None,
-                    debug,
-                )?;
+            "--recursive" | "-r" if command == "mv" => {
+                opts.recursive = true;
+                i += 1;
             }
-        }
-        (ObjectRef::Local(src_path), ObjectRef::Local(dst_path)) => {
-            fs::copy(src_path, dst_path).map_err(|e| e.to_string())?;
-            if command == "mv" {
-                fs::remove_file(src_path).map_err(|e| e.to_string())?;
+            "--download-concurrency" => {
+                let value = args
+                    .get(i + 1)
+                    .ok_or("--download-concurrency expects a value")?;
+                opts.download_concurrency = Some(parse_download_concurrency(value)?);
+                i += 2;
+            }
+            "--if-none-match" | "--if-match" => {
+                let value = args
+                    .get(i + 1)
+                    .ok_or_else(|| format!("{} expects a value", args[i]))?;
+                opts.upload
+                    .preconditions
+                    .push(precondition_header(&args[i], value)?);
+                i += 2;
+            }
+            "--checksum-algorithm" => {
+                let value = args
+                    .get(i + 1)
+                    .ok_or_else(|| "--checksum-algorithm expects a value".to_string())?;
+                opts.upload.checksum = Some(ChecksumAlgorithm::parse(value)?);
+                i += 2;
+            }
+            "--content-disposition" | "--cache-control" | "--content-language" => {
+                let value = args
+                    .get(i + 1)
+                    .ok_or_else(|| format!("{} expects a value", args[i]))?;
+                opts.upload.headers.push(object_header(&args[i], value)?);
+                i += 2;
+            }
+            f if f.starts_with('-') && f.len() > 1 => {
+                return Err(format!("unknown {command} flag: {f}"));
+            }
+            _ => {
+                positional.push(&args[i]);
+                i += 1;
             }
         }
     }
 
-    if json {
-        println!(
-            "{{\"status\":\"ok\",\"command\":\"{}\",\"source\":\"{}\",\"target\":\"{}\"}}",
-            escape_json(command),
-            escape_json(source),
-            escape_json(target)
+    if opts.checksum && !opts.if_size_differs {
+        return Err("--checksum requires --if-size-differs".to_string());
+    }
+    if opts.recursive && (opts.if_size_differs || opts.download_concurrency.is_some()) {
+        return Err(
+            "mv --recursive cannot be combined with --if-size-differs or --download-concurrency"
+                .to_string(),
         );
-    } else {
-        println!("{}: {} -> {}", command, source, target);
     }
-    Ok(())
-}
-
-#[derive(Clone)]
-struct S3ObjectRef {
-    alias: AliasConfig,
-    bucket: String,
-    key: String,
+    if positional.len() != 2 {
+        let recursive = if command == "mv" {
+            " [--recursive]"
+        } else {
+            ""
+        };
+        return Err(format!(
+            "usage: s4 {command}{recursive} [--dry-run] [--preserve] [--if-size-differs [--checksum]] [--download-concurrency N] [--if-none-match '*'] [--if-match ETAG] [--checksum-algorithm CRC32|SHA256] [--content-disposition V] [--cache-control V] [--content-language V] <source> <target>"
+        ));
+    }
+    Ok((opts, positional[0].clone(), positional[1].clone()))
 }
 
-enum ObjectRef {
-    S3(S3ObjectRef),
-    Local(String),
+fn parse_download_concurrency(value: &str) -> Result<usize, String> {
+    value
+        .parse::<usize>()
+        .ok()
+        .filter(|n| *n > 0)
+        .ok_or_else(|| format!("invalid --download-concurrency value: {value}"))
 }
 
-fn classify_ref(config: &AppConfig, value: &str) -> ObjectRef {
-    if let Ok(t) = parse_target(value) {
-        if let Some(alias) = config.aliases.get(&t.alias) {
-            if let (Some(bucket), Some(key)) = (t.bucket, t.key) {
-                return ObjectRef::S3(S3ObjectRef {
-                    alias: alias.clone(),
-                    bucket,
-                    key,
-                });
+fn parse_rm_args(args: &[String]) -> Result<(RmOptions, S3Target), String> {
+    let usage = "usage: s4 rm [--dry-run] [--bypass-governance] <alias/bucket/key>\n       s4 rm --recursive --older-than DURATION --force [--dry-run] [--bypass-governance] <alias/bucket[/prefix]>";
+    let mut opts = RmOptions::default();
+    let mut target = None;
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--dry-run" => opts.dry_run = true,
+            "--bypass-governance" => opts.bypass_governance = true,
+            "--recursive" | "-r" => opts.recursive = true,
+            "--force" => opts.force = true,
+            "--older-than" => {
+                let value = args.get(i + 1).ok_or("--older-than expects a value")?;
+                opts.older_than = Some(parse_human_duration(value)?);
+                i += 1;
             }
+            f if f.starts_with('-') => return Err(format!("unknown rm flag: {f}")),
+            _ if target.is_none() => target = Some(parse_target(&args[i])?),
+            _ => return Err(usage.to_string()),
         }
+        i += 1;
     }
-    ObjectRef::Local(value.to_string())
+    let target = target.ok_or_else(|| usage.to_string())?;
+    if opts.recursive != opts.older_than.is_some() {
+        return Err(
+            "rm --recursive only deletes by age: use it together with --older-than".to_string(),
+        );
+    }
+    if opts.recursive && !opts.force && !opts.dry_run {
+        return Err(
+            "rm --recursive --older-than deletes every matching object; add --force (or preview with --dry-run)"
+                .to_string(),
+        );
+    }
+    Ok((opts, target))
 }
 
-fn copy_object_s3_to_s3(src: &S3ObjectRef, dst: &S3ObjectRef, debug: bool) -> Result<(), String> {
-    let copy_source = format!(
-        "/{}/{}",
-        uri_encode_segment(&src.bucket),
-        uri_encode_path(&src.key)
-    );
-    let headers = vec![format!("x-amz-copy-source: {}", copy_source)];
-    s3_request_with_headers(
-        &dst.alias,
-        "PUT",
-        &dst.bucket,
-        Some(&dst.key),
-        "",
-        None,
-        None,
-        &headers,
-        debug,
-    )?;
-    Ok(())
+fn parse_compress(value: &str) -> Result<bool, String> {
+    match value {
+        "gzip" => Ok(true),
+        _ => Err(format!(
+            "unsupported --compress value '{value}' (expected gzip)"
+        )),
+    }
 }
 
-fn cmd_find(
-    alias: &AliasConfig,
-    bucket: &str,
-    prefix: &str,
-    needle: Option<&str>,
-    json: bool,
-    debug: bool,
-) -> Result<(), String> {
-    let keys = list_object_keys(alias, bucket, prefix, debug)?;
-    for key in keys {
-        if let Some(n) = needle {
-            if !key.contains(n) {
-                continue;
+fn parse_pipe_args(args: &[String]) -> Result<(UploadOptions, S3Target), String> {
+    let mut upload = UploadOptions::default();
+    let mut target = None;
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--compress" => {
+                let value = args
+                    .get(i + 1)
+                    .ok_or_else(|| "--compress expects a value".to_string())?;
+                upload.compress = parse_compress(value)?;
+                i += 2;
+            }
+            f if f.starts_with('-') => return Err(format!("unknown pipe flag: {f}")),
+            _ if target.is_none() => {
+                target = Some(parse_target(&args[i])?);
+                i += 1;
             }
+            _ => return Err("usage: s4 pipe [--compress gzip] <alias/bucket/key>".to_string()),
         }
-        if json {
-            println!(
-                "{{\"bucket\":\"{}\",\"key\":\"{}\"}}",
-                escape_json(bucket),
-                escape_json(&key)
-            );
-        } else {
-            println!("{}", key);
+    }
+    let target =
+        target.ok_or_else(|| "usage: s4 pipe [--compress gzip] <alias/bucket/key>".to_string())?;
+    Ok((upload, target))
+}
+
+const APPEND_USAGE: &str = "usage: s4 append [--native] <alias/bucket/key> < data";
+
+/// `append [--native] TARGET`; `--native` forces the `x-amz-write-offset-bytes` path.
+fn parse_append_args(args: &[String]) -> Result<(bool, S3Target), String> {
+    let mut native = false;
+    let mut target = None;
+    for arg in &args[1..] {
+        match arg.as_str() {
+            "--native" => native = true,
+            f if f.starts_with('-') => return Err(format!("unknown append flag: {f}")),
+            _ if target.is_none() => target = Some(parse_target(arg)?),
+            _ => return Err(APPEND_USAGE.to_string()),
         }
     }
-    Ok(())
+    Ok((native, target.ok_or(APPEND_USAGE)?))
 }
 
-fn cmd_tree(
-    alias: &AliasConfig,
-    bucket: &str,
-    prefix: &str,
-    _json: bool,
-    debug: bool,
-) -> Result<(), String> {
-    let mut keys = list_object_keys(alias, bucket, prefix, debug)?;
-    keys.sort();
-    println!("{}/", bucket);
-    for key in keys {
-        let depth = key.matches('/').count();
-        let indent = "  ".repeat(depth + 1);
-        let name = key.rsplit('/').next().unwrap_or(&key);
-        println!("{}{}", indent, name);
+/// `--content-disposition`, `--cache-control` and `--content-language` on put/cp: stored
+/// with the object and served back on every GET/HEAD.
+fn object_header(flag: &str, value: &str) -> Result<String, String> {
+    let name = match flag {
+        "--content-disposition" => "Content-Disposition",
+        "--cache-control" => "Cache-Control",
+        _ => "Content-Language",
+    };
+    if value.trim().is_empty() || value.contains(['\r', '\n']) {
+        return Err(format!("invalid {flag} value: {value:?}"));
     }
-    Ok(())
+    Ok(format!("{name}: {}", value.trim()))
 }
 
-fn cmd_head(
-    alias: &AliasConfig,
+/// `--if-none-match` only accepts `*` (S3 conditional writes); `--if-match` takes an ETag,
+/// quoted here if the user passed the bare hex form.
+fn precondition_header(flag: &str, value: &str) -> Result<String, String> {
+    match flag {
+        "--if-none-match" if value == "*" => Ok("If-None-Match: *".to_string()),
+        "--if-none-match" => Err("--if-none-match only supports '*'".to_string()),
+        _ if value == "*" || value.starts_with('"') => Ok(format!("If-Match: {value}")),
+        _ => Ok(format!("If-Match: \"{value}\"")),
+    }
+}
+
+/// Turns a 412 from a conditional write into a message naming the object that blocked it.
+fn explain_precondition_failure(
+    err: S4Error,
     bucket: &str,
     key: &str,
-    lines: usize,
-    debug: bool,
-) -> Result<(), String> {
-    let body = s3_request(alias, "GET", bucket, Some(key), "", None, None, debug)?;
-    for line in body.lines().take(lines) {
-        println!("{}", line);
+    preconditions: &[String],
+) -> S4Error {
+    match err {
+        S4Error::Http { status: 412, .. }
+            if preconditions.iter().any(|h| h.starts_with("If-None-Match")) =>
+        {
+            S4Error::Other(format!("object already exists: {bucket}/{key}"))
+        }
+        S4Error::Http { status: 412, .. } => S4Error::Other(format!(
+            "object {bucket}/{key} does not match the expected ETag (precondition failed)"
+        )),
+        other => other,
     }
-    Ok(())
 }
 
-fn cmd_ping(alias_name: &str, alias: &AliasConfig, json: bool, debug: bool) -> Result<(), String> {
-    let start = Instant::now();
-    let _ = s3_request(alias, "GET", "", None, "", None, None, debug)?;
-    let ms = start.elapsed().as_millis();
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let p = pattern.as_bytes();
+    let t = text.as_bytes();
+    let mut pi = 0usize;
+    let mut ti = 0usize;
+    let mut star: Option<usize> = None;
+    let mut match_ti = 0usize;
 
-    if json {
-        println!(
-            "{{\"alias\":\"{}\",\"status\":\"ok\",\"latency_ms\":{}}}",
-            escape_json(alias_name),
-            ms
-        );
-    } else {
-        println!("{} is alive ({} ms)", alias_name, ms);
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == b'?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == b'*' {
+            star = Some(pi);
+            pi += 1;
+            match_ti = ti;
+        } else if let Some(star_idx) = star {
+            pi = star_idx + 1;
+            match_ti += 1;
+            ti = match_ti;
+        } else {
+            return false;
+        }
     }
-    Ok(())
+
+    while pi < p.len() && p[pi] == b'*' {
+        pi += 1;
+    }
+
+    pi == p.len()
 }
 
-fn looks_ready_xml(body: &str) -> bool {
-    body.contains("<ListAllMyBucketsResult") || body.contains("<Error")
+fn is_excluded(key: &str, patterns: &[String]) -> bool {
+    patterns.iter().any(|p| wildcard_match(p, key))
 }
 
-fn cmd_ready(alias_name: &str, alias: &AliasConfig, json: bool, debug: bool) -> Result<(), String> {
-    let body = s3_request(alias, "GET", "", None, "", None, None, debug)?;
-    if !looks_ready_xml(&body) {
-        return Err("ready check got unexpected response body".to_string());
+fn parse_human_duration(input: &str) -> Result<u64, String> {
+    if input.is_empty() {
+        return Err("duration cannot be empty".to_string());
     }
-
-    if json {
-        println!(
-            "{{\"alias\":\"{}\",\"ready\":true}}",
-            escape_json(alias_name)
-        );
-    } else {
-        println!("{} is ready", alias_name);
+    let mut total = 0u64;
+    let mut value = 0u64;
+    let mut has_unit = false;
+    for c in input.chars() {
+        if c.is_ascii_digit() {
+            value = value
+                .checked_mul(10)
+                .and_then(|v| v.checked_add((c as u8 - b'0') as u64))
+                .ok_or_else(|| "duration value overflow".to_string())?;
+            has_unit = false;
+            continue;
+        }
+        let unit = match c {
+            'd' => 86_400u64,
+            'h' => 3_600u64,
+            'm' => 60u64,
+            's' => 1u64,
+            _ => return Err(format!("unsupported duration unit: {c}")),
+        };
+        total = total
+            .checked_add(
+                value
+                    .checked_mul(unit)
+                    .ok_or_else(|| "duration overflow".to_string())?,
+            )
+            .ok_or_else(|| "duration overflow".to_string())?;
+        value = 0;
+        has_unit = true;
     }
-    Ok(())
+    if !has_unit || value != 0 {
+        return Err("duration must end with unit (d/h/m/s)".to_string());
+    }
+    Ok(total)
 }
 
-fn cmd_pipe(
+fn object_age_seconds(
     alias: &AliasConfig,
     bucket: &str,
     key: &str,
-    json: bool,
     debug: bool,
-) -> Result<(), String> {
-    let mut stdin_bytes = Vec::new();
-    std::io::stdin()
-        .read_to_end(&mut stdin_bytes)
+) -> Result<Option<u64>, S4Error> {
+    let headers = s3_request(
+        alias,
+        S3Call {
+            method: "HEAD",
+            bucket,
+            key: Some(key),
+            ..S3Call::default()
+        },
+        debug,
+    )?;
+    let mut last_modified: Option<String> = None;
+    for line in headers.lines() {
+        let lower = line.to_ascii_lowercase();
+        if lower.starts_with("last-modified:")
+            && let Some((_, value)) = line.split_once(':')
+        {
+            last_modified = Some(value.trim().to_string());
+            break;
+        }
+    }
+    let Some(last_modified) = last_modified else {
+        return Ok(None);
+    };
+    let mut cmd = Command::new("python3");
+    cmd.arg("-c")
+        .arg(
+            "import sys,time,email.utils; dt=email.utils.parsedate_to_datetime(sys.argv[1]); print(int(time.time()-dt.timestamp()))",
+        )
+        .arg(&last_modified);
+    let out = run_tool(&mut cmd)?;
+    if !out.status.success() {
+        return Err(format!(
+            "failed to parse Last-Modified header: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        )
+        .into());
+    }
+    let age = String::from_utf8_lossy(&out.stdout)
+        .trim()
+        .parse::<u64>()
         .map_err(|e| e.to_string())?;
+    Ok(Some(age))
+}
 
-    let ts = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map_err(|e| e.to_string())?
-        .as_nanos();
-    let temp_path = env::temp_dir().join(format!("s4-pipe-{}-{}", std::process::id(), ts));
-    fs::write(&temp_path, &stdin_bytes).map_err(|e| e.to_string())?;
-
-    let upload_result = upload_file_to_s3(alias, bucket, key, &temp_path, debug);
-    let _ = fs::remove_file(&temp_path);
-    upload_result?;
+fn watch_interval() -> Duration {
+    let seconds = env::var("S4_SYNC_WATCH_INTERVAL_SEC")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(2);
+    Duration::from_secs(seconds.max(1))
+}
 
-    if json {
-        println!(
-            "{{\"uploaded\":{{\"bucket\":\"{}\",\"key\":\"{}\",\"source\":\"stdin\"}}}}",
-            escape_json(bucket),
-            escape_json(key)
-        );
-    } else {
-        println!("Uploaded STDIN to '{}/{}'", bucket, key);
+/// Where `key` lands under the local directory `root`. Keys come from the server, so
+/// an absolute one or one that climbs out with `..` is refused instead of written
+/// outside `root`.
+fn local_entry_path(root: &Path, key: &str) -> Result<PathBuf, S4Error> {
+    let relative = Path::new(key);
+    let inside = relative.components().all(|c| {
+        matches!(
+            c,
+            std::path::Component::Normal(_) | std::path::Component::CurDir
+        )
+    });
+    if key.is_empty() || !inside {
+        return Err(S4Error::Other(format!(
+            "refusing to write key '{key}' outside '{}'",
+            root.display()
+        )));
     }
-    Ok(())
+    Ok(root.join(relative))
 }
 
-fn cmd_ls(alias: &AliasConfig, target: &S3Target, json: bool, debug: bool) -> Result<(), String> {
-    match &target.bucket {
-        None => {
-            let body = s3_request(alias, "GET", "", None, "", None, None, debug)?;
-            if json {
-                println!("{{\"xml\":\"{}\"}}", escape_json(&body));
-            } else {
-                println!("{body}");
-            }
+/// One side of `sync`/`mirror`: an S3 bucket/prefix or a local directory.
+enum SyncEndpoint {
+    S3 {
+        alias_name: String,
+        alias: AliasConfig,
+        bucket: String,
+        prefix: String,
+    },
+    Local(PathBuf),
+}
+
+impl SyncEndpoint {
+    /// Local paths are detected the way `cp` does it (see `classify_ref`).
+    fn resolve(config: &AppConfig, value: &str) -> Result<Self, S4Error> {
+        let explicit = value
+            .split('/')
+            .next()
+            .is_some_and(|a| config.aliases.contains_key(a));
+        if !explicit
+            && (value.starts_with('.') || value.starts_with('/') || Path::new(value).is_dir())
+        {
+            return Ok(Self::Local(PathBuf::from(value)));
         }
-        Some(bucket) => {
-            let body = s3_request(alias, "GET", bucket, None, "list-type=2", None, None, debug)?;
-            if json {
-                println!("{{\"xml\":\"{}\"}}", escape_json(&body));
-            } else {
-                println!("{body}");
-            }
+        let target = parse_target(value).map_err(S4Error::Usage)?;
+        let alias = config
+            .aliases
+            .get(&target.alias)
+            .ok_or_else(|| unknown_alias(&target.alias))?;
+        let bucket = req_bucket(&target, "sync").map_err(S4Error::Usage)?;
+        Ok(Self::S3 {
+            alias_name: target.alias.clone(),
+            alias: alias.clone(),
+            bucket,
+            prefix: target.key.unwrap_or_default(),
+        })
+    }
+
+    fn label(&self) -> String {
+        match self {
+            Self::S3 {
+                alias_name, bucket, ..
+            } => format!("{alias_name}/{bucket}"),
+            Self::Local(root) => root.display().to_string(),
         }
     }
-    Ok(())
-}
 
-fn list_object_keys(
-    alias: &AliasConfig,
-    bucket: &str,
-    prefix: &str,
-    debug: bool,
-) -> Result<Vec<String>, String> {
-    let mut keys = Vec::new();
-    let mut continuation: Option<String> = None;
+    fn prefix(&self) -> &str {
+        match self {
+            Self::S3 { prefix, .. } => prefix,
+            Self::Local(_) => "",
+        }
+    }
 
-    loop {
-        let mut query = String::from("list-type=2");
-        if !prefix.is_empty() {
-            query.push_str("&prefix=");
-            query.push_str(&uri_encode_path(prefix));
+    /// Fully qualified entry (`alias/bucket/key` or a path) for JSON output.
+    fn entry_target(&self, key: &str) -> String {
+        match self {
+            Self::S3 {
+                alias_name, bucket, ..
+            } => format!("{alias_name}/{bucket}/{key}"),
+            Self::Local(root) => root.join(key).display().to_string(),
         }
-        if let Some(token) = continuation.as_ref() {
-            query.push_str("&continuation-token=");
-            query.push_str(&uri_encode_path(token));
+    }
+
+    /// How one key on this side is shown in `--dry-run` output.
+    fn entry_label(&self, key: &str) -> String {
+        match self {
+            Self::S3 { bucket, .. } => format!("{bucket}/{key}"),
+            Self::Local(root) => root.join(key).display().to_string(),
         }
+    }
 
-        let body = s3_request(alias, "GET", bucket, None, &query, None, None, debug)?;
-        keys.extend(
-            extract_tag_values(&body, "Key")
+    /// Size and (for S3, when the ETag is a plain MD5) checksum of every entry.
+    fn fingerprints(
+        &self,
+        follow_symlinks: bool,
+        debug: bool,
+    ) -> Result<HashMap<String, TransferFingerprint>, S4Error> {
+        match self {
+            Self::S3 {
+                alias,
+                bucket,
+                prefix,
+                ..
+            } => Ok(list_objects(alias, bucket, prefix, false, debug)?
                 .into_iter()
-                .map(|k| xml_unescape(&k)),
-        );
+                .map(|o| {
+                    let md5 = etag_md5(&o.etag);
+                    (o.key, TransferFingerprint { size: o.size, md5 })
+                })
+                .collect()),
+            Self::Local(root) if !root.exists() => Ok(HashMap::new()),
+            Self::Local(root) => collect_local_files(root, follow_symlinks)?
+                .into_iter()
+                .map(|(path, relative)| {
+                    let size = fs::metadata(&path)?.len();
+                    Ok((relative, TransferFingerprint { size, md5: None }))
+                })
+                .collect(),
+        }
+    }
 
-        let is_truncated = extract_tag_values(&body, "IsTruncated")
-            .into_iter()
-            .next()
-            .unwrap_or_else(|| "false".to_string())
-            .trim()
-            .eq("true");
+    /// MD5 of a local entry; S3 entries already carry theirs in the ETag.
+    fn local_md5(&self, key: &str) -> Result<Option<String>, S4Error> {
+        match self {
+            Self::S3 { .. } => Ok(None),
+            Self::Local(root) => file_md5_hex(&root.join(key)).map(Some),
+        }
+    }
 
-        if is_truncated {
-            continuation = extract_tag_values(&body, "NextContinuationToken")
+    /// Keys (S3 keys, or `/`-joined paths relative to the directory) with their
+    /// modification time when known. `dir_markers` only applies to S3 listings and
+    /// `follow_symlinks` only to local ones.
+    fn list(
+        &self,
+        dir_markers: DirMarkers,
+        follow_symlinks: bool,
+        debug: bool,
+    ) -> Result<Vec<(String, Option<i64>)>, S4Error> {
+        match self {
+            Self::S3 {
+                alias,
+                bucket,
+                prefix,
+                ..
+            } => Ok(listed_modified_times(
+                list_objects(alias, bucket, prefix, false, debug)?,
+                dir_markers,
+            )),
+            Self::Local(root) if !root.exists() => Ok(Vec::new()),
+            Self::Local(root) => collect_local_files(root, follow_symlinks)?
                 .into_iter()
-                .next()
-                .map(|v| xml_unescape(&v));
-            if continuation.is_none() {
-                break;
-            }
-        } else {
-            break;
+                .map(|(path, relative)| {
+                    let modified = fs::metadata(&path)?
+                        .modified()
+                        .ok()
+                        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                        .map(|d| d.as_secs() as i64);
+                    Ok((relative, modified))
+                })
+                .collect(),
         }
     }
+}
 
-    Ok(keys)
+/// Keys with the modification instant from the listing's `<LastModified>`, so time
+/// filters need no HEAD per key; `None` where the server left the date out.
+fn listed_modified_times(
+    objects: Vec<ListedObject>,
+    dir_markers: DirMarkers,
+) -> Vec<(String, Option<i64>)> {
+    objects
+        .into_iter()
+        .filter(|o| dir_markers.keeps(o))
+        .map(|o| {
+            let modified = parse_rfc3339(&o.last_modified).ok();
+            (o.key, modified)
+        })
+        .collect()
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-struct ObjectVersion {
-    key: String,
-    version_id: String,
+/// What one sync pass did, or under `--dry-run` would do.
+#[derive(Debug, Default)]
+struct SyncPass {
+    copied: usize,
+    removed: usize,
+    /// Keys left untouched because `--deadline` passed mid-run.
+    remaining: usize,
+    /// Keys not copied again because `--journal` recorded them in an earlier run.
+    resumed: usize,
+    /// Bytes actually transferred (zero under `--dry-run`).
+    bytes: u64,
+    /// `--verify`: copied objects whose copy matched, and the keys that did not.
+    verified: usize,
+    mismatched: Vec<String>,
+    /// Planned actions, collected only for `--json --dry-run`.
+    planned: Vec<SyncAction>,
 }
 
-fn list_object_versions(
-    alias: &AliasConfig,
-    bucket: &str,
-    debug: bool,
-) -> Result<Vec<ObjectVersion>, String> {
-    let mut versions = Vec::new();
-    let mut key_marker: Option<String> = None;
-    let mut version_id_marker: Option<String> = None;
+#[derive(Debug)]
+struct SyncAction {
+    op: &'static str,
+    src: Option<String>,
+    dst: String,
+}
 
-    loop {
-        let mut query = String::from("versions=");
-        if let Some(marker) = key_marker.as_ref() {
-            query.push_str("&key-marker=");
-            query.push_str(&uri_encode_query_component(marker));
-        }
-        if let Some(marker) = version_id_marker.as_ref() {
-            query.push_str("&version-id-marker=");
-            query.push_str(&uri_encode_query_component(marker));
-        }
+/// Seconds since `modified`; timestamps in the future count as age zero.
+fn object_age_secs(modified: i64, now: i64) -> u64 {
+    now.saturating_sub(modified).max(0) as u64
+}
 
-        let body = s3_request(alias, "GET", bucket, None, &query, None, None, debug)?;
-        versions.extend(extract_version_entries(&body, "Version"));
-        versions.extend(extract_version_entries(&body, "DeleteMarker"));
+/// Relative (`--newer-than`/`--older-than`) and absolute (`--modified-*`) filters
+/// against an entry's modification instant.
+fn sync_time_filter_matches(options: &SyncOptions, modified: i64, now: i64) -> bool {
+    let age = object_age_secs(modified, now);
+    options.newer_than.is_none_or(|limit| age <= limit)
+        && options.older_than.is_none_or(|limit| age >= limit)
+        && options.modified_after.is_none_or(|t| modified > t)
+        && options.modified_before.is_none_or(|t| modified < t)
+}
 
-        let is_truncated = extract_tag_values(&body, "IsTruncated")
-            .into_iter()
-            .next()
-            .unwrap_or_else(|| "false".to_string())
-            .trim()
-            .eq("true");
+fn cmd_sync_once(
+    source: &SyncEndpoint,
+    destination: &SyncEndpoint,
+    options: &SyncOptions,
+    deadline: Option<Instant>,
+    json: bool,
+    debug: bool,
+) -> Result<SyncPass, S4Error> {
+    let src_prefix = source.prefix().to_string();
+    let dst_prefix = destination.prefix().to_string();
 
-        if !is_truncated {
-            break;
+    // A `dir/` marker cannot become a local file, so downloads never copy them.
+    let dir_markers = if options.skip_dir_markers || matches!(destination, SyncEndpoint::Local(_)) {
+        DirMarkers::Skip
+    } else {
+        DirMarkers::Keep
+    };
+    let listed = source.list(dir_markers, options.follow_symlinks, debug)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| e.to_string())?
+        .as_secs() as i64;
+    let mut filtered_keys: Vec<String> = Vec::new();
+    for (key, modified) in listed {
+        if is_excluded(&key, &options.excludes) {
+            continue;
         }
-
-        key_marker = extract_tag_values(&body, "NextKeyMarker")
-            .into_iter()
-            .next()
-            .map(|v| xml_unescape(&v));
-        version_id_marker = extract_tag_values(&body, "NextVersionIdMarker")
-            .into_iter()
-            .next()
-            .map(|v| xml_unescape(&v));
-
-        if key_marker.is_none() {
-            break;
+        if options.newer_than.is_some()
+            || options.older_than.is_some()
+            || options.modified_after.is_some()
+            || options.modified_before.is_some()
+        {
+            // The listing already carries LastModified; HEAD only when it is missing.
+            let modified = match (modified, source) {
+                (Some(modified), _) => Some(modified),
+                (None, SyncEndpoint::S3 { alias, bucket, .. }) => {
+                    object_age_seconds(alias, bucket, &key, debug)?.map(|age| now - age as i64)
+                }
+                (None, SyncEndpoint::Local(_)) => None,
+            };
+            let Some(modified) = modified else {
+                continue;
+            };
+            if !sync_time_filter_matches(options, modified, now) {
+                continue;
+            }
         }
+        filtered_keys.push(key);
     }
 
-    Ok(versions)
-}
-
-fn extract_tag_blocks(xml: &str, tag: &str) -> Vec<String> {
-    let open = format!("<{tag}>");
-    let close = format!("</{tag}>");
-
-    let mut out = Vec::new();
-    let mut remaining = xml;
+    let mut pass = SyncPass::default();
+    let journal_header = format!(
+        "# s4 sync journal {} -> {}",
+        source.entry_target(&src_prefix),
+        destination.entry_target(&dst_prefix)
+    );
 
-    while let Some(start) = remaining.find(&open) {
-        let after_open = &remaining[start + open.len()..];
-        let Some(end) = after_open.find(&close) else {
-            break;
+    if options.dry_run {
+        let done = match &options.journal {
+            Some(path) if path.exists() => SyncJournal::load(path, &journal_header)?,
+            _ => HashSet::new(),
         };
-        out.push(after_open[..end].to_string());
-        remaining = &after_open[end + close.len()..];
-    }
+        for key in &filtered_keys {
+            if done.contains(key) {
+                pass.resumed += 1;
+                continue;
+            }
+            let dest_key = sync_destination_key(key, &src_prefix, &dst_prefix);
+            if json {
+                pass.planned.push(SyncAction {
+                    op: "copy",
+                    src: Some(source.entry_target(key)),
+                    dst: destination.entry_target(&dest_key),
+                });
+            } else {
+                println!(
+                    "[dry-run] copy {} -> {}",
+                    source.entry_label(key),
+                    destination.entry_label(&dest_key)
+                );
+            }
+            pass.copied += 1;
+        }
+    } else {
+        let mut journal = match &options.journal {
+            Some(path) => Some(SyncJournal::open(path, &journal_header)?),
+            None => None,
+        };
+        let temp_root = temp_path(format!("s4-sync-{}", std::process::id()));
+        fs::create_dir_all(&temp_root).map_err(|e| e.to_string())?;
 
-    out
-}
+        for (idx, key) in filtered_keys.iter().enumerate() {
+            if deadline.is_some_and(|d| Instant::now() >= d) {
+                pass.remaining = filtered_keys.len() - idx;
+                break;
+            }
+            if journal.as_ref().is_some_and(|j| j.done.contains(key)) {
+                pass.resumed += 1;
+                continue;
+            }
+            let dest_key = sync_destination_key(key, &src_prefix, &dst_prefix);
+            let temp_file = temp_root.join(format!("obj-{idx}"));
+            pass.bytes += sync_transfer(source, destination, key, &dest_key, &temp_file, debug)?;
+            pass.copied += 1;
+            if let Some(journal) = &mut journal {
+                journal.record(key)?;
+            }
+        }
 
-fn extract_version_entries(xml: &str, tag: &str) -> Vec<ObjectVersion> {
-    let mut out = Vec::new();
-    for block in extract_tag_blocks(xml, tag) {
-        let key = extract_tag_values(&block, "Key")
-            .into_iter()
-            .next()
-            .map(|v| xml_unescape(&v));
-        let version_id = extract_tag_values(&block, "VersionId")
-            .into_iter()
-            .next()
-            .map(|v| xml_unescape(&v));
-        if let (Some(key), Some(version_id)) = (key, version_id) {
-            out.push(ObjectVersion { key, version_id });
+        fs::remove_dir_all(&temp_root).ok();
+        if pass.remaining > 0 {
+            return Ok(pass);
+        }
+        // Every key is on the destination now; the next run starts from scratch.
+        if let Some(journal) = journal {
+            journal.finish();
+        }
+        if options.verify {
+            verify_sync_pass(
+                source,
+                destination,
+                &filtered_keys,
+                options.follow_symlinks,
+                &mut pass,
+                debug,
+            )?;
         }
     }
-    out
-}
 
-fn purge_bucket_versions(alias: &AliasConfig, bucket: &str, debug: bool) -> Result<(), String> {
-    for entry in list_object_versions(alias, bucket, debug)? {
-        let query = format!(
-            "versionId={}",
-            uri_encode_query_component(&entry.version_id)
+    // An empty source usually means a wrong prefix or an over-broad --exclude;
+    // mirroring it with --remove would wipe the destination.
+    if options.remove && filtered_keys.is_empty() && !options.allow_empty_source {
+        eprintln!(
+            "warning: source {} has no matching entries; skipping --remove (use --allow-empty-source to delete anyway)",
+            source.label()
         );
-        match s3_request(
-            alias,
-            "DELETE",
-            bucket,
-            Some(&entry.key),
-            &query,
-            None,
-            None,
-            debug,
-        ) {
-            Ok(_) => {}
-            Err(err) if should_retry_with_governance_bypass(&err) => {
-                let headers = vec!["x-amz-bypass-governance-retention: true".to_string()];
-                s3_request_with_headers(
-                    alias,
-                    "DELETE",
-                    bucket,
-                    Some(&entry.key),
-                    &query,
-                    None,
-                    None,
-                    &headers,
-                    debug,
-                )?;
+    } else if options.remove {
+        // Skipped markers on the destination are left alone rather than removed.
+        let dst_keys: Vec<String> = destination
+            .list(dir_markers, options.follow_symlinks, debug)?
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        let expected: HashSet<String> = filtered_keys
+            .iter()
+            .map(|k| sync_destination_key(k, &src_prefix, &dst_prefix))
+            .collect();
+        for key in dst_keys {
+            if !expected.contains(&key) {
+                if options.dry_run {
+                    if json {
+                        pass.planned.push(SyncAction {
+                            op: "remove",
+                            src: None,
+                            dst: destination.entry_target(&key),
+                        });
+                    } else {
+                        println!("[dry-run] remove {}", destination.entry_label(&key));
+                    }
+                } else {
+                    match destination {
+                        SyncEndpoint::S3 { alias, bucket, .. } => {
+                            s3_request(
+                                alias,
+                                S3Call {
+                                    method: "DELETE",
+                                    bucket,
+                                    key: Some(&key),
+                                    ..S3Call::default()
+                                },
+                                debug,
+                            )?;
+                        }
+                        SyncEndpoint::Local(root) => {
+                            fs::remove_file(local_entry_path(root, &key)?)?
+                        }
+                    }
+                }
+                pass.removed += 1;
             }
-            Err(err) => return Err(err),
         }
     }
-    Ok(())
+
+    Ok(pass)
 }
 
-fn sync_destination_key(source_key: &str, src_prefix: &str, dst_prefix: &str) -> String {
-    let normalized_src = src_prefix.trim_matches('/');
-    let mut relative = source_key.to_string();
+/// Totals for `sync --metrics-file`, accumulated over every pass of the run (one pass
+/// unless `--watch`), in node_exporter's textfile format.
+struct SyncMetrics {
+    started: Instant,
+    src: String,
+    dst: String,
+    copied: usize,
+    removed: usize,
+    bytes: u64,
+    errors: usize,
+}
 
-    if !normalized_src.is_empty() {
-        if source_key == normalized_src {
-            relative.clear();
-        } else if let Some(rest) = source_key.strip_prefix(&(normalized_src.to_string() + "/")) {
-            relative = rest.to_string();
+impl SyncMetrics {
+    fn start(source: &SyncEndpoint, destination: &SyncEndpoint) -> Self {
+        Self {
+            started: Instant::now(),
+            src: source.label(),
+            dst: destination.label(),
+            copied: 0,
+            removed: 0,
+            bytes: 0,
+            errors: 0,
         }
     }
 
-    let normalized_dst = dst_prefix.trim_matches('/');
-    if normalized_dst.is_empty() {
-        return relative;
+    /// A failed pass, a pass cut short by `--deadline` and every `--verify` mismatch
+    /// count as errors.
+    fn record(&mut self, pass: &Result<SyncPass, S4Error>) {
+        match pass {
+            Ok(pass) => {
+                self.copied += pass.copied;
+                self.removed += pass.removed;
+                self.bytes += pass.bytes;
+                self.errors += pass.mismatched.len() + usize::from(pass.remaining > 0);
+            }
+            Err(_) => self.errors += 1,
+        }
     }
-    if relative.is_empty() {
-        return normalized_dst.to_string();
+
+    fn render(&self) -> String {
+        let labels = format!(
+            "{{src=\"{}\",dst=\"{}\"}}",
+            prometheus_label(&self.src),
+            prometheus_label(&self.dst)
+        );
+        let metrics = [
+            (
+                "s4_sync_objects_copied",
+                "gauge",
+                "Objects copied by the s4 sync run.",
+                self.copied.to_string(),
+            ),
+            (
+                "s4_sync_objects_removed",
+                "gauge",
+                "Objects removed by the s4 sync run (--remove).",
+                self.removed.to_string(),
+            ),
+            (
+                "s4_sync_bytes_total",
+                "counter",
+                "Bytes transferred by the s4 sync run.",
+                self.bytes.to_string(),
+            ),
+            (
+                "s4_sync_duration_seconds",
+                "gauge",
+                "Wall-clock duration of the s4 sync run.",
+                format!("{:.3}", self.started.elapsed().as_secs_f64()),
+            ),
+            (
+                "s4_sync_errors_total",
+                "counter",
+                "Failed passes, deadline aborts and verify mismatches in the s4 sync run.",
+                self.errors.to_string(),
+            ),
+        ];
+        metrics
+            .iter()
+            .map(|(name, kind, help, value)| {
+                format!("# HELP {name} {help}\n# TYPE {name} {kind}\n{name}{labels} {value}\n")
+            })
+            .collect()
     }
 
-    format!("{normalized_dst}/{relative}")
+    /// Written to a sibling temp file and renamed over `path`, so the collector never
+    /// reads half a file. A failure only warns: the sync result matters more.
+    fn write(&self, path: &Path) {
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(format!(".{}.tmp", std::process::id()));
+        let temp = PathBuf::from(temp);
+        let written = fs::write(&temp, self.render()).and_then(|_| fs::rename(&temp, path));
+        if let Err(e) = written {
+            let _ = fs::remove_file(&temp);
+            eprintln!(
+                "warning: could not write metrics file {}: {e}",
+                path.display()
+            );
+        }
+    }
 }
 
-fn extract_tag_values(xml: &str, tag: &str) -> Vec<String> {
-    let open = format!("<{tag}>");
-    let close = format!("</{tag}>");
+/// `sync --journal`: the source keys a sync has copied, one per line after a header
+/// naming both sides. Each key is appended as soon as its copy finished, so after a
+/// crash or `--deadline` a re-run with the same journal skips what is already done.
+struct SyncJournal {
+    path: PathBuf,
+    done: HashSet<String>,
+    file: fs::File,
+}
 
-    let mut out = Vec::new();
-    let mut remaining = xml;
+impl SyncJournal {
+    /// Keys recorded in an existing journal. One written for another source or
+    /// destination is refused rather than silently skipping unrelated keys.
+    fn load(path: &Path, header: &str) -> Result<HashSet<String>, S4Error> {
+        let text = fs::read_to_string(path)?;
+        parse_sync_journal(&text, header)
+            .map_err(|e| S4Error::Usage(format!("journal {}: {e}", path.display())))
+    }
 
-    while let Some(start) = remaining.find(&open) {
-        let after_open = &remaining[start + open.len()..];
-        let Some(end) = after_open.find(&close) else {
-            break;
+    fn open(path: &Path, header: &str) -> Result<Self, S4Error> {
+        let existing = path.metadata().is_ok_and(|m| m.len() > 0);
+        let done = if existing {
+            Self::load(path, header)?
+        } else {
+            HashSet::new()
         };
-        out.push(after_open[..end].to_string());
-        remaining = &after_open[end + close.len()..];
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        if !existing {
+            file.write_all(format!("{header}\n").as_bytes())?;
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            done,
+            file,
+        })
     }
 
-    out
+    /// One `write` per key: a crash can cut off at most the last, unterminated line,
+    /// which `parse_sync_journal` ignores.
+    fn record(&mut self, key: &str) -> Result<(), S4Error> {
+        self.file
+            .write_all(format!("{}\n", encode_journal_key(key)).as_bytes())?;
+        Ok(())
+    }
+
+    fn finish(self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            eprintln!(
+                "warning: could not remove finished journal {}: {e}",
+                self.path.display()
+            );
+        }
+    }
 }
 
-fn xml_unescape(s: &str) -> String {
-    s.replace("&amp;", "&")
-        .replace("&lt;", "<")
-        .replace("&gt;", ">")
-        .replace("&quot;", "\"")
-        .replace("&apos;", "'")
+fn parse_sync_journal(text: &str, header: &str) -> Result<HashSet<String>, String> {
+    let mut lines = text.split_inclusive('\n');
+    match lines.next() {
+        Some(first) if first.trim_end() == header => {}
+        Some(first) => {
+            return Err(format!(
+                "written for a different sync ('{}'); remove it or pass another --journal",
+                first.trim_end().trim_start_matches("# s4 sync journal ")
+            ));
+        }
+        None => return Ok(HashSet::new()),
+    }
+    Ok(lines
+        .filter_map(|line| line.strip_suffix('\n'))
+        .map(decode_journal_key)
+        .collect())
 }
 
-fn should_retry_with_governance_bypass(err: &str) -> bool {
-    let lower = err.to_ascii_lowercase();
-    lower.contains("accessdenied")
-        || lower.contains("retention")
-        || lower.contains("governance")
-        || (lower.contains("invalidrequest") && lower.contains("worm"))
-        || lower.contains("worm protected")
+/// Keys may contain anything, so `%` and line breaks are percent-encoded.
+fn encode_journal_key(key: &str) -> String {
+    key.replace('%', "%25")
+        .replace('\n', "%0A")
+        .replace('\r', "%0D")
 }
 
-fn req_bucket(target: &S3Target, cmd: &str) -> Result<String, String> {
-    target
-        .bucket
-        .clone()
-        .ok_or_else(|| format!("{cmd} requires alias/bucket"))
+fn decode_journal_key(line: &str) -> String {
+    line.replace("%0D", "\r")
+        .replace("%0A", "\n")
+        .replace("%25", "%")
 }
 
-fn req_key(target: &S3Target, cmd: &str) -> Result<String, String> {
-    target
-        .key
-        .clone()
-        .ok_or_else(|| format!("{cmd} requires alias/bucket/key"))
+/// Escapes a Prometheus label value (backslash, double quote and newline).
+fn prometheus_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
 }
 
-fn normalize_sigv4_query(query: &str) -> String {
-    if query.is_empty() {
-        return String::new();
+/// `sync --verify`: re-lists both sides and checks every copied key landed with the
+/// same size and, where both sides have a plain MD5 ETag, the same ETag. A local file
+/// is hashed only when the S3 side has an MD5 to compare against; multipart ETags
+/// fall back to the size.
+fn verify_sync_pass(
+    source: &SyncEndpoint,
+    destination: &SyncEndpoint,
+    keys: &[String],
+    follow_symlinks: bool,
+    pass: &mut SyncPass,
+    debug: bool,
+) -> Result<(), S4Error> {
+    let src_listing = source.fingerprints(follow_symlinks, debug)?;
+    let dst_listing = destination.fingerprints(follow_symlinks, debug)?;
+    for key in keys {
+        let dest_key = sync_destination_key(key, source.prefix(), destination.prefix());
+        let (Some(src), Some(dst)) = (src_listing.get(key), dst_listing.get(&dest_key)) else {
+            pass.mismatched.push(destination.entry_target(&dest_key));
+            continue;
+        };
+        let (mut src, mut dst) = (src.clone(), dst.clone());
+        if src.md5.is_none() && dst.md5.is_some() {
+            src.md5 = source.local_md5(key)?;
+        }
+        if dst.md5.is_none() && src.md5.is_some() {
+            dst.md5 = destination.local_md5(&dest_key)?;
+        }
+        if src.matches(&dst) {
+            pass.verified += 1;
+        } else {
+            pass.mismatched.push(destination.entry_target(&dest_key));
+        }
     }
-    query
-        .split('&')
-        .map(|part| {
-            if part.is_empty() {
-                String::new()
-            } else if part.contains('=') {
-                part.to_string()
-            } else {
-                format!("{}=", part)
-            }
+    Ok(())
+}
+
+/// JSON `actions` array for a `--json --dry-run` sync pass.
+fn sync_actions_json(actions: &[SyncAction]) -> String {
+    let items: Vec<String> = actions
+        .iter()
+        .map(|a| {
+            let src = a
+                .src
+                .as_ref()
+                .map(|src| format!(",\"src\":\"{}\"", escape_json(src)))
+                .unwrap_or_default();
+            format!(
+                "{{\"op\":\"{}\"{},\"dst\":\"{}\"}}",
+                a.op,
+                src,
+                escape_json(&a.dst)
+            )
         })
-        .collect::<Vec<_>>()
-        .join("&")
+        .collect();
+    format!("[{}]", items.join(","))
 }
 
-fn s3_request(
-    alias: &AliasConfig,
-    method: &str,
-    bucket: &str,
-    key: Option<&str>,
-    query: &str,
-    upload_file: Option<&Path>,
-    output_file: Option<&Path>,
+/// Copies one entry and returns its size; S3 to S3 goes through `temp_file`, the
+/// other pairs stream directly.
+fn sync_transfer(
+    source: &SyncEndpoint,
+    destination: &SyncEndpoint,
+    key: &str,
+    dest_key: &str,
+    temp_file: &Path,
     debug: bool,
-) -> Result<String, String> {
-    s3_request_with_headers(
-        alias,
-        method,
-        bucket,
-        key,
-        query,
-        upload_file,
-        output_file,
-        &[],
-        debug,
-    )
-}
-
-fn normalize_resolve_entry(entry: &str) -> String {
-    if entry.contains('=') {
-        entry.replacen('=', ":", 1)
-    } else {
-        entry.to_string()
+) -> Result<u64, S4Error> {
+    match (source, destination) {
+        (
+            SyncEndpoint::S3 {
+                alias: src_alias,
+                bucket: src_bucket,
+                ..
+            },
+            SyncEndpoint::S3 { alias, bucket, .. },
+        ) => {
+            s3_request(
+                src_alias,
+                S3Call {
+                    method: "GET",
+                    bucket: src_bucket,
+                    key: Some(key),
+                    output_file: Some(temp_file),
+                    ..S3Call::default()
+                },
+                debug,
+            )?;
+            upload_file_to_s3(
+                alias,
+                bucket,
+                dest_key,
+                temp_file,
+                &UploadOptions::default(),
+                debug,
+            )?;
+            Ok(fs::metadata(temp_file)?.len())
+        }
+        (SyncEndpoint::Local(src_root), SyncEndpoint::S3 { alias, bucket, .. }) => {
+            let path = src_root.join(key);
+            upload_file_to_s3(
+                alias,
+                bucket,
+                dest_key,
+                &path,
+                &UploadOptions::default(),
+                debug,
+            )?;
+            Ok(fs::metadata(&path)?.len())
+        }
+        (SyncEndpoint::S3 { alias, bucket, .. }, SyncEndpoint::Local(root)) => {
+            let out = local_entry_path(root, dest_key)?;
+            if let Some(parent) = out.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut partial = out.as_os_str().to_owned();
+            partial.push(".s4part");
+            let partial = PathBuf::from(partial);
+            track_temp(&partial);
+            if let Err(e) = s3_request(
+                alias,
+                S3Call {
+                    method: "GET",
+                    bucket,
+                    key: Some(key),
+                    output_file: Some(&partial),
+                    ..S3Call::default()
+                },
+                debug,
+            ) {
+                remove_temp(&partial);
+                return Err(e);
+            }
+            fs::rename(&partial, &out)?;
+            untrack_temp(&partial);
+            Ok(fs::metadata(&out)?.len())
+        }
+        (SyncEndpoint::Local(src_root), SyncEndpoint::Local(root)) => {
+            let out = local_entry_path(root, dest_key)?;
+            if let Some(parent) = out.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            Ok(fs::copy(src_root.join(key), &out)?)
+        }
     }
 }
 
-fn apply_curl_global_flags(cmd: &mut Command, is_upload: bool, is_download: bool) {
-    if CURL_INSECURE.load(Ordering::Relaxed) {
-        cmd.arg("-k");
+fn cmd_sync(
+    config: &AppConfig,
+    source: &str,
+    destination: &str,
+    options: &SyncOptions,
+    json: bool,
+    debug: bool,
+) -> Result<(), S4Error> {
+    let source = SyncEndpoint::resolve(config, source)?;
+    let destination = SyncEndpoint::resolve(config, destination)?;
+    if let SyncEndpoint::Local(root) = &source
+        && !root.is_dir()
+    {
+        return Err(format!("source directory not found: {}", root.display()).into());
     }
-    if let Ok(opts) = curl_global_opts().lock() {
-        for resolve in &opts.resolve {
-            cmd.arg("--resolve").arg(normalize_resolve_entry(resolve));
+
+    let deadline = options
+        .deadline
+        .map(|secs| Instant::now() + Duration::from_secs(secs));
+    let mut metrics = SyncMetrics::start(&source, &destination);
+    loop {
+        let started = Instant::now();
+        let pass = cmd_sync_once(&source, &destination, options, deadline, json, debug);
+        metrics.record(&pass);
+        if let Some(path) = &options.metrics_file {
+            metrics.write(path);
+        }
+        let SyncPass {
+            copied,
+            removed,
+            remaining,
+            resumed,
+            bytes,
+            verified,
+            mismatched,
+            planned,
+        } = pass?;
+        let summary = TransferSummary {
+            started,
+            objects: copied,
+            bytes,
+        };
+
+        if remaining > 0 {
+            let secs = options.deadline.unwrap_or_default();
+            if json {
+                println!(
+                    "{{\"status\":\"deadline_exceeded\",\"copied\":{},\"remaining\":{},\"deadline_seconds\":{},\"src\":\"{}\",\"dst\":\"{}\"}}",
+                    copied,
+                    remaining,
+                    secs,
+                    escape_json(&source.label()),
+                    escape_json(&destination.label())
+                );
+            } else {
+                eprintln!(
+                    "Sync deadline of {}s reached: copied {} object(s), {} not processed",
+                    secs, copied, remaining
+                );
+            }
+            // Under --watch the deadline is how the run is meant to end, not a failure.
+            if options.watch {
+                break;
+            }
+            return Err(S4Error::Other(format!(
+                "sync aborted after --deadline {secs}s"
+            )));
         }
-        if is_upload {
-            if let Some(limit_upload) = &opts.limit_upload {
-                cmd.arg("--limit-rate").arg(limit_upload);
+
+        if json {
+            let mut actions = if options.dry_run {
+                format!(",\"actions\":{}", sync_actions_json(&planned))
+            } else {
+                format!(",\"summary\":{}", summary.json())
+            };
+            if options.journal.is_some() {
+                actions.push_str(&format!(",\"resumed\":{resumed}"));
+            }
+            if options.verify && !options.dry_run {
+                let keys: Vec<String> = mismatched
+                    .iter()
+                    .map(|k| format!("\"{}\"", escape_json(k)))
+                    .collect();
+                actions.push_str(&format!(
+                    ",\"verify\":{{\"verified\":{},\"mismatched\":[{}]}}",
+                    verified,
+                    keys.join(",")
+                ));
+            }
+            println!(
+                "{{\"status\":\"ok\",\"copied\":{},\"removed\":{},\"dry_run\":{},\"watch\":{},\"src\":\"{}\",\"dst\":\"{}\"{}}}",
+                copied,
+                removed,
+                options.dry_run,
+                options.watch,
+                escape_json(&source.label()),
+                escape_json(&destination.label()),
+                actions
+            );
+        } else if !is_quiet() {
+            println!(
+                "Synced {} object(s) from {} to {} (removed: {}, dry-run: {}, watch: {})",
+                copied,
+                source.label(),
+                destination.label(),
+                removed,
+                options.dry_run,
+                options.watch
+            );
+            if resumed > 0 {
+                println!("Skipped {resumed} object(s) already copied according to the journal");
+            }
+            if !options.dry_run {
+                println!("{}", summary.line());
+            }
+            if options.verify && !options.dry_run {
+                println!(
+                    "Verified {} of {} copied object(s)",
+                    verified,
+                    verified + mismatched.len()
+                );
             }
-        } else if is_download {
-            if let Some(limit_download) = &opts.limit_download {
-                cmd.arg("--limit-rate").arg(limit_download);
+        }
+        if !mismatched.is_empty() {
+            for key in &mismatched {
+                eprintln!("verify: {} does not match its source", key);
             }
+            return Err(S4Error::Other(format!(
+                "sync --verify: {} copied object(s) did not match",
+                mismatched.len()
+            )));
         }
-        for header in &opts.custom_headers {
-            cmd.arg("-H").arg(header);
+
+        if !options.watch {
+            break;
+        }
+        // Under --watch the deadline just ends the loop once the current pass is done.
+        if deadline.is_some_and(|d| Instant::now() + watch_interval() >= d) {
+            break;
         }
+        sleep(watch_interval());
     }
+
+    Ok(())
 }
 
-fn s3_request_with_headers(
+/// `get` (with a destination) and `cat` (to stdout). Downloads land in a sibling
+/// `.s4part` file first so a 304 or an error never clobbers an existing copy.
+fn cmd_get(
     alias: &AliasConfig,
-    method: &str,
-    bucket: &str,
-    key: Option<&str>,
-    query: &str,
-    upload_file: Option<&Path>,
-    output_file: Option<&Path>,
-    extra_headers: &[String],
+    target: &S3Target,
+    destination: Option<&Path>,
+    opts: &GetOptions,
+    json: bool,
     debug: bool,
-) -> Result<String, String> {
-    let endpoint = parse_endpoint(&alias.endpoint)?;
-    let mut uri_path = endpoint.base_path.clone();
+) -> Result<(), S4Error> {
+    let command = if destination.is_some() { "get" } else { "cat" };
+    let bucket = req_bucket(target, command).map_err(S4Error::Usage)?;
+    let key = req_key(target, command).map_err(S4Error::Usage)?;
+
+    let headers = get_request_headers(alias, &bucket, &key, opts, debug);
+    let Some(destination) = destination else {
+        return cat_object(alias, &bucket, &key, &headers, opts, debug);
+    };
 
-    if alias.path_style {
-        if !bucket.is_empty() {
-            uri_path.push('/');
-            uri_path.push_str(&uri_encode_segment(bucket));
-        }
-        if let Some(k) = key {
-            uri_path.push('/');
-            uri_path.push_str(&uri_encode_path(k));
-        }
+    if download_to_file(alias, &bucket, &key, destination, &headers, opts, debug)? {
+        print_downloaded(&bucket, &key, destination, json);
     } else {
-        return Err("only --path-style aliases are supported in this build".to_string());
+        print_not_modified(&bucket, &key, destination, json);
     }
+    Ok(())
+}
 
-    if uri_path.is_empty() {
-        uri_path = "/".to_string();
+/// Last path segment of a key, used as the file name when downloading into a directory.
+fn key_basename(key: &str) -> Result<&str, S4Error> {
+    match key.rsplit('/').next() {
+        Some(name) if !name.is_empty() && name != "." && name != ".." => Ok(name),
+        _ => Err(S4Error::Usage(format!(
+            "cannot derive a file name from key '{key}'"
+        ))),
     }
+}
 
-    let canonical_query = normalize_sigv4_query(query);
-    let payload_hash = payload_hash(upload_file)?;
-    let sign = sign_v4(
-        method,
-        &uri_path,
-        &canonical_query,
-        &endpoint.host,
-        &alias.region,
-        &alias.access_key,
-        &alias.secret_key,
-        &payload_hash,
-    )?;
-
-    let mut url = format!("{}://{}{}", endpoint.scheme, endpoint.host, uri_path);
-    if !query.is_empty() {
-        url.push('?');
-        url.push_str(query);
+/// `get SRC1 SRC2... DIR`: each object lands in `DIR/<basename>`, downloaded in
+/// parallel up to `--max-concurrent`. Two sources with the same basename are refused
+/// up front rather than overwriting each other.
+fn cmd_get_many(
+    config: &AppConfig,
+    targets: &[S3Target],
+    dir: &Path,
+    opts: &GetOptions,
+    json: bool,
+    debug: bool,
+) -> Result<(), S4Error> {
+    if !dir.is_dir() {
+        return Err(S4Error::Usage(format!(
+            "destination '{}' is not a directory (required with several sources)",
+            dir.display()
+        )));
     }
-
-    let mut cmd = Command::new("curl");
-    apply_curl_global_flags(&mut cmd, upload_file.is_some(), output_file.is_some());
-    cmd.arg("-sS").arg(&url);
-    if method != "HEAD" {
-        cmd.arg("-X").arg(method);
+    let mut jobs = Vec::with_capacity(targets.len());
+    let mut names = HashSet::new();
+    for target in targets {
+        let alias = config
+            .aliases
+            .get(&target.alias)
+            .ok_or_else(|| unknown_alias(&target.alias))?;
+        let bucket = req_bucket(target, "get").map_err(S4Error::Usage)?;
+        let key = req_key(target, "get").map_err(S4Error::Usage)?;
+        let name = key_basename(&key)?.to_string();
+        if !names.insert(name.clone()) {
+            return Err(S4Error::Usage(format!(
+                "several sources would be saved as '{}'",
+                dir.join(&name).display()
+            )));
+        }
+        jobs.push((target.alias.as_str(), alias, bucket, key, dir.join(name)));
     }
-    cmd.arg("-H")
-        .arg(format!("Host: {}", endpoint.host))
-        .arg("-H")
-        .arg(format!("x-amz-date: {}", sign.amz_date))
-        .arg("-H")
-        .arg(format!("x-amz-content-sha256: {}", payload_hash))
-        .arg("-H")
-        .arg(format!("Authorization: {}", sign.authorization));
 
-    for header in extra_headers {
-        cmd.arg("-H").arg(header);
+    let mut summary = TransferSummary::start();
+    let (mut downloaded, mut not_modified) = (Vec::new(), Vec::new());
+    run_ordered(
+        jobs.len(),
+        MAX_CONCURRENT.load(Ordering::Relaxed),
+        |idx| {
+            let (alias_name, alias, bucket, key, destination) = &jobs[idx];
+            let headers = get_request_headers(alias, bucket, key, opts, debug);
+            download_to_file(alias, bucket, key, destination, &headers, opts, debug)
+                .map_err(|e| not_found_error(e, alias_name, bucket, key))
+        },
+        |idx, fetched| {
+            let (_, _, bucket, key, destination) = &jobs[idx];
+            if fetched {
+                summary.add(fs::metadata(destination)?.len());
+            }
+            if !json {
+                if fetched {
+                    print_downloaded(bucket, key, destination, false);
+                } else {
+                    print_not_modified(bucket, key, destination, false);
+                }
+            }
+            let entry = format!(
+                "{{\"bucket\":\"{}\",\"key\":\"{}\",\"to\":\"{}\"}}",
+                escape_json(bucket),
+                escape_json(key),
+                escape_json(&destination.display().to_string())
+            );
+            if fetched {
+                downloaded.push(entry);
+            } else {
+                not_modified.push(entry);
+            }
+            Ok(())
+        },
+    )?;
+    if json {
+        println!(
+            "{{\"downloaded\":[{}],\"not_modified\":[{}],\"summary\":{}}}",
+            downloaded.join(","),
+            not_modified.join(","),
+            summary.json()
+        );
+    } else if !is_quiet() {
+        println!("{}", summary.line());
     }
+    Ok(())
+}
 
-    if let Some(file) = upload_file {
-        cmd.arg("--data-binary").arg(format!("@{}", file.display()));
+fn print_downloaded(bucket: &str, key: &str, destination: &Path, json: bool) {
+    if json {
+        println!(
+            "{{\"downloaded\":{{\"bucket\":\"{}\",\"key\":\"{}\",\"to\":\"{}\"}}}}",
+            escape_json(bucket),
+            escape_json(key),
+            escape_json(&destination.display().to_string())
+        );
+    } else if !is_quiet() {
+        println!(
+            "Downloaded '{}/{}' to '{}'",
+            bucket,
+            key,
+            destination.display()
+        );
     }
+}
 
-    if method == "HEAD" {
-        // Use curl native HEAD mode instead of `-X HEAD` + body suppression.
-        // This avoids curl(18) "transfer closed with bytes remaining" on servers
-        // that return Content-Length for HEAD responses.
-        cmd.arg("-I");
-    } else if let Some(out) = output_file {
-        cmd.arg("-o").arg(out);
+fn print_not_modified(bucket: &str, key: &str, destination: &Path, json: bool) {
+    if json {
+        println!(
+            "{{\"not_modified\":{{\"bucket\":\"{}\",\"key\":\"{}\",\"to\":\"{}\"}}}}",
+            escape_json(bucket),
+            escape_json(key),
+            escape_json(&destination.display().to_string())
+        );
+    } else if !is_quiet() {
+        println!(
+            "Not modified: '{}/{}' (kept '{}')",
+            bucket,
+            key,
+            destination.display()
+        );
     }
+}
 
-    if debug {
-        eprintln!("[debug] request: {} {}", method, url);
+/// Downloads one object to `destination` through a sibling `.s4part` file. Returns
+/// `false` when a conditional GET came back 304 and the existing file was kept.
+fn download_to_file(
+    alias: &AliasConfig,
+    bucket: &str,
+    key: &str,
+    destination: &Path,
+    headers: &[String],
+    opts: &GetOptions,
+    debug: bool,
+) -> Result<bool, S4Error> {
+    if let Some(parent) = destination.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let mut partial = destination.as_os_str().to_owned();
+    partial.push(".s4part");
+    let partial = PathBuf::from(partial);
+    track_temp(&partial);
+    // Conditional and decompressing GETs need the single response's status and headers.
+    let ranged = match opts.download_concurrency {
+        Some(n) if n > 1 && headers.is_empty() && !opts.decompress => {
+            download_ranged(alias, bucket, key, &partial, n, debug).inspect_err(|_| {
+                remove_temp(&partial);
+            })?
+        }
+        _ => false,
+    };
+    let result = if ranged {
+        Ok(S3Response {
+            body: Vec::new(),
+            content_encoding: String::new(),
+        })
+    } else {
+        s3_request_response(
+            alias,
+            S3Call {
+                method: "GET",
+                bucket,
+                key: Some(key),
+                output_file: Some(&partial),
+                headers,
+                ..S3Call::default()
+            },
+            debug,
+        )
+    };
+    match result {
+        Ok(response) if opts.decompress && is_gzip_encoding(&response.content_encoding) => {
+            let mut inflated = partial.as_os_str().to_owned();
+            inflated.push(".inflate");
+            let inflated = PathBuf::from(inflated);
+            track_temp(&inflated);
+            let decoded = fs::File::create(&inflated)
+                .map_err(S4Error::from)
+                .and_then(|mut out| gunzip_file(&partial, &mut out));
+            remove_temp(&partial);
+            if let Err(e) = decoded {
+                remove_temp(&inflated);
+                return Err(e);
+            }
+            fs::rename(&inflated, destination)?;
+            untrack_temp(&inflated);
+        }
+        Ok(_) => {
+            fs::rename(&partial, destination)?;
+            untrack_temp(&partial);
+        }
+        Err(S4Error::NotModified) => {
+            remove_temp(&partial);
+            return Ok(false);
+        }
+        Err(e) => {
+            remove_temp(&partial);
+            return Err(e);
+        }
     }
 
-    cmd.arg("-w").arg("\nHTTPSTATUS:%{http_code}");
+    Ok(true)
+}
 
-    let output = cmd.output().map_err(|e| e.to_string())?;
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-        return Err(format!("request execution failed: {}", stderr.trim()));
+/// Smallest range worth a separate request; smaller objects are streamed whole.
+const DOWNLOAD_RANGE_MIN_BYTES: u64 = 1024 * 1024;
+/// Largest range held in memory by one worker before it is written out.
+const DOWNLOAD_RANGE_MAX_BYTES: u64 = 64 * 1024 * 1024;
+
+/// First value of header `name` (case-insensitive) in a HEAD response.
+fn head_header(head: &str, name: &str) -> Option<String> {
+    head.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.trim().to_string())
+    })
+}
+
+/// Object size from a HEAD when the server advertises `Accept-Ranges: bytes`.
+fn ranged_download_size(head: &str) -> Option<u64> {
+    let mut size = None;
+    let mut ranges = false;
+    for line in head.lines() {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-length" => size = value.trim().parse::<u64>().ok(),
+            "accept-ranges" => ranges = value.trim().eq_ignore_ascii_case("bytes"),
+            _ => {}
+        }
     }
+    size.filter(|_| ranges)
+}
 
-    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-    let (body, status_part) = stdout
-        .rsplit_once("\nHTTPSTATUS:")
-        .ok_or_else(|| "unable to parse HTTP status".to_string())?;
-    let status = status_part.trim();
-    if !status.starts_with('2') {
-        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-        return Err(format!(
-            "request failed with status {status}: body='{}' stderr='{}'",
-            body.trim(),
-            stderr.trim()
-        ));
+/// Splits `size` bytes into inclusive `(start, end)` ranges: at least `concurrency` of
+/// them, more when a range would exceed `DOWNLOAD_RANGE_MAX_BYTES`, but none (except a
+/// few bytes off the last) below `DOWNLOAD_RANGE_MIN_BYTES`. Empty when the object is
+/// too small for two ranges.
+fn split_download_ranges(size: u64, concurrency: usize) -> Vec<(u64, u64)> {
+    let parts = (concurrency as u64)
+        .max(size.div_ceil(DOWNLOAD_RANGE_MAX_BYTES))
+        .min(size / DOWNLOAD_RANGE_MIN_BYTES);
+    if parts < 2 {
+        return Vec::new();
     }
+    let chunk = size.div_ceil(parts);
+    (0..parts)
+        .map(|i| (i * chunk, ((i + 1) * chunk).min(size)))
+        .filter(|(start, end)| start < end)
+        .map(|(start, end)| (start, end - 1))
+        .collect()
+}
 
-    Ok(body.to_string())
+/// Fetches the object as parallel `Range` GETs written straight into `output` at their
+/// offsets. Every range carries `If-Match` with the HEAD's ETag, so an overwrite during
+/// the download fails it instead of mixing two versions. Returns `false` (nothing
+/// written) when the server does not support ranges or the object is too small to
+/// split, so the caller falls back to a single stream.
+fn download_ranged(
+    alias: &AliasConfig,
+    bucket: &str,
+    key: &str,
+    output: &Path,
+    concurrency: usize,
+    debug: bool,
+) -> Result<bool, S4Error> {
+    let head = s3_request(
+        alias,
+        S3Call {
+            method: "HEAD",
+            bucket,
+            key: Some(key),
+            ..S3Call::default()
+        },
+        debug,
+    )?;
+    let Some(size) = ranged_download_size(&head) else {
+        return Ok(false);
+    };
+    let ranges = split_download_ranges(size, concurrency);
+    if ranges.is_empty() {
+        return Ok(false);
+    }
+    let etag = head_header(&head, "etag");
+
+    let file = fs::File::create(output)?;
+    file.set_len(size)?;
+    drop(file);
+    let result = run_ordered(
+        ranges.len(),
+        concurrency,
+        |idx| {
+            fetch_range_into(
+                alias,
+                bucket,
+                key,
+                etag.as_deref(),
+                output,
+                ranges[idx],
+                debug,
+            )
+        },
+        |_, ()| Ok(()),
+    );
+    if let Err(err) = result {
+        let _ = fs::remove_file(output);
+        return Err(err);
+    }
+    Ok(true)
 }
 
-fn sign_v4(
-    method: &str,
-    uri_path: &str,
-    query: &str,
-    host: &str,
-    region: &str,
-    access_key: &str,
-    secret_key: &str,
-    payload_hash: &str,
-) -> Result<SignatureParts, String> {
-    let py = r#"
-import sys, hmac, hashlib, datetime
-method, path, query, host, region, access, secret, payload_hash = sys.argv[1:]
-service = 's3'
-amz_date = datetime.datetime.utcnow().strftime('%Y%m%dT%H%M%SZ')
-date_stamp = amz_date[:8]
-canonical_headers = f'host:{host}\n' + f'x-amz-content-sha256:{payload_hash}\n' + f'x-amz-date:{amz_date}\n'
-signed_headers = 'host;x-amz-content-sha256;x-amz-date'
-canonical_request = '\n'.join([method, path, query, canonical_headers, signed_headers, payload_hash])
-algorithm = 'AWS4-HMAC-SHA256'
-credential_scope = f'{date_stamp}/{region}/{service}/aws4_request'
-string_to_sign = '\n'.join([algorithm, amz_date, credential_scope, hashlib.sha256(canonical_request.encode()).hexdigest()])
-def sign(key, msg):
-    return hmac.new(key, msg.encode(), hashlib.sha256).digest()
-k_date = sign(('AWS4' + secret).encode(), date_stamp)
-k_region = sign(k_date, region)
-k_service = sign(k_region, service)
-k_signing = sign(k_service, 'aws4_request')
-signature = hmac.new(k_signing, string_to_sign.encode(), hashlib.sha256).hexdigest()
-auth = f'{algorithm} Credential={access}/{credential_scope}, SignedHeaders={signed_headers}, Signature={signature}'
-print(amz_date)
-print(auth)
-"#;
-
-    let out = Command::new("python3")
-        .arg("-c")
-        .arg(py)
-        .arg(method)
-        .arg(uri_path)
-        .arg(query)
-        .arg(host)
-        .arg(region)
-        .arg(access_key)
-        .arg(secret_key)
-        .arg(payload_hash)
-        .output()
-        .map_err(|e| e.to_string())?;
-
-    if !out.status.success() {
-        return Err(String::from_utf8_lossy(&out.stderr).to_string());
-    }
-
-    let lines: Vec<String> = String::from_utf8_lossy(&out.stdout)
-        .lines()
-        .map(ToString::to_string)
-        .collect();
-    if lines.len() < 2 {
-        return Err("signature helper returned unexpected output".to_string());
+/// Writes the inclusive byte `range` of the object into `output` at the same offset.
+fn fetch_range_into(
+    alias: &AliasConfig,
+    bucket: &str,
+    key: &str,
+    etag: Option<&str>,
+    output: &Path,
+    (start, end): (u64, u64),
+    debug: bool,
+) -> Result<(), S4Error> {
+    let mut headers = vec![format!("Range: bytes={start}-{end}")];
+    if let Some(etag) = etag {
+        headers.push(format!("If-Match: {etag}"));
     }
-
-    Ok(SignatureParts {
-        amz_date: lines[0].clone(),
-        authorization: lines[1].clone(),
-    })
-}
-
-fn payload_hash(upload_file: Option<&Path>) -> Result<String, String> {
-    if let Some(path) = upload_file {
-        let out = Command::new("python3")
-            .arg("-c")
-            .arg("import hashlib,sys;print(hashlib.sha256(open(sys.argv[1],'rb').read()).hexdigest())")
-            .arg(path)
-            .output()
-            .map_err(|e| e.to_string())?;
-        if !out.status.success() {
-            return Err(String::from_utf8_lossy(&out.stderr).to_string());
-        }
-        Ok(String::from_utf8_lossy(&out.stdout).trim().to_string())
-    } else {
-        Ok("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".to_string())
+    let body = s3_request_bytes(
+        alias,
+        S3Call {
+            method: "GET",
+            bucket,
+            key: Some(key),
+            headers: &headers,
+            ..S3Call::default()
+        },
+        debug,
+    )
+    .map_err(|err| match err {
+        S4Error::Http { status: 412, .. } => S4Error::Other(format!(
+            "{bucket}/{key} changed during the ranged download (ETag no longer matches); retry"
+        )),
+        other => other,
+    })?;
+    let got = body.len() as u64;
+    if got != end - start + 1 {
+        return Err(format!(
+            "ranged download of bytes {start}-{end} returned {got} bytes (server ignored Range?)"
+        )
+        .into());
     }
+    let mut out = fs::OpenOptions::new().write(true).open(output)?;
+    out.seek(SeekFrom::Start(start))?;
+    out.write_all(&body)?;
+    Ok(())
 }
 
-const MULTIPART_THRESHOLD_BYTES: u64 = 16 * 1024 * 1024;
-const MULTIPART_PART_SIZE_BYTES: usize = 8 * 1024 * 1024;
-
-fn upload_file_to_s3(
+/// Request headers for a `get`/`cat`: the conditional headers plus `Accept-Encoding`.
+/// `--accept-encoding auto` decides from the object's Content-Type; if that HEAD fails
+/// the GET goes out uncompressed and reports the error itself.
+fn get_request_headers(
     alias: &AliasConfig,
     bucket: &str,
     key: &str,
-    path: &Path,
+    opts: &GetOptions,
     debug: bool,
-) -> Result<(), String> {
-    let size = fs::metadata(path).map_err(|e| e.to_string())?.len();
-    if size < MULTIPART_THRESHOLD_BYTES {
-        s3_request(alias, "PUT", bucket, Some(key), "", Some(path), None, debug)?;
-        return Ok(());
+) -> Vec<String> {
+    let mut headers = opts.conditions.clone();
+    let encoding = match opts.accept_encoding {
+        None => None,
+        Some(AcceptEncoding::Gzip) => Some("gzip"),
+        Some(AcceptEncoding::Zstd) => Some("zstd"),
+        Some(AcceptEncoding::Auto) => {
+            let content_type = s3_request(
+                alias,
+                S3Call {
+                    method: "HEAD",
+                    bucket,
+                    key: Some(key),
+                    ..S3Call::default()
+                },
+                debug,
+            )
+            .ok()
+            .and_then(|head| head_header(&head, "content-type"))
+            .unwrap_or_default();
+            let text = is_text_like_content_type(&content_type);
+            if debug {
+                eprintln!(
+                    "[debug] accept-encoding auto: content-type '{content_type}' {}",
+                    if text { "-> gzip" } else { "-> identity" }
+                );
+            }
+            text.then_some("gzip")
+        }
+    };
+    if let Some(encoding) = encoding {
+        headers.push(format!("Accept-Encoding: {encoding}"));
     }
-
-    multipart_upload_file(alias, bucket, key, path, debug)
+    headers
 }
 
-fn multipart_upload_file(
+/// `cat` streams the body from a temp file to stdout byte for byte, so binary objects
+/// survive `s4 cat a/b/img.png > img.png`. Only the line filters (`-n`, `--head`,
+/// `--tail`) work on text and decode it lossily.
+fn cat_object(
     alias: &AliasConfig,
     bucket: &str,
     key: &str,
-    path: &Path,
+    headers: &[String],
+    opts: &GetOptions,
     debug: bool,
-) -> Result<(), String> {
-    let init_xml = s3_request(
+) -> Result<(), S4Error> {
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| e.to_string())?
+        .as_nanos();
+    let body_path = temp_path(format!("s4-cat-{}-{}", std::process::id(), ts));
+    let result = s3_request_response(
         alias,
-        "POST",
-        bucket,
-        Some(key),
-        "uploads",
-        None,
-        None,
+        S3Call {
+            method: "GET",
+            bucket,
+            key: Some(key),
+            output_file: Some(&body_path),
+            headers,
+            ..S3Call::default()
+        },
         debug,
-    )?;
-    let upload_id = extract_tag_values(&init_xml, "UploadId")
-        .into_iter()
-        .next()
-        .map(|v| xml_unescape(&v))
-        .ok_or_else(|| "multipart init did not return UploadId".to_string())?;
-
-    let mut file = fs::File::open(path).map_err(|e| e.to_string())?;
-    let mut part_number = 1usize;
-    let mut etags: Vec<(usize, String)> = Vec::new();
-
-    loop {
-        let mut chunk = vec![0u8; MULTIPART_PART_SIZE_BYTES];
-        let n = file.read(&mut chunk).map_err(|e| e.to_string())?;
-        if n == 0 {
-            break;
+    );
+    let printed = match result {
+        Ok(response) => {
+            let gunzip = opts.decompress && is_gzip_encoding(&response.content_encoding);
+            let mut stdout = std::io::stdout().lock();
+            if opts.lines.is_active() {
+                let mut bytes = Vec::new();
+                let read = if gunzip {
+                    gunzip_file(&body_path, &mut bytes)
+                } else {
+                    fs::File::open(&body_path)
+                        .and_then(|mut f| f.read_to_end(&mut bytes))
+                        .map(|_| ())
+                        .map_err(S4Error::from)
+                };
+                read.and_then(|_| {
+                    stdout
+                        .write_all(
+                            opts.lines
+                                .apply(&String::from_utf8_lossy(&bytes))
+                                .as_bytes(),
+                        )
+                        .map_err(S4Error::from)
+                })
+            } else if gunzip {
+                gunzip_file(&body_path, &mut stdout)
+            } else {
+                fs::File::open(&body_path)
+                    .and_then(|mut f| std::io::copy(&mut f, &mut stdout))
+                    .map(|_| ())
+                    .map_err(S4Error::from)
+            }
         }
-        chunk.truncate(n);
-
-        let temp_part = env::temp_dir().join(format!(
-            "s4-mpu-part-{}-{}-{}",
-            std::process::id(),
-            part_number,
-            SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .map_err(|e| e.to_string())?
-                .as_nanos()
-        ));
-        fs::write(&temp_part, &chunk).map_err(|e| e.to_string())?;
-
-        let uploaded = upload_part(
-            alias,
-            bucket,
-            key,
-            &upload_id,
-            part_number,
-            &temp_part,
-            debug,
-        );
-        let _ = fs::remove_file(&temp_part);
-        let etag = match uploaded {
-            Ok(v) => v,
-            Err(e) => {
-                let _ = abort_multipart(alias, bucket, key, &upload_id, debug);
-                return Err(e);
+        Err(S4Error::NotModified) => {
+            if !is_quiet() {
+                eprintln!("Not modified: '{}/{}'", bucket, key);
             }
-        };
-
-        etags.push((part_number, etag));
-        part_number += 1;
-    }
-
-    if etags.is_empty() {
-        let _ = abort_multipart(alias, bucket, key, &upload_id, debug);
-        return Err("multipart upload had no parts".to_string());
-    }
-
-    let complete_xml = build_complete_multipart_xml(&etags);
-    let complete_path = env::temp_dir().join(format!(
-        "s4-mpu-complete-{}-{}",
-        std::process::id(),
-        SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .map_err(|e| e.to_string())?
-            .as_nanos()
-    ));
-    fs::write(&complete_path, complete_xml).map_err(|e| e.to_string())?;
+            Ok(())
+        }
+        Err(e) => Err(e),
+    };
+    remove_temp(&body_path);
+    printed
+}
 
-    let query = format!("uploadId={}", uri_encode_query_component(&upload_id));
-    let complete_res = s3_request(
-        alias,
-        "POST",
-        bucket,
-        Some(key),
-        &query,
-        Some(&complete_path),
-        None,
-        debug,
-    );
-    let _ = fs::remove_file(&complete_path);
+fn is_gzip_encoding(content_encoding: &str) -> bool {
+    content_encoding
+        .split(',')
+        .any(|e| e.trim().eq_ignore_ascii_case("gzip"))
+}
 
-    if let Err(err) = complete_res {
-        let _ = abort_multipart(alias, bucket, key, &upload_id, debug);
-        return Err(err);
-    }
+fn gzip_file(src: &Path, dst: &Path) -> Result<(), S4Error> {
+    let mut encoder =
+        flate2::write::GzEncoder::new(fs::File::create(dst)?, flate2::Compression::default());
+    std::io::copy(&mut fs::File::open(src)?, &mut encoder)?;
+    encoder.finish()?;
+    Ok(())
+}
 
+fn gunzip_file(src: &Path, out: &mut dyn std::io::Write) -> Result<(), S4Error> {
+    let mut decoder = flate2::read::MultiGzDecoder::new(fs::File::open(src)?);
+    std::io::copy(&mut decoder, out)
+        .map_err(|e| S4Error::Other(format!("failed to decompress gzip body: {e}")))?;
     Ok(())
 }
 
-fn upload_part(
+fn cmd_rm(
     alias: &AliasConfig,
-    bucket: &str,
-    key: &str,
-    upload_id: &str,
-    part_number: usize,
-    file_path: &Path,
+    target: &S3Target,
+    opts: &RmOptions,
+    json: bool,
     debug: bool,
-) -> Result<String, String> {
-    let endpoint = parse_endpoint(&alias.endpoint)?;
-    let mut uri_path = endpoint.base_path.clone();
-    if !bucket.is_empty() {
-        uri_path.push('/');
-        uri_path.push_str(&uri_encode_segment(bucket));
-    }
-    uri_path.push('/');
-    uri_path.push_str(&uri_encode_path(key));
-
-    let query = format!(
-        "partNumber={}&uploadId={}",
-        part_number,
-        uri_encode_query_component(upload_id)
-    );
-    let payload_hash = payload_hash(Some(file_path))?;
-    let sign = sign_v4(
-        "PUT",
-        &uri_path,
-        &query,
-        &endpoint.host,
-        &alias.region,
-        &alias.access_key,
-        &alias.secret_key,
-        &payload_hash,
-    )?;
-
-    let url = format!(
-        "{}://{}{}?{}",
-        endpoint.scheme, endpoint.host, uri_path, query
-    );
-    let mut cmd = Command::new("curl");
-    apply_curl_global_flags(&mut cmd, true, false);
-    cmd.arg("-sS")
-        .arg("-X")
-        .arg("PUT")
-        .arg(&url)
-        .arg("-H")
-        .arg(format!("Host: {}", endpoint.host))
-        .arg("-H")
-        .arg(format!("x-amz-date: {}", sign.amz_date))
-        .arg("-H")
-        .arg(format!("x-amz-content-sha256: {}", payload_hash))
-        .arg("-H")
-        .arg(format!("Authorization: {}", sign.authorization))
-        .arg("--data-binary")
-        .arg(format!("@{}", file_path.display()))
-        .arg("-D")
-        .arg("-")
-        .arg("-o")
-        .arg("/dev/null")
-        .arg("-w")
-        .arg(
-            "
-HTTPSTATUS:%{http_code}",
+) -> Result<(), S4Error> {
+    let bucket = req_bucket(target, "rm").map_err(S4Error::Usage)?;
+    if let Some(older_than) = opts.older_than {
+        return cmd_rm_older_than(alias, target, older_than, opts, json, debug);
+    }
+    let key = req_key(target, "rm").map_err(S4Error::Usage)?;
+    if opts.dry_run {
+        print_dry_run(
+            json,
+            &[PlannedAction {
+                op: "DELETE",
+                target: format!("{}/{}/{}", target.alias, bucket, key),
+                bytes: None,
+            }],
         );
-
-    if debug {
-        eprintln!("[debug] multipart upload part request: PUT {}", url);
+        return Ok(());
     }
-
-    let out = cmd.output().map_err(|e| e.to_string())?;
-    if !out.status.success() {
-        return Err(format!(
-            "multipart part request execution failed: {}",
-            String::from_utf8_lossy(&out.stderr).trim()
-        ));
+    delete_object(alias, &bucket, &key, opts.bypass_governance, debug)?;
+    if json {
+        println!(
+            "{{\"deleted\":{{\"bucket\":\"{}\",\"key\":\"{}\"}}}}",
+            escape_json(&bucket),
+            escape_json(&key)
+        );
+    } else if !is_quiet() {
+        println!("Deleted '{}/{}'", bucket, key);
     }
+    Ok(())
+}
 
-    let stdout = String::from_utf8_lossy(&out.stdout).to_string();
-    let (headers, status_part) = stdout
-        .rsplit_once(
-            "
-HTTPSTATUS:",
-        )
-        .ok_or_else(|| "unable to parse multipart part status".to_string())?;
-    let status = status_part.trim();
-    if !status.starts_with('2') {
-        return Err(format!("multipart part failed with status {}", status));
-    }
+/// Keys per DeleteObjects request; S3 rejects larger batches.
+const DELETE_BATCH_KEYS: usize = 1000;
 
-    for line in headers.lines() {
-        let l = line.trim();
-        if l.to_ascii_lowercase().starts_with("etag:") {
-            let v = l
-                .split_once(':')
-                .map(|(_, r)| r.trim().trim_matches('"').to_string())
-                .unwrap_or_default();
-            if !v.is_empty() {
-                return Ok(v);
-            }
+/// Deletes `keys` with DeleteObjects (`POST ?delete`, quiet mode) in batches of
+/// `DELETE_BATCH_KEYS`. On failure, including keys the server lists under `<Error>`,
+/// returns how many keys were deleted before it.
+fn delete_objects_batch(
+    alias: &AliasConfig,
+    bucket: &str,
+    keys: &[String],
+    debug: bool,
+) -> Result<usize, (usize, S4Error)> {
+    let mut deleted = 0;
+    let temp = temp_path(format!("s4-delete-{}.xml", std::process::id()));
+    for batch in keys.chunks(DELETE_BATCH_KEYS) {
+        let objects: String = batch
+            .iter()
+            .map(|key| format!("<Object><Key>{}</Key></Object>", xml_escape(key)))
+            .collect();
+        let body = format!("<Delete><Quiet>true</Quiet>{objects}</Delete>");
+        let result = fs::write(&temp, body)
+            .map_err(S4Error::from)
+            .and_then(|_| Ok(content_md5_header(&temp)?))
+            .and_then(|md5| {
+                s3_request(
+                    alias,
+                    S3Call {
+                        method: "POST",
+                        bucket,
+                        query: "delete",
+                        upload_file: Some(&temp),
+                        headers: &[format!("Content-MD5: {md5}")],
+                        ..S3Call::default()
+                    },
+                    debug,
+                )
+            });
+        remove_temp(&temp);
+        let response = result.map_err(|e| (deleted, e))?;
+        let errors = parse_delete_errors(&response);
+        deleted += batch.len() - errors.len().min(batch.len());
+        if let Some((key, code, message)) = errors.first() {
+            return Err((
+                deleted,
+                S4Error::Other(format!(
+                    "delete of {} key(s) failed, first {bucket}/{key}: {code} {message}",
+                    errors.len()
+                )),
+            ));
         }
     }
-    Err("multipart part response missing ETag".to_string())
+    Ok(deleted)
 }
 
-fn abort_multipart(
+/// `(key, code, message)` of every `<Error>` in a DeleteObjects response.
+fn parse_delete_errors(body: &str) -> Vec<(String, String, String)> {
+    extract_tag_blocks(body, "Error")
+        .iter()
+        .map(|block| {
+            (
+                first_tag_value(block, "Key"),
+                first_tag_value(block, "Code"),
+                first_tag_value(block, "Message"),
+            )
+        })
+        .collect()
+}
+
+/// DELETE one object; a GOVERNANCE retention refusal is retried once with the bypass
+/// header unless `bypass_governance` already sent it.
+fn delete_object(
     alias: &AliasConfig,
     bucket: &str,
     key: &str,
-    upload_id: &str,
+    bypass_governance: bool,
     debug: bool,
-) -> Result<(), String> {
-    let query = format!("uploadId={}", uri_encode_query_component(upload_id));
-    let _ = s3_request(
+) -> Result<(), S4Error> {
+    let bypass = vec!["x-amz-bypass-governance-retention: true".to_string()];
+    let headers = if bypass_governance {
+        bypass.as_slice()
+    } else {
+        &[]
+    };
+    match s3_request(
         alias,
-        "DELETE",
-        bucket,
-        Some(key),
-        &query,
-        None,
-        None,
+        S3Call {
+            method: "DELETE",
+            bucket,
+            key: Some(key),
+            headers,
+            ..S3Call::default()
+        },
         debug,
-    )?;
-    Ok(())
-}
-
-fn build_complete_multipart_xml(etags: &[(usize, String)]) -> String {
-    let mut out = String::from("<CompleteMultipartUpload>");
-    for (part, etag) in etags {
-        out.push_str("<Part>");
-        out.push_str(&format!("<PartNumber>{}</PartNumber>", part));
-        out.push_str(&format!("<ETag>\"{}\"</ETag>", escape_xml(etag)));
-        out.push_str("</Part>");
+    ) {
+        Ok(_) => Ok(()),
+        Err(err) if !bypass_governance && should_retry_with_governance_bypass(&err.to_string()) => {
+            s3_request(
+                alias,
+                S3Call {
+                    method: "DELETE",
+                    bucket,
+                    key: Some(key),
+                    headers: &bypass,
+                    ..S3Call::default()
+                },
+                debug,
+            )
+            .map(|_| ())
+        }
+        Err(err) => Err(err),
     }
-    out.push_str("</CompleteMultipartUpload>");
-    out
 }
 
-fn escape_xml(s: &str) -> String {
-    s.replace('&', "&amp;")
-        .replace('<', "&lt;")
-        .replace('>', "&gt;")
-        .replace('"', "&quot;")
-        .replace('\'', "&apos;")
+/// Listed objects at least `older_than` seconds old by their LastModified; entries
+/// without a parseable date are kept.
+fn objects_older_than(objects: Vec<ListedObject>, older_than: u64, now: i64) -> Vec<ListedObject> {
+    objects
+        .into_iter()
+        .filter(|obj| {
+            parse_rfc3339(&obj.last_modified)
+                .is_ok_and(|modified| object_age_secs(modified, now) >= older_than)
+        })
+        .collect()
 }
 
-fn uri_encode_query_component(s: &str) -> String {
-    let mut out = String::new();
-    for b in s.bytes() {
-        let c = b as char;
-        if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' || c == '~' {
-            out.push(c);
-        } else {
-            out.push_str(&format!("%{:02X}", b));
+/// `rm --recursive --older-than`: a manual stand-in for an expiration lifecycle rule.
+/// Ages come from the listing, so no object is HEADed; DELETEs run in parallel up to
+/// `--max-concurrent` and stop at the first failure. Each listing page is deleted
+/// before the next is fetched, so the prefix is never held in memory.
+fn cmd_rm_older_than(
+    alias: &AliasConfig,
+    target: &S3Target,
+    older_than: u64,
+    opts: &RmOptions,
+    json: bool,
+    debug: bool,
+) -> Result<(), S4Error> {
+    let alias_name = &target.alias;
+    let bucket = target.bucket.as_deref().unwrap_or_default();
+    let prefix = target.key.as_deref().unwrap_or_default();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| e.to_string())?
+        .as_secs() as i64;
+    let mut actions = Vec::new();
+    let (mut deleted, mut bytes) = (0usize, 0u64);
+    // Deleting keys that were already listed does not move the listing cursor.
+    let result = for_each_object_page(alias, bucket, prefix, false, debug, |page| {
+        let expired = objects_older_than(page, older_than, now);
+        if opts.dry_run {
+            actions.extend(expired.iter().map(|obj| PlannedAction {
+                op: "DELETE",
+                target: format!("{}/{}/{}", alias_name, bucket, obj.key),
+                bytes: Some(obj.size),
+            }));
+            return Ok(());
+        }
+        run_ordered(
+            expired.len(),
+            MAX_CONCURRENT.load(Ordering::Relaxed),
+            |idx| {
+                delete_object(
+                    alias,
+                    bucket,
+                    &expired[idx].key,
+                    opts.bypass_governance,
+                    debug,
+                )
+            },
+            |idx, ()| {
+                deleted += 1;
+                bytes += expired[idx].size;
+                Ok(())
+            },
+        )
+    });
+    if let Err(err) = result {
+        if !opts.dry_run {
+            eprintln!(
+                "rm: deleted {} object(s) older than {}s before the run failed",
+                deleted, older_than
+            );
         }
+        return Err(err);
     }
-    out
-}
-
-fn parse_endpoint(raw: &str) -> Result<Endpoint, String> {
-    let (scheme, rest) = if let Some(v) = raw.strip_prefix("http://") {
-        ("http", v)
-    } else if let Some(v) = raw.strip_prefix("https://") {
-        ("https", v)
-    } else {
-        return Err("endpoint must start with http:// or https://".to_string());
-    };
-
-    let mut parts = rest.splitn(2, '/');
-    let host = parts.next().unwrap_or("").to_string();
-    if host.is_empty() {
-        return Err("endpoint host is empty".to_string());
+    if opts.dry_run {
+        print_dry_run(json, &actions);
+        return Ok(());
     }
-    let base_path = match parts.next() {
-        Some(v) if !v.is_empty() => format!("/{}", v.trim_end_matches('/')),
-        _ => "".to_string(),
-    };
-
-    Ok(Endpoint {
-        scheme: scheme.to_string(),
-        host,
-        base_path,
-    })
+    if json {
+        println!(
+            "{{\"deleted\":{},\"bytes\":{},\"bucket\":\"{}\",\"prefix\":\"{}\",\"older_than_seconds\":{}}}",
+            deleted,
+            bytes,
+            escape_json(bucket),
+            escape_json(prefix),
+            older_than
+        );
+    } else if !is_quiet() {
+        println!(
+            "Deleted {} object(s) older than {}s from '{}/{}' ({})",
+            deleted,
+            older_than,
+            bucket,
+            prefix,
+            format_bytes(bytes)
+        );
+    }
+    Ok(())
 }
 
-fn resolve_config_path(custom_dir: Option<&Path>) -> Result<PathBuf, String> {
-    match custom_dir {
-        Some(p) => Ok(p.join("config.toml")),
-        None => {
-            let home = env::var("HOME").map_err(|_| "HOME is not set".to_string())?;
-            Ok(PathBuf::from(home).join(".s4").join("config.toml"))
+/// `--dry-run` output: one `[dry-run] OP target` line per action, or a JSON action list.
+fn print_dry_run(json: bool, actions: &[PlannedAction]) {
+    if json {
+        let items: Vec<String> = actions
+            .iter()
+            .map(|a| {
+                let bytes = a
+                    .bytes
+                    .map(|b| format!(",\"bytes\":{b}"))
+                    .unwrap_or_default();
+                format!(
+                    "{{\"op\":\"{}\",\"target\":\"{}\"{}}}",
+                    a.op,
+                    escape_json(&a.target),
+                    bytes
+                )
+            })
+            .collect();
+        println!("{{\"dry_run\":true,\"actions\":[{}]}}", items.join(","));
+        return;
+    }
+    for action in actions {
+        match action.bytes {
+            Some(bytes) => println!(
+                "[dry-run] {} {} ({} bytes)",
+                action.op, action.target, bytes
+            ),
+            None => println!("[dry-run] {} {}", action.op, action.target),
         }
     }
 }
 
-fn load_config(path: &Path) -> Result<AppConfig, String> {
-    if !path.exists() {
-        return Ok(AppConfig::default());
+fn cmd_put(
+    alias: &AliasConfig,
+    source: &Path,
+    target: &S3Target,
+    opts: &PutOptions,
+    json: bool,
+    debug: bool,
+) -> Result<(), S4Error> {
+    if let Some(url) = &opts.from_url {
+        return put_from_url(alias, url, target, opts, json, debug);
     }
-
-    let mut file = fs::File::open(path).map_err(|e| e.to_string())?;
-    let mut s = String::new();
-    file.read_to_string(&mut s).map_err(|e| e.to_string())?;
-    parse_config(&s)
-}
-
-fn save_config(path: &Path, cfg: &AppConfig) -> Result<(), String> {
-    if let Some(parent) = path.parent() {
-        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    // A file whose name really contains `*` or `?` is still uploaded as itself.
+    let glob = !source.exists() && has_wildcard(&source.to_string_lossy());
+    if !source.exists() && !glob {
+        return Err(format!("source file not found: {}", source.display()).into());
     }
-
-    let text = serialize_config(cfg);
-    fs::write(path, text).map_err(|e| e.to_string())
-}
-
-fn parse_config(text: &str) -> Result<AppConfig, String> {
-    let mut cfg = AppConfig::default();
-    for (ln, line) in text.lines().enumerate() {
-        let line = line.trim();
-        if line.is_empty() || line.starts_with('#') {
-            continue;
+    let bucket = req_bucket(target, "put").map_err(S4Error::Usage)?;
+    if glob || source.is_dir() {
+        let files = if glob {
+            expand_put_glob(source)?
+        } else if opts.recursive {
+            collect_local_files(source, opts.follow_symlinks)?
+        } else {
+            return Err(S4Error::Usage(format!(
+                "'{}' is a directory; use put --recursive to upload its contents",
+                source.display()
+            )));
+        };
+        if files.is_empty() && glob {
+            if !opts.allow_empty {
+                return Err(format!("no files match {}", source.display()).into());
+            }
+            if !json {
+                if !is_quiet() {
+                    println!("No files match {}; nothing to upload", source.display());
+                }
+                return Ok(());
+            }
         }
-        let parts: Vec<&str> = line.split('\t').collect();
-        if parts.len() != 6 {
-            return Err(format!("invalid config at line {}", ln + 1));
+        let prefix = target.key.clone().unwrap_or_default();
+        if opts.dry_run {
+            let mut actions = Vec::new();
+            for (path, relative) in files {
+                let key = sync_destination_key(&relative, "", &prefix);
+                actions.push(PlannedAction {
+                    op: "PUT",
+                    target: format!("{}/{}/{}", target.alias, bucket, key),
+                    bytes: Some(fs::metadata(&path)?.len()),
+                });
+            }
+            print_dry_run(json, &actions);
+            return Ok(());
         }
-        cfg.aliases.insert(
-            parts[0].to_string(),
-            AliasConfig {
-                endpoint: parts[1].to_string(),
-                access_key: parts[2].to_string(),
-                secret_key: parts[3].to_string(),
-                region: parts[4].to_string(),
-                path_style: parts[5] == "1",
-            },
+        return put_files(alias, &files, source, target, &opts.upload, json, debug);
+    }
+
+    let key = req_key(target, "put").map_err(S4Error::Usage)?;
+    if opts.dry_run {
+        print_dry_run(
+            json,
+            &[PlannedAction {
+                op: "PUT",
+                target: format!("{}/{}/{}", target.alias, bucket, key),
+                bytes: Some(fs::metadata(source)?.len()),
+            }],
         );
+        return Ok(());
     }
-    Ok(cfg)
+    upload_file_to_s3(alias, &bucket, &key, source, &opts.upload, debug)?;
+    if json {
+        println!(
+            "{{\"uploaded\":{{\"bucket\":\"{}\",\"key\":\"{}\"}}}}",
+            escape_json(&bucket),
+            escape_json(&key)
+        );
+    } else if !is_quiet() {
+        println!("Uploaded '{}' to '{}/{}'", source.display(), bucket, key);
+    }
+    Ok(())
 }
 
-fn serialize_config(cfg: &AppConfig) -> String {
-    let mut out = String::new();
-    for (name, a) in &cfg.aliases {
-        out.push_str(&format!(
-            "{}\t{}\t{}\t{}\t{}\t{}\n",
-            name,
-            a.endpoint,
-            a.access_key,
-            a.secret_key,
-            a.region,
-            if a.path_style { "1" } else { "0" }
-        ));
+fn put_from_url(
+    alias: &AliasConfig,
+    url: &str,
+    target: &S3Target,
+    opts: &PutOptions,
+    json: bool,
+    debug: bool,
+) -> Result<(), S4Error> {
+    let bucket = req_bucket(target, "put").map_err(S4Error::Usage)?;
+    let key = req_key(target, "put").map_err(S4Error::Usage)?;
+    if opts.dry_run {
+        print_dry_run(
+            json,
+            &[PlannedAction {
+                op: "PUT",
+                target: format!("{}/{}/{}", target.alias, bucket, key),
+                bytes: None,
+            }],
+        );
+        return Ok(());
     }
-    out
+    // The curl backend cannot pipe one transfer into another while still
+    // signing the upload, so the source is staged in a temp file first.
+    let staged = temp_path(format!("s4-from-url-{}", std::process::id()));
+    let result = fetch_source_url(url, &staged, debug)
+        .and_then(|_| upload_file_to_s3(alias, &bucket, &key, &staged, &opts.upload, debug));
+    remove_temp(&staged);
+    result?;
+    if json {
+        println!(
+            "{{\"uploaded\":{{\"bucket\":\"{}\",\"key\":\"{}\",\"source\":\"{}\"}}}}",
+            escape_json(&bucket),
+            escape_json(&key),
+            escape_json(url)
+        );
+    } else if !is_quiet() {
+        println!("Uploaded '{}' to '{}/{}'", url, bucket, key);
+    }
+    Ok(())
 }
 
-fn parse_target(input: &str) -> Result<S3Target, String> {
-    let mut parts = input.splitn(3, '/');
-    let alias = parts
-        .next()
-        .ok_or_else(|| "target must start with alias".to_string())?
-        .to_string();
-    if alias.is_empty() {
-        return Err("target alias is empty".to_string());
+/// Uploads a directory's files or a wildcard's matches under the target prefix, keyed by
+/// their relative paths; `source` is only used in the summary line.
+fn put_files(
+    alias: &AliasConfig,
+    files: &[(PathBuf, String)],
+    source: &Path,
+    target: &S3Target,
+    upload: &UploadOptions,
+    json: bool,
+    debug: bool,
+) -> Result<(), S4Error> {
+    let bucket = target.bucket.as_deref().unwrap_or_default();
+    let prefix = target.key.as_deref().unwrap_or_default();
+    let mut summary = TransferSummary::start();
+    for (path, relative) in files {
+        let key = sync_destination_key(relative, "", prefix);
+        upload_file_to_s3(alias, bucket, &key, path, upload, debug)?;
+        summary.add(fs::metadata(path)?.len());
     }
-    let bucket = parts.next().map(ToString::to_string);
-    let key = parts.next().map(ToString::to_string);
-    Ok(S3Target { alias, bucket, key })
+
+    if json {
+        println!(
+            "{{\"uploaded\":{{\"bucket\":\"{}\",\"prefix\":\"{}\",\"files\":{},\"bytes\":{}}},\"summary\":{}}}",
+            escape_json(bucket),
+            escape_json(prefix),
+            files.len(),
+            summary.bytes,
+            summary.json()
+        );
+    } else if !is_quiet() {
+        println!(
+            "Uploaded {} file(s), {} from '{}' to '{}/{}'",
+            files.len(),
+            format_bytes(summary.bytes),
+            source.display(),
+            bucket,
+            prefix
+        );
+        println!("{}", summary.line());
+    }
+    Ok(())
 }
 
-fn uri_encode_segment(s: &str) -> String {
-    uri_encode_path(s)
+fn has_wildcard(value: &str) -> bool {
+    value.contains(['*', '?'])
 }
 
-fn uri_encode_path(s: &str) -> String {
-    let mut out = String::new();
-    for b in s.bytes() {
-        let c = b as char;
-        if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' || c == '~' || c == '/' {
-            out.push(c);
-        } else {
-            out.push_str(&format!("%{:02X}", b));
+/// `put 'logs/*.gz'` where the shell left the pattern alone: matches regular files in
+/// the pattern's directory by name. Like a shell glob, `*` skips dot files unless the
+/// pattern itself starts with a dot; wildcards in the directory part are not supported.
+fn expand_put_glob(pattern: &Path) -> Result<Vec<(PathBuf, String)>, S4Error> {
+    let name = pattern
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let dir = pattern
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    if has_wildcard(&dir.to_string_lossy()) || !has_wildcard(&name) {
+        return Err(S4Error::Usage(format!(
+            "wildcards are only supported in the file name: {}",
+            pattern.display()
+        )));
+    }
+    let entries = fs::read_dir(dir)
+        .map_err(|e| S4Error::Other(format!("cannot read {}: {e}", dir.display())))?;
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        if file_name.starts_with('.') && !name.starts_with('.') {
+            continue;
+        }
+        if wildcard_match(&name, &file_name) && entry.path().is_file() {
+            files.push((entry.path(), file_name));
         }
     }
-    out
-}
-
-fn escape_json(s: &str) -> String {
-    s.replace('\\', "\\\\")
-        .replace('"', "\\\"")
-        .replace('\n', "\\n")
+    files.sort();
+    Ok(files)
 }
 
-fn print_status(json: bool, field: &str, value: &str) {
-    if json {
-        println!("{{\"{}\":\"{}\"}}", escape_json(field), escape_json(value));
+/// Regular files under `root` (recursively, sorted) paired with their `/`-separated relative path.
+/// Symlinks below `root` are skipped unless `follow_symlinks`; followed links that dangle
+/// are skipped with a warning, as are links back to a directory already on the path.
+fn collect_local_files(
+    root: &Path,
+    follow_symlinks: bool,
+) -> Result<Vec<(PathBuf, String)>, S4Error> {
+    let mut out = Vec::new();
+    // Each pending directory carries the canonical paths of itself and its ancestors,
+    // which is all a followed link can loop back to.
+    let ancestors = if follow_symlinks {
+        vec![fs::canonicalize(root)?]
     } else {
-        println!("{field}: {value}");
+        Vec::new()
+    };
+    let mut pending = vec![(root.to_path_buf(), ancestors)];
+    while let Some((dir, ancestors)) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let is_link = entry.file_type()?.is_symlink();
+            if is_link && !follow_symlinks {
+                continue;
+            }
+            let meta = match fs::metadata(&path) {
+                Ok(meta) => meta,
+                Err(_) if is_link => {
+                    if !is_quiet() {
+                        eprintln!("warning: skipping broken symlink {}", path.display());
+                    }
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if meta.is_dir() {
+                let mut chain = ancestors.clone();
+                if follow_symlinks {
+                    let canonical = fs::canonicalize(&path)?;
+                    if ancestors.contains(&canonical) {
+                        if !is_quiet() {
+                            eprintln!("warning: skipping symlink loop {}", path.display());
+                        }
+                        continue;
+                    }
+                    chain.push(canonical);
+                }
+                pending.push((path, chain));
+            } else if meta.is_file() {
+                let relative = path
+                    .strip_prefix(root)
+                    .map_err(|e| e.to_string())?
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy().to_string())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                out.push((path, relative));
+            }
+        }
     }
+    out.sort_by(|a, b| a.1.cmp(&b.1));
+    Ok(out)
 }
 
-fn print_help() {
-    println!(
-        "s4 - S3 client utility in Rust
-
-USAGE:
-  s4 [FLAGS] COMMAND [ARGS]
+fn cmd_cp_mv(
+    command: &str,
+    config: &AppConfig,
+    source: &str,
+    target: &str,
+    opts: &CopyOptions,
+    json: bool,
+    debug: bool,
+) -> Result<(), S4Error> {
+    let src = classify_ref(config, source);
+    let dst = classify_ref(config, target);
+    if opts.dry_run {
+        print_dry_run(json, &plan_cp_mv(command, &src, &dst, source, target)?);
+        return Ok(());
+    }
+    if opts.if_size_differs
+        && let Some(dst_print) = fingerprint(&dst, opts.checksum, debug)?
+        && let Some(src_print) = fingerprint(&src, opts.checksum, debug)?
+        && src_print.matches(&dst_print)
+    {
+        if json {
+            println!(
+                "{{\"status\":\"skipped\",\"reason\":\"up to date\",\"command\":\"{}\",\"source\":\"{}\",\"target\":\"{}\"}}",
+                escape_json(command),
+                escape_json(source),
+                escape_json(target)
+            );
+        } else if !is_quiet() {
+            println!("{}: {} -> {} skipped (up to date)", command, source, target);
+        }
+        return Ok(());
+    }
 
-COMMANDS:
-  alias      manage aliases in local config
-  ls         list buckets/objects
-  mb         make bucket
-  rb         remove bucket
-  legalhold  manage legal hold for object(s) (set/clear/info)
-  retention  manage retention for object(s) (set/clear/info)
-  sql        run SQL queries on objects
-  replicate  manage server-side bucket replication [placeholder]
-  put        upload object
-  get        download object
-  rm         remove object
-  stat       object metadata (raw headers)
-  cat        print object content
-  cors       manage bucket CORS configuration (set/get/remove)
-  encrypt    manage bucket encryption config (set/clear/info)
-  event      manage bucket notifications (add/remove/list)
-  idp        manage identity providers (openid/ldap) [placeholder]
+    let mut copied = None;
+    match (&src, &dst) {
+        (ObjectRef::Local(src_path), ObjectRef::S3(dst_s3)) => {
+            let body_path = PathBuf::from(src_path);
+            if !body_path.exists() {
+                return Err(format!("source file not found: {}", body_path.display()).into());
+            }
+            upload_file_to_s3(
+                &dst_s3.alias,
+                &dst_s3.bucket,
+                &dst_s3.key,
+                &body_path,
+                &opts.upload,
+                debug,
+            )?;
+            if command == "mv" {
+                fs::remove_file(&body_path).map_err(|e| e.to_string())?;
+            }
+        }
+        (ObjectRef::S3(src_s3), ObjectRef::Local(dst_path)) => {
+            if !opts.upload.preconditions.is_empty() || !opts.upload.headers.is_empty() {
+                return Err(S4Error::Usage(
+                    "--if-none-match/--if-match and object header flags apply only when the target is S3"
+                        .to_string(),
+                ));
+            }
+            let out = PathBuf::from(dst_path);
+            if let Some(parent) = out.parent()
+                && !parent.as_os_str().is_empty()
+            {
+                fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+            }
+            let ranged = match opts.download_concurrency {
+                Some(n) if n > 1 => {
+                    download_ranged(&src_s3.alias, &src_s3.bucket, &src_s3.key, &out, n, debug)?
+                }
+                _ => false,
+            };
+            if !ranged {
+                s3_request(
+                    &src_s3.alias,
+                    S3Call {
+                        method: "GET",
+                        bucket: &src_s3.bucket,
+                        key: Some(&src_s3.key),
+                        output_file: Some(&out),
+                        ..S3Call::default()
+                    },
+                    debug,
+                )?;
+            }
+            if command == "mv" {
+                s3_request(
+                    &src_s3.alias,
+                    S3Call {
+                        method: "DELETE",
+                        bucket: &src_s3.bucket,
+                        key: Some(&src_s3.key),
+                        ..S3Call::default()
+                    },
+                    debug,
+                )?;
+            }
+        }
+        (ObjectRef::S3(src_s3), ObjectRef::S3(dst_s3)) => {
+            copied = Some(copy_object_s3_to_s3(
+                src_s3,
+                dst_s3,
+                &opts.upload,
+                opts.preserve,
+                debug,
+            )?);
+            if command == "mv" {
+                s3_request(
+                    &src_s3.alias,
+                    S3Call {
+                        method: "DELETE",
+                        bucket: &src_s3.bucket,
+                        key: Some(&src_s3.key),
+                        ..S3Call::default()
+                    },
+                    debug,
+                )?;
+            }
+        }
+        (ObjectRef::Local(src_path), ObjectRef::Local(dst_path)) => {
+            if !opts.upload.preconditions.is_empty() || !opts.upload.headers.is_empty() {
+                return Err(S4Error::Usage(
+                    "--if-none-match/--if-match and object header flags apply only when the target is S3"
+                        .to_string(),
+                ));
+            }
+            fs::copy(src_path, dst_path).map_err(|e| e.to_string())?;
+            if command == "mv" {
+                fs::remove_file(src_path).map_err(|e| e.to_string())?;
+            }
+        }
+    }
+
+    if json {
+        println!(
+            "{{\"status\":\"ok\",\"command\":\"{}\",\"source\":\"{}\",\"target\":\"{}\"{}}}",
+            escape_json(command),
+            escape_json(source),
+            escape_json(target),
+            copied.map(|c| copy_result_json(&c)).unwrap_or_default()
+        );
+    } else if !is_quiet() {
+        println!("{}: {} -> {}", command, source, target);
+    }
+    Ok(())
+}
+
+/// Extra `cp`/`mv --json` fields for a server-side copy; empty values are left out.
+fn copy_result_json(copied: &CopyResult) -> String {
+    let mut out = String::new();
+    for (name, value) in [
+        ("etag", &copied.etag),
+        ("last_modified", &copied.last_modified),
+    ] {
+        if !value.is_empty() {
+            out.push_str(&format!(",\"{name}\":\"{}\"", escape_json(value)));
+        }
+    }
+    out
+}
+
+/// What `diff` found between two listings, keyed by path relative to each prefix.
+#[derive(Debug, Default, PartialEq)]
+struct ListingDiff {
+    only_in_source: Vec<String>,
+    only_in_target: Vec<String>,
+    /// Same relative key on both sides, but a different size or ETag.
+    different: Vec<(String, ListedObject, ListedObject)>,
+    same: usize,
+}
+
+fn diff_listings(
+    source: Vec<ListedObject>,
+    src_prefix: &str,
+    target: Vec<ListedObject>,
+    dst_prefix: &str,
+) -> ListingDiff {
+    let relative = |objects: Vec<ListedObject>, prefix: &str| -> BTreeMap<String, ListedObject> {
+        objects
+            .into_iter()
+            .map(|obj| (sync_destination_key(&obj.key, prefix, ""), obj))
+            .collect()
+    };
+    let source = relative(source, src_prefix);
+    let mut target = relative(target, dst_prefix);
+    let mut diff = ListingDiff::default();
+    for (key, src_obj) in source {
+        match target.remove(&key) {
+            None => diff.only_in_source.push(key),
+            Some(dst_obj)
+                if dst_obj.size != src_obj.size
+                    || dst_obj.etag.trim_matches('"') != src_obj.etag.trim_matches('"') =>
+            {
+                diff.different.push((key, src_obj, dst_obj));
+            }
+            Some(_) => diff.same += 1,
+        }
+    }
+    diff.only_in_target = target.into_keys().collect();
+    diff
+}
+
+/// Human output may use ANSI colors only on a terminal, and never with `--no-color` or
+/// a non-empty `NO_COLOR`. JSON/CSV output never calls `paint`.
+fn color_wanted(
+    stdout_tty: bool,
+    no_color_flag: bool,
+    no_color_env: Option<&std::ffi::OsStr>,
+) -> bool {
+    stdout_tty && !no_color_flag && no_color_env.is_none_or(|v| v.is_empty())
+}
+
+fn color_enabled() -> bool {
+    COLOR.load(Ordering::Relaxed)
+}
+
+/// Directories, prefixes and bucket names in listings.
+const STYLE_DIR: &str = "34";
+/// Sizes in listings.
+const STYLE_DIM: &str = "2";
+
+/// Display width of every column of `rows`, for `aligned_row`.
+fn column_widths(rows: &[Vec<String>]) -> Vec<usize> {
+    let mut widths = Vec::new();
+    for row in rows {
+        widths.resize(widths.len().max(row.len()), 0);
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    widths
+}
+
+/// One human table line, see `table_row`; columns are padded only on a terminal.
+fn aligned_row(
+    row: &[String],
+    widths: &[usize],
+    right: &[usize],
+    style: impl Fn(usize, &str) -> Option<&'static str>,
+) -> String {
+    let align = ALIGN_COLUMNS.load(Ordering::Relaxed);
+    table_row(row, align.then_some(widths), right, color_enabled(), style)
+}
+
+/// With `widths`, cells padded to them (the `right` columns right-aligned, the last
+/// left-aligned cell unpadded), joined by two spaces and painted after padding, so colors
+/// keep alignment. Without, the plain cells joined by tabs for scripts reading a pipe.
+fn table_row(
+    row: &[String],
+    widths: Option<&[usize]>,
+    right: &[usize],
+    color: bool,
+    style: impl Fn(usize, &str) -> Option<&'static str>,
+) -> String {
+    let Some(widths) = widths else {
+        return row.join("\t");
+    };
+    row.iter()
+        .enumerate()
+        .map(|(i, cell)| {
+            let width = widths.get(i).copied().unwrap_or(0);
+            let padded = if right.contains(&i) {
+                format!("{cell:>width$}")
+            } else if i + 1 == row.len() {
+                cell.clone()
+            } else {
+                format!("{cell:<width$}")
+            };
+            match style(i, cell) {
+                Some(ansi) => paint(&padded, ansi, color),
+                None => padded,
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("  ")
+}
+
+/// `STYLE_DIR` for directory markers and prefixes (keys ending in `/`).
+fn key_style(key: &str) -> Option<&'static str> {
+    key.ends_with('/').then_some(STYLE_DIR)
+}
+
+fn paint(text: &str, ansi: &str, color: bool) -> String {
+    if color {
+        format!("\x1b[{ansi}m{text}\x1b[0m")
+    } else {
+        text.to_string()
+    }
+}
+
+fn cmd_diff(
+    config: &AppConfig,
+    source: &str,
+    target: &str,
+    json: bool,
+    debug: bool,
+) -> Result<(), S4Error> {
+    let list = |value: &str| -> Result<(Vec<ListedObject>, String), S4Error> {
+        let target = parse_target(value).map_err(S4Error::Usage)?;
+        let alias = config
+            .aliases
+            .get(&target.alias)
+            .ok_or_else(|| unknown_alias(&target.alias))?;
+        let bucket = req_bucket(&target, "diff").map_err(S4Error::Usage)?;
+        let prefix = folder_prefix(&target.key.unwrap_or_default());
+        Ok((list_objects(alias, &bucket, &prefix, false, debug)?, prefix))
+    };
+    let (src_objects, src_prefix) = list(source)?;
+    let (dst_objects, dst_prefix) = list(target)?;
+    let diff = diff_listings(src_objects, &src_prefix, dst_objects, &dst_prefix);
+    let differences = diff.only_in_source.len() + diff.only_in_target.len() + diff.different.len();
+    // Like diff(1): the listing is the output, the exit code says whether it is empty.
+    let outcome = match differences {
+        0 => Ok(()),
+        n => Err(S4Error::Other(format!(
+            "{n} difference(s) between {source} and {target}"
+        ))),
+    };
+
+    if json {
+        let keys = |keys: &[String]| {
+            keys.iter()
+                .map(|k| format!("\"{}\"", escape_json(k)))
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        let different: Vec<String> = diff
+            .different
+            .iter()
+            .map(|(key, src, dst)| {
+                format!(
+                    "{{\"key\":\"{}\",\"source_size\":{},\"target_size\":{},\"source_etag\":\"{}\",\"target_etag\":\"{}\"}}",
+                    escape_json(key),
+                    src.size,
+                    dst.size,
+                    escape_json(src.etag.trim_matches('"')),
+                    escape_json(dst.etag.trim_matches('"'))
+                )
+            })
+            .collect();
+        println!(
+            "{{\"source\":\"{}\",\"target\":\"{}\",\"only_in_source\":[{}],\"only_in_target\":[{}],\"different\":[{}],\"identical\":{}}}",
+            escape_json(source),
+            escape_json(target),
+            keys(&diff.only_in_source),
+            keys(&diff.only_in_target),
+            different.join(","),
+            diff.same
+        );
+        return outcome;
+    }
+
+    let color = color_enabled();
+    for key in &diff.only_in_source {
+        println!("{}", paint(&format!("< {key}"), "31", color));
+    }
+    for key in &diff.only_in_target {
+        println!("{}", paint(&format!("> {key}"), "32", color));
+    }
+    for (key, src, dst) in &diff.different {
+        let detail = if src.size != dst.size {
+            format!("size {} -> {}", src.size, dst.size)
+        } else {
+            format!(
+                "etag {} -> {}",
+                src.etag.trim_matches('"'),
+                dst.etag.trim_matches('"')
+            )
+        };
+        println!("{}", paint(&format!("! {key} ({detail})"), "33", color));
+    }
+    if !is_quiet() {
+        if diff.only_in_source.is_empty()
+            && diff.only_in_target.is_empty()
+            && diff.different.is_empty()
+        {
+            println!("No differences ({} object(s) compared)", diff.same);
+        } else {
+            println!(
+                "{} only in {}, {} only in {}, {} different, {} identical",
+                diff.only_in_source.len(),
+                source,
+                diff.only_in_target.len(),
+                target,
+                diff.different.len(),
+                diff.same
+            );
+        }
+    }
+    outcome
+}
+
+/// `mv --recursive`: copies every object under the source prefix (server-side when
+/// both sides share an endpoint), and only once all copies succeeded deletes the
+/// sources, so a failure never leaves objects missing from both places.
+fn cmd_mv_recursive(
+    config: &AppConfig,
+    source: &str,
+    target: &str,
+    opts: &CopyOptions,
+    json: bool,
+    debug: bool,
+) -> Result<(), S4Error> {
+    let src = SyncEndpoint::resolve(config, source)?;
+    let dst = SyncEndpoint::resolve(config, target)?;
+    let (
+        SyncEndpoint::S3 {
+            alias_name: src_name,
+            alias: src_alias,
+            bucket: src_bucket,
+            prefix: src_prefix,
+        },
+        SyncEndpoint::S3 {
+            alias_name: dst_name,
+            alias: dst_alias,
+            bucket: dst_bucket,
+            prefix: dst_prefix,
+        },
+    ) = (&src, &dst)
+    else {
+        return Err(S4Error::Usage(
+            "mv --recursive moves between S3 prefixes; use put --recursive or sync for local directories"
+                .to_string(),
+        ));
+    };
+    let folder = folder_prefix(src_prefix);
+    if src_name == dst_name && src_bucket == dst_bucket && prefix_inside_folder(dst_prefix, &folder)
+    {
+        return Err(S4Error::Usage(format!(
+            "destination {target} is inside the source {source}; moving a prefix into itself would overwrite objects before they are moved"
+        )));
+    }
+    let moves: Vec<(String, String, u64)> =
+        list_objects(src_alias, src_bucket, &folder, false, debug)?
+            .into_iter()
+            .map(|obj| {
+                let dest_key = sync_destination_key(&obj.key, &folder, dst_prefix);
+                (obj.key, dest_key, obj.size)
+            })
+            .collect();
+    if moves.is_empty() {
+        return Err(S4Error::NotFound(format!(
+            "no objects under {src_name}/{src_bucket}/{folder}"
+        )));
+    }
+    let src_label = |key: &str| format!("{src_name}/{src_bucket}/{key}");
+    let dst_label = |key: &str| format!("{dst_name}/{dst_bucket}/{key}");
+    if opts.dry_run {
+        let mut actions = Vec::new();
+        for (key, dest_key, size) in &moves {
+            actions.push(PlannedAction {
+                op: "COPY",
+                target: format!("{} -> {}", src_label(key), dst_label(dest_key)),
+                bytes: Some(*size),
+            });
+        }
+        for (key, _, _) in &moves {
+            actions.push(PlannedAction {
+                op: "DELETE",
+                target: src_label(key),
+                bytes: None,
+            });
+        }
+        print_dry_run(json, &actions);
+        return Ok(());
+    }
+
+    let server_side =
+        src_alias.endpoint == dst_alias.endpoint && src_alias.access_key == dst_alias.access_key;
+    let temp_root = temp_path(format!("s4-mv-{}", std::process::id()));
+    fs::create_dir_all(&temp_root)?;
+    let mut summary = TransferSummary::start();
+    for (idx, (key, dest_key, size)) in moves.iter().enumerate() {
+        let copied = if server_side {
+            copy_object_s3_to_s3(
+                &S3ObjectRef {
+                    alias: src_alias.clone(),
+                    bucket: src_bucket.clone(),
+                    key: key.clone(),
+                },
+                &S3ObjectRef {
+                    alias: dst_alias.clone(),
+                    bucket: dst_bucket.clone(),
+                    key: dest_key.clone(),
+                },
+                &opts.upload,
+                opts.preserve,
+                debug,
+            )
+            .map(|_| ())
+        } else {
+            sync_transfer(
+                &src,
+                &dst,
+                key,
+                dest_key,
+                &temp_root.join(format!("obj-{idx}")),
+                debug,
+            )
+            .map(|_| ())
+        };
+        if let Err(e) = copied {
+            fs::remove_dir_all(&temp_root).ok();
+            eprintln!(
+                "mv: copied {} of {} object(s) before {} failed; no sources were deleted",
+                idx,
+                moves.len(),
+                src_label(key)
+            );
+            return Err(e);
+        }
+        summary.add(*size);
+    }
+    fs::remove_dir_all(&temp_root).ok();
+
+    let sources: Vec<String> = moves.iter().map(|(key, _, _)| key.clone()).collect();
+    if let Err((deleted, err)) = delete_objects_batch(src_alias, src_bucket, &sources, debug) {
+        eprintln!(
+            "mv: copied all {} object(s) but deleted only {} of {} source(s); the rest exist in both places",
+            moves.len(),
+            deleted,
+            moves.len()
+        );
+        return Err(err);
+    }
+
+    if json {
+        println!(
+            "{{\"status\":\"ok\",\"command\":\"mv\",\"recursive\":true,\"source\":\"{}\",\"target\":\"{}\",\"moved\":{},\"summary\":{}}}",
+            escape_json(source),
+            escape_json(target),
+            moves.len(),
+            summary.json()
+        );
+    } else if !is_quiet() {
+        println!("mv: {} -> {} ({} object(s))", source, target, moves.len());
+        println!("{}", summary.line());
+    }
+    Ok(())
+}
+
+/// A prefix names a "folder": `photos` must not also pick up `photos2/...`, so it is
+/// listed as `photos/` (the whole bucket stays empty).
+fn folder_prefix(prefix: &str) -> String {
+    match prefix.trim_matches('/') {
+        "" => String::new(),
+        p => format!("{p}/"),
+    }
+}
+
+/// Whether `prefix` is `folder` itself or somewhere below it; `folder` is a normalized
+/// `dir/` prefix, empty for the whole bucket.
+fn prefix_inside_folder(prefix: &str, folder: &str) -> bool {
+    format!("{}/", prefix.trim_matches('/')).starts_with(folder)
+}
+
+/// `rename alias/bucket/old alias/bucket/new`: both sides must name objects in the
+/// same alias and bucket; anything else is a `mv`.
+fn parse_rename_args(args: &[String]) -> Result<(S3Target, S3Target), String> {
+    let usage = "usage: s4 rename <alias/bucket/old-key> <alias/bucket/new-key>";
+    if args.len() != 3 || args[1..].iter().any(|a| a.starts_with('-')) {
+        return Err(usage.to_string());
+    }
+    let from = parse_target(&args[1])?;
+    let to = parse_target(&args[2])?;
+    if from.key.is_none() || to.key.is_none() {
+        return Err(usage.to_string());
+    }
+    if from.alias != to.alias || from.bucket != to.bucket {
+        return Err(format!(
+            "rename works within one bucket ({} vs {}); use 's4 mv' to move between buckets or aliases",
+            args[1], args[2]
+        ));
+    }
+    if from.key == to.key {
+        return Err(format!("source and destination are the same: {}", args[1]));
+    }
+    Ok((from, to))
+}
+
+fn cmd_rename(
+    alias: &AliasConfig,
+    from: &S3Target,
+    to: &S3Target,
+    json: bool,
+    debug: bool,
+) -> Result<(), S4Error> {
+    let bucket = req_bucket(from, "rename").map_err(S4Error::Usage)?;
+    let old_key = req_key(from, "rename").map_err(S4Error::Usage)?;
+    let new_key = req_key(to, "rename").map_err(S4Error::Usage)?;
+    let object = |key: &str| S3ObjectRef {
+        alias: alias.clone(),
+        bucket: bucket.clone(),
+        key: key.to_string(),
+    };
+    copy_object_s3_to_s3(
+        &object(&old_key),
+        &object(&new_key),
+        &UploadOptions::default(),
+        false,
+        debug,
+    )
+    .map_err(|e| not_found_error(e, &from.alias, &bucket, &old_key))?;
+    s3_request(
+        alias,
+        S3Call {
+            method: "DELETE",
+            bucket: &bucket,
+            key: Some(&old_key),
+            ..S3Call::default()
+        },
+        debug,
+    )?;
+    let source = format!("{}/{}/{}", from.alias, bucket, old_key);
+    let target = format!("{}/{}/{}", to.alias, bucket, new_key);
+    if json {
+        println!(
+            "{{\"status\":\"ok\",\"command\":\"rename\",\"source\":\"{}\",\"target\":\"{}\"}}",
+            escape_json(&source),
+            escape_json(&target)
+        );
+    } else if !is_quiet() {
+        println!("rename: {} -> {}", source, target);
+    }
+    Ok(())
+}
+
+#[derive(Clone)]
+struct S3ObjectRef {
+    alias: AliasConfig,
+    bucket: String,
+    key: String,
+}
+
+enum ObjectRef {
+    S3(S3ObjectRef),
+    Local(String),
+}
+
+/// What `--if-size-differs` compares: the size, plus the MD5 when `--checksum` asked
+/// for it and it is known (multipart ETags are not an MD5 of the object).
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TransferFingerprint {
+    size: u64,
+    md5: Option<String>,
+}
+
+impl TransferFingerprint {
+    fn matches(&self, other: &TransferFingerprint) -> bool {
+        self.size == other.size
+            && match (&self.md5, &other.md5) {
+                (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+                _ => true,
+            }
+    }
+}
+
+/// `None` when the object or file does not exist.
+fn fingerprint(
+    object: &ObjectRef,
+    checksum: bool,
+    debug: bool,
+) -> Result<Option<TransferFingerprint>, S4Error> {
+    match object {
+        ObjectRef::Local(path) => {
+            let path = Path::new(path);
+            let Ok(meta) = fs::metadata(path) else {
+                return Ok(None);
+            };
+            let md5 = if checksum {
+                Some(file_md5_hex(path)?)
+            } else {
+                None
+            };
+            Ok(Some(TransferFingerprint {
+                size: meta.len(),
+                md5,
+            }))
+        }
+        ObjectRef::S3(obj) => {
+            let head = match s3_request(
+                &obj.alias,
+                S3Call {
+                    method: "HEAD",
+                    bucket: &obj.bucket,
+                    key: Some(&obj.key),
+                    ..S3Call::default()
+                },
+                debug,
+            ) {
+                Ok(head) => head,
+                Err(S4Error::Http { status: 404, .. }) => return Ok(None),
+                Err(err) => return Err(err),
+            };
+            Ok(head_fingerprint(&head, checksum))
+        }
+    }
+}
+
+fn head_fingerprint(head: &str, checksum: bool) -> Option<TransferFingerprint> {
+    let size = head_header(head, "content-length")?.parse().ok()?;
+    let md5 = head_header(head, "etag")
+        .and_then(|etag| etag_md5(&etag))
+        .filter(|_| checksum);
+    Some(TransferFingerprint { size, md5 })
+}
+
+/// The MD5 an ETag stands for, unless it is a multipart (`...-N`) or otherwise opaque ETag.
+fn etag_md5(etag: &str) -> Option<String> {
+    let etag = etag.trim_matches('"');
+    (etag.len() == 32 && !etag.contains('-')).then(|| etag.to_string())
+}
+
+fn file_md5_hex(path: &Path) -> Result<String, S4Error> {
+    let script = r#"
+import hashlib, sys
+h = hashlib.md5()
+with open(sys.argv[1], 'rb') as f:
+    for chunk in iter(lambda: f.read(1 << 20), b''):
+        h.update(chunk)
+print(h.hexdigest())
+"#;
+    let out = run_tool(Command::new("python3").arg("-c").arg(script).arg(path))?;
+    if !out.status.success() {
+        return Err(S4Error::Io(format!(
+            "failed to hash {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&out.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
+
+fn classify_ref(config: &AppConfig, value: &str) -> ObjectRef {
+    // Only an explicit alias may shadow a path that exists (or is clearly local).
+    let explicit = value
+        .split('/')
+        .next()
+        .is_some_and(|a| config.aliases.contains_key(a));
+    if !explicit && (value.starts_with('.') || value.starts_with('/') || Path::new(value).exists())
+    {
+        return ObjectRef::Local(value.to_string());
+    }
+    if let Ok(t) = parse_target(value)
+        && let Some(alias) = config.aliases.get(&t.alias)
+        && let (Some(bucket), Some(key)) = (t.bucket, t.key)
+    {
+        return ObjectRef::S3(S3ObjectRef {
+            alias: alias.clone(),
+            bucket,
+            key,
+        });
+    }
+    ObjectRef::Local(value.to_string())
+}
+
+fn plan_cp_mv(
+    command: &str,
+    src: &ObjectRef,
+    dst: &ObjectRef,
+    source: &str,
+    target: &str,
+) -> Result<Vec<PlannedAction>, S4Error> {
+    let mut actions = Vec::new();
+    match (src, dst) {
+        (ObjectRef::Local(src_path), ObjectRef::S3(_)) => {
+            let size = fs::metadata(src_path)
+                .map_err(|_| format!("source file not found: {src_path}"))?
+                .len();
+            actions.push(PlannedAction {
+                op: "PUT",
+                target: target.to_string(),
+                bytes: Some(size),
+            });
+        }
+        (ObjectRef::S3(_), ObjectRef::Local(_)) => actions.push(PlannedAction {
+            op: "GET",
+            target: format!("{source} -> {target}"),
+            bytes: None,
+        }),
+        (ObjectRef::S3(_), ObjectRef::S3(_)) => actions.push(PlannedAction {
+            op: "COPY",
+            target: format!("{source} -> {target}"),
+            bytes: None,
+        }),
+        (ObjectRef::Local(_), ObjectRef::Local(_)) => actions.push(PlannedAction {
+            op: "COPY",
+            target: format!("{source} -> {target}"),
+            bytes: None,
+        }),
+    }
+    if command == "mv" {
+        actions.push(PlannedAction {
+            op: "DELETE",
+            target: source.to_string(),
+            bytes: None,
+        });
+    }
+    Ok(actions)
+}
+
+fn copy_object_s3_to_s3(
+    src: &S3ObjectRef,
+    dst: &S3ObjectRef,
+    upload: &UploadOptions,
+    preserve: bool,
+    debug: bool,
+) -> Result<CopyResult, S4Error> {
+    let copy_source = format!(
+        "/{}/{}",
+        uri_encode_segment(&src.bucket),
+        uri_encode_path(&src.key)
+    );
+    let mut headers = vec![format!("x-amz-copy-source: {}", copy_source)];
+    headers.extend_from_slice(&upload.preconditions);
+    if let Some(algorithm) = upload.checksum {
+        // CopyObject recomputes the checksum server-side from the source bytes.
+        headers.push(format!("x-amz-checksum-algorithm: {}", algorithm.name()));
+    }
+    let head = if preserve || !upload.headers.is_empty() {
+        s3_request(
+            &src.alias,
+            S3Call {
+                method: "HEAD",
+                bucket: &src.bucket,
+                key: Some(&src.key),
+                ..S3Call::default()
+            },
+            debug,
+        )?
+    } else {
+        String::new()
+    };
+    if !upload.headers.is_empty() {
+        headers.extend(copy_replace_headers(&head, &upload.headers));
+    }
+    // CopyObject copies tags by default but never the source's object-lock settings.
+    let tagging = if preserve {
+        headers.extend(object_lock_copy_headers(&head));
+        // Some gateways answer 404 instead of an empty TagSet for untagged objects.
+        match s3_request(
+            &src.alias,
+            S3Call {
+                method: "GET",
+                bucket: &src.bucket,
+                key: Some(&src.key),
+                query: "tagging",
+                ..S3Call::default()
+            },
+            debug,
+        ) {
+            Ok(xml) => Some(xml).filter(|xml| xml.contains("<Tag>")),
+            Err(S4Error::Http { status: 404, .. }) => None,
+            Err(err) => return Err(err),
+        }
+    } else {
+        None
+    };
+    let body = s3_request(
+        &dst.alias,
+        S3Call {
+            method: "PUT",
+            bucket: &dst.bucket,
+            key: Some(&dst.key),
+            headers: &headers,
+            ..S3Call::default()
+        },
+        debug,
+    )
+    .and_then(|body| parse_copy_result(&body))
+    .map_err(|e| explain_precondition_failure(e, &dst.bucket, &dst.key, &upload.preconditions))?;
+    if let Some(xml) = tagging {
+        // Re-apply explicitly rather than trusting the COPY directive, which some
+        // gateways ignore; PUT ?tagging is also not bound by header size limits.
+        let temp = temp_path(format!("s4-tagging-{}.xml", std::process::id()));
+        fs::write(&temp, xml).map_err(|e| e.to_string())?;
+        let md5 = content_md5_header(&temp)?;
+        let res = s3_request(
+            &dst.alias,
+            S3Call {
+                method: "PUT",
+                bucket: &dst.bucket,
+                key: Some(&dst.key),
+                query: "tagging",
+                upload_file: Some(&temp),
+                headers: &[format!("Content-MD5: {}", md5)],
+                ..S3Call::default()
+            },
+            debug,
+        );
+        remove_temp(&temp);
+        res?;
+    }
+    Ok(body)
+}
+
+/// CopyObject ignores new object headers unless told to replace the metadata, and
+/// REPLACE drops whatever is not re-sent, so the source's headers (from its HEAD)
+/// come along unless `overrides` sets the same name.
+fn copy_replace_headers(head: &str, overrides: &[String]) -> Vec<String> {
+    let overridden = |line: &String| {
+        let name = line.split(':').next().unwrap_or_default();
+        overrides.iter().any(|h| {
+            h.split(':')
+                .next()
+                .unwrap_or_default()
+                .eq_ignore_ascii_case(name)
+        })
+    };
+    let mut headers = vec!["x-amz-metadata-directive: REPLACE".to_string()];
+    headers.extend(
+        append_carry_headers(head)
+            .into_iter()
+            .filter(|line| !overridden(line)),
+    );
+    headers.extend_from_slice(overrides);
+    headers
+}
+
+/// The new object as reported in a CopyObject response.
+#[derive(Debug, Default, PartialEq)]
+struct CopyResult {
+    etag: String,
+    last_modified: String,
+}
+
+/// Like CompleteMultipartUpload, CopyObject can fail after answering `200 OK`, with an
+/// `<Error>` body instead of `<CopyObjectResult>`.
+fn parse_copy_result(body: &str) -> Result<CopyResult, S4Error> {
+    if let Some(err) = parse_s3_error(body) {
+        return Err(S4Error::Http {
+            status: 200,
+            body: format!("{}: {}", err.code, err.message),
+        });
+    }
+    Ok(CopyResult {
+        etag: first_tag_value(body, "ETag")
+            .trim()
+            .trim_matches('"')
+            .to_string(),
+        last_modified: first_tag_value(body, "LastModified").trim().to_string(),
+    })
+}
+
+/// Object-lock response headers from a HEAD, re-sent on CopyObject by `--preserve`.
+fn object_lock_copy_headers(head: &str) -> Vec<String> {
+    head.lines()
+        .filter_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            let name = name.trim().to_ascii_lowercase();
+            matches!(
+                name.as_str(),
+                "x-amz-object-lock-mode"
+                    | "x-amz-object-lock-retain-until-date"
+                    | "x-amz-object-lock-legal-hold"
+            )
+            .then(|| format!("{}: {}", name, value.trim()))
+        })
+        .collect()
+}
+
+#[derive(Debug, Default)]
+struct DuOptions {
+    /// `--depth N`: roll sizes up per prefix down to N levels below the target prefix.
+    depth: usize,
+    /// `--sort name`: order rows by prefix instead of by total bytes (descending).
+    by_name: bool,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct DuEntry {
+    prefix: String,
+    objects: u64,
+    bytes: u64,
+}
+
+fn parse_du_args(args: &[String]) -> Result<(DuOptions, S3Target), String> {
+    let usage = "usage: s4 du [--depth N] [--sort size|name] <alias/bucket[/prefix]>";
+    let mut opts = DuOptions::default();
+    let mut target = None;
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--depth" => {
+                let value = args
+                    .get(i + 1)
+                    .ok_or_else(|| "--depth expects a value".to_string())?;
+                opts.depth = value
+                    .parse()
+                    .map_err(|_| format!("invalid --depth value: {value}"))?;
+                i += 2;
+            }
+            "--sort" => {
+                opts.by_name = match args.get(i + 1).map(String::as_str) {
+                    Some("size") => false,
+                    Some("name") => true,
+                    Some(other) => return Err(format!("invalid --sort value: {other}")),
+                    None => return Err("--sort expects size or name".to_string()),
+                };
+                i += 2;
+            }
+            f if f.starts_with('-') => return Err(format!("unknown du flag: {f}")),
+            _ if target.is_none() => {
+                target = Some(parse_target(&args[i])?);
+                i += 1;
+            }
+            _ => return Err(usage.to_string()),
+        }
+    }
+    let target = target.ok_or_else(|| usage.to_string())?;
+    Ok((opts, target))
+}
+
+/// Groups objects by their first `depth` key segments below `prefix`, counting each
+/// object towards every enclosing level (like `du -d N`). The final entry is the
+/// rollup of the whole prefix; the others are sorted by size, or by name.
+fn du_rollup(objects: &[ListedObject], prefix: &str, depth: usize, by_name: bool) -> Vec<DuEntry> {
+    let mut groups: BTreeMap<String, DuEntry> = BTreeMap::new();
+    let mut total = DuEntry {
+        prefix: prefix.to_string(),
+        ..DuEntry::default()
+    };
+    for obj in objects {
+        total.objects += 1;
+        total.bytes += obj.size;
+        let rest = obj.key.strip_prefix(prefix).unwrap_or(&obj.key);
+        let dirs: Vec<&str> = rest.split('/').collect();
+        let levels = depth.min(dirs.len() - 1);
+        for level in 1..=levels {
+            let group = format!("{}{}/", prefix, dirs[..level].join("/"));
+            let entry = groups.entry(group.clone()).or_insert_with(|| DuEntry {
+                prefix: group,
+                ..DuEntry::default()
+            });
+            entry.objects += 1;
+            entry.bytes += obj.size;
+        }
+    }
+    let mut entries: Vec<DuEntry> = groups.into_values().collect();
+    if !by_name {
+        entries.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.prefix.cmp(&b.prefix)));
+    }
+    entries.push(total);
+    entries
+}
+
+fn cmd_du(
+    alias: &AliasConfig,
+    bucket: &str,
+    prefix: &str,
+    opts: &DuOptions,
+    output: OutputFormat,
+    debug: bool,
+) -> Result<(), S4Error> {
+    let objects = list_objects(alias, bucket, prefix, false, debug)?;
+    let rows: Vec<Vec<String>> = du_rollup(&objects, prefix, opts.depth, opts.by_name)
+        .into_iter()
+        .map(|e| vec![e.prefix, e.objects.to_string(), e.bytes.to_string()])
+        .collect();
+    print_records(output, &["prefix", "objects", "bytes"], &rows, |row| {
+        let bytes: u64 = row[2].parse().unwrap_or(0);
+        format!(
+            "{:>10}  {:>8}  {}/{}",
+            format_bytes(bytes),
+            row[1],
+            bucket,
+            row[0]
+        )
+    });
+    Ok(())
+}
+
+const USAGE_USAGE: &str = "usage: s4 usage [--quota] <alias/bucket[/prefix]>";
+
+fn parse_usage_args(args: &[String]) -> Result<(bool, S3Target), String> {
+    let mut quota = false;
+    let mut target = None;
+    for arg in &args[1..] {
+        match arg.as_str() {
+            "--quota" => quota = true,
+            f if f.starts_with('-') => return Err(format!("unknown usage flag: {f}")),
+            _ if target.is_none() => target = Some(parse_target(arg)?),
+            _ => return Err(USAGE_USAGE.to_string()),
+        }
+    }
+    Ok((quota, target.ok_or(USAGE_USAGE)?))
+}
+
+/// A MinIO bucket quota as returned by the admin API.
+#[derive(Debug, PartialEq)]
+struct BucketQuota {
+    bytes: u64,
+    /// `hard` (writes rejected past the limit) on current MinIO releases.
+    kind: String,
+}
+
+/// `{"quota":1073741824,"quotatype":"hard",...}`; a zero quota means none is set.
+fn parse_minio_quota(body: &str) -> Result<Option<BucketQuota>, String> {
+    let objects = parse_json_object_array(&format!("[{}]", body.trim()))?;
+    let obj = objects.first().ok_or("empty quota response")?;
+    let bytes = match obj.get("quota") {
+        Some(JsonScalar::Num(n)) => n
+            .parse::<u64>()
+            .map_err(|_| format!("invalid quota value: {n}"))?,
+        _ => return Err("quota response has no \"quota\" field".to_string()),
+    };
+    let kind = match obj.get("quotatype") {
+        Some(JsonScalar::Str(kind)) => kind.clone(),
+        _ => "hard".to_string(),
+    };
+    Ok((bytes > 0).then_some(BucketQuota { bytes, kind }))
+}
+
+/// Asks the MinIO admin API (`/minio/admin/v3/get-bucket-quota`) for the bucket quota.
+/// It needs admin rights and does not exist elsewhere, so failures come back as `Err`
+/// for the caller to report as "unavailable"; "no quota configured" is `Ok(None)`.
+fn fetch_minio_quota(
+    alias: &AliasConfig,
+    bucket: &str,
+    debug: bool,
+) -> Result<Option<BucketQuota>, S4Error> {
+    // The admin API lives at a fixed path, whatever addressing the alias uses for buckets.
+    let admin = AliasConfig {
+        path_style: true,
+        ..alias.clone()
+    };
+    let query = format!("bucket={}", uri_encode_segment(bucket));
+    match s3_request(
+        &admin,
+        S3Call {
+            method: "GET",
+            bucket: "minio",
+            key: Some("admin/v3/get-bucket-quota"),
+            query: &query,
+            ..S3Call::default()
+        },
+        debug,
+    ) {
+        Ok(body) => parse_minio_quota(&body).map_err(S4Error::Other),
+        Err(S4Error::Http { body, .. }) if body.contains("NoSuchQuotaConfiguration") => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// `usage`: bytes and object count under a bucket or prefix (one listing pass, like a
+/// `du` total), plus the MinIO bucket quota with `--quota`.
+fn cmd_usage(
+    alias: &AliasConfig,
+    bucket: &str,
+    prefix: &str,
+    quota: bool,
+    json: bool,
+    debug: bool,
+) -> Result<(), S4Error> {
+    let (mut used, mut count) = (0u64, 0usize);
+    for_each_object_page(alias, bucket, prefix, false, debug, |page| {
+        count += page.len();
+        used += page.iter().map(|obj| obj.size).sum::<u64>();
+        Ok(())
+    })?;
+    let quota = if quota {
+        match fetch_minio_quota(alias, bucket, debug) {
+            Ok(quota) => Some(quota),
+            Err(err) => {
+                eprintln!("warning: bucket quota unavailable (MinIO admin API): {err}");
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let label = if prefix.is_empty() {
+        bucket.to_string()
+    } else {
+        format!("{bucket}/{prefix}")
+    };
+
+    if json {
+        let quota = match quota.flatten() {
+            Some(q) => format!(
+                "{{\"bytes\":{},\"type\":\"{}\"}}",
+                q.bytes,
+                escape_json(&q.kind)
+            ),
+            None => "null".to_string(),
+        };
+        println!(
+            "{{\"bucket\":\"{}\",\"prefix\":\"{}\",\"used_bytes\":{used},\"object_count\":{},\"quota\":{quota}}}",
+            escape_json(bucket),
+            escape_json(prefix),
+            count
+        );
+        return Ok(());
+    }
+    println!("{}  {count} object(s)  {label}", format_bytes(used));
+    match quota {
+        Some(Some(q)) => println!(
+            "quota {} ({}), {:.1}% used",
+            format_bytes(q.bytes),
+            q.kind,
+            used as f64 * 100.0 / q.bytes as f64
+        ),
+        Some(None) => println!("quota none"),
+        None => {}
+    }
+    Ok(())
+}
+
+const API_USAGE: &str = "usage: s4 api [--method GET|HEAD|PUT|POST|DELETE] [--query QUERY] [--body FILE] [--header 'Name: value']... <alias[/bucket[/key]]>";
+
+/// `s4 api`: one raw request exactly as given.
+#[derive(Debug)]
+struct ApiRequest {
+    method: String,
+    /// Sent and signed as written (`acl`, `versioning`, `uploads&prefix=a%2Fb`).
+    query: String,
+    body: Option<PathBuf>,
+    headers: Vec<String>,
+    target: S3Target,
+}
+
+fn parse_api_args(args: &[String]) -> Result<ApiRequest, String> {
+    let mut method = "GET".to_string();
+    let mut query = String::new();
+    let mut body = None;
+    let mut headers = Vec::new();
+    let mut target = None;
+    let mut i = 1;
+    while i < args.len() {
+        let flag = args[i].as_str();
+        let value = || {
+            args.get(i + 1)
+                .cloned()
+                .ok_or_else(|| format!("{flag} expects a value"))
+        };
+        match flag {
+            "--method" | "-X" => {
+                method = value()?.to_ascii_uppercase();
+                if !matches!(method.as_str(), "GET" | "HEAD" | "PUT" | "POST" | "DELETE") {
+                    return Err(format!("unsupported --method: {method}"));
+                }
+                i += 2;
+            }
+            "--query" => {
+                query = value()?.trim_start_matches('?').to_string();
+                i += 2;
+            }
+            "--body" => {
+                body = Some(PathBuf::from(value()?));
+                i += 2;
+            }
+            "--header" | "-H" => {
+                let header = value()?;
+                match header.split_once(':') {
+                    Some((name, _)) if !name.trim().is_empty() => headers.push(header),
+                    _ => return Err(format!("--header expects 'Name: value', got: {header}")),
+                }
+                i += 2;
+            }
+            f if f.starts_with('-') => return Err(format!("unknown api flag: {f}")),
+            _ if target.is_none() => {
+                target = Some(parse_target(&args[i])?);
+                i += 1;
+            }
+            _ => return Err(API_USAGE.to_string()),
+        }
+    }
+    if body.is_some() && !matches!(method.as_str(), "PUT" | "POST") {
+        return Err(format!("--body needs --method PUT or POST, not {method}"));
+    }
+    Ok(ApiRequest {
+        method,
+        query,
+        body,
+        headers,
+        target: target.ok_or(API_USAGE)?,
+    })
+}
+
+/// `s4 api`: the advanced escape hatch behind every wrapped command. Signs and sends
+/// the request as given, with no validation or confirmation, and writes the raw
+/// response body (headers for HEAD) to stdout; a non-2xx status is an error as usual.
+fn cmd_api(alias: &AliasConfig, request: &ApiRequest, debug: bool) -> Result<(), S4Error> {
+    let target = &request.target;
+    let body = s3_request_bytes(
+        alias,
+        S3Call {
+            method: &request.method,
+            bucket: target.bucket.as_deref().unwrap_or(""),
+            key: target.key.as_deref(),
+            query: &request.query,
+            upload_file: request.body.as_deref(),
+            headers: &request.headers,
+            ..S3Call::default()
+        },
+        debug,
+    )?;
+    let mut stdout = std::io::stdout().lock();
+    stdout
+        .write_all(&body)
+        .and_then(|_| stdout.flush())
+        .map_err(|e| S4Error::Io(e.to_string()))
+}
+
+const FIND_USAGE: &str = "usage: s4 find [--owner] [--skip-dir-markers|--only-dir-markers] <alias/bucket[/prefix]> [needle]";
+
+#[derive(Debug, Default)]
+struct FindOptions {
+    /// `--owner`: list with `fetch-owner=true` and add owner columns.
+    owner: bool,
+    dir_markers: DirMarkers,
+    /// Substring a key must contain; all keys when absent.
+    needle: Option<String>,
+}
+
+/// Flags come before the target; everything after it is positional, so a needle that
+/// looks like a flag (`s4 find a/b --owner`) is searched for, not swallowed.
+fn parse_find_args(args: &[String]) -> Result<(FindOptions, S3Target), String> {
+    let mut opts = FindOptions::default();
+    let mut i = 1;
+    while let Some(arg) = args.get(i).filter(|a| a.starts_with('-')) {
+        if arg == "--owner" {
+            opts.owner = true;
+        } else if !opts.dir_markers.parse_flag(arg)? {
+            return Err(format!("unknown find flag: {arg}"));
+        }
+        i += 1;
+    }
+    let target = match &args[i..] {
+        [target] => target,
+        [target, needle] => {
+            opts.needle = Some(needle.clone());
+            target
+        }
+        _ => return Err(FIND_USAGE.to_string()),
+    };
+    Ok((opts, parse_target(target)?))
+}
+
+fn cmd_find(
+    alias: &AliasConfig,
+    target: &S3Target,
+    opts: &FindOptions,
+    output: OutputFormat,
+    debug: bool,
+) -> Result<(), S4Error> {
+    let bucket = req_bucket(target, "find").map_err(S4Error::Usage)?;
+    let bucket = bucket.as_str();
+    let prefix = target.key.as_deref().unwrap_or_default();
+    let (owner, dir_markers, needle) = (opts.owner, opts.dir_markers, opts.needle.as_deref());
+    let mut columns = vec!["bucket", "key"];
+    if owner {
+        columns.extend(OWNER_COLUMNS);
+    }
+    // Matches are printed page by page, so huge buckets are never held in memory;
+    // human columns are aligned within each page.
+    let mut stream = RecordStream::new(output, &columns);
+    for_each_object_page(alias, bucket, prefix, owner, debug, |page| {
+        let rows: Vec<Vec<String>> = page
+            .into_iter()
+            .filter(|obj| dir_markers.keeps(obj))
+            .filter(|obj| needle.is_none_or(|n| obj.key.contains(n)))
+            .map(|obj| {
+                let mut row = vec![bucket.to_string(), obj.key];
+                if owner {
+                    row.extend(owner_fields(obj.owner.as_ref()));
+                }
+                row
+            })
+            .collect();
+        let key_width = column_widths(&rows).get(1).copied().unwrap_or(0);
+        for row in &rows {
+            stream.row(row, |row| {
+                let cells = if owner {
+                    vec![row[1].clone(), row[3].clone()]
+                } else {
+                    vec![row[1].clone()]
+                };
+                aligned_row(&cells, &[key_width], &[], |i, cell| {
+                    (i == 0).then(|| key_style(cell)).flatten()
+                })
+            });
+        }
+        Ok(())
+    })?;
+    stream.finish();
+    Ok(())
+}
+
+fn cmd_tree(
+    alias: &AliasConfig,
+    bucket: &str,
+    prefix: &str,
+    _json: bool,
+    debug: bool,
+) -> Result<(), S4Error> {
+    let mut keys = list_object_keys(alias, bucket, prefix, debug)?;
+    keys.sort();
+    let color = color_enabled();
+    println!("{}", paint(&format!("{bucket}/"), STYLE_DIR, color));
+    for line in tree_lines(&keys, color) {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+/// The lines under the bucket root for sorted `keys`: each directory once, indented two
+/// spaces per level, then its entries. A directory marker (`dir/`) only opens its
+/// directory. Sorted keys keep each directory contiguous, so a directory line is
+/// emitted the first time a key enters it and `open` only tracks the current path.
+fn tree_lines(keys: &[String], color: bool) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut open: Vec<&str> = Vec::new();
+    for key in keys {
+        let mut parts: Vec<&str> = key.split('/').collect();
+        let name = parts.pop().unwrap_or_default();
+        let common = open.iter().zip(&parts).take_while(|(a, b)| a == b).count();
+        open.truncate(common);
+        for dir in &parts[common..] {
+            let indent = "  ".repeat(open.len() + 1);
+            lines.push(format!(
+                "{indent}{}",
+                paint(&format!("{dir}/"), STYLE_DIR, color)
+            ));
+            open.push(dir);
+        }
+        if !name.is_empty() {
+            lines.push(format!("{}{name}", "  ".repeat(parts.len() + 1)));
+        }
+    }
+    lines
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HeadMode {
+    Lines(usize),
+    Bytes(u64),
+}
+
+fn parse_head_args(args: &[String]) -> Result<(HeadMode, bool, S3Target), String> {
+    let usage = "usage: s4 head [--bytes N] [--decompress] <alias/bucket/key> [lines]";
+    let mut mode = None;
+    let mut decompress = false;
+    let mut target = None;
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--bytes" | "-c" => {
+                let value = args.get(i + 1).ok_or("--bytes expects a value")?;
+                let n = value
+                    .parse::<u64>()
+                    .map_err(|_| format!("invalid --bytes value: {value}"))?;
+                mode = Some(HeadMode::Bytes(n));
+                i += 2;
+            }
+            "--decompress" => {
+                decompress = true;
+                i += 1;
+            }
+            f if f.starts_with('-') => return Err(format!("unknown head flag: {f}")),
+            _ if target.is_none() => {
+                target = Some(parse_target(&args[i])?);
+                i += 1;
+            }
+            v if mode.is_none() => {
+                let n = v
+                    .parse::<usize>()
+                    .map_err(|_| "head lines must be integer".to_string())?;
+                mode = Some(HeadMode::Lines(n));
+                i += 1;
+            }
+            _ => return Err(usage.to_string()),
+        }
+    }
+    let target = target.ok_or(usage)?;
+    Ok((mode.unwrap_or(HeadMode::Lines(10)), decompress, target))
+}
+
+/// First ranged read for `head` in line mode; doubled on every further read.
+const HEAD_CHUNK_BYTES: u64 = 64 * 1024;
+
+/// Byte offset just past the `lines`-th newline in `buf`, if there are that many.
+fn head_line_end(buf: &[u8], lines: usize) -> Option<usize> {
+    if lines == 0 {
+        return Some(0);
+    }
+    buf.iter()
+        .enumerate()
+        .filter(|(_, b)| **b == b'\n')
+        .nth(lines - 1)
+        .map(|(idx, _)| idx + 1)
+}
+
+/// What `head` has read so far. With `--decompress` on a gzip-encoded object the
+/// ranged chunks are inflated as they arrive, so limits apply to the decoded bytes.
+struct HeadBody {
+    fetched: u64,
+    raw: Vec<u8>,
+    gunzip: Option<flate2::write::MultiGzDecoder<Vec<u8>>>,
+}
+
+impl HeadBody {
+    fn new(gzip: bool) -> Self {
+        HeadBody {
+            fetched: 0,
+            raw: Vec::new(),
+            gunzip: gzip.then(|| flate2::write::MultiGzDecoder::new(Vec::new())),
+        }
+    }
+
+    fn push(&mut self, chunk: &[u8]) -> Result<(), S4Error> {
+        self.fetched += chunk.len() as u64;
+        match self.gunzip.as_mut() {
+            Some(decoder) => decoder
+                .write_all(chunk)
+                .and_then(|_| decoder.flush())
+                .map_err(|e| S4Error::Other(format!("failed to decompress gzip body: {e}"))),
+            None => {
+                self.raw.extend_from_slice(chunk);
+                Ok(())
+            }
+        }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        match &self.gunzip {
+            Some(decoder) => decoder.get_ref(),
+            None => &self.raw,
+        }
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        match self.gunzip {
+            // Not `finish()`: head usually stops in the middle of the gzip stream.
+            Some(mut decoder) => std::mem::take(decoder.get_mut()),
+            None => self.raw,
+        }
+    }
+}
+
+/// Where the `head` output ends in `buf`, once enough has been read.
+fn head_end(buf: &[u8], mode: HeadMode) -> Option<usize> {
+    match mode {
+        HeadMode::Bytes(n) => (buf.len() as u64 >= n).then_some(n as usize),
+        HeadMode::Lines(lines) => head_line_end(buf, lines),
+    }
+}
+
+/// Ranged GET of `start..=end`; `None` once `start` is past the end of the object (416).
+fn fetch_head_range(
+    alias: &AliasConfig,
+    bucket: &str,
+    key: &str,
+    start: u64,
+    end: u64,
+    debug: bool,
+) -> Result<Option<S3Response>, S4Error> {
+    match s3_request_response(
+        alias,
+        S3Call {
+            method: "GET",
+            bucket,
+            key: Some(key),
+            headers: &[format!("Range: bytes={start}-{end}")],
+            ..S3Call::default()
+        },
+        debug,
+    ) {
+        Ok(response) => Ok(Some(response)),
+        Err(S4Error::Http { status: 416, .. }) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Reads only as much of the object as needed: the first N bytes, or growing ranges
+/// until N newlines are seen. Output is written raw, so binary objects survive;
+/// `--decompress` inflates a gzip `Content-Encoding` before counting.
+fn cmd_head(
+    alias: &AliasConfig,
+    bucket: &str,
+    key: &str,
+    mode: HeadMode,
+    decompress: bool,
+    debug: bool,
+) -> Result<(), S4Error> {
+    let mut chunk = match mode {
+        HeadMode::Bytes(n) => n,
+        HeadMode::Lines(_) => HEAD_CHUNK_BYTES,
+    };
+    let mut body = HeadBody::new(false);
+    while head_end(body.bytes(), mode).is_none() {
+        let start = body.fetched;
+        let Some(response) = fetch_head_range(alias, bucket, key, start, start + chunk - 1, debug)?
+        else {
+            break;
+        };
+        let got = response.body.len() as u64;
+        if start == 0 || got > chunk {
+            // A server that ignores Range sends the whole object.
+            body = HeadBody::new(decompress && is_gzip_encoding(&response.content_encoding));
+        }
+        body.push(&response.body)?;
+        if got != chunk {
+            break;
+        }
+        chunk *= 2;
+    }
+    let mut out = body.into_bytes();
+    if let Some(end) = head_end(&out, mode) {
+        out.truncate(end);
+    }
+    if matches!(mode, HeadMode::Lines(_)) && out.last().is_some_and(|b| *b != b'\n') {
+        out.push(b'\n');
+    }
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(&out)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+fn cmd_ping(alias_name: &str, alias: &AliasConfig, json: bool, debug: bool) -> Result<(), S4Error> {
+    let start = Instant::now();
+    let _ = s3_request(
+        alias,
+        S3Call {
+            method: "GET",
+            ..S3Call::default()
+        },
+        debug,
+    )?;
+    let ms = start.elapsed().as_millis();
+
+    if json {
+        println!(
+            "{{\"alias\":\"{}\",\"status\":\"ok\",\"latency_ms\":{}}}",
+            escape_json(alias_name),
+            ms
+        );
+    } else if !is_quiet() {
+        println!("{} is alive ({} ms)", alias_name, ms);
+    }
+    Ok(())
+}
+
+fn parse_ping_args(args: &[String]) -> Result<(Option<usize>, S3Target), String> {
+    let usage = "usage: s4 ping [--count N] <alias>";
+    let mut count = None;
+    let mut target = None;
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--count" | "-c" => {
+                let value = args.get(i + 1).ok_or("--count expects a value")?;
+                count = Some(
+                    value
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|n| *n > 0)
+                        .ok_or_else(|| format!("invalid --count value: {value}"))?,
+                );
+                i += 2;
+            }
+            f if f.starts_with('-') => return Err(format!("unknown ping flag: {f}")),
+            _ if target.is_none() => {
+                target = Some(parse_target(&args[i])?);
+                i += 1;
+            }
+            _ => return Err(usage.to_string()),
+        }
+    }
+    Ok((count, target.ok_or(usage)?))
+}
+
+/// `(min, avg, max, p95)` in ms over successful samples; p95 uses the nearest rank.
+fn ping_stats(latencies: &[u128]) -> Option<(u128, u128, u128, u128)> {
+    if latencies.is_empty() {
+        return None;
+    }
+    let mut sorted = latencies.to_vec();
+    sorted.sort_unstable();
+    let rank = (sorted.len() * 95).div_ceil(100).max(1);
+    let avg = sorted.iter().sum::<u128>() / sorted.len() as u128;
+    Some((sorted[0], avg, sorted[sorted.len() - 1], sorted[rank - 1]))
+}
+
+/// `ping --count N`: N timed `GET /` requests one second apart, then a summary.
+/// Fails only when every request failed, like `ping` with no replies.
+fn cmd_ping_count(
+    alias_name: &str,
+    alias: &AliasConfig,
+    count: usize,
+    json: bool,
+    debug: bool,
+) -> Result<(), S4Error> {
+    let mut samples: Vec<Result<u128, S4Error>> = Vec::with_capacity(count);
+    for seq in 1..=count {
+        if seq > 1 {
+            sleep(Duration::from_secs(1));
+        }
+        let start = Instant::now();
+        let sample = s3_request(
+            alias,
+            S3Call {
+                method: "GET",
+                ..S3Call::default()
+            },
+            debug,
+        )
+        .map(|_| start.elapsed().as_millis());
+        if !json && !is_quiet() {
+            match &sample {
+                Ok(ms) => println!("{alias_name}: seq={seq} time={ms} ms"),
+                Err(err) => println!("{alias_name}: seq={seq} error: {err}"),
+            }
+        }
+        samples.push(sample);
+    }
+
+    let latencies: Vec<u128> = samples
+        .iter()
+        .filter_map(|s| s.as_ref().ok())
+        .copied()
+        .collect();
+    let lost = count - latencies.len();
+    let stats = ping_stats(&latencies);
+    if json {
+        let items: Vec<String> = samples
+            .iter()
+            .enumerate()
+            .map(|(idx, sample)| match sample {
+                Ok(ms) => format!("{{\"seq\":{},\"latency_ms\":{ms}}}", idx + 1),
+                Err(err) => format!(
+                    "{{\"seq\":{},\"error\":\"{}\"}}",
+                    idx + 1,
+                    escape_json(&err.to_string())
+                ),
+            })
+            .collect();
+        let summary = match stats {
+            Some((min, avg, max, p95)) => format!(
+                "\"count\":{count},\"lost\":{lost},\"min_ms\":{min},\"avg_ms\":{avg},\"max_ms\":{max},\"p95_ms\":{p95}"
+            ),
+            None => format!("\"count\":{count},\"lost\":{lost}"),
+        };
+        println!(
+            "{{\"alias\":\"{}\",\"samples\":[{}],\"summary\":{{{}}}}}",
+            escape_json(alias_name),
+            items.join(","),
+            summary
+        );
+    } else if !is_quiet() {
+        println!("--- {alias_name} ping statistics ---");
+        println!("{count} requests, {} ok, {lost} lost", latencies.len());
+        if let Some((min, avg, max, p95)) = stats {
+            println!("min/avg/max/p95 = {min}/{avg}/{max}/{p95} ms");
+        }
+    }
+
+    match samples.into_iter().find_map(Result::err) {
+        Some(err) if lost == count => Err(err),
+        _ => Ok(()),
+    }
+}
+
+fn looks_ready_xml(body: &str) -> bool {
+    body.contains("<ListAllMyBucketsResult") || body.contains("<Error")
+}
+
+/// The `GET /` probe behind `ready`, shared with `alias set --test`.
+fn check_ready(alias: &AliasConfig, debug: bool) -> Result<(), S4Error> {
+    let body = s3_request(
+        alias,
+        S3Call {
+            method: "GET",
+            ..S3Call::default()
+        },
+        debug,
+    )?;
+    if !looks_ready_xml(&body) {
+        return Err("ready check got unexpected response body".into());
+    }
+    Ok(())
+}
+
+fn parse_ready_args(args: &[String]) -> Result<(bool, S3Target), String> {
+    let usage = "usage: s4 ready [--health-endpoint] <alias>";
+    let mut health_endpoint = false;
+    let mut target = None;
+    for arg in &args[1..] {
+        match arg.as_str() {
+            "--health-endpoint" => health_endpoint = true,
+            f if f.starts_with('-') => return Err(format!("unknown ready flag: {f}")),
+            _ if target.is_none() => target = Some(parse_target(arg)?),
+            _ => return Err(usage.to_string()),
+        }
+    }
+    Ok((health_endpoint, target.ok_or(usage)?))
+}
+
+/// MinIO's unauthenticated liveness/readiness probes, checked in this order.
+const MINIO_HEALTH_PATHS: [&str; 2] = ["/minio/health/live", "/minio/health/ready"];
+
+/// Unsigned `GET` of `path` under the alias endpoint; returns the HTTP status code.
+fn health_probe(alias: &AliasConfig, path: &str, debug: bool) -> Result<String, S4Error> {
+    let endpoint = parse_endpoint(&alias.endpoint)?;
+    let url = format!(
+        "{}://{}{}{}",
+        endpoint.scheme, endpoint.host, endpoint.base_path, path
+    );
+    let mut cmd = Command::new("curl");
+    apply_curl_global_flags(&mut cmd, false, false);
+    cmd.arg("-sS")
+        .arg(&url)
+        .arg("-o")
+        .arg("/dev/null")
+        .arg("-w")
+        .arg("%{http_code}");
+    if debug {
+        eprintln!("[debug] health probe: GET {}", url);
+    }
+    let out = run_curl(&mut cmd, false)?;
+    if !out.status.success() {
+        return Err(S4Error::Network(format!(
+            "health probe {} failed: {}",
+            path,
+            String::from_utf8_lossy(&out.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
+
+/// `ready --health-endpoint`: every MinIO health path must answer 200.
+fn check_health_endpoints(alias: &AliasConfig, debug: bool) -> Result<(), S4Error> {
+    for path in MINIO_HEALTH_PATHS {
+        let status = health_probe(alias, path, debug)?;
+        if status != "200" {
+            return Err(S4Error::Http {
+                status: status.parse().unwrap_or(0),
+                body: format!("health endpoint {path} returned HTTP {status}"),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn cmd_ready(
+    alias_name: &str,
+    alias: &AliasConfig,
+    health_endpoint: bool,
+    json: bool,
+    debug: bool,
+) -> Result<(), S4Error> {
+    if health_endpoint {
+        check_health_endpoints(alias, debug)?;
+    } else {
+        check_ready(alias, debug)?;
+    }
+
+    let check = if health_endpoint { "health" } else { "s3" };
+    if json {
+        println!(
+            "{{\"alias\":\"{}\",\"ready\":true,\"check\":\"{}\"}}",
+            escape_json(alias_name),
+            check
+        );
+    } else if !is_quiet() {
+        println!("{} is ready", alias_name);
+    }
+    Ok(())
+}
+
+/// `whoami`: proves the alias credentials with a signed `ListBuckets` and prints which
+/// identity they belong to, alongside the alias settings with the secret redacted.
+fn cmd_whoami(
+    alias_name: &str,
+    alias: &AliasConfig,
+    json: bool,
+    debug: bool,
+) -> Result<(), S4Error> {
+    let check = s3_request(
+        alias,
+        S3Call {
+            method: "GET",
+            ..S3Call::default()
+        },
+        debug,
+    );
+    let (owner_id, owner_name) = match &check {
+        Ok(body) => list_buckets_owner(body),
+        Err(_) => (String::new(), String::new()),
+    };
+    let secret = redact_secret(&alias.secret_key);
+    if json {
+        let profile = alias
+            .aws_profile
+            .as_ref()
+            .map(|p| format!("\"{}\"", escape_json(p)))
+            .unwrap_or_else(|| "null".to_string());
+        let auth = match &check {
+            Ok(_) => format!(
+                "\"authenticated\":true,\"owner\":{{\"id\":\"{}\",\"display_name\":\"{}\"}}",
+                escape_json(&owner_id),
+                escape_json(&owner_name)
+            ),
+            Err(err) => format!(
+                "\"authenticated\":false,\"error\":\"{}\"",
+                escape_json(&err.to_string())
+            ),
+        };
+        println!(
+            "{{\"alias\":\"{}\",\"endpoint\":\"{}\",\"region\":\"{}\",\"path_style\":{},\"access_key\":\"{}\",\"secret_key\":\"{}\",\"session_token\":{},\"aws_profile\":{},{}}}",
+            escape_json(alias_name),
+            escape_json(&alias.endpoint),
+            escape_json(&alias.region),
+            alias.path_style,
+            escape_json(&alias.access_key),
+            escape_json(&secret),
+            alias.session_token.is_some(),
+            profile,
+            auth
+        );
+    } else {
+        println!("Alias:         {alias_name}");
+        println!("Endpoint:      {}", alias.endpoint);
+        println!("Region:        {}", alias.region);
+        println!("Path style:    {}", alias.path_style);
+        println!("Access key:    {}", alias.access_key);
+        println!("Secret key:    {secret}");
+        if let Some(profile) = &alias.aws_profile {
+            println!("AWS profile:   {profile}");
+        }
+        if alias.session_token.is_some() {
+            println!("Session token: set");
+        }
+        match (&check, owner_name.is_empty(), owner_id.is_empty()) {
+            (Err(_), _, _) => println!("Auth:          failed"),
+            (Ok(_), true, true) => println!("Auth:          ok"),
+            (Ok(_), true, false) => println!("Auth:          ok (owner id {owner_id})"),
+            (Ok(_), false, _) => println!("Auth:          ok (owner {owner_name}, id {owner_id})"),
+        }
+    }
+    // The original error keeps its exit code: 5 for a rejected key, 7 for a network failure.
+    check.map(|_| ())
+}
+
+/// `(ID, DisplayName)` of the `<Owner>` in a `ListAllMyBucketsResult`; empty when absent.
+fn list_buckets_owner(body: &str) -> (String, String) {
+    extract_tag_blocks(body, "Owner")
+        .first()
+        .map(|owner| {
+            (
+                first_tag_value(owner, "ID"),
+                first_tag_value(owner, "DisplayName"),
+            )
+        })
+        .unwrap_or_default()
+}
+
+/// Keeps only the last four characters of a secret, and only when it is long enough
+/// that they do not give it away.
+fn redact_secret(secret: &str) -> String {
+    let chars: Vec<char> = secret.chars().collect();
+    match chars.len() {
+        0 => String::new(),
+        n if n < 16 => "****".to_string(),
+        n => format!("****{}", chars[n - 4..].iter().collect::<String>()),
+    }
+}
+
+/// Only `BucketAlreadyOwnedByYou` counts as success: `BucketAlreadyExists` means the
+/// name is taken by another account, so treating it as idempotent would hide a real failure.
+fn is_bucket_owned_error(err: &str) -> bool {
+    parse_s3_error(err).is_some_and(|e| e.code == "BucketAlreadyOwnedByYou")
+}
+
+/// `us-east-1` is the implicit default and must not be sent as a LocationConstraint.
+fn build_create_bucket_xml(region: &str) -> Option<String> {
+    if region.is_empty() || region == "us-east-1" {
+        return None;
+    }
+    Some(format!(
+        "<CreateBucketConfiguration><LocationConstraint>{}</LocationConstraint></CreateBucketConfiguration>",
+        xml_escape(region)
+    ))
+}
+
+fn parse_location_constraint(body: &str) -> String {
+    // The element usually carries an xmlns attribute and is self-closing for us-east-1.
+    let Some(start) = body.find("<LocationConstraint") else {
+        return "us-east-1".to_string();
+    };
+    let rest = &body[start..];
+    let Some(open_end) = rest.find('>') else {
+        return "us-east-1".to_string();
+    };
+    if rest[..open_end].ends_with('/') {
+        return "us-east-1".to_string();
+    }
+    let inner = &rest[open_end + 1..];
+    let value = inner
+        .find("</LocationConstraint>")
+        .map(|end| inner[..end].trim())
+        .unwrap_or("");
+    match value {
+        "" => "us-east-1".to_string(),
+        // Legacy value returned for buckets created in the original EU region.
+        "EU" => "eu-west-1".to_string(),
+        other => xml_unescape(other),
+    }
+}
+
+fn cmd_location(alias: &AliasConfig, bucket: &str, json: bool, debug: bool) -> Result<(), S4Error> {
+    let body = s3_request(
+        alias,
+        S3Call {
+            method: "GET",
+            bucket,
+            query: "location",
+            ..S3Call::default()
+        },
+        debug,
+    )?;
+    let region = parse_location_constraint(&body);
+    if json {
+        println!(
+            "{{\"bucket\":\"{}\",\"region\":\"{}\"}}",
+            escape_json(bucket),
+            escape_json(&region)
+        );
+    } else {
+        println!("{}", region);
+    }
+    Ok(())
+}
+
+fn cmd_pipe(
+    alias: &AliasConfig,
+    bucket: &str,
+    key: &str,
+    upload: &UploadOptions,
+    json: bool,
+    debug: bool,
+) -> Result<(), S4Error> {
+    // curl needs a file (or its own stdin) per request, so the body cannot go out as
+    // one streaming request. Small inputs are buffered into one temp file; anything
+    // past the multipart threshold is sent part by part without storing the whole stream.
+    // Hashing every staged part before sending it is pure latency for a stream; TLS
+    // already protects the body in transit, so only skip it over HTTPS.
+    if alias.endpoint.starts_with("https://") {
+        UNSIGNED_PAYLOAD_MODE.store(true, Ordering::Relaxed);
+    }
+    let mut stdin = std::io::stdin().lock();
+    let mut head = Vec::new();
+    if !upload.compress {
+        (&mut stdin)
+            .take(MULTIPART_THRESHOLD_BYTES)
+            .read_to_end(&mut head)
+            .map_err(|e| e.to_string())?;
+        if head.len() as u64 == MULTIPART_THRESHOLD_BYTES {
+            let mut body = std::io::Cursor::new(head).chain(stdin);
+            multipart_upload_reader(alias, bucket, key, &mut body, None, upload, debug)?;
+            print_pipe_uploaded(bucket, key, json);
+            return Ok(());
+        }
+    }
+
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| e.to_string())?
+        .as_nanos();
+    let temp_path = temp_path(format!("s4-pipe-{}-{}", std::process::id(), ts));
+    let written = fs::File::create(&temp_path).and_then(|mut out| {
+        out.write_all(&head)?;
+        std::io::copy(&mut stdin, &mut out)
+    });
+    let upload_result = written
+        .map_err(S4Error::from)
+        .and_then(|_| upload_file_to_s3(alias, bucket, key, &temp_path, upload, debug));
+    remove_temp(&temp_path);
+    upload_result?;
+    print_pipe_uploaded(bucket, key, json);
+    Ok(())
+}
+
+fn print_pipe_uploaded(bucket: &str, key: &str, json: bool) {
+    if json {
+        println!(
+            "{{\"uploaded\":{{\"bucket\":\"{}\",\"key\":\"{}\",\"source\":\"stdin\"}}}}",
+            escape_json(bucket),
+            escape_json(key)
+        );
+    } else if !is_quiet() {
+        println!("Uploaded STDIN to '{}/{}'", bucket, key);
+    }
+}
+
+/// How `append` added the data: a write-offset PUT, or a rewrite of the whole object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AppendMode {
+    Unchanged,
+    Created,
+    Native,
+    Rewrite,
+}
+
+impl AppendMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Unchanged => "unchanged",
+            Self::Created => "created",
+            Self::Native => "native",
+            Self::Rewrite => "rewrite",
+        }
+    }
+}
+
+/// Only S3 Express directory buckets (`name--zone--x-s3`) advertise append through
+/// their naming; elsewhere a server that ignores `x-amz-write-offset-bytes` would
+/// replace the object with just the appended bytes, so that path needs `--native`.
+fn supports_native_append(bucket: &str) -> bool {
+    bucket.ends_with("--x-s3")
+}
+
+/// Object headers a rewrite must carry over so the object keeps its type and metadata.
+fn append_carry_headers(head: &str) -> Vec<String> {
+    head.lines()
+        .filter_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            let lower = name.trim().to_ascii_lowercase();
+            let keep = lower.starts_with("x-amz-meta-")
+                || matches!(
+                    lower.as_str(),
+                    "content-type"
+                        | "content-encoding"
+                        | "content-disposition"
+                        | "content-language"
+                        | "cache-control"
+                );
+            keep.then(|| format!("{}: {}", name.trim(), value.trim()))
+        })
+        .collect()
+}
+
+/// `append`: adds stdin to the end of an object. Without backend support the object is
+/// downloaded, extended locally and uploaded again with `If-Match` on the old ETag, so a
+/// concurrent writer makes the append fail instead of being silently overwritten.
+fn cmd_append(
+    alias: &AliasConfig,
+    bucket: &str,
+    key: &str,
+    native: bool,
+    json: bool,
+    debug: bool,
+) -> Result<(), S4Error> {
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| e.to_string())?
+        .as_nanos();
+    let data_path = temp_path(format!("s4-append-{}-{}", std::process::id(), ts));
+    let result = fs::File::create(&data_path)
+        .and_then(|mut out| std::io::copy(&mut std::io::stdin().lock(), &mut out))
+        .map_err(S4Error::from)
+        .and_then(|appended| {
+            append_staged(alias, bucket, key, &data_path, native, debug)
+                .map(|(mode, size)| (appended, mode, size))
+        });
+    remove_temp(&data_path);
+    let (appended, mode, size) = result?;
+    if json {
+        println!(
+            "{{\"appended\":{{\"bucket\":\"{}\",\"key\":\"{}\",\"bytes\":{},\"size\":{},\"mode\":\"{}\"}}}}",
+            escape_json(bucket),
+            escape_json(key),
+            appended,
+            size,
+            mode.as_str()
+        );
+    } else if !is_quiet() {
+        println!(
+            "Appended {} to '{}/{}' (now {}, {})",
+            format_bytes(appended),
+            bucket,
+            key,
+            format_bytes(size),
+            mode.as_str()
+        );
+    }
+    Ok(())
+}
+
+/// Appends the staged file; returns how, and the object size afterwards.
+fn append_staged(
+    alias: &AliasConfig,
+    bucket: &str,
+    key: &str,
+    data_path: &Path,
+    native: bool,
+    debug: bool,
+) -> Result<(AppendMode, u64), S4Error> {
+    let appended = fs::metadata(data_path)?.len();
+    let head = match s3_request(
+        alias,
+        S3Call {
+            method: "HEAD",
+            bucket,
+            key: Some(key),
+            ..S3Call::default()
+        },
+        debug,
+    ) {
+        Ok(head) => head,
+        Err(S4Error::Http { status: 404, .. }) => {
+            let upload = UploadOptions {
+                preconditions: vec!["If-None-Match: *".to_string()],
+                ..UploadOptions::default()
+            };
+            upload_file_to_s3(alias, bucket, key, data_path, &upload, debug)?;
+            return Ok((AppendMode::Created, appended));
+        }
+        Err(err) => return Err(err),
+    };
+    let size: u64 = head_header(&head, "content-length")
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| S4Error::Other(format!("no Content-Length for {bucket}/{key}")))?;
+    let etag = head_header(&head, "etag")
+        .ok_or_else(|| S4Error::Other(format!("no ETag for {bucket}/{key}")))?;
+    if appended == 0 {
+        return Ok((AppendMode::Unchanged, size));
+    }
+
+    if native || supports_native_append(bucket) {
+        s3_request(
+            alias,
+            S3Call {
+                method: "PUT",
+                bucket,
+                key: Some(key),
+                upload_file: Some(data_path),
+                headers: &[format!("x-amz-write-offset-bytes: {size}")],
+                ..S3Call::default()
+            },
+            debug,
+        )?;
+        let after = s3_request(
+            alias,
+            S3Call {
+                method: "HEAD",
+                bucket,
+                key: Some(key),
+                ..S3Call::default()
+            },
+            debug,
+        )?;
+        let new_size: u64 = head_header(&after, "content-length")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        if new_size != size + appended {
+            return Err(S4Error::Other(format!(
+                "{bucket}/{key} is {new_size} bytes after a native append of {appended} to {size}; \
+                 the backend does not support x-amz-write-offset-bytes and may have replaced the object"
+            )));
+        }
+        return Ok((AppendMode::Native, new_size));
+    }
+
+    let mut whole = data_path.as_os_str().to_owned();
+    whole.push(".whole");
+    let whole = PathBuf::from(whole);
+    track_temp(&whole);
+    let result = s3_request(
+        alias,
+        S3Call {
+            method: "GET",
+            bucket,
+            key: Some(key),
+            output_file: Some(&whole),
+            ..S3Call::default()
+        },
+        debug,
+    )
+    .and_then(|_| {
+        let mut out = fs::OpenOptions::new().append(true).open(&whole)?;
+        std::io::copy(&mut fs::File::open(data_path)?, &mut out)?;
+        Ok(())
+    })
+    .and_then(|_| {
+        let upload = UploadOptions {
+            preconditions: vec![format!("If-Match: {etag}")],
+            headers: append_carry_headers(&head),
+            ..UploadOptions::default()
+        };
+        upload_file_to_s3(alias, bucket, key, &whole, &upload, debug)
+    });
+    remove_temp(&whole);
+    result?;
+    Ok((AppendMode::Rewrite, size + appended))
+}
+
+#[derive(Debug, Default)]
+struct LsOptions {
+    /// `--owner`: list with `fetch-owner=true` and add owner columns.
+    owner: bool,
+    /// `--folders`/`-d`: list with `delimiter=/` and print only the common prefixes.
+    folders: bool,
+    /// `--lock-status`: HEAD every listed object for its retention and legal hold.
+    lock_status: bool,
+    dir_markers: DirMarkers,
+}
+
+const LS_USAGE: &str = "usage: s4 ls [--owner] [--folders|-d] [--lock-status] [--skip-dir-markers|--only-dir-markers] <alias[/bucket[/prefix]]>";
+
+fn parse_ls_args(args: &[String]) -> Result<(LsOptions, S3Target), String> {
+    let mut opts = LsOptions::default();
+    let mut target = None;
+    for arg in &args[1..] {
+        match arg.as_str() {
+            "--owner" => opts.owner = true,
+            "--folders" | "-d" => opts.folders = true,
+            "--lock-status" => opts.lock_status = true,
+            f if opts.dir_markers.parse_flag(f)? => {}
+            f if f.starts_with('-') => return Err(format!("unknown ls flag: {f}")),
+            _ if target.is_none() => target = Some(parse_target(arg)?),
+            _ => return Err(LS_USAGE.to_string()),
+        }
+    }
+    let target = target.ok_or_else(|| LS_USAGE.to_string())?;
+    if opts.folders && opts.owner {
+        return Err("--folders cannot be combined with --owner".to_string());
+    }
+    if opts.lock_status && (opts.folders || opts.owner) {
+        return Err("--lock-status cannot be combined with --folders or --owner".to_string());
+    }
+    if opts.dir_markers != DirMarkers::Keep && (opts.folders || opts.lock_status) {
+        return Err(
+            "--skip-dir-markers/--only-dir-markers cannot be combined with --folders or --lock-status"
+                .to_string(),
+        );
+    }
+    if opts.lock_status && target.bucket.is_none() {
+        return Err(
+            "ls --lock-status needs a bucket: s4 ls --lock-status <alias/bucket[/prefix]>"
+                .to_string(),
+        );
+    }
+    if opts.folders && target.bucket.is_none() {
+        return Err(
+            "ls --folders needs a bucket: s4 ls --folders <alias/bucket[/prefix]>".to_string(),
+        );
+    }
+    Ok((opts, target))
+}
+
+/// Owner columns appended to listing rows for `--owner`.
+const OWNER_COLUMNS: [&str; 2] = ["owner_id", "owner_display_name"];
+
+/// Retention and legal hold of one object, from its HEAD response; empty when the
+/// object (or bucket) has no object-lock settings.
+#[derive(Debug, Default, PartialEq)]
+struct ObjectLockStatus {
+    mode: String,
+    retain_until: String,
+    legal_hold: String,
+}
+
+fn object_lock_status(head: &str) -> ObjectLockStatus {
+    let header = |name: &str| head_header(head, name).unwrap_or_default();
+    ObjectLockStatus {
+        mode: header("x-amz-object-lock-mode"),
+        retain_until: header("x-amz-object-lock-retain-until-date"),
+        legal_hold: header("x-amz-object-lock-legal-hold"),
+    }
+}
+
+/// `ls --lock-status`: the listing has no lock information, so every object costs a
+/// HEAD; those run in parallel up to `--max-concurrent`, printed in listing order.
+fn ls_lock_status(
+    alias: &AliasConfig,
+    bucket: &str,
+    prefix: &str,
+    output: OutputFormat,
+    debug: bool,
+) -> Result<(), S4Error> {
+    let objects = list_objects(alias, bucket, prefix, false, debug)?;
+    let mut rows = Vec::with_capacity(objects.len());
+    run_ordered(
+        objects.len(),
+        MAX_CONCURRENT.load(Ordering::Relaxed),
+        |idx| {
+            let head = s3_request(
+                alias,
+                S3Call {
+                    method: "HEAD",
+                    bucket,
+                    key: Some(&objects[idx].key),
+                    ..S3Call::default()
+                },
+                debug,
+            )?;
+            Ok(object_lock_status(&head))
+        },
+        |idx, status| {
+            rows.push(vec![
+                objects[idx].key.clone(),
+                status.mode,
+                status.retain_until,
+                status.legal_hold,
+            ]);
+            Ok(())
+        },
+    )?;
+    let cells = |row: &[String]| {
+        let or_dash = |v: &String| {
+            if v.is_empty() {
+                "-".to_string()
+            } else {
+                v.clone()
+            }
+        };
+        vec![
+            row[0].clone(),
+            or_dash(&row[1]),
+            or_dash(&row[2]),
+            format!("legal_hold={}", or_dash(&row[3])),
+        ]
+    };
+    let widths = column_widths(&rows.iter().map(|row| cells(row)).collect::<Vec<_>>());
+    print_records(
+        output,
+        &["key", "mode", "retain_until", "legal_hold"],
+        &rows,
+        |row| {
+            aligned_row(&cells(row), &widths, &[], |i, cell| {
+                (i == 0).then(|| key_style(cell)).flatten()
+            })
+        },
+    );
+    Ok(())
+}
+
+fn owner_fields(owner: Option<&ListedOwner>) -> [String; 2] {
+    owner
+        .map(|o| [o.id.clone(), o.display_name.clone()])
+        .unwrap_or_default()
+}
+
+fn cmd_ls(
+    alias: &AliasConfig,
+    target: &S3Target,
+    opts: &LsOptions,
+    output: OutputFormat,
+    debug: bool,
+) -> Result<(), S4Error> {
+    let Some(bucket) = &target.bucket else {
+        let body = s3_request(
+            alias,
+            S3Call {
+                method: "GET",
+                ..S3Call::default()
+            },
+            debug,
+        )?;
+        let rows = bucket_list_rows(&body);
+        let widths = column_widths(&rows);
+        print_records(output, &["name", "creation_date"], &rows, |row| {
+            aligned_row(row, &widths, &[], |i, _| (i == 0).then_some(STYLE_DIR))
+        });
+        return Ok(());
+    };
+    if opts.folders {
+        let prefix = target.key.clone().unwrap_or_default();
+        let prefixes = list_common_prefixes(alias, bucket, &prefix, debug)?;
+        print_prefixes(output, &prefixes);
+        return Ok(());
+    }
+    if opts.lock_status {
+        let prefix = target.key.clone().unwrap_or_default();
+        return ls_lock_status(alias, bucket, &prefix, output, debug);
+    }
+    let page = ListQuery {
+        fetch_owner: opts.owner,
+        ..ListQuery::default()
+    };
+    let (body, _) = list_page(alias, bucket, &page, None, debug)?;
+    // Human object listings stay the raw XML response; scripts (and CI) grep it directly.
+    // A marker filter cannot be applied to the XML, so it switches to the row output.
+    if output == OutputFormat::Human && opts.dir_markers == DirMarkers::Keep {
+        println!("{body}");
+        return Ok(());
+    }
+    let mut columns = vec!["key", "size", "last_modified", "etag"];
+    let mut rows = object_list_rows(&body, opts.dir_markers);
+    if opts.owner {
+        columns.extend(OWNER_COLUMNS);
+        let listed = parse_listed_objects(&body).into_iter();
+        for (row, obj) in rows
+            .iter_mut()
+            .zip(listed.filter(|o| opts.dir_markers.keeps(o)))
+        {
+            row.extend(owner_fields(obj.owner.as_ref()));
+        }
+    }
+    let widths = column_widths(&rows);
+    print_records(output, &columns, &rows, |row| {
+        aligned_row(row, &widths, &[1], |i, cell| match i {
+            0 => key_style(cell),
+            1 => Some(STYLE_DIM),
+            _ => None,
+        })
+    });
+    Ok(())
+}
+
+fn bucket_list_rows(xml: &str) -> Vec<Vec<String>> {
+    extract_tag_blocks(xml, "Bucket")
+        .iter()
+        .map(|block| {
+            vec![
+                first_tag_value(block, "Name"),
+                first_tag_value(block, "CreationDate"),
+            ]
+        })
+        .collect()
+}
+
+fn object_list_rows(xml: &str, dir_markers: DirMarkers) -> Vec<Vec<String>> {
+    parse_listed_objects(xml)
+        .into_iter()
+        .filter(|obj| dir_markers.keeps(obj))
+        .map(|obj| vec![obj.key, obj.size.to_string(), obj.last_modified, obj.etag])
+        .collect()
+}
+
+fn first_tag_value(xml: &str, tag: &str) -> String {
+    extract_tag_values(xml, tag)
+        .first()
+        .map(|v| xml_unescape(v))
+        .unwrap_or_default()
+}
+
+/// Single place that turns tabular command results into the selected `--output` format.
+/// `human` renders one line per row for the terminal.
+fn print_records(
+    output: OutputFormat,
+    columns: &[&str],
+    rows: &[Vec<String>],
+    human: impl Fn(&[String]) -> String,
+) {
+    let mut stream = RecordStream::new(output, columns);
+    for row in rows {
+        stream.row(row, &human);
+    }
+    stream.finish();
+}
+
+/// `print_records` for rows that arrive page by page: the CSV header and the JSON
+/// array are opened up front and every row is printed as soon as it is known.
+struct RecordStream<'a> {
+    output: OutputFormat,
+    columns: &'a [&'a str],
+    rows: usize,
+}
+
+impl<'a> RecordStream<'a> {
+    fn new(output: OutputFormat, columns: &'a [&'a str]) -> Self {
+        if output == OutputFormat::Csv {
+            println!("{}", columns.join(","));
+        }
+        Self {
+            output,
+            columns,
+            rows: 0,
+        }
+    }
+
+    fn row(&mut self, row: &[String], human: impl Fn(&[String]) -> String) {
+        match self.output {
+            OutputFormat::Human => println!("{}", human(row)),
+            OutputFormat::Json => print!(
+                "{}{}",
+                if self.rows == 0 { "[" } else { "," },
+                json_record(self.columns, row)
+            ),
+            OutputFormat::Jsonl => println!("{}", json_record(self.columns, row)),
+            OutputFormat::Csv => println!(
+                "{}",
+                row.iter()
+                    .map(|v| csv_field(v))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+        }
+        self.rows += 1;
+    }
+
+    /// Closes the JSON array. Dropping the stream does the same, so a listing that
+    /// fails mid-way still leaves a well-formed (if partial) array before the error.
+    fn finish(self) {}
+}
+
+impl Drop for RecordStream<'_> {
+    fn drop(&mut self) {
+        if self.output == OutputFormat::Json {
+            println!("{}]", if self.rows == 0 { "[" } else { "" });
+        }
+    }
+}
+
+/// Columns emitted as JSON numbers rather than strings.
+const NUMERIC_COLUMNS: [&str; 3] = ["size", "objects", "bytes"];
+
+fn json_record(columns: &[&str], row: &[String]) -> String {
+    let fields: Vec<String> = columns
+        .iter()
+        .zip(row)
+        .map(|(c, v)| {
+            if NUMERIC_COLUMNS.contains(c) && v.parse::<u64>().is_ok() {
+                format!("\"{}\":{v}", escape_json(c))
+            } else {
+                format!("\"{}\":\"{}\"", escape_json(c), escape_json(v))
+            }
+        })
+        .collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn list_object_keys(
+    alias: &AliasConfig,
+    bucket: &str,
+    prefix: &str,
+    debug: bool,
+) -> Result<Vec<String>, S4Error> {
+    Ok(list_objects(alias, bucket, prefix, false, debug)?
+        .into_iter()
+        .map(|obj| obj.key)
+        .collect())
+}
+
+/// Zero-byte keys ending in `/` that consoles create to show empty "folders".
+fn is_dir_marker(obj: &ListedObject) -> bool {
+    obj.size == 0 && obj.key.ends_with('/')
+}
+
+/// `--skip-dir-markers` / `--only-dir-markers` on listings.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum DirMarkers {
+    #[default]
+    Keep,
+    Skip,
+    Only,
+}
+
+impl DirMarkers {
+    fn keeps(self, obj: &ListedObject) -> bool {
+        match self {
+            Self::Keep => true,
+            Self::Skip => !is_dir_marker(obj),
+            Self::Only => is_dir_marker(obj),
+        }
+    }
+
+    /// Sets the mode from a flag; `Ok(false)` when `flag` is not one of the two.
+    fn parse_flag(&mut self, flag: &str) -> Result<bool, String> {
+        let mode = match flag {
+            "--skip-dir-markers" => Self::Skip,
+            "--only-dir-markers" => Self::Only,
+            _ => return Ok(false),
+        };
+        if *self != Self::Keep && *self != mode {
+            return Err(
+                "use either --skip-dir-markers or --only-dir-markers, not both".to_string(),
+            );
+        }
+        *self = mode;
+        Ok(true)
+    }
+}
+
+/// One `<Contents>` entry of a ListObjectsV2 page. Fields the server omits are left
+/// empty (or 0 for the size).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct ListedObject {
+    key: String,
+    size: u64,
+    etag: String,
+    last_modified: String,
+    storage_class: String,
+    /// Only returned when the listing asked for `fetch-owner=true`.
+    owner: Option<ListedOwner>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct ListedOwner {
+    id: String,
+    display_name: String,
+}
+
+fn list_common_prefixes(
+    alias: &AliasConfig,
+    bucket: &str,
+    prefix: &str,
+    debug: bool,
+) -> Result<Vec<String>, S4Error> {
+    let mut prefixes = Vec::new();
+    let mut cursor: Option<String> = None;
+
+    loop {
+        let page = ListQuery {
+            prefix,
+            delimiter: true,
+            max_keys: LIST_PAGE_SIZE.load(Ordering::Relaxed),
+            ..ListQuery::default()
+        };
+        let (body, next) = list_page(alias, bucket, &page, cursor.as_deref(), debug)?;
+        prefixes.extend(parse_common_prefixes(&body));
+        cursor = next;
+        if cursor.is_none() {
+            break;
+        }
+    }
+
+    Ok(prefixes)
+}
+
+fn parse_common_prefixes(body: &str) -> Vec<String> {
+    extract_tag_values(body, "CommonPrefixes")
+        .iter()
+        .filter_map(|block| extract_tag_values(block, "Prefix").into_iter().next())
+        .map(|p| {
+            let mut p = xml_unescape(p.trim());
+            if !p.ends_with('/') {
+                p.push('/');
+            }
+            p
+        })
+        .collect()
+}
+
+fn print_prefixes(output: OutputFormat, prefixes: &[String]) {
+    match output {
+        OutputFormat::Json => println!(
+            "[{}]",
+            prefixes
+                .iter()
+                .map(|p| format!("\"{}\"", escape_json(p)))
+                .collect::<Vec<_>>()
+                .join(",")
+        ),
+        _ => {
+            let rows: Vec<Vec<String>> = prefixes.iter().map(|p| vec![p.clone()]).collect();
+            let color = color_enabled();
+            print_records(output, &["prefix"], &rows, |row| {
+                paint(&row[0], STYLE_DIR, color)
+            });
+        }
+    }
+}
+
+fn list_objects(
+    alias: &AliasConfig,
+    bucket: &str,
+    prefix: &str,
+    fetch_owner: bool,
+    debug: bool,
+) -> Result<Vec<ListedObject>, S4Error> {
+    let mut objects = Vec::new();
+    for_each_object_page(alias, bucket, prefix, fetch_owner, debug, |page| {
+        objects.extend(page);
+        Ok(())
+    })?;
+    Ok(objects)
+}
+
+/// Streaming form of `list_objects` for huge buckets: each page is handed to `visit`
+/// and dropped before the next request, so memory stays at one page (`--page-size`).
+/// An error from `visit` stops the listing.
+fn for_each_object_page(
+    alias: &AliasConfig,
+    bucket: &str,
+    prefix: &str,
+    fetch_owner: bool,
+    debug: bool,
+    mut visit: impl FnMut(Vec<ListedObject>) -> Result<(), S4Error>,
+) -> Result<(), S4Error> {
+    let mut cursor: Option<String> = None;
+
+    loop {
+        let page = ListQuery {
+            prefix,
+            fetch_owner,
+            max_keys: LIST_PAGE_SIZE.load(Ordering::Relaxed),
+            ..ListQuery::default()
+        };
+        let (body, next) = list_page(alias, bucket, &page, cursor.as_deref(), debug)?;
+        visit(parse_listed_objects(&body))?;
+        cursor = next;
+        if cursor.is_none() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Query options shared by the V2 and V1 listing calls.
+#[derive(Debug, Default, Clone, Copy)]
+struct ListQuery<'a> {
+    prefix: &'a str,
+    delimiter: bool,
+    fetch_owner: bool,
+    /// `max-keys`; 0 leaves the server default. Only callers that follow the cursor
+    /// set it (from `--page-size`): a single-page `ls` must not be cut short.
+    max_keys: usize,
+}
+
+impl ListQuery<'_> {
+    /// `cursor` is a continuation token for V2 and a marker (a key) for V1. V1 has no
+    /// `fetch-owner`: those servers always return the owner.
+    fn render(&self, v1: bool, cursor: Option<&str>) -> String {
+        let mut query = String::new();
+        if !v1 {
+            query.push_str("list-type=2");
+            if self.fetch_owner {
+                query.push_str("&fetch-owner=true");
+            }
+        }
+        if self.delimiter {
+            query.push_str("&delimiter=%2F");
+        }
+        if !self.prefix.is_empty() {
+            query.push_str("&prefix=");
+            query.push_str(&uri_encode_path(self.prefix));
+        }
+        if self.max_keys > 0 {
+            query.push_str(&format!("&max-keys={}", self.max_keys));
+        }
+        if let Some(cursor) = cursor {
+            query.push_str(if v1 {
+                "&marker="
+            } else {
+                "&continuation-token="
+            });
+            query.push_str(&uri_encode_path(cursor));
+        }
+        query.trim_start_matches('&').to_string()
+    }
+}
+
+/// Fetches one listing page and returns it with the cursor for the next one (`None`
+/// once the listing is complete). Uses ListObjectsV2 until the server rejects it, or
+/// answers a truncated V2 request without a continuation token (it ignored
+/// `list-type`), and V1 `marker=` paging from then on.
+fn list_page(
+    alias: &AliasConfig,
+    bucket: &str,
+    page: &ListQuery,
+    cursor: Option<&str>,
+    debug: bool,
+) -> Result<(String, Option<String>), S4Error> {
+    if !lists_with_v1(&alias.endpoint) {
+        let query = page.render(false, cursor);
+        match s3_request(
+            alias,
+            S3Call {
+                method: "GET",
+                bucket,
+                query: &query,
+                ..S3Call::default()
+            },
+            debug,
+        ) {
+            Ok(body) => {
+                if !listing_truncated(&body) {
+                    return Ok((body, None));
+                }
+                let token = first_tag_value(&body, "NextContinuationToken");
+                if !token.is_empty() {
+                    return Ok((body, Some(token)));
+                }
+                fall_back_to_list_v1(&alias.endpoint, "the server ignored list-type=2");
+                let next = list_v1_next_marker(&body);
+                return Ok((body, next));
+            }
+            Err(err) if cursor.is_none() && rejects_list_v2(&err) => {
+                fall_back_to_list_v1(&alias.endpoint, "the server rejected ListObjectsV2");
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    let query = page.render(true, cursor);
+    let body = s3_request(
+        alias,
+        S3Call {
+            method: "GET",
+            bucket,
+            query: &query,
+            ..S3Call::default()
+        },
+        debug,
+    )?;
+    let next = if listing_truncated(&body) {
+        list_v1_next_marker(&body)
+    } else {
+        None
+    };
+    Ok((body, next))
+}
+
+fn list_v1_endpoints() -> &'static Mutex<HashSet<String>> {
+    LIST_V1_ENDPOINTS.get_or_init(|| Mutex::new(HashSet::new()))
+}
+
+fn lists_with_v1(endpoint: &str) -> bool {
+    LIST_V1.load(Ordering::Relaxed)
+        || list_v1_endpoints()
+            .lock()
+            .map(|set| set.contains(endpoint))
+            .unwrap_or(false)
+}
+
+fn fall_back_to_list_v1(endpoint: &str, reason: &str) {
+    let first = list_v1_endpoints()
+        .lock()
+        .map(|mut set| set.insert(endpoint.to_string()))
+        .unwrap_or(false);
+    if first && !QUIET.load(Ordering::Relaxed) {
+        eprintln!("warning: {reason}; listing with ListObjects V1 (marker paging)");
+    }
+}
+
+/// Errors old gateways return for `list-type=2`; anything else is a real failure.
+fn rejects_list_v2(err: &S4Error) -> bool {
+    let S4Error::Http { status, body } = err else {
+        return false;
+    };
+    let code = parse_s3_error(body).map(|e| e.code).unwrap_or_default();
+    // A bare 400 without a code is as likely a proxy or a bad request as a missing
+    // V2, so it is not taken as a reason to switch.
+    *status == 501
+        || (*status == 400 && matches!(code.as_str(), "NotImplemented" | "InvalidArgument"))
+}
+
+fn listing_truncated(body: &str) -> bool {
+    first_tag_value(body, "IsTruncated").trim() == "true"
+}
+
+/// V1 pages continue after `<NextMarker>` when the server sends one (it does only for
+/// delimiter listings), otherwise after the last key or common prefix on the page.
+fn list_v1_next_marker(body: &str) -> Option<String> {
+    let marker = first_tag_value(body, "NextMarker");
+    if !marker.is_empty() {
+        return Some(marker);
+    }
+    let last_key = parse_listed_objects(body).pop().map(|obj| obj.key);
+    let last_prefix = parse_common_prefixes(body).pop();
+    last_key.max(last_prefix)
+}
+
+/// Each `<Contents>` block is parsed as a unit so key, size and dates stay correlated.
+fn parse_listed_objects(xml: &str) -> Vec<ListedObject> {
+    extract_tag_blocks(xml, "Contents")
+        .iter()
+        .map(|block| ListedObject {
+            key: first_tag_value(block, "Key"),
+            size: first_tag_value(block, "Size").trim().parse().unwrap_or(0),
+            etag: first_tag_value(block, "ETag").trim_matches('"').to_string(),
+            last_modified: first_tag_value(block, "LastModified"),
+            storage_class: first_tag_value(block, "StorageClass"),
+            owner: extract_tag_blocks(block, "Owner")
+                .first()
+                .map(|owner| ListedOwner {
+                    id: first_tag_value(owner, "ID"),
+                    display_name: first_tag_value(owner, "DisplayName"),
+                }),
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct ObjectVersion {
+    key: String,
+    version_id: String,
+}
+
+fn list_object_versions(
+    alias: &AliasConfig,
+    bucket: &str,
+    debug: bool,
+) -> Result<Vec<ObjectVersion>, S4Error> {
+    let mut versions = Vec::new();
+    let mut key_marker: Option<String> = None;
+    let mut version_id_marker: Option<String> = None;
+
+    loop {
+        let mut query = String::from("versions=");
+        if let Some(marker) = key_marker.as_ref() {
+            query.push_str("&key-marker=");
+            query.push_str(&uri_encode_query_component(marker));
+        }
+        if let Some(marker) = version_id_marker.as_ref() {
+            query.push_str("&version-id-marker=");
+            query.push_str(&uri_encode_query_component(marker));
+        }
+
+        let body = s3_request(
+            alias,
+            S3Call {
+                method: "GET",
+                bucket,
+                query: &query,
+                ..S3Call::default()
+            },
+            debug,
+        )?;
+        versions.extend(extract_version_entries(&body, "Version"));
+        versions.extend(extract_version_entries(&body, "DeleteMarker"));
+
+        let is_truncated = extract_tag_values(&body, "IsTruncated")
+            .into_iter()
+            .next()
+            .unwrap_or_else(|| "false".to_string())
+            .trim()
+            .eq("true");
+
+        if !is_truncated {
+            break;
+        }
+
+        key_marker = extract_tag_values(&body, "NextKeyMarker")
+            .into_iter()
+            .next()
+            .map(|v| xml_unescape(&v));
+        version_id_marker = extract_tag_values(&body, "NextVersionIdMarker")
+            .into_iter()
+            .next()
+            .map(|v| xml_unescape(&v));
+
+        if key_marker.is_none() {
+            break;
+        }
+    }
+
+    Ok(versions)
+}
+
+fn extract_tag_blocks(xml: &str, tag: &str) -> Vec<String> {
+    let open = format!("<{tag}>");
+    let close = format!("</{tag}>");
+
+    let mut out = Vec::new();
+    let mut remaining = xml;
+
+    while let Some(start) = remaining.find(&open) {
+        let after_open = &remaining[start + open.len()..];
+        let Some(end) = after_open.find(&close) else {
+            break;
+        };
+        out.push(after_open[..end].to_string());
+        remaining = &after_open[end + close.len()..];
+    }
+
+    out
+}
+
+fn extract_version_entries(xml: &str, tag: &str) -> Vec<ObjectVersion> {
+    let mut out = Vec::new();
+    for block in extract_tag_blocks(xml, tag) {
+        let key = extract_tag_values(&block, "Key")
+            .into_iter()
+            .next()
+            .map(|v| xml_unescape(&v));
+        let version_id = extract_tag_values(&block, "VersionId")
+            .into_iter()
+            .next()
+            .map(|v| xml_unescape(&v));
+        if let (Some(key), Some(version_id)) = (key, version_id) {
+            out.push(ObjectVersion { key, version_id });
+        }
+    }
+    out
+}
+
+fn purge_bucket_versions(alias: &AliasConfig, bucket: &str, debug: bool) -> Result<(), S4Error> {
+    for entry in list_object_versions(alias, bucket, debug)? {
+        let query = format!(
+            "versionId={}",
+            uri_encode_query_component(&entry.version_id)
+        );
+        match s3_request(
+            alias,
+            S3Call {
+                method: "DELETE",
+                bucket,
+                key: Some(&entry.key),
+                query: &query,
+                ..S3Call::default()
+            },
+            debug,
+        ) {
+            Ok(_) => {}
+            Err(err) if should_retry_with_governance_bypass(&err.to_string()) => {
+                let headers = vec!["x-amz-bypass-governance-retention: true".to_string()];
+                s3_request(
+                    alias,
+                    S3Call {
+                        method: "DELETE",
+                        bucket,
+                        key: Some(&entry.key),
+                        query: &query,
+                        headers: &headers,
+                        ..S3Call::default()
+                    },
+                    debug,
+                )?;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+fn sync_destination_key(source_key: &str, src_prefix: &str, dst_prefix: &str) -> String {
+    let normalized_src = src_prefix.trim_matches('/');
+    let mut relative = source_key.to_string();
+
+    if !normalized_src.is_empty() {
+        if source_key == normalized_src {
+            relative.clear();
+        } else if let Some(rest) = source_key.strip_prefix(&(normalized_src.to_string() + "/")) {
+            relative = rest.to_string();
+        }
+    }
+
+    let normalized_dst = dst_prefix.trim_matches('/');
+    if normalized_dst.is_empty() {
+        return relative;
+    }
+    if relative.is_empty() {
+        return normalized_dst.to_string();
+    }
+
+    format!("{normalized_dst}/{relative}")
+}
+
+fn extract_tag_values(xml: &str, tag: &str) -> Vec<String> {
+    let open = format!("<{tag}>");
+    let close = format!("</{tag}>");
+
+    let mut out = Vec::new();
+    let mut remaining = xml;
+
+    while let Some(start) = remaining.find(&open) {
+        let after_open = &remaining[start + open.len()..];
+        let Some(end) = after_open.find(&close) else {
+            break;
+        };
+        out.push(after_open[..end].to_string());
+        remaining = &after_open[end + close.len()..];
+    }
+
+    out
+}
+
+fn xml_unescape(s: &str) -> String {
+    s.replace("&amp;", "&")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+}
+
+fn should_retry_with_governance_bypass(err: &str) -> bool {
+    let lower = err.to_ascii_lowercase();
+    lower.contains("accessdenied")
+        || lower.contains("retention")
+        || lower.contains("governance")
+        || (lower.contains("invalidrequest") && lower.contains("worm"))
+        || lower.contains("worm protected")
+}
+
+fn req_bucket(target: &S3Target, cmd: &str) -> Result<String, String> {
+    target
+        .bucket
+        .clone()
+        .ok_or_else(|| format!("{cmd} requires alias/bucket"))
+}
+
+fn req_key(target: &S3Target, cmd: &str) -> Result<String, String> {
+    target
+        .key
+        .clone()
+        .ok_or_else(|| format!("{cmd} requires alias/bucket/key"))
+}
+
+fn normalize_sigv4_query(query: &str) -> String {
+    if query.is_empty() {
+        return String::new();
+    }
+    query
+        .split('&')
+        .map(|part| {
+            if part.is_empty() {
+                String::new()
+            } else if part.contains('=') {
+                part.to_string()
+            } else {
+                format!("{}=", part)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// One S3 request; the alias, signing and `--debug` are added by `s3_request` and
+/// friends. Unset fields mean no key, no query string, no body and no extra headers.
+#[derive(Debug, Default, Clone, Copy)]
+struct S3Call<'a> {
+    method: &'a str,
+    /// Empty for service-level requests such as ListBuckets.
+    bucket: &'a str,
+    key: Option<&'a str>,
+    /// Raw query string, as `normalize_sigv4_query` expects it.
+    query: &'a str,
+    /// Request body, sent as is.
+    upload_file: Option<&'a Path>,
+    /// Write the response body here instead of returning it.
+    output_file: Option<&'a Path>,
+    /// `Name: value` request headers, signed (see `partition_signed_headers`).
+    headers: &'a [String],
+}
+
+/// The lossy text view of `s3_request_bytes`, for XML and other text replies.
+fn s3_request(alias: &AliasConfig, call: S3Call, debug: bool) -> Result<String, S4Error> {
+    s3_request_bytes(alias, call, debug).map(|body| String::from_utf8_lossy(&body).into_owned())
+}
+
+fn normalize_resolve_entry(entry: &str) -> String {
+    if entry.contains('=') {
+        entry.replacen('=', ":", 1)
+    } else {
+        entry.to_string()
+    }
+}
+
+/// Every curl flag the global options imply, for requests to the alias endpoint.
+fn apply_curl_global_flags(cmd: &mut Command, is_upload: bool, is_download: bool) {
+    apply_curl_transport_flags(cmd, is_upload, is_download);
+    if CURL_INSECURE.load(Ordering::Relaxed) {
+        cmd.arg("-k");
+    }
+    if let Ok(opts) = curl_global_opts().lock() {
+        if let Some(path) = &opts.ca_bundle {
+            cmd.arg("--cacert").arg(path);
+        }
+        if let Some(path) = &opts.client_cert {
+            cmd.arg("--cert").arg(path);
+        }
+        if let Some(path) = &opts.client_key {
+            cmd.arg("--key").arg(path);
+        }
+        for resolve in &opts.resolve {
+            cmd.arg("--resolve").arg(normalize_resolve_entry(resolve));
+        }
+        for header in &opts.custom_headers {
+            cmd.arg("-H").arg(header);
+        }
+    }
+}
+
+/// The part of the global options that is safe for any URL: tracing, rate limits and
+/// the user agent. TLS trust, client certificates, `--resolve` and `-H` headers are
+/// meant for the S3 endpoint and must not follow a `--from-url` fetch (or its
+/// redirects) to a third-party host.
+fn apply_curl_transport_flags(cmd: &mut Command, is_upload: bool, is_download: bool) {
+    if CURL_TRACE.load(Ordering::Relaxed) || SHOW_HEADERS.load(Ordering::Relaxed) {
+        cmd.arg("-v");
+    }
+    if let Ok(opts) = curl_global_opts().lock() {
+        let limit = if !is_upload && !is_download {
+            None
+        } else if opts.command_limit.is_some() {
+            opts.command_limit.as_ref()
+        } else if is_upload {
+            opts.limit_upload.as_ref()
+        } else {
+            opts.limit_download.as_ref()
+        };
+        if let Some(rate) = limit
+            && !is_unlimited_rate(rate)
+        {
+            cmd.arg("--limit-rate").arg(rate);
+        }
+        match &opts.user_agent {
+            Some(agent) => cmd.arg("--user-agent").arg(agent),
+            None => cmd.arg("--user-agent").arg(default_user_agent()),
+        };
+    }
+}
+
+/// Bodies from this size on announce themselves with `Expect: 100-continue`.
+const EXPECT_CONTINUE_THRESHOLD_BYTES: u64 = 1024 * 1024;
+
+/// Whether an upload of `size` bytes should ask for `100 Continue` first. A `-H Expect:`
+/// the user passed (for example to turn it off for a broken proxy) always wins.
+fn wants_expect_continue(size: u64, custom_headers: &[String]) -> bool {
+    size >= EXPECT_CONTINUE_THRESHOLD_BYTES
+        && !custom_headers.iter().any(|h| {
+            h.split_once(':')
+                .map_or(h.as_str(), |(name, _)| name)
+                .trim()
+                .eq_ignore_ascii_case("expect")
+        })
+}
+
+/// With `Expect: 100-continue` curl sends only the headers and waits (up to its
+/// one-second expect timeout) for the server, so a 403 or a wrong region is answered
+/// before gigabytes of body go out instead of after.
+fn apply_expect_continue(cmd: &mut Command, body: &Path) {
+    let size = fs::metadata(body).map(|m| m.len()).unwrap_or(0);
+    let wanted = curl_global_opts()
+        .lock()
+        .map(|opts| wants_expect_continue(size, &opts.custom_headers))
+        .unwrap_or(false);
+    if wanted {
+        cmd.arg("-H").arg("Expect: 100-continue");
+    }
+}
+
+/// `-w '%header{name}'` exists since curl 7.84.0.
+const CURL_HEADER_WRITEOUT_VERSION: (u32, u32) = (7, 84);
+
+/// Whether the installed curl can report response headers in `-w`; asked once per run.
+fn curl_has_header_writeout() -> bool {
+    static SUPPORTED: OnceLock<bool> = OnceLock::new();
+    *SUPPORTED.get_or_init(|| {
+        let version = run_tool(Command::new("curl").arg("--version"))
+            .ok()
+            .and_then(|out| parse_curl_version(&String::from_utf8_lossy(&out.stdout)));
+        match version {
+            Some(version) => version >= CURL_HEADER_WRITEOUT_VERSION,
+            // Unknown: assume a current curl rather than lose the headers.
+            None => true,
+        }
+    })
+}
+
+/// `(major, minor)` from the first line of `curl --version` (`curl 7.88.1 (x86_64...`).
+fn parse_curl_version(text: &str) -> Option<(u32, u32)> {
+    let version = text.strip_prefix("curl ")?.split_whitespace().next()?;
+    let mut parts = version.split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+fn default_user_agent() -> String {
+    format!("s4/{}", env!("CARGO_PKG_VERSION"))
+}
+
+const DEFAULT_MAX_CONCURRENT: usize = 8;
+
+/// Ceiling on in-flight curl requests across every worker, set by `--max-concurrent`.
+static MAX_CONCURRENT: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_CONCURRENT);
+static IN_FLIGHT: OnceLock<(Mutex<usize>, Condvar)> = OnceLock::new();
+
+struct RequestPermit;
+
+impl RequestPermit {
+    fn acquire() -> Self {
+        let (count, cvar) = IN_FLIGHT.get_or_init(|| (Mutex::new(0), Condvar::new()));
+        let max = MAX_CONCURRENT.load(Ordering::Relaxed).max(1);
+        let mut in_flight = count.lock().unwrap_or_else(|e| e.into_inner());
+        while *in_flight >= max {
+            in_flight = cvar.wait(in_flight).unwrap_or_else(|e| e.into_inner());
+        }
+        *in_flight += 1;
+        RequestPermit
+    }
+}
+
+impl Drop for RequestPermit {
+    fn drop(&mut self) {
+        if let Some((count, cvar)) = IN_FLIGHT.get() {
+            let mut in_flight = count.lock().unwrap_or_else(|e| e.into_inner());
+            *in_flight = in_flight.saturating_sub(1);
+            cvar.notify_one();
+        }
+    }
+}
+
+/// Every HTTP request goes through here so parallel callers share the `--max-concurrent` cap.
+/// `live_progress` (from `apply_curl_progress_flags`) leaves curl's stderr on the terminal.
+fn run_curl(cmd: &mut Command, live_progress: bool) -> Result<Output, S4Error> {
+    let _permit = RequestPermit::acquire();
+    let mut output = run_child(cmd, live_progress)?;
+    let trace = CURL_TRACE.load(Ordering::Relaxed);
+    if trace || SHOW_HEADERS.load(Ordering::Relaxed) {
+        // Echo the `-v` trace and keep only curl's own errors for the caller's messages.
+        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        let (shown, rest) = split_curl_trace(&stderr, !trace);
+        // One block per request, so parallel transfers do not interleave their headers.
+        let mut err = std::io::stderr().lock();
+        for line in shown {
+            if trace {
+                let _ = writeln!(err, "[debug] {line}");
+            } else {
+                let _ = writeln!(err, "{line}");
+            }
+        }
+        output.stderr = rest.into_bytes();
+    }
+    Ok(output)
+}
+
+/// Splits curl `-v` stderr into the (redacted) trace lines to echo and everything else.
+/// `headers_only` keeps just the `>` request and `<` response header lines.
+fn split_curl_trace(stderr: &str, headers_only: bool) -> (Vec<String>, String) {
+    let mut shown = Vec::new();
+    let mut rest = String::new();
+    for line in stderr.lines() {
+        if !is_curl_trace_line(line) {
+            rest.push_str(line);
+            rest.push('\n');
+        } else if !headers_only || line.starts_with('>') || line.starts_with('<') {
+            shown.push(redact_curl_trace_line(line));
+        }
+    }
+    (shown, rest)
+}
+
+fn is_curl_trace_line(line: &str) -> bool {
+    ["* ", "> ", "< ", "{ ", "} "]
+        .iter()
+        .any(|p| line.starts_with(p))
+        || matches!(line, "*" | ">" | "<")
+}
+
+/// Request headers whose values are credentials and never reach a trace.
+const REDACTED_TRACE_HEADERS: [&str; 2] = ["authorization", "x-amz-security-token"];
+
+fn redact_curl_trace_line(line: &str) -> String {
+    if let Some((name, _)) = line.strip_prefix("> ").and_then(|h| h.split_once(':'))
+        && REDACTED_TRACE_HEADERS.contains(&name.trim().to_ascii_lowercase().as_str())
+    {
+        return format!("> {}: [REDACTED]", name.trim());
+    }
+    line.to_string()
+}
+
+fn is_unlimited_rate(rate: &str) -> bool {
+    rate == "0" || rate.eq_ignore_ascii_case("unlimited")
+}
+
+/// Flags of the `--limit` commands that take a value, which `take_command_limit` steps over.
+const LIMIT_COMMAND_VALUE_FLAGS: &[&str] = &[
+    "--accept-encoding",
+    "--cache-control",
+    "--checksum-algorithm",
+    "--compress",
+    "--content-disposition",
+    "--content-language",
+    "--deadline",
+    "--download-concurrency",
+    "--exclude",
+    "--from-url",
+    "--head",
+    "--if-match",
+    "--if-modified-since",
+    "--if-none-match",
+    "--journal",
+    "--metrics-file",
+    "--modified-after",
+    "--modified-before",
+    "--newer-than",
+    "--older-than",
+    "--tail",
+];
+
+/// Removes a command-level `--limit RATE` from `args` (after the command name). Only the
+/// flags before the first operand (or `--`) are searched, so a key, file or flag value
+/// spelled `--limit` reaches the command untouched.
+fn take_command_limit(args: &mut Vec<String>) -> Result<Option<String>, String> {
+    let mut i = 1;
+    while let Some(arg) = args.get(i) {
+        match arg.as_str() {
+            "--limit" => {
+                if i + 1 >= args.len() {
+                    return Err("--limit expects a value".to_string());
+                }
+                let value = args.remove(i + 1);
+                args.remove(i);
+                return Ok(Some(value));
+            }
+            flag if LIMIT_COMMAND_VALUE_FLAGS.contains(&flag) => i += 2,
+            "--" => break,
+            flag if flag.starts_with('-') => i += 1,
+            _ => break,
+        }
+    }
+    Ok(None)
+}
+
+static BUCKET_REGIONS: OnceLock<Mutex<HashMap<(String, String), String>>> = OnceLock::new();
+
+fn bucket_regions() -> &'static Mutex<HashMap<(String, String), String>> {
+    BUCKET_REGIONS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Region to sign with for `bucket`, preferring one discovered from a previous redirect.
+fn effective_region(alias: &AliasConfig, bucket: &str) -> String {
+    if !bucket.is_empty()
+        && let Ok(regions) = bucket_regions().lock()
+        && let Some(region) = regions.get(&(alias.endpoint.clone(), bucket.to_string()))
+    {
+        return region.clone();
+    }
+    alias.region.clone()
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct S3ErrorBody {
+    code: String,
+    message: String,
+    region: Option<String>,
+}
+
+fn parse_s3_error(body: &str) -> Option<S3ErrorBody> {
+    let block = extract_tag_blocks(body, "Error").into_iter().next()?;
+    let first = |tag: &str| {
+        extract_tag_values(&block, tag)
+            .into_iter()
+            .next()
+            .map(|v| xml_unescape(v.trim()))
+    };
+    Some(S3ErrorBody {
+        code: first("Code").unwrap_or_default(),
+        message: first("Message").unwrap_or_default(),
+        region: first("Region").filter(|r| !r.is_empty()),
+    })
+}
+
+/// Returns the bucket's real region when the server rejected the request for being
+/// signed against (or sent to) the wrong one.
+fn wrong_region_hint(status: &str, body: &str, header_region: &str) -> Option<String> {
+    let err = parse_s3_error(body);
+    let code = err.as_ref().map(|e| e.code.as_str()).unwrap_or("");
+    if status != "301" && code != "AuthorizationHeaderMalformed" && code != "PermanentRedirect" {
+        return None;
+    }
+    err.and_then(|e| e.region)
+        .or_else(|| Some(header_region.trim().to_string()).filter(|r| !r.is_empty()))
+}
+
+/// Raw body plus the response headers callers act on.
+struct S3Response {
+    body: Vec<u8>,
+    content_encoding: String,
+}
+
+enum RequestOutcome {
+    Done(S3Response),
+    WrongRegion { region: String, error: S4Error },
+}
+
+/// Turns the headers of a `Range: bytes=0-0` GET into HEAD-style headers: the
+/// `Content-Length` becomes the full object size taken from `Content-Range`.
+fn ranged_get_as_head(headers: &str) -> String {
+    let mut total = None;
+    let mut out = Vec::new();
+    for line in headers.lines() {
+        let lower = line.to_ascii_lowercase();
+        if lower.starts_with("content-range:") {
+            total = line.rsplit_once('/').map(|(_, t)| t.trim().to_string());
+        } else if !lower.starts_with("content-length:") {
+            out.push(line.trim_end().to_string());
+        }
+    }
+    while out.last().is_some_and(|l| l.is_empty()) {
+        out.pop();
+    }
+    if let Some(total) = total.filter(|t| t != "*") {
+        out.push(format!("Content-Length: {total}"));
+    }
+    out.join("\n") + "\n"
+}
+
+/// Rewrites a 404 for `alias/bucket/key` into a friendly `NotFound`. HEAD replies carry
+/// no body, so anything but an explicit `NoSuchBucket` reads as a missing object.
+fn not_found_error(err: S4Error, alias: &str, bucket: &str, key: &str) -> S4Error {
+    match err {
+        S4Error::Http { status: 404, body } => {
+            if parse_s3_error(&body).is_some_and(|e| e.code == "NoSuchBucket") {
+                S4Error::NotFound(format!("no such bucket: {alias}/{bucket}"))
+            } else {
+                S4Error::NotFound(format!("no such object: {alias}/{bucket}/{key}"))
+            }
+        }
+        other => other,
+    }
+}
+
+fn http_error(status: &str, body: &str) -> S4Error {
+    S4Error::Http {
+        status: status.trim().parse().unwrap_or(0),
+        body: body.trim().to_string(),
+    }
+}
+
+/// Object transfers let curl's progress meter (bytes, rate, ETA) reach the terminal;
+/// every other request stays silent so its stderr can be captured for error messages.
+/// Returns whether stderr must stay live, for `run_curl`.
+fn apply_curl_progress_flags(cmd: &mut Command, is_transfer: bool) -> bool {
+    let live = is_transfer && TRANSFER_PROGRESS.load(Ordering::Relaxed);
+    cmd.arg(if live { "-S" } else { "-sS" });
+    live
+}
+
+/// The request primitive: the body exactly as received. `s3_request` is the lossy
+/// text view of it for XML and other text replies.
+fn s3_request_bytes(alias: &AliasConfig, call: S3Call, debug: bool) -> Result<Vec<u8>, S4Error> {
+    s3_request_response(alias, call, debug).map(|response| response.body)
+}
+
+fn s3_request_response(
+    alias: &AliasConfig,
+    call: S3Call,
+    debug: bool,
+) -> Result<S3Response, S4Error> {
+    let bucket = call.bucket;
+    let region = effective_region(alias, bucket);
+    let outcome = s3_request_attempt(alias, &region, call, debug)?;
+    let (corrected, error) = match outcome {
+        RequestOutcome::Done(response) => return Ok(response),
+        RequestOutcome::WrongRegion { region: r, error } if r != region => (r, error),
+        RequestOutcome::WrongRegion { error, .. } => return Err(error),
+    };
+
+    if bucket.is_empty() {
+        return Err(error);
+    }
+    eprintln!(
+        "warning: bucket '{}' is in region '{}' but alias is configured for '{}'; retrying with '{}'",
+        bucket, corrected, alias.region, corrected
+    );
+    if let Ok(mut regions) = bucket_regions().lock() {
+        regions.insert(
+            (alias.endpoint.clone(), bucket.to_string()),
+            corrected.clone(),
+        );
+    }
+    match s3_request_attempt(alias, &corrected, call, debug)? {
+        RequestOutcome::Done(response) => Ok(response),
+        RequestOutcome::WrongRegion { error, .. } => Err(error),
+    }
+}
+
+fn s3_request_attempt(
+    alias: &AliasConfig,
+    region: &str,
+    call: S3Call,
+    debug: bool,
+) -> Result<RequestOutcome, S4Error> {
+    let attempt = |head_via_get| s3_request_send(alias, region, call, head_via_get, debug);
+    match attempt(false) {
+        Err(S4Error::Network(msg))
+            if call.method == "HEAD" && HEAD_VIA_GET.load(Ordering::Relaxed) =>
+        {
+            if debug {
+                eprintln!("[debug] HEAD failed ({msg}); retrying as GET with Range: bytes=0-0");
+            }
+            attempt(true)
+        }
+        other => other,
+    }
+}
+
+/// With `head_via_get`, a HEAD is sent as a one-byte ranged GET whose headers are
+/// rewritten to look like the HEAD response (see `ranged_get_as_head`).
+fn s3_request_send(
+    alias: &AliasConfig,
+    region: &str,
+    call: S3Call,
+    head_via_get: bool,
+    debug: bool,
+) -> Result<RequestOutcome, S4Error> {
+    let S3Call {
+        method,
+        bucket,
+        key,
+        query,
+        upload_file,
+        output_file,
+        headers: extra_headers,
+    } = call;
+    let method = if head_via_get { "GET" } else { method };
+    let endpoint = parse_endpoint(&alias.endpoint)?;
+    let (host, uri_path) = endpoint.request_target(alias.path_style, bucket, key);
+
+    let canonical_query = normalize_sigv4_query(query);
+    let payload_hash = payload_hash(upload_file)?;
+    let (signed_extra, extra_headers) = partition_signed_headers(extra_headers);
+    let mut signed = signed_request_headers(alias);
+    signed.extend(signed_extra);
+    let sign = sign_v4(
+        &CanonicalRequest {
+            method,
+            uri_path: &uri_path,
+            query: &canonical_query,
+            host: &host,
+            payload_hash: &payload_hash,
+            headers: &signed,
+        },
+        region,
+        S3_SIGNING_SERVICE,
+        &alias.access_key,
+        &alias.secret_key,
+    )?;
+
+    let mut url = format!("{}://{}{}", endpoint.scheme, host, uri_path);
+    if !query.is_empty() {
+        url.push('?');
+        url.push_str(query);
+    }
+
+    let is_transfer = key.is_some()
+        && query.is_empty()
+        && ((method == "PUT" && upload_file.is_some())
+            || (method == "GET" && output_file.is_some()));
+    // Object bodies read into memory (`head`, ranged reads) count as downloads too.
+    let is_download =
+        output_file.is_some() || (method == "GET" && key.is_some() && query.is_empty());
+    let mut cmd = Command::new("curl");
+    apply_curl_global_flags(&mut cmd, upload_file.is_some(), is_download);
+    let live_progress = apply_curl_progress_flags(&mut cmd, is_transfer);
+    cmd.arg(&url);
+    if method != "HEAD" {
+        cmd.arg("-X").arg(method);
+    }
+    cmd.arg("-H")
+        .arg(format!("Host: {}", host))
+        .arg("-H")
+        .arg(format!("x-amz-date: {}", sign.amz_date))
+        .arg("-H")
+        .arg(format!("x-amz-content-sha256: {}", payload_hash))
+        .arg("-H")
+        .arg(format!("Authorization: {}", sign.authorization));
+    for (name, value) in &signed {
+        cmd.arg("-H").arg(format!("{name}: {value}"));
+    }
+
+    for header in &extra_headers {
+        cmd.arg("-H").arg(header);
+    }
+    // Our own Accept-Encoding header wins over curl's default list, but `--compressed`
+    // is still what makes curl decode the reply before writing it out.
+    let compressed = signed.iter().any(|(name, _)| name == "accept-encoding");
+    if compressed {
+        cmd.arg("--compressed");
+    }
+
+    if let Some(file) = upload_file {
+        apply_expect_continue(&mut cmd, file);
+        cmd.arg("--data-binary").arg(format!("@{}", file.display()));
+    }
+
+    if head_via_get {
+        cmd.arg("-H")
+            .arg("Range: bytes=0-0")
+            .arg("-D")
+            .arg("-")
+            .arg("-o")
+            .arg("/dev/null");
+    } else if method == "HEAD" {
+        // Use curl native HEAD mode instead of `-X HEAD` + body suppression.
+        // This avoids curl(18) "transfer closed with bytes remaining" on servers
+        // that return Content-Length for HEAD responses.
+        cmd.arg("-I");
+    } else if let Some(out) = output_file {
+        cmd.arg("-o").arg(out);
+    }
+
+    if debug {
+        eprintln!("[debug] request: {} {}", method, url);
+    }
+
+    // Older curl prints `%header{...}` literally, so the header lines stay empty there.
+    cmd.arg("-w").arg(if curl_has_header_writeout() {
+        "\nHTTPSTATUS:%{http_code}\nBUCKETREGION:%header{x-amz-bucket-region}\nCONTENTENCODING:%header{content-encoding}\nSIZEDOWNLOAD:%{size_download}"
+    } else {
+        "\nHTTPSTATUS:%{http_code}\nBUCKETREGION:\nCONTENTENCODING:\nSIZEDOWNLOAD:%{size_download}"
+    });
+
+    let output = run_curl(&mut cmd, live_progress)?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        return Err(S4Error::Network(format!(
+            "request execution failed: {}",
+            stderr.trim()
+        )));
+    }
+
+    let (raw_body, trailer) = split_status_trailer(&output.stdout)
+        .ok_or_else(|| "unable to parse HTTP status".to_string())?;
+    let status_part = trailer.as_str();
+    let (status, header_region) = status_part
+        .split_once("\nBUCKETREGION:")
+        .unwrap_or((status_part, ""));
+    let (header_region, content_encoding) = header_region
+        .split_once("\nCONTENTENCODING:")
+        .unwrap_or((header_region, ""));
+    let (content_encoding, wire_bytes) = content_encoding
+        .split_once("\nSIZEDOWNLOAD:")
+        .unwrap_or((content_encoding, ""));
+    let status = status.trim();
+    if debug && compressed && status.starts_with('2') {
+        let decoded = match output_file {
+            Some(out) => fs::metadata(out).map(|m| m.len()).unwrap_or(0),
+            None => raw_body.len() as u64,
+        };
+        eprintln!(
+            "[debug] accept-encoding: {} byte(s) on the wire (content-encoding '{}') for {} byte(s) decoded",
+            wire_bytes.trim(),
+            content_encoding.trim(),
+            decoded
+        );
+    }
+    if head_via_get && (status.starts_with('2') || status == "416") {
+        // 416 is what a ranged GET of an empty object returns.
+        return Ok(RequestOutcome::Done(S3Response {
+            body: ranged_get_as_head(&String::from_utf8_lossy(raw_body)).into_bytes(),
+            content_encoding: content_encoding.trim().to_string(),
+        }));
+    }
+    if status == "304" {
+        return Err(S4Error::NotModified);
+    }
+    if !status.starts_with('2') {
+        let body = String::from_utf8_lossy(raw_body);
+        let error = http_error(status, &body);
+        if let Some(region) = wrong_region_hint(status, &body, header_region) {
+            return Ok(RequestOutcome::WrongRegion { region, error });
+        }
+        return Err(error);
+    }
+
+    Ok(RequestOutcome::Done(S3Response {
+        body: raw_body.to_vec(),
+        content_encoding: content_encoding.trim().to_string(),
+    }))
+}
+
+/// Splits curl's stdout into the raw body and the `-w` trailer that follows the last
+/// `\nHTTPSTATUS:`. The body may be binary; only the trailer is read as text.
+fn split_status_trailer(stdout: &[u8]) -> Option<(&[u8], String)> {
+    const MARKER: &[u8] = b"\nHTTPSTATUS:";
+    let split = stdout
+        .windows(MARKER.len())
+        .rposition(|window| window == MARKER)?;
+    let (body, trailer) = stdout.split_at(split);
+    Some((
+        body,
+        String::from_utf8_lossy(&trailer[MARKER.len()..]).into_owned(),
+    ))
+}
+
+/// Headers that are both sent and signed: `--signed-header` values,
+/// `x-amz-request-payer` for requester-pays aliases and the session token of
+/// temporary AWS credentials.
+fn signed_request_headers(alias: &AliasConfig) -> Vec<(String, String)> {
+    let mut headers = curl_global_opts()
+        .lock()
+        .map(|opts| opts.signed_headers.clone())
+        .unwrap_or_default();
+    if alias.request_payer || REQUEST_PAYER.load(Ordering::Relaxed) {
+        headers.push(("x-amz-request-payer".to_string(), "requester".to_string()));
+    }
+    if let Some(token) = &alias.session_token {
+        headers.push(("x-amz-security-token".to_string(), token.clone()));
+    }
+    headers
+}
+
+/// Splits `Name: value` request headers into the signed ones (lowercase name and
+/// value with whitespace runs collapsed, as `sign_v4` expects) and the ones sent
+/// unsigned. Every header a command adds is signed, so none of them (`x-amz-*`,
+/// `Content-MD5`, preconditions, metadata) can be changed in transit; only curl's
+/// `Name:` removal syntax stays out. `-H` headers never get here: they are added by
+/// `apply_curl_global_flags` and stay unsigned.
+fn partition_signed_headers(headers: &[String]) -> (Vec<(String, String)>, Vec<&String>) {
+    let mut signed = Vec::new();
+    let mut unsigned = Vec::new();
+    for header in headers {
+        match header.split_once(':') {
+            Some((name, value)) if !value.trim().is_empty() => {
+                let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+                signed.push((name.trim().to_ascii_lowercase(), value));
+            }
+            _ => unsigned.push(header),
+        }
+    }
+    (signed, unsigned)
+}
+
+/// One canonical header per name: curl sends repeated headers (two `--signed-header`
+/// with the same name) separately, and the server joins them with commas before
+/// checking the signature, so the values are joined the same way here.
+fn merge_signed_headers(headers: &[(String, String)]) -> Vec<(String, String)> {
+    let mut merged: Vec<(String, String)> = Vec::new();
+    for (name, value) in headers {
+        match merged.iter_mut().find(|(n, _)| n == name) {
+            Some((_, joined)) => {
+                joined.push(',');
+                joined.push_str(value);
+            }
+            None => merged.push((name.clone(), value.clone())),
+        }
+    }
+    merged
+}
+
+/// Service name in the SigV4 credential scope of every S3 request. S3-adjacent APIs
+/// (STS, a gateway's admin endpoints) pass their own to `sign_v4`.
+const S3_SIGNING_SERVICE: &str = "s3";
+
+/// SigV4 `Authorization` for one request, scoped to `date/region/service/aws4_request`.
+fn sign_v4(
+    request: &CanonicalRequest,
+    region: &str,
+    service: &str,
+    access_key: &str,
+    secret_key: &str,
+) -> Result<SignatureParts, S4Error> {
+    let py = r#"
+import sys, hmac, hashlib, datetime
+method, path, query, host, region, service, access, secret, payload_hash = sys.argv[1:10]
+extra = sys.argv[10:]
+amz_date = datetime.datetime.utcnow().strftime('%Y%m%dT%H%M%SZ')
+date_stamp = amz_date[:8]
+headers = sorted([('host', host), ('x-amz-content-sha256', payload_hash), ('x-amz-date', amz_date)] + list(zip(extra[0::2], extra[1::2])))
+canonical_headers = ''.join(f'{k}:{v}\n' for k, v in headers)
+signed_headers = ';'.join(k for k, _ in headers)
+canonical_request = '\n'.join([method, path, query, canonical_headers, signed_headers, payload_hash])
+algorithm = 'AWS4-HMAC-SHA256'
+credential_scope = f'{date_stamp}/{region}/{service}/aws4_request'
+string_to_sign = '\n'.join([algorithm, amz_date, credential_scope, hashlib.sha256(canonical_request.encode()).hexdigest()])
+def sign(key, msg):
+    return hmac.new(key, msg.encode(), hashlib.sha256).digest()
+k_date = sign(('AWS4' + secret).encode(), date_stamp)
+k_region = sign(k_date, region)
+k_service = sign(k_region, service)
+k_signing = sign(k_service, 'aws4_request')
+signature = hmac.new(k_signing, string_to_sign.encode(), hashlib.sha256).hexdigest()
+auth = f'{algorithm} Credential={access}/{credential_scope}, SignedHeaders={signed_headers}, Signature={signature}'
+print(amz_date)
+print(auth)
+"#;
+
+    let mut cmd = Command::new("python3");
+    cmd.arg("-c")
+        .arg(py)
+        .arg(request.method)
+        .arg(request.uri_path)
+        .arg(request.query)
+        .arg(request.host)
+        .arg(region)
+        .arg(service)
+        .arg(access_key)
+        .arg(secret_key)
+        .arg(request.payload_hash);
+    for (name, value) in merge_signed_headers(request.headers) {
+        cmd.arg(name).arg(value);
+    }
+    let out = run_tool(&mut cmd)?;
+
+    if !out.status.success() {
+        return Err(S4Error::Signing(
+            String::from_utf8_lossy(&out.stderr).to_string(),
+        ));
+    }
+
+    let lines: Vec<String> = String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .map(ToString::to_string)
+        .collect();
+    if lines.len() < 2 {
+        return Err(S4Error::Signing(
+            "signature helper returned unexpected output".to_string(),
+        ));
+    }
+
+    Ok(SignatureParts {
+        amz_date: lines[0].clone(),
+        authorization: lines[1].clone(),
+    })
+}
+
+/// Runs an external helper (`curl`, `python3`), turning a missing binary into a clear message.
+fn run_tool(cmd: &mut Command) -> Result<Output, S4Error> {
+    run_child(cmd, false)
+}
+
+/// Runs `cmd` like `Command::output` (stdin closed, stdout and stderr captured unless
+/// `live_stderr` leaves stderr on the terminal) and registers the child for the
+/// signal handler while it runs.
+fn run_child(cmd: &mut Command, live_stderr: bool) -> Result<Output, S4Error> {
+    let program = cmd.get_program().to_string_lossy().to_string();
+    cmd.stdin(Stdio::null()).stdout(Stdio::piped());
+    cmd.stderr(if live_stderr {
+        Stdio::inherit()
+    } else {
+        Stdio::piped()
+    });
+    let child = cmd.spawn().map_err(|e| {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            S4Error::Config(format!(
+                "the `{program}` binary is required but was not found in PATH"
+            ))
+        } else {
+            S4Error::Io(format!("failed to run `{program}`: {e}"))
+        }
+    })?;
+    let pid = child.id();
+    if let Ok(mut registry) = cleanup_registry().lock() {
+        registry.children.insert(pid);
+    }
+    let output = child.wait_with_output();
+    if let Ok(mut registry) = cleanup_registry().lock() {
+        registry.children.remove(&pid);
+    }
+    output.map_err(|e| S4Error::Io(format!("failed to run `{program}`: {e}")))
+}
+
+/// Sent as `x-amz-content-sha256` (and signed) instead of the body hash.
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+fn payload_hash(upload_file: Option<&Path>) -> Result<String, S4Error> {
+    if upload_file.is_some() && UNSIGNED_PAYLOAD_MODE.load(Ordering::Relaxed) {
+        return Ok(UNSIGNED_PAYLOAD.to_string());
+    }
+    if let Some(path) = upload_file {
+        let mut cmd = Command::new("python3");
+        cmd.arg("-c")
+            .arg("import hashlib,sys;print(hashlib.sha256(open(sys.argv[1],'rb').read()).hexdigest())")
+            .arg(path);
+        let out = run_tool(&mut cmd)?;
+        if !out.status.success() {
+            return Err(S4Error::Signing(
+                String::from_utf8_lossy(&out.stderr).to_string(),
+            ));
+        }
+        Ok(String::from_utf8_lossy(&out.stdout).trim().to_string())
+    } else {
+        Ok("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".to_string())
+    }
+}
+
+const MULTIPART_THRESHOLD_BYTES: u64 = 16 * 1024 * 1024;
+const MULTIPART_PART_SIZE_BYTES: usize = 8 * 1024 * 1024;
+
+/// Preconditions go on the request S3 evaluates them against: the single PUT, or
+/// CompleteMultipartUpload for multipart uploads.
+fn upload_file_to_s3(
+    alias: &AliasConfig,
+    bucket: &str,
+    key: &str,
+    path: &Path,
+    upload: &UploadOptions,
+    debug: bool,
+) -> Result<(), S4Error> {
+    if upload.compress {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| e.to_string())?
+            .as_nanos();
+        let gz_path = temp_path(format!("s4-gzip-{}-{}", std::process::id(), ts));
+        let mut compressed = upload.clone();
+        compressed.compress = false;
+        compressed
+            .headers
+            .push("Content-Encoding: gzip".to_string());
+        // Hashing, signing and the multipart threshold all see the compressed file.
+        let result = gzip_file(path, &gz_path)
+            .and_then(|_| upload_file_to_s3(alias, bucket, key, &gz_path, &compressed, debug));
+        remove_temp(&gz_path);
+        return result;
+    }
+    let size = fs::metadata(path).map_err(|e| e.to_string())?.len();
+    let result = if size < MULTIPART_THRESHOLD_BYTES {
+        let mut headers = upload.preconditions.clone();
+        headers.extend_from_slice(&upload.headers);
+        if upload.content_md5 {
+            headers.push(format!("Content-MD5: {}", content_md5_header(path)?));
+        }
+        if let Some(algorithm) = upload.checksum {
+            headers.extend(checksum_headers(
+                algorithm,
+                &checksum_value(path, algorithm)?,
+            ));
+        }
+        s3_request(
+            alias,
+            S3Call {
+                method: "PUT",
+                bucket,
+                key: Some(key),
+                upload_file: Some(path),
+                headers: &headers,
+                ..S3Call::default()
+            },
+            debug,
+        )
+        .map(|_| ())
+    } else {
+        multipart_upload_file(alias, bucket, key, path, upload, debug)
+    };
+    result.map_err(|e| explain_precondition_failure(e, bucket, key, &upload.preconditions))
+}
+
+fn multipart_upload_file(
+    alias: &AliasConfig,
+    bucket: &str,
+    key: &str,
+    path: &Path,
+    upload: &UploadOptions,
+    debug: bool,
+) -> Result<(), S4Error> {
+    let mut file = fs::File::open(path).map_err(|e| e.to_string())?;
+    let total_size = file.metadata().map_err(|e| e.to_string())?.len();
+    multipart_upload_reader(
+        alias,
+        bucket,
+        key,
+        &mut file,
+        Some(total_size),
+        upload,
+        debug,
+    )
+}
+
+/// Multipart upload of everything `reader` yields, one part-sized chunk at a time;
+/// `total_size` is only used for progress output (stdin has none).
+fn multipart_upload_reader(
+    alias: &AliasConfig,
+    bucket: &str,
+    key: &str,
+    reader: &mut dyn Read,
+    total_size: Option<u64>,
+    upload: &UploadOptions,
+    debug: bool,
+) -> Result<(), S4Error> {
+    let mut init_headers = upload.headers.clone();
+    if let Some(algorithm) = upload.checksum {
+        init_headers.push(format!("x-amz-checksum-algorithm: {}", algorithm.name()));
+    }
+    let init_xml = s3_request(
+        alias,
+        S3Call {
+            method: "POST",
+            bucket,
+            key: Some(key),
+            query: "uploads",
+            headers: &init_headers,
+            ..S3Call::default()
+        },
+        debug,
+    )?;
+    let upload_id = extract_tag_values(&init_xml, "UploadId")
+        .into_iter()
+        .next()
+        .map(|v| xml_unescape(&v))
+        .ok_or_else(|| "multipart init did not return UploadId".to_string())?;
+    let active = ActiveUpload::register(alias, bucket, key, &upload_id);
+
+    let show_progress = TRANSFER_PROGRESS.load(Ordering::Relaxed);
+    let mut uploaded_bytes = 0u64;
+    let mut part_number = 1usize;
+    let mut etags: Vec<(usize, String, Option<String>)> = Vec::new();
+
+    loop {
+        // Fill the whole part: pipes return short reads, and only the last part may be
+        // smaller than the S3 minimum part size.
+        let mut chunk = Vec::with_capacity(MULTIPART_PART_SIZE_BYTES);
+        let n = reader
+            .take(MULTIPART_PART_SIZE_BYTES as u64)
+            .read_to_end(&mut chunk)
+            .map_err(|e| e.to_string())?;
+        if n == 0 {
+            break;
+        }
+        if show_progress {
+            match total_size {
+                Some(total) => eprintln!(
+                    "part {}/{} ({} of {} done)",
+                    part_number,
+                    total.div_ceil(MULTIPART_PART_SIZE_BYTES as u64),
+                    format_bytes(uploaded_bytes),
+                    format_bytes(total)
+                ),
+                None => eprintln!(
+                    "part {} ({} done)",
+                    part_number,
+                    format_bytes(uploaded_bytes)
+                ),
+            }
+        }
+
+        let temp_part = temp_path(format!(
+            "s4-mpu-part-{}-{}-{}",
+            std::process::id(),
+            part_number,
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_err(|e| e.to_string())?
+                .as_nanos()
+        ));
+        fs::write(&temp_part, &chunk).map_err(|e| e.to_string())?;
+
+        let mut part_headers = Vec::new();
+        if upload.content_md5 {
+            part_headers.push(format!("Content-MD5: {}", content_md5_header(&temp_part)?));
+        }
+        let mut part_checksum = None;
+        if let Some(algorithm) = upload.checksum {
+            let value = checksum_value(&temp_part, algorithm)?;
+            part_headers.extend(checksum_headers(algorithm, &value));
+            part_checksum = Some(value);
+        }
+        let query = format!(
+            "partNumber={}&uploadId={}",
+            part_number,
+            uri_encode_query_component(&upload_id)
+        );
+        let uploaded = upload_part(
+            alias,
+            S3Call {
+                method: "PUT",
+                bucket,
+                key: Some(key),
+                query: &query,
+                upload_file: Some(&temp_part),
+                headers: &part_headers,
+                ..S3Call::default()
+            },
+            debug,
+        );
+        remove_temp(&temp_part);
+        let etag = match uploaded {
+            Ok(v) => v,
+            Err(e) => {
+                active.abort(alias, bucket, key, debug);
+                return Err(e);
+            }
+        };
+
+        etags.push((part_number, etag, part_checksum));
+        uploaded_bytes += n as u64;
+        part_number += 1;
+    }
+
+    if etags.is_empty() {
+        active.abort(alias, bucket, key, debug);
+        return Err("multipart upload had no parts".into());
+    }
+
+    let complete_xml = build_complete_multipart_xml(&etags, upload.checksum);
+    let complete_path = temp_path(format!(
+        "s4-mpu-complete-{}-{}",
+        std::process::id(),
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| e.to_string())?
+            .as_nanos()
+    ));
+    fs::write(&complete_path, complete_xml).map_err(|e| e.to_string())?;
+
+    let query = format!("uploadId={}", uri_encode_query_component(&upload_id));
+    let mut complete_res = Err(S4Error::Other("multipart completion not attempted".into()));
+    for attempt in 1..=MULTIPART_COMPLETE_ATTEMPTS {
+        complete_res = s3_request(
+            alias,
+            S3Call {
+                method: "POST",
+                bucket,
+                key: Some(key),
+                query: &query,
+                upload_file: Some(&complete_path),
+                headers: &upload.preconditions,
+                ..S3Call::default()
+            },
+            debug,
+        )
+        .and_then(|body| check_complete_multipart_response(&body, etags.len()));
+        match &complete_res {
+            Err(err) if attempt < MULTIPART_COMPLETE_ATTEMPTS && is_transient_error(err) => {
+                if debug {
+                    eprintln!("[debug] CompleteMultipartUpload attempt {attempt} failed: {err}");
+                }
+                sleep(Duration::from_millis(500 * attempt as u64));
+            }
+            _ => break,
+        }
+    }
+    remove_temp(&complete_path);
+
+    if let Err(err) = complete_res {
+        active.abort(alias, bucket, key, debug);
+        return Err(err);
+    }
+    active.settle();
+
+    Ok(())
+}
+
+const MULTIPART_COMPLETE_ATTEMPTS: usize = 3;
+
+/// S3 may answer CompleteMultipartUpload with `200 OK` and an `<Error>` body, so the
+/// body is checked too; the composite ETag must end in `-<part count>`.
+fn check_complete_multipart_response(body: &str, part_count: usize) -> Result<(), S4Error> {
+    if let Some(err) = parse_s3_error(body) {
+        return Err(S4Error::Http {
+            status: 200,
+            body: format!("{}: {}", err.code, err.message),
+        });
+    }
+    let etag = first_tag_value(body, "ETag");
+    let etag = etag.trim().trim_matches('"');
+    if !etag.ends_with(&format!("-{part_count}")) {
+        return Err(S4Error::Other(format!(
+            "multipart upload completed with unexpected ETag '{etag}' (expected a -{part_count} suffix)"
+        )));
+    }
+    Ok(())
+}
+
+/// Failures worth repeating a request for: network errors, 5xx, and error bodies on 200.
+fn is_transient_error(err: &S4Error) -> bool {
+    match err {
+        S4Error::Network(_) => true,
+        S4Error::Http { status, .. } => *status == 200 || *status >= 500,
+        _ => false,
+    }
+}
+
+/// Sends one UploadPart: `call` carries the `partNumber`/`uploadId` query and the
+/// part file, and the part's ETag is returned.
+fn upload_part(alias: &AliasConfig, call: S3Call, debug: bool) -> Result<String, S4Error> {
+    let S3Call {
+        method,
+        bucket,
+        key,
+        query,
+        upload_file,
+        headers: extra_headers,
+        ..
+    } = call;
+    let file_path = upload_file.ok_or("multipart part has no file to upload")?;
+    let endpoint = parse_endpoint(&alias.endpoint)?;
+    let (host, uri_path) = endpoint.request_target(alias.path_style, bucket, key);
+
+    let payload_hash = payload_hash(Some(file_path))?;
+    let (signed_extra, extra_headers) = partition_signed_headers(extra_headers);
+    let mut signed = signed_request_headers(alias);
+    signed.extend(signed_extra);
+    let sign = sign_v4(
+        &CanonicalRequest {
+            method,
+            uri_path: &uri_path,
+            query,
+            host: &host,
+            payload_hash: &payload_hash,
+            headers: &signed,
+        },
+        &effective_region(alias, bucket),
+        S3_SIGNING_SERVICE,
+        &alias.access_key,
+        &alias.secret_key,
+    )?;
+
+    let url = format!("{}://{}{}?{}", endpoint.scheme, host, uri_path, query);
+    let mut cmd = Command::new("curl");
+    apply_curl_global_flags(&mut cmd, true, false);
+    let live_progress = apply_curl_progress_flags(&mut cmd, true);
+    cmd.arg("-X")
+        .arg(method)
+        .arg(&url)
+        .arg("-H")
+        .arg(format!("Host: {}", host))
+        .arg("-H")
+        .arg(format!("x-amz-date: {}", sign.amz_date))
+        .arg("-H")
+        .arg(format!("x-amz-content-sha256: {}", payload_hash))
+        .arg("-H")
+        .arg(format!("Authorization: {}", sign.authorization));
+    for (name, value) in &signed {
+        cmd.arg("-H").arg(format!("{name}: {value}"));
+    }
+    for header in &extra_headers {
+        cmd.arg("-H").arg(header);
+    }
+    apply_expect_continue(&mut cmd, file_path);
+    cmd.arg("--data-binary")
+        .arg(format!("@{}", file_path.display()))
+        .arg("-D")
+        .arg("-")
+        .arg("-o")
+        .arg("/dev/null")
+        .arg("-w")
+        .arg(
+            "
+HTTPSTATUS:%{http_code}",
+        );
+
+    if debug {
+        eprintln!("[debug] multipart upload part request: {method} {}", url);
+    }
+
+    let out = run_curl(&mut cmd, live_progress)?;
+    if !out.status.success() {
+        return Err(format!(
+            "multipart part request execution failed: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        )
+        .into());
+    }
+
+    let stdout = String::from_utf8_lossy(&out.stdout).to_string();
+    let (headers, status_part) = stdout
+        .rsplit_once(
+            "
+HTTPSTATUS:",
+        )
+        .ok_or_else(|| "unable to parse multipart part status".to_string())?;
+    let status = status_part.trim();
+    if !status.starts_with('2') {
+        return Err(format!("multipart part failed with status {}", status).into());
+    }
+
+    for line in headers.lines() {
+        let l = line.trim();
+        if l.to_ascii_lowercase().starts_with("etag:") {
+            let v = l
+                .split_once(':')
+                .map(|(_, r)| r.trim().trim_matches('"').to_string())
+                .unwrap_or_default();
+            if !v.is_empty() {
+                return Ok(v);
+            }
+        }
+    }
+    Err("multipart part response missing ETag".into())
+}
+
+fn abort_multipart(
+    alias: &AliasConfig,
+    bucket: &str,
+    key: &str,
+    upload_id: &str,
+    debug: bool,
+) -> Result<(), S4Error> {
+    let query = format!("uploadId={}", uri_encode_query_component(upload_id));
+    let _ = s3_request(
+        alias,
+        S3Call {
+            method: "DELETE",
+            bucket,
+            key: Some(key),
+            query: &query,
+            ..S3Call::default()
+        },
+        debug,
+    )?;
+    Ok(())
+}
+
+/// Upload ids the bucket still lists as in progress (ListMultipartUploads, all pages).
+fn list_multipart_upload_ids(
+    alias: &AliasConfig,
+    bucket: &str,
+    debug: bool,
+) -> Result<HashSet<String>, S4Error> {
+    let mut ids = HashSet::new();
+    let mut query = "uploads".to_string();
+    loop {
+        let body = s3_request(
+            alias,
+            S3Call {
+                method: "GET",
+                bucket,
+                query: &query,
+                ..S3Call::default()
+            },
+            debug,
+        )?;
+        ids.extend(
+            extract_tag_blocks(&body, "Upload")
+                .iter()
+                .map(|block| first_tag_value(block, "UploadId")),
+        );
+        if !listing_truncated(&body) {
+            break;
+        }
+        query = format!(
+            "uploads&key-marker={}&upload-id-marker={}",
+            uri_encode_query_component(&first_tag_value(&body, "NextKeyMarker")),
+            uri_encode_query_component(&first_tag_value(&body, "NextUploadIdMarker"))
+        );
+    }
+    Ok(ids)
+}
+
+/// Records of uploads started against this endpoint and bucket, oldest first.
+fn load_upload_records(dir: &Path, endpoint: &str, bucket: &str) -> Vec<(PathBuf, UploadRecord)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut records: Vec<(PathBuf, UploadRecord)> = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let record = UploadRecord::parse(&fs::read_to_string(&path).ok()?)?;
+            Some((path, record))
+        })
+        .filter(|(_, r)| r.endpoint == endpoint && r.bucket == bucket)
+        .collect();
+    records.sort_by_key(|(_, r)| r.initiated);
+    records
+}
+
+/// How `multipart recover` treats each record: uploads of live processes are left
+/// alone, ones the bucket no longer lists only lose their record, the rest are aborted.
+struct RecoverPlan<'a> {
+    abort: Vec<(&'a Path, &'a UploadRecord)>,
+    stale: Vec<(&'a Path, &'a UploadRecord)>,
+    running: Vec<&'a UploadRecord>,
+}
+
+impl<'a> RecoverPlan<'a> {
+    fn new(
+        records: &'a [(PathBuf, UploadRecord)],
+        listed: &HashSet<String>,
+        process_running: impl Fn(u32) -> bool,
+    ) -> Self {
+        let mut plan = Self {
+            abort: Vec::new(),
+            stale: Vec::new(),
+            running: Vec::new(),
+        };
+        for (path, record) in records {
+            if process_running(record.pid) {
+                plan.running.push(record);
+            } else if listed.contains(&record.upload_id) {
+                plan.abort.push((path, record));
+            } else {
+                plan.stale.push((path, record));
+            }
+        }
+        plan
+    }
+}
+
+/// Probes the pid with signal 0. Anything but "no such process" (a pid of another
+/// user, one out of range) counts as running, so an upload is never aborted under a
+/// live process.
+fn process_running(pid: u32) -> bool {
+    use nix::errno::Errno;
+    use nix::unistd::Pid;
+    match i32::try_from(pid) {
+        Ok(raw) if raw > 0 => !matches!(
+            nix::sys::signal::kill(Pid::from_raw(raw), None),
+            Err(Errno::ESRCH)
+        ),
+        _ => true,
+    }
+}
+
+fn cmd_multipart_recover(
+    alias: &AliasConfig,
+    bucket: &str,
+    dry_run: bool,
+    json: bool,
+    debug: bool,
+) -> Result<(), S4Error> {
+    let dirs = UPLOAD_STATE_DIRS
+        .get()
+        .ok_or_else(|| S4Error::Config("multipart state directory is not set".to_string()))?;
+    let tmp = temp_dir();
+    let mut records: Vec<(PathBuf, UploadRecord)> = dirs
+        .iter()
+        .filter(|dir| !dir.starts_with(&tmp) || is_private_dir(dir))
+        .flat_map(|dir| load_upload_records(dir, &alias.endpoint, bucket))
+        .collect();
+    records.sort_by_key(|(_, r)| r.initiated);
+    let listed = if records.is_empty() {
+        HashSet::new()
+    } else {
+        list_multipart_upload_ids(alias, bucket, debug)?
+    };
+
+    let plan = RecoverPlan::new(&records, &listed, process_running);
+    let mut aborted = Vec::new();
+    // One failed abort must not leave the rest behind; its record stays for a retry.
+    let mut failed = Vec::new();
+    let mut planned = Vec::new();
+    for (path, record) in &plan.abort {
+        if dry_run {
+            planned.push(PlannedAction {
+                op: "ABORT",
+                target: format!("{bucket}/{} ({})", record.key, record.upload_id),
+                bytes: None,
+            });
+        } else {
+            match abort_multipart(alias, bucket, &record.key, &record.upload_id, debug) {
+                Ok(()) => {
+                    let _ = fs::remove_file(path);
+                    aborted.push(*record);
+                }
+                Err(err) => {
+                    eprintln!(
+                        "error: could not abort multipart upload of '{bucket}/{}': {err}",
+                        record.key
+                    );
+                    failed.push(*record);
+                }
+            }
+        }
+    }
+    let running = plan.running;
+    let stale: Vec<&UploadRecord> = plan.stale.iter().map(|(_, r)| *r).collect();
+    if !dry_run {
+        // Completed, aborted or expired by a lifecycle rule: only the record is left.
+        for (path, _) in &plan.stale {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    if dry_run {
+        print_dry_run(json, &planned);
+        return Ok(());
+    }
+    if json {
+        let items = |records: &[&UploadRecord]| {
+            records
+                .iter()
+                .map(|r| {
+                    format!(
+                        "{{\"key\":\"{}\",\"upload_id\":\"{}\",\"pid\":{}}}",
+                        escape_json(&r.key),
+                        escape_json(&r.upload_id),
+                        r.pid
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        println!(
+            "{{\"status\":\"{}\",\"bucket\":\"{}\",\"aborted\":[{}],\"failed\":[{}],\"stale\":[{}],\"running\":[{}]}}",
+            if failed.is_empty() { "ok" } else { "error" },
+            escape_json(bucket),
+            items(&aborted),
+            items(&failed),
+            items(&stale),
+            items(&running)
+        );
+        return recover_result(&failed);
+    }
+    for record in &running {
+        eprintln!(
+            "Skipped '{bucket}/{}': the upload is still running (pid {})",
+            record.key, record.pid
+        );
+    }
+    if !is_quiet() {
+        for record in &aborted {
+            println!("Aborted multipart upload of '{bucket}/{}'", record.key);
+        }
+        for record in &stale {
+            println!(
+                "Dropped record of '{bucket}/{}': the upload is no longer listed",
+                record.key
+            );
+        }
+        if records.is_empty() {
+            println!("No recorded multipart uploads for '{bucket}'");
+        }
+    }
+    recover_result(&failed)
+}
+
+fn recover_result(failed: &[&UploadRecord]) -> Result<(), S4Error> {
+    if failed.is_empty() {
+        return Ok(());
+    }
+    Err(S4Error::Other(format!(
+        "{} multipart upload(s) could not be aborted; their records are kept for the next recover",
+        failed.len()
+    )))
+}
+
+fn build_complete_multipart_xml(
+    etags: &[(usize, String, Option<String>)],
+    checksum: Option<ChecksumAlgorithm>,
+) -> String {
+    let mut out = String::from("<CompleteMultipartUpload>");
+    for (part, etag, part_checksum) in etags {
+        out.push_str("<Part>");
+        out.push_str(&format!("<PartNumber>{}</PartNumber>", part));
+        out.push_str(&format!("<ETag>\"{}\"</ETag>", escape_xml(etag)));
+        if let (Some(algorithm), Some(value)) = (checksum, part_checksum) {
+            let tag = algorithm.xml_tag();
+            out.push_str(&format!("<{tag}>{}</{tag}>", escape_xml(value)));
+        }
+        out.push_str("</Part>");
+    }
+    out.push_str("</CompleteMultipartUpload>");
+    out
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date (Howard Hinnant's algorithm).
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Unix seconds for `YYYY-MM-DD` or `YYYY-MM-DDTHH:MM:SS[.fff](Z|±HH:MM)`.
+fn parse_rfc3339(value: &str) -> Result<i64, String> {
+    let invalid = || format!("invalid RFC3339 timestamp: {value}");
+    let num = |s: &str| s.parse::<i64>().map_err(|_| invalid());
+    let (date, time) = value.split_once(['T', 't', ' ']).unwrap_or((value, ""));
+    let parts: Vec<&str> = date.split('-').collect();
+    if parts.len() != 3 {
+        return Err(invalid());
+    }
+    let (year, month, day) = (num(parts[0])?, num(parts[1])?, num(parts[2])?);
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return Err(invalid());
+    }
+    let mut secs = days_from_civil(year, month as u32, day as u32) * 86400;
+    if time.is_empty() {
+        return Ok(secs);
+    }
+
+    let (clock, offset) = if let Some(c) = time.strip_suffix(['Z', 'z']) {
+        (c, 0)
+    } else if let Some(idx) = time.rfind(['+', '-']) {
+        let (h, m) = time[idx + 1..].split_once(':').ok_or_else(invalid)?;
+        let sign = if &time[idx..idx + 1] == "-" { -1 } else { 1 };
+        (&time[..idx], sign * (num(h)? * 3600 + num(m)? * 60))
+    } else {
+        return Err(invalid());
+    };
+    let clock = clock.split('.').next().unwrap_or(clock);
+    let hms: Vec<&str> = clock.split(':').collect();
+    if hms.len() != 3 {
+        return Err(invalid());
+    }
+    let (hour, minute, second) = (num(hms[0])?, num(hms[1])?, num(hms[2])?);
+    // 60 is a leap second; it lands on the next minute like everywhere else here.
+    if !(0..24).contains(&hour) || !(0..60).contains(&minute) || !(0..=60).contains(&second) {
+        return Err(invalid());
+    }
+    secs += hour * 3600 + minute * 60 + second;
+    Ok(secs - offset)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// IMF-fixdate (`Sun, 06 Nov 1994 08:49:37 GMT`) as used by HTTP date headers.
+fn http_date(secs: i64) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let days = secs.div_euclid(86400);
+    let rem = secs.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[days.rem_euclid(7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Objects and bytes moved by a bulk transfer (`put -r`, `mv -r`, `sync`) and the
+/// time it took, for the closing summary line.
+struct TransferSummary {
+    started: Instant,
+    objects: usize,
+    bytes: u64,
+}
+
+impl TransferSummary {
+    fn start() -> Self {
+        Self {
+            started: Instant::now(),
+            objects: 0,
+            bytes: 0,
+        }
+    }
+
+    fn add(&mut self, bytes: u64) {
+        self.objects += 1;
+        self.bytes += bytes;
+    }
+
+    fn line(&self) -> String {
+        transfer_summary_line(self.objects, self.bytes, self.started.elapsed())
+    }
+
+    fn json(&self) -> String {
+        let elapsed = self.started.elapsed();
+        format!(
+            "{{\"objects\":{},\"bytes\":{},\"elapsed_ms\":{},\"bytes_per_second\":{}}}",
+            self.objects,
+            self.bytes,
+            elapsed.as_millis(),
+            transfer_rate(self.bytes, elapsed)
+        )
+    }
+}
+
+fn transfer_rate(bytes: u64, elapsed: Duration) -> u64 {
+    (bytes as f64 / elapsed.as_secs_f64().max(0.001)) as u64
+}
+
+/// `Transferred 1.2 GiB in 34 object(s) (4.1 MiB/s, 00:05:01)`.
+fn transfer_summary_line(objects: usize, bytes: u64, elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    format!(
+        "Transferred {} in {} object(s) ({}/s, {:02}:{:02}:{:02})",
+        format_bytes(bytes),
+        objects,
+        format_bytes(transfer_rate(bytes, elapsed)),
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn uri_encode_query_component(s: &str) -> String {
+    let mut out = String::new();
+    for b in s.bytes() {
+        let c = b as char;
+        if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' || c == '~' {
+            out.push(c);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+fn parse_endpoint(raw: &str) -> Result<Endpoint, String> {
+    let (scheme, rest) = if let Some(v) = raw.strip_prefix("http://") {
+        ("http", v)
+    } else if let Some(v) = raw.strip_prefix("https://") {
+        ("https", v)
+    } else {
+        return Err("endpoint must start with http:// or https://".to_string());
+    };
+
+    let mut parts = rest.splitn(2, '/');
+    let host = parts.next().unwrap_or("").to_string();
+    if host.is_empty() {
+        return Err("endpoint host is empty".to_string());
+    }
+    let base_path = match parts.next() {
+        Some(v) if !v.is_empty() => format!("/{}", v.trim_end_matches('/')),
+        _ => "".to_string(),
+    };
+
+    Ok(Endpoint {
+        scheme: scheme.to_string(),
+        host,
+        base_path,
+    })
+}
+
+/// Profile names become part of a file name, so keep them to a safe character set.
+fn parse_profile_name(value: &str) -> Result<String, String> {
+    let valid = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        && !value.starts_with('.');
+    if valid {
+        Ok(value.to_string())
+    } else {
+        Err(format!("invalid --profile value: {value}"))
+    }
+}
+
+/// `--profile NAME` selects `config-NAME.toml` inside the config directory
+/// (`--config-dir` or `~/.s4`); without it the file is `config.toml`.
+/// Precedence: `--config-dir` (with `--profile`), then the `S4_CONFIG` file, then
+/// `$HOME/.s4`. `--profile` alone still picks its file in `$HOME/.s4`: the env var names
+/// one file, so it only stands in for the default `config.toml`.
+fn resolve_config_path(
+    custom_dir: Option<&Path>,
+    profile: Option<&str>,
+    env_file: Option<&Path>,
+) -> Result<PathBuf, S4Error> {
+    if let (None, None, Some(file)) = (custom_dir, profile, env_file) {
+        return Ok(file.to_path_buf());
+    }
+    let file = match profile {
+        Some(name) => format!("config-{name}.toml"),
+        None => "config.toml".to_string(),
+    };
+    match custom_dir {
+        Some(p) => Ok(p.join(file)),
+        None => {
+            let home =
+                env::var("HOME").map_err(|_| S4Error::Config("HOME is not set".to_string()))?;
+            Ok(PathBuf::from(home).join(".s4").join(file))
+        }
+    }
+}
+
+/// One profile from the AWS shared credentials/config files.
+#[derive(Debug, Default, PartialEq)]
+struct AwsProfile {
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+    region: Option<String>,
+    endpoint_url: Option<String>,
+}
+
+/// `key = value` pairs of one `[section]` in an INI-style AWS file, or None if absent.
+fn ini_section(text: &str, section: &str) -> Option<HashMap<String, String>> {
+    let mut found: Option<HashMap<String, String>> = None;
+    let mut current = false;
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            current = name.split_whitespace().collect::<Vec<_>>().join(" ") == section;
+            if current {
+                found.get_or_insert_with(HashMap::new);
+            }
+            continue;
+        }
+        if current
+            && let Some((key, value)) = line.split_once('=')
+            && let Some(map) = found.as_mut()
+        {
+            map.insert(key.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+    found
+}
+
+/// Merges `[name]` from the credentials file with `[profile name]` (or `[default]`)
+/// from the config file; credentials-file values win.
+fn parse_aws_profile(credentials: &str, config: &str, name: &str) -> Result<AwsProfile, String> {
+    let config_section = if name == "default" {
+        "default".to_string()
+    } else {
+        format!("profile {name}")
+    };
+    let creds = ini_section(credentials, name);
+    let conf = ini_section(config, &config_section).or_else(|| ini_section(config, name));
+    if creds.is_none() && conf.is_none() {
+        return Err(format!(
+            "AWS profile '{name}' not found in ~/.aws/credentials or ~/.aws/config"
+        ));
+    }
+    let get = |key: &str| {
+        creds
+            .as_ref()
+            .and_then(|m| m.get(key))
+            .or_else(|| conf.as_ref().and_then(|m| m.get(key)))
+            .filter(|v| !v.is_empty())
+            .cloned()
+    };
+    let (Some(access_key), Some(secret_key)) =
+        (get("aws_access_key_id"), get("aws_secret_access_key"))
+    else {
+        return Err(format!(
+            "AWS profile '{name}' has no aws_access_key_id/aws_secret_access_key"
+        ));
+    };
+    Ok(AwsProfile {
+        access_key,
+        secret_key,
+        session_token: get("aws_session_token"),
+        region: get("region"),
+        endpoint_url: get("endpoint_url"),
+    })
+}
+
+/// Reads `name` from `AWS_SHARED_CREDENTIALS_FILE`/`AWS_CONFIG_FILE`, defaulting to
+/// `~/.aws/credentials` and `~/.aws/config`; a missing file counts as empty.
+fn load_aws_profile(name: &str) -> Result<AwsProfile, S4Error> {
+    let home = env::var_os("HOME").map(PathBuf::from).unwrap_or_default();
+    let read = |var: &str, file: &str| -> Result<String, S4Error> {
+        let path = env::var_os(var)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| home.join(".aws").join(file));
+        match fs::read_to_string(&path) {
+            Ok(text) => Ok(text),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+            Err(e) => Err(S4Error::Config(format!("{}: {e}", path.display()))),
+        }
+    };
+    let credentials = read("AWS_SHARED_CREDENTIALS_FILE", "credentials")?;
+    let config = read("AWS_CONFIG_FILE", "config")?;
+    parse_aws_profile(&credentials, &config, name).map_err(S4Error::Config)
+}
+
+fn apply_aws_profile(alias: &mut AliasConfig, profile: AwsProfile) {
+    alias.access_key = profile.access_key;
+    alias.secret_key = profile.secret_key;
+    alias.session_token = profile.session_token;
+    if let Some(region) = profile.region {
+        alias.region = region;
+    }
+}
+
+/// Aliases a command line can reach: the first `/` segment of any argument that names
+/// one, plus the default alias for targets that omit it.
+fn referenced_aliases(config: &AppConfig, args: &[String]) -> HashSet<String> {
+    args.iter()
+        .filter_map(|arg| arg.split('/').next())
+        .filter(|name| config.aliases.contains_key(*name))
+        .map(str::to_string)
+        .chain(config.default_alias.clone())
+        .collect()
+}
+
+/// Fills in credentials for the aliases in `used` that are mapped to an AWS profile
+/// (others are left alone, so a broken profile only fails commands that use it) and,
+/// with the global `--aws-profile NAME`, adds (or re-keys) an alias called NAME.
+fn resolve_aws_aliases(
+    config: &mut AppConfig,
+    global: Option<&str>,
+    used: &HashSet<String>,
+) -> Result<(), S4Error> {
+    for (name, alias) in config.aliases.iter_mut() {
+        if !used.contains(name) {
+            continue;
+        }
+        if let Some(profile) = alias.aws_profile.clone() {
+            let resolved = load_aws_profile(&profile)
+                .map_err(|e| S4Error::Config(format!("alias '{name}': {e}")))?;
+            apply_aws_profile(alias, resolved);
+        }
+    }
+    let Some(name) = global else {
+        return Ok(());
+    };
+    let profile = load_aws_profile(name)?;
+    let alias = config.aliases.entry(name.to_string()).or_insert_with(|| {
+        let region = profile
+            .region
+            .clone()
+            .unwrap_or_else(|| "us-east-1".to_string());
+        AliasConfig {
+            endpoint: profile
+                .endpoint_url
+                .clone()
+                .unwrap_or_else(|| format!("https://s3.{region}.amazonaws.com")),
+            access_key: String::new(),
+            secret_key: String::new(),
+            region,
+            path_style: false,
+            request_payer: false,
+            aws_profile: Some(name.to_string()),
+            session_token: None,
+        }
+    });
+    apply_aws_profile(alias, profile);
+    Ok(())
+}
+
+fn load_config(path: &Path) -> Result<AppConfig, S4Error> {
+    if !path.exists() {
+        return Ok(AppConfig::default());
+    }
+
+    let mut file = fs::File::open(path)?;
+    let mut s = String::new();
+    file.read_to_string(&mut s)?;
+    parse_config(&s).map_err(S4Error::Config)
+}
+
+fn save_config(path: &Path, cfg: &AppConfig) -> Result<(), S4Error> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let text = serialize_config(cfg);
+    fs::write(path, text)?;
+    Ok(())
+}
+
+fn parse_config(text: &str) -> Result<AppConfig, String> {
+    let mut cfg = AppConfig::default();
+    for (ln, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parts: Vec<&str> = line.split('\t').collect();
+        if parts.len() == 2 && parts[0] == "default_alias" {
+            cfg.default_alias = Some(parts[1].to_string());
+            continue;
+        }
+        if parts.len() < 6 {
+            return Err(format!("invalid config at line {}", ln + 1));
+        }
+        cfg.aliases.insert(
+            parts[0].to_string(),
+            AliasConfig {
+                endpoint: parts[1].to_string(),
+                access_key: parts[2].to_string(),
+                secret_key: parts[3].to_string(),
+                region: parts[4].to_string(),
+                path_style: parts[5] == "1",
+                request_payer: alias_option(&parts[6..], "request_payer")
+                    .map_err(|e| format!("invalid config at line {}: {e}", ln + 1))?,
+                aws_profile: alias_option_value(&parts[6..], "aws_profile")
+                    .map_err(|e| format!("invalid config at line {}: {e}", ln + 1))?,
+                session_token: None,
+            },
+        );
+    }
+    Ok(cfg)
+}
+
+fn serialize_config(cfg: &AppConfig) -> String {
+    let mut out = String::new();
+    if let Some(name) = &cfg.default_alias {
+        out.push_str(&format!("default_alias\t{name}\n"));
+    }
+    for (name, a) in &cfg.aliases {
+        out.push_str(&format!(
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            name,
+            a.endpoint,
+            a.access_key,
+            a.secret_key,
+            a.region,
+            if a.path_style { "1" } else { "0" }
+        ));
+        if a.request_payer {
+            out.push_str("\trequest_payer=1");
+        }
+        if let Some(profile) = &a.aws_profile {
+            out.push_str(&format!("\taws_profile={profile}"));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// `alias set` flags. Fields left `None` keep their current value, which is what lets
+/// `alias set NAME --region r` update a single field of an existing alias.
+#[derive(Debug, Default)]
+struct AliasSetFlags {
+    endpoint: Option<String>,
+    region: Option<String>,
+    path_style: Option<bool>,
+    request_payer: Option<bool>,
+    aws_profile: Option<String>,
+    test: bool,
+    test_required: bool,
+}
+
+impl AliasSetFlags {
+    fn updates_fields(&self) -> bool {
+        self.endpoint.is_some()
+            || self.region.is_some()
+            || self.path_style.is_some()
+            || self.request_payer.is_some()
+            || self.aws_profile.is_some()
+    }
+
+    fn apply(&self, alias: &mut AliasConfig) {
+        if let Some(endpoint) = &self.endpoint {
+            alias.endpoint = endpoint.clone();
+        }
+        if let Some(region) = &self.region {
+            alias.region = region.clone();
+        }
+        if let Some(path_style) = self.path_style {
+            alias.path_style = path_style;
+        }
+        if let Some(request_payer) = self.request_payer {
+            alias.request_payer = request_payer;
+        }
+        if let Some(profile) = &self.aws_profile {
+            alias.aws_profile = Some(profile.clone());
+        }
+    }
+}
+
+fn parse_alias_set_flags(args: &[String]) -> Result<AliasSetFlags, String> {
+    let mut flags = AliasSetFlags::default();
+    let mut i = 0;
+    while i < args.len() {
+        let value = || {
+            args.get(i + 1)
+                .cloned()
+                .ok_or_else(|| format!("{} expects a value", args[i]))
+        };
+        match args[i].as_str() {
+            "--endpoint" => {
+                flags.endpoint = Some(value()?);
+                i += 1;
+            }
+            "--region" => {
+                flags.region = Some(value()?);
+                i += 1;
+            }
+            "--aws-profile" => {
+                flags.aws_profile = Some(value()?);
+                i += 1;
+            }
+            "--path-style" => flags.path_style = Some(true),
+            "--no-path-style" => flags.path_style = Some(false),
+            "--request-payer" => flags.request_payer = Some(true),
+            "--no-request-payer" => flags.request_payer = Some(false),
+            "--test" => flags.test = true,
+            "--test-required" => {
+                flags.test = true;
+                flags.test_required = true;
+            }
+            other => return Err(format!("unknown alias set flag: {other}")),
+        }
+        i += 1;
+    }
+    Ok(flags)
+}
+
+/// Optional `name=1` columns after the six fixed alias fields; absent means off.
+fn alias_option(extra: &[&str], name: &str) -> Result<bool, String> {
+    Ok(alias_option_value(extra, name)?.is_some_and(|v| v == "1"))
+}
+
+/// Optional `name=value` column after the six fixed alias fields.
+fn alias_option_value(extra: &[&str], name: &str) -> Result<Option<String>, String> {
+    for field in extra {
+        let (key, value) = field
+            .split_once('=')
+            .ok_or_else(|| format!("malformed alias option '{field}'"))?;
+        if key == name {
+            return Ok(Some(value.to_string()));
+        }
+    }
+    Ok(None)
+}
+
+/// Aliases as a JSON array for `alias export`; `secrets: false` leaves the keys out.
+fn aliases_to_json(cfg: &AppConfig, secrets: bool) -> String {
+    let items: Vec<String> = cfg
+        .aliases
+        .iter()
+        .map(|(name, a)| {
+            let keys = if secrets {
+                format!(
+                    ",\"access_key\":\"{}\",\"secret_key\":\"{}\"",
+                    escape_json(&a.access_key),
+                    escape_json(&a.secret_key)
+                )
+            } else {
+                String::new()
+            };
+            let profile = a
+                .aws_profile
+                .as_ref()
+                .map(|p| format!(",\"aws_profile\":\"{}\"", escape_json(p)))
+                .unwrap_or_default();
+            format!(
+                "  {{\"name\":\"{}\",\"endpoint\":\"{}\"{},\"region\":\"{}\",\"path_style\":{},\"request_payer\":{}{}}}",
+                escape_json(name),
+                escape_json(&a.endpoint),
+                keys,
+                escape_json(&a.region),
+                a.path_style,
+                a.request_payer,
+                profile
+            )
+        })
+        .collect();
+    format!("[\n{}\n]", items.join(",\n"))
+}
+
+/// Entries exported with `--no-secrets` come back with empty keys; `alias import`
+/// fills them in from the aliases they replace (`adopt_existing_keys`).
+fn aliases_from_json(text: &str) -> Result<Vec<(String, AliasConfig)>, String> {
+    let mut out: Vec<(String, AliasConfig)> = Vec::new();
+    for obj in parse_json_object_array(text)? {
+        let field = |k: &str| match obj.get(k) {
+            Some(JsonScalar::Str(v)) => Some(v.clone()),
+            _ => None,
+        };
+        let name = field("name").ok_or("alias entry without \"name\"")?;
+        // The config file is tab- and line-separated, so these would corrupt it.
+        for (key, value) in &obj {
+            if let JsonScalar::Str(v) = value
+                && v.chars().any(char::is_control)
+            {
+                return Err(format!(
+                    "alias '{}' has a control character in \"{key}\"",
+                    name.escape_debug()
+                ));
+            }
+        }
+        if out.iter().any(|(existing, _)| *existing == name) {
+            return Err(format!("alias '{name}' appears more than once"));
+        }
+        let endpoint =
+            field("endpoint").ok_or_else(|| format!("alias '{name}' has no endpoint"))?;
+        let aws_profile = field("aws_profile");
+        let (access_key, secret_key) = match (field("access_key"), field("secret_key")) {
+            (Some(access), Some(secret)) => (access, secret),
+            (None, None) => (String::new(), String::new()),
+            _ => {
+                return Err(format!(
+                    "alias '{name}' has only one of access_key/secret_key"
+                ));
+            }
+        };
+        out.push((
+            name,
+            AliasConfig {
+                endpoint,
+                access_key,
+                secret_key,
+                region: field("region").unwrap_or_else(|| "us-east-1".to_string()),
+                path_style: matches!(obj.get("path_style"), Some(JsonScalar::Bool(true))),
+                request_payer: matches!(obj.get("request_payer"), Some(JsonScalar::Bool(true))),
+                aws_profile,
+                session_token: None,
+            },
+        ));
+    }
+    Ok(out)
+}
+
+/// Imported aliases without keys (a `--no-secrets` export) keep the keys of the
+/// configured alias they replace; any other keyless entry is refused by name.
+fn adopt_existing_keys(
+    imported: &mut [(String, AliasConfig)],
+    config: &AppConfig,
+) -> Result<(), String> {
+    let mut missing = Vec::new();
+    for (name, alias) in imported.iter_mut() {
+        if !alias.access_key.is_empty() || alias.aws_profile.is_some() {
+            continue;
+        }
+        match config.aliases.get(name) {
+            Some(existing) if !existing.access_key.is_empty() => {
+                alias.access_key = existing.access_key.clone();
+                alias.secret_key = existing.secret_key.clone();
+            }
+            _ => missing.push(name.as_str()),
+        }
+    }
+    if missing.is_empty() {
+        return Ok(());
+    }
+    Err(format!(
+        "alias(es) without access_key/secret_key: {} (exported with --no-secrets); add the keys to the file, or import over existing aliases of the same name with --overwrite to keep their keys",
+        missing.join(", ")
+    ))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum JsonScalar {
+    Str(String),
+    Bool(bool),
+    Num(String),
+    Null,
+}
+
+/// Parses `[{...}, ...]` where every value is a scalar; enough for alias files.
+fn parse_json_object_array(text: &str) -> Result<Vec<BTreeMap<String, JsonScalar>>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut pos = 0;
+    let skip_ws = |pos: &mut usize| {
+        while *pos < chars.len() && chars[*pos].is_whitespace() {
+            *pos += 1;
+        }
+    };
+    let expect = |pos: &mut usize, c: char| -> Result<(), String> {
+        skip_ws(pos);
+        if chars.get(*pos) == Some(&c) {
+            *pos += 1;
+            Ok(())
+        } else {
+            Err(format!("invalid JSON: expected '{c}' at offset {}", *pos))
+        }
+    };
+    let parse_hex4 = |pos: &mut usize| -> Result<u32, String> {
+        let hex: String = chars.iter().skip(*pos).take(4).collect();
+        if hex.len() != 4 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err("invalid JSON \\u escape".to_string());
+        }
+        *pos += 4;
+        u32::from_str_radix(&hex, 16).map_err(|_| "invalid JSON \\u escape".to_string())
+    };
+    let parse_string = |pos: &mut usize| -> Result<String, String> {
+        expect(pos, '"')?;
+        let mut out = String::new();
+        while let Some(&c) = chars.get(*pos) {
+            *pos += 1;
+            match c {
+                '"' => return Ok(out),
+                '\\' => {
+                    let esc = chars.get(*pos).copied().ok_or("invalid JSON escape")?;
+                    *pos += 1;
+                    match esc {
+                        '"' | '\\' | '/' => out.push(esc),
+                        'b' => out.push('\u{8}'),
+                        'f' => out.push('\u{c}'),
+                        'n' => out.push('\n'),
+                        'r' => out.push('\r'),
+                        't' => out.push('\t'),
+                        'u' => {
+                            let mut code = parse_hex4(pos)?;
+                            // Characters outside the BMP come as a UTF-16 surrogate pair.
+                            if (0xd800..0xdc00).contains(&code)
+                                && chars.get(*pos) == Some(&'\\')
+                                && chars.get(*pos + 1) == Some(&'u')
+                            {
+                                *pos += 2;
+                                let low = parse_hex4(pos)?;
+                                if !(0xdc00..0xe000).contains(&low) {
+                                    return Err("invalid JSON surrogate pair".to_string());
+                                }
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            out.push(
+                                char::from_u32(code)
+                                    .ok_or("invalid JSON \\u escape: lone surrogate")?,
+                            );
+                        }
+                        other => return Err(format!("invalid JSON escape: \\{other}")),
+                    }
+                }
+                c if c.is_control() => {
+                    return Err("invalid JSON: unescaped control character in string".to_string());
+                }
+                _ => out.push(c),
+            }
+        }
+        Err("invalid JSON: unterminated string".to_string())
+    };
+
+    let mut objects = Vec::new();
+    expect(&mut pos, '[')?;
+    skip_ws(&mut pos);
+    if chars.get(pos) == Some(&']') {
+        return Ok(objects);
+    }
+    loop {
+        expect(&mut pos, '{')?;
+        let mut obj = BTreeMap::new();
+        skip_ws(&mut pos);
+        if chars.get(pos) == Some(&'}') {
+            pos += 1;
+        } else {
+            loop {
+                skip_ws(&mut pos);
+                let key = parse_string(&mut pos)?;
+                expect(&mut pos, ':')?;
+                skip_ws(&mut pos);
+                let value = if chars.get(pos) == Some(&'"') {
+                    JsonScalar::Str(parse_string(&mut pos)?)
+                } else {
+                    let start = pos;
+                    while pos < chars.len()
+                        && !matches!(chars[pos], ',' | '}' | ']')
+                        && !chars[pos].is_whitespace()
+                    {
+                        pos += 1;
+                    }
+                    match chars[start..pos].iter().collect::<String>().as_str() {
+                        "true" => JsonScalar::Bool(true),
+                        "false" => JsonScalar::Bool(false),
+                        "null" => JsonScalar::Null,
+                        n if n.parse::<f64>().is_ok() => JsonScalar::Num(n.to_string()),
+                        other => return Err(format!("invalid JSON value: {other}")),
+                    }
+                };
+                if obj.contains_key(&key) {
+                    return Err(format!("invalid JSON: duplicate name \"{key}\""));
+                }
+                obj.insert(key, value);
+                skip_ws(&mut pos);
+                match chars.get(pos) {
+                    Some(',') => pos += 1,
+                    Some('}') => {
+                        pos += 1;
+                        break;
+                    }
+                    _ => {
+                        return Err(format!(
+                            "invalid JSON: expected ',' or '}}' at offset {pos}"
+                        ));
+                    }
+                }
+            }
+        }
+        objects.push(obj);
+        skip_ws(&mut pos);
+        match chars.get(pos) {
+            Some(',') => pos += 1,
+            Some(']') => return Ok(objects),
+            _ => return Err(format!("invalid JSON: expected ',' or ']' at offset {pos}")),
+        }
+    }
+}
+
+fn parse_target(input: &str) -> Result<S3Target, String> {
+    let shell = shell_cwd().lock().ok().and_then(|cwd| cwd.clone());
+    if let (Some(cwd), Some((known, _))) = (shell, TARGET_ALIASES.get()) {
+        return split_target(&cwd.resolve(input, known));
+    }
+    let target = split_target(input)?;
+    Ok(match TARGET_ALIASES.get() {
+        Some((known, default)) => apply_default_alias(target, known, default.as_deref()),
+        None => target,
+    })
+}
+
+fn split_target(input: &str) -> Result<S3Target, String> {
+    let mut parts = input.splitn(3, '/');
+    let alias = parts
+        .next()
+        .ok_or_else(|| "target must start with alias".to_string())?
+        .to_string();
+    if alias.is_empty() {
+        return Err("target alias is empty".to_string());
+    }
+    let bucket = parts.next().map(ToString::to_string);
+    let key = parts.next().map(ToString::to_string);
+    Ok(S3Target { alias, bucket, key })
+}
+
+/// Known alias names and the default alias, recorded once the config is loaded so
+/// `parse_target` can resolve `bucket/key` against the default.
+static TARGET_ALIASES: OnceLock<(HashSet<String>, Option<String>)> = OnceLock::new();
+
+/// Current directory of `s4 shell` while one of its commands runs.
+static SHELL_CWD: OnceLock<Mutex<Option<ShellCwd>>> = OnceLock::new();
+
+fn shell_cwd() -> &'static Mutex<Option<ShellCwd>> {
+    SHELL_CWD.get_or_init(|| Mutex::new(None))
+}
+
+fn aliases_of(config: &AppConfig) -> HashSet<String> {
+    config.aliases.keys().cloned().collect()
+}
+
+/// Where `s4 shell` is: an alias plus the bucket and prefix segments below it.
+#[derive(Debug, Clone, PartialEq)]
+struct ShellCwd {
+    alias: String,
+    /// Bucket first, then prefix segments; empty at the alias root.
+    path: Vec<String>,
+}
+
+impl ShellCwd {
+    fn display(&self) -> String {
+        let mut out = format!("{}/", self.alias);
+        for segment in &self.path {
+            out.push_str(segment);
+            out.push('/');
+        }
+        out
+    }
+
+    /// `input` applied to the current path: `.`/`..` segments are folded, a leading
+    /// `/` starts at the alias root and a known alias name replaces the current one.
+    fn join(&self, input: &str, known: &HashSet<String>) -> (String, Vec<String>) {
+        let mut segments = input.split('/');
+        let (alias, mut path) = match input.split('/').next() {
+            Some(first) if known.contains(first) => {
+                segments.next();
+                (first.to_string(), Vec::new())
+            }
+            Some("") => (self.alias.clone(), Vec::new()),
+            _ => (self.alias.clone(), self.path.clone()),
+        };
+        for segment in segments {
+            match segment {
+                "" | "." => {}
+                ".." => {
+                    path.pop();
+                }
+                name => path.push(name.to_string()),
+            }
+        }
+        (alias, path)
+    }
+
+    fn cd(&mut self, input: &str, known: &HashSet<String>) {
+        (self.alias, self.path) = self.join(input, known);
+    }
+
+    /// Target string for `parse_target`. Anything that names a directory (`.`, `..`,
+    /// a trailing `/`) keeps a trailing slash so it reads as a prefix below the bucket.
+    fn resolve(&self, input: &str, known: &HashSet<String>) -> String {
+        let (alias, path) = self.join(input, known);
+        let mut out = alias;
+        if !path.is_empty() {
+            out.push('/');
+            out.push_str(&path.join("/"));
+        }
+        let dir = input.is_empty()
+            || input.ends_with('/')
+            || matches!(input.rsplit('/').next(), Some("." | ".."));
+        if dir && path.len() > 1 {
+            out.push('/');
+        }
+        out
+    }
+}
+
+/// Explicit aliases win; otherwise the first segment is the bucket of the default alias.
+fn apply_default_alias(
+    target: S3Target,
+    known: &HashSet<String>,
+    default: Option<&str>,
+) -> S3Target {
+    let Some(default) = default else {
+        return target;
+    };
+    if known.contains(&target.alias) {
+        return target;
+    }
+    let key = match (target.bucket, target.key) {
+        (Some(b), Some(k)) => Some(format!("{b}/{k}")),
+        (Some(b), None) => Some(b),
+        (None, _) => None,
+    };
+    S3Target {
+        alias: default.to_string(),
+        bucket: Some(target.alias),
+        key: key.filter(|k| !k.is_empty()),
+    }
+}
+
+fn unknown_alias(name: &str) -> String {
+    match TARGET_ALIASES.get() {
+        Some((_, Some(_))) | None => format!("unknown alias: {name}"),
+        Some((_, None)) => format!(
+            "unknown alias: {name} (no default alias is set; use `s4 alias set-default NAME` to address targets as bucket/key)"
+        ),
+    }
+}
+
+fn uri_encode_segment(s: &str) -> String {
+    uri_encode_path(s)
+}
+
+fn uri_encode_path(s: &str) -> String {
+    let mut out = String::new();
+    for b in s.bytes() {
+        let c = b as char;
+        if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' || c == '~' || c == '/' {
+            out.push(c);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+fn escape_json(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn print_status(json: bool, field: &str, value: &str) {
+    if json {
+        println!("{{\"{}\":\"{}\"}}", escape_json(field), escape_json(value));
+    } else if !is_quiet() {
+        println!("{field}: {value}");
+    }
+}
+
+fn print_help() {
+    println!(
+        "s4 - S3 client utility in Rust
+
+USAGE:
+  s4 [FLAGS] COMMAND [ARGS]
+
+COMMANDS:
+  alias      manage aliases in local config (set/set-default/ls/rm/export/import)
+  ls         list buckets/objects (--folders/-d lists only common prefixes)
+  mb         make bucket
+  rb         remove bucket
+  legalhold  manage legal hold for object(s) (set/clear/info)
+  retention  manage retention for object(s) (set/clear/info)
+  sql        run SQL queries on objects
+  replicate  manage server-side bucket replication [placeholder]
+  put        upload object (--recursive uploads a directory under a prefix, a quoted 'dir/*.gz' uploads the matches,
+             --from-url copies a remote http(s) URL)
+  get        download object
+  rm         remove object
+  stat       object metadata (raw headers, lifecycle expiry); --checksum FILE compares it with a local file
+  cat        print object content
+  cors       manage bucket CORS configuration (set/get/remove)
+  encrypt    manage bucket encryption config (set/clear/info)
+  event      manage bucket notifications (add/remove/list)
+  idp        manage identity providers (openid/ldap) [placeholder]
   ilm        manage lifecycle (rule/tier/restore) [placeholder]
   sync       sync objects from source bucket/prefix to destination
   mirror     alias for sync (mc-compatible naming)
   cp         copy object(s) between local and S3
   mv         move object(s) between local and S3
+  rename     rename an object within its bucket (alias: ren)
+  diff       compare two prefixes: keys only on one side, size/ETag mismatches
   find       find objects in bucket/prefix
   tree       show object tree in bucket/prefix
+  du         disk usage per prefix (--depth N rolls up sizes, sorted by size)
+  usage      total bytes and object count of a bucket or prefix (--quota: MinIO bucket quota)
   head       print first N lines from object
   pipe       upload stdin stream to object
+  append     append stdin to an object (experimental; rewrites it unless the backend supports append)
   ping       perform liveness check
   ready      check that alias endpoint is ready
+  whoami     verify alias credentials and show the identity they belong to
+  multipart  recover: abort multipart uploads left by crashed s4 runs (recorded per config dir)
+  restore    wait: poll x-amz-restore until an archived object's restore completes (--timeout, --interval)
+  location   print bucket region (LocationConstraint)
+  shell      interactive prompt with cd/pwd; targets resolve relative to the current alias/bucket/prefix
+  api        advanced: send one raw signed request (--method, --query, --body, --header) and print the response as is
   version    print version
 
-FLAGS:
-  -C, --config-dir <DIR>
-  --json
-  --debug
-  --insecure
-  --resolve <HOST:PORT=IP>
-  --limit-upload <RATE>
-  --limit-download <RATE>
-  -H, --custom-header <KEY:VALUE>
-  -h, --help
-  -v, --version
+FLAGS:
+  -C, --config-dir <DIR>  also S4_CONFIG=<FILE> (config file path; the flag wins)
+  --tmp-dir <DIR>      temp files (parts, bodies, sync staging); also S4_TMP_DIR
+  --profile <NAME>     use config-NAME.toml from the config dir instead of config.toml
+  --json               same as --output json
+  -o, --output <FMT>   human|json|jsonl|csv (tabular for ls/find)
+  -q, --quiet          suppress success messages (errors and --json output remain)
+  --debug
+  --insecure
+  --ca-bundle <PATH>   trust the CA certificates in PATH (PEM); safer than --insecure
+  --client-cert <PATH> client certificate for mutual TLS (PEM; may include the key)
+  --client-key <PATH>  private key for --client-cert when it is a separate file
+  --head-via-get retry a failing HEAD as GET with Range: bytes=0-0
+  --list-v1      list buckets with ListObjects V1 (marker paging) for old gateways
+  --page-size N  keys per listing request (max-keys, 1-1000; default 1000)
+  --show-headers print every request/response header block to stderr (Authorization redacted)
+  --no-color     plain ls/tree/find/diff output even on a terminal (also NO_COLOR=1)
+  --unsigned-payload   sign uploads with UNSIGNED-PAYLOAD instead of hashing the body
+  --resolve <HOST:PORT=IP>
+  --limit-upload <RATE>
+  --limit-download <RATE>
+  -H, --custom-header <KEY:VALUE>  sent as is, NOT covered by the signature
+  --signed-header <KEY:VALUE>      sent and included in the SigV4 signed headers
+  --request-payer      send x-amz-request-payer: requester (requester-pays buckets)
+  --aws-profile <NAME> use alias NAME with credentials from ~/.aws/credentials and ~/.aws/config
+  --endpoint <URL> --access-key <K> --secret-key <S> [--region <R>] [--path-style]
+                       one-off alias '_' (not saved): s4 --endpoint ... ls _/bucket
+  --user-agent <UA>    User-Agent for every request (default s4/<version>)
+  --max-concurrent <N> cap on in-flight HTTP requests (default 8)
+  -h, --help
+  -v, --version
+
+NOTE:
+  mb supports --with-lock for object-lock buckets (used by legalhold tests)
+  mb --ignore-existing succeeds when the bucket already exists and is owned by you
+  mb sends a LocationConstraint for non-us-east-1 regions (override with --region)
+  get/cat accept --if-none-match ETAG and --if-modified-since RFC3339; 304 skips the download
+  --debug traces every curl request/response on stderr (Authorization redacted)
+  get/cat accept --decompress to inflate objects served with Content-Encoding: gzip
+  ls/find --skip-dir-markers hides zero-byte dir/ keys, --only-dir-markers shows only them
+  put -r and local sync skip symlinks by default; --follow-symlinks uploads their targets
+  get/cat --accept-encoding gzip|zstd|auto negotiates transfer compression (auto: text-like types only)
+  cat accepts -n (number lines) and --head N / --tail N
+  sql --concurrency N queries N objects at once with -r (output still in key order)
+  head reads ranged chunks until N lines are seen; head --bytes N prints the first N bytes raw
+  head --decompress inflates a gzip Content-Encoding and counts lines/bytes after decoding
+  ready --health-endpoint probes MinIO's unsigned /minio/health/live and /minio/health/ready (expects 200)
+  ls/find --owner request fetch-owner=true and add owner_id/owner_display_name columns
+  cors set/encrypt set/event add check the XML locally first; --validate-only skips the upload
+  cors get/encrypt info/event ls --json print parsed rules; add --raw for the XML string
+  ping --count N sends N requests and reports min/avg/max/p95 latency and losses
+  get/cp accept --download-concurrency N: parallel ranged GETs when the server sends Accept-Ranges
+  put/rm/cp/mv accept --dry-run: print the planned requests and send nothing
+  cp/mv --if-size-differs skips when the destination has the same size (--checksum: and MD5/ETag)
+  cp/mv S3 -> S3 is one server-side CopyObject (sources up to 5 GiB); --json adds its etag and last_modified
+  cp/mv --preserve carries tags, retention and legal hold to an S3 destination
+  rm --bypass-governance sends x-amz-bypass-governance-retention (COMPLIANCE objects cannot be bypassed)
+  put/pipe accept --compress gzip (uploads the gzipped body with Content-Encoding: gzip)
+  put/cp/mv accept --if-none-match '*' (fail if the object exists) and --if-match ETAG
+  put accepts --content-md5 to send Content-MD5 (per part for multipart uploads)
+  put/cp accept --content-disposition, --cache-control and --content-language (stored with the object)
+  put/cp/mv accept --checksum-algorithm CRC32|SHA256 (x-amz-checksum-*, per part for multipart)
+  put/get/cp/mv/sync/mirror/pipe/cat accept --limit <RATE> (0 or unlimited disables);
+  it goes before the paths and takes precedence over --limit-upload/--limit-download for that command"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        AcceptEncoding, AliasConfig, AppConfig, BucketQuota, CURL_HEADER_WRITEOUT_VERSION,
+        CanonicalRequest, CatLines, ChecksumAlgorithm, CopyResult, CorsCommand, DirMarkers,
+        EXPECT_CONTINUE_THRESHOLD_BYTES, EncryptCommand, EventCommand, GlobalOpts, HeadBody,
+        HeadMode, IdpKind, IlmKind, JsonScalar, LegalHoldCommand, ListQuery, ListedObject,
+        ListedOwner, ObjectExpiration, ObjectLockStatus, OutputFormat, RecoverPlan,
+        ReplicateSubcommand, RestoreState, RestoreWait, RetentionCommand, RetentionPeriod,
+        S3_SIGNING_SERVICE, S4Error, STYLE_DIM, ShellCwd, StoredDigest, SyncEndpoint, SyncMetrics,
+        SyncOptions, SyncPass, TransferFingerprint, UploadRecord, adopt_existing_keys,
+        aliases_from_json, aliases_to_json, append_carry_headers, apply_curl_global_flags,
+        apply_curl_transport_flags, apply_default_alias, base64_encode, bucket_list_rows,
+        build_complete_multipart_xml, build_create_bucket_xml, build_object_lock_config_xml,
+        build_select_request_xml, check_complete_multipart_response, check_tmp_dir,
+        check_xml_well_formed, checksum_headers, checksum_value, cleanup_registry, cmd_sql,
+        cmd_sync, cmd_sync_once, collect_local_files, color_wanted, column_widths,
+        comparable_digests, content_md5_header, copy_replace_headers, copy_result_json,
+        cors_rules_json, create_state_dir, csv_field, curl_global_opts, diff_listings, du_rollup,
+        encode_journal_key, encryption_rules_json, ephemeral_alias, etag_md5, expand_put_glob,
+        explain_precondition_failure, extract_tag_blocks, extract_tag_values,
+        extract_version_entries, fall_back_to_list_v1, file_md5_hex, folder_prefix, format_bytes,
+        gunzip_file, gzip_file, has_wildcard, head_end, head_fingerprint, head_line_end,
+        hex_digest_base64, http_date, http_error, is_archive_storage_class, is_bucket_owned_error,
+        is_curl_trace_line, is_excluded, is_gzip_encoding, is_private_dir,
+        is_text_like_content_type, is_transient_error, is_unlimited_rate, json_record,
+        key_basename, key_style, list_buckets_owner, list_v1_next_marker, listed_modified_times,
+        lists_with_v1, load_upload_records, local_entry_path, looks_ready_xml,
+        merge_signed_headers, normalize_resolve_entry, normalize_sigv4_query, not_found_error,
+        notification_configs_json, object_age_secs, object_header, object_list_rows,
+        object_lock_copy_headers, object_lock_status, objects_older_than, parse_alias_set_flags,
+        parse_api_args, parse_append_args, parse_aws_profile, parse_common_prefixes, parse_config,
+        parse_copy_result, parse_cors_args, parse_cp_args, parse_curl_version, parse_delete_errors,
+        parse_download_concurrency, parse_du_args, parse_encrypt_args, parse_endpoint,
+        parse_event_args, parse_event_stream_records, parse_expiration_header, parse_find_args,
+        parse_get_args, parse_globals, parse_head_args, parse_human_duration, parse_idp_args,
+        parse_ilm_args, parse_json_object_array, parse_legalhold_args, parse_listed_objects,
+        parse_location_constraint, parse_ls_args, parse_minio_quota, parse_multipart_args,
+        parse_ping_args, parse_pipe_args, parse_profile_name, parse_put_args, parse_ready_args,
+        parse_rename_args, parse_replicate_args, parse_restore_args, parse_restore_header,
+        parse_retention_args, parse_rfc3339, parse_rm_args, parse_s3_error, parse_signed_header,
+        parse_source_url, parse_sql_args, parse_stat_args, parse_sync_args, parse_sync_journal,
+        parse_target, parse_usage_args, partition_signed_headers, ping_stats, precondition_header,
+        prefix_inside_folder, process_running, ranged_download_size, ranged_get_as_head,
+        redact_curl_trace_line, redact_secret, referenced_aliases, rejects_list_v2, remove_temp,
+        resolve_aws_aliases, resolve_config_path, run_ordered, run_tool, serialize_config,
+        should_retry_with_governance_bypass, sign_v4, split_curl_trace, split_download_ranges,
+        split_shell_words, split_status_trailer, supports_native_append, sync_actions_json,
+        sync_destination_key, sync_time_filter_matches, table_row, take_command_limit, track_temp,
+        transfer_summary_line, tree_lines, untrack_temp, upload_state_dirs, uri_encode_path,
+        uri_encode_query_component, verify_sync_pass, wants_expect_continue, wildcard_match,
+        wrong_region_hint, xml_unescape,
+    };
+    use std::collections::BTreeMap;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+
+    #[test]
+    fn parse_target_with_key() {
+        let t = parse_target("local/bucket/folder/file.txt").expect("target should parse");
+        assert_eq!(t.alias, "local");
+        assert_eq!(t.bucket.as_deref(), Some("bucket"));
+        assert_eq!(t.key.as_deref(), Some("folder/file.txt"));
+    }
+
+    #[test]
+    fn default_alias_resolves_targets_without_a_known_alias() {
+        let known: std::collections::HashSet<String> = ["s3".to_string()].into_iter().collect();
+        let raw = |s: &str| {
+            let mut parts = s.splitn(3, '/');
+            super::S3Target {
+                alias: parts.next().unwrap_or_default().to_string(),
+                bucket: parts.next().map(ToString::to_string),
+                key: parts.next().map(ToString::to_string),
+            }
+        };
+        let t = apply_default_alias(raw("photos/2024/a.jpg"), &known, Some("s3"));
+        assert_eq!(t.alias, "s3");
+        assert_eq!(t.bucket.as_deref(), Some("photos"));
+        assert_eq!(t.key.as_deref(), Some("2024/a.jpg"));
+        let bucket_only = apply_default_alias(raw("photos"), &known, Some("s3"));
+        assert_eq!(bucket_only.bucket.as_deref(), Some("photos"));
+        assert_eq!(bucket_only.key, None);
+        let explicit = apply_default_alias(raw("s3/other/k"), &known, Some("s3"));
+        assert_eq!(explicit.bucket.as_deref(), Some("other"));
+        let no_default = apply_default_alias(raw("photos/a.jpg"), &known, None);
+        assert_eq!(no_default.alias, "photos");
+    }
+
+    #[test]
+    fn alias_set_flags_update_only_given_fields() {
+        let args = |list: &[&str]| list.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        let mut alias = AliasConfig {
+            endpoint: "http://127.0.0.1:9000".to_string(),
+            access_key: "AK".to_string(),
+            secret_key: "SK".to_string(),
+            region: "us-east-1".to_string(),
+            path_style: true,
+            request_payer: true,
+            aws_profile: None,
+            session_token: None,
+        };
+        let flags = parse_alias_set_flags(&args(&["--region", "eu-west-1"])).unwrap();
+        assert!(flags.updates_fields());
+        flags.apply(&mut alias);
+        assert_eq!(alias.region, "eu-west-1");
+        assert_eq!(alias.endpoint, "http://127.0.0.1:9000");
+        assert!(alias.path_style && alias.request_payer);
+        assert_eq!(
+            (alias.access_key.as_str(), alias.secret_key.as_str()),
+            ("AK", "SK")
+        );
+
+        let flags = parse_alias_set_flags(&args(&[
+            "--endpoint",
+            "https://s3.example.com",
+            "--no-path-style",
+            "--test",
+        ]))
+        .unwrap();
+        flags.apply(&mut alias);
+        assert_eq!(alias.endpoint, "https://s3.example.com");
+        assert!(!alias.path_style && alias.request_payer && flags.test);
+        assert_eq!(alias.region, "eu-west-1");
+
+        assert!(
+            !parse_alias_set_flags(&args(&["--test"]))
+                .unwrap()
+                .updates_fields()
+        );
+        assert!(parse_alias_set_flags(&args(&["--region"])).is_err());
+        assert!(parse_alias_set_flags(&args(&["--bogus"])).is_err());
+    }
+
+    #[test]
+    fn alias_set_test_flags_parse() {
+        let args = |list: &[&str]| list.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        let flags = parse_alias_set_flags(&args(&["--test"])).unwrap();
+        assert!(flags.test && !flags.test_required);
+        assert!(!flags.updates_fields());
+
+        let flags = parse_alias_set_flags(&args(&["--test-required"])).unwrap();
+        assert!(flags.test && flags.test_required);
+        assert!(!flags.updates_fields());
+
+        // A later --test does not weaken an earlier --test-required.
+        let flags = parse_alias_set_flags(&args(&["--test-required", "--test"])).unwrap();
+        assert!(flags.test && flags.test_required);
+
+        let flags =
+            parse_alias_set_flags(&args(&["--region", "eu-west-1", "--test-required"])).unwrap();
+        assert_eq!(flags.region.as_deref(), Some("eu-west-1"));
+        assert!(flags.test_required && flags.updates_fields());
+
+        assert!(parse_alias_set_flags(&args(&["--test=required"])).is_err());
+        assert!(parse_alias_set_flags(&args(&["--required"])).is_err());
+    }
+
+    #[test]
+    fn aliases_roundtrip_through_json_export() {
+        let mut aliases = BTreeMap::new();
+        aliases.insert(
+            "prod".to_string(),
+            AliasConfig {
+                endpoint: "https://s3.example.com".to_string(),
+                access_key: "AK\"1".to_string(),
+                secret_key: "secret".to_string(),
+                region: "eu-west-1".to_string(),
+                path_style: true,
+                request_payer: true,
+                aws_profile: None,
+                session_token: None,
+            },
+        );
+        let cfg = AppConfig {
+            aliases,
+            default_alias: None,
+        };
+        let imported = aliases_from_json(&aliases_to_json(&cfg, true)).expect("json should parse");
+        assert_eq!(imported.len(), 1);
+        assert_eq!(imported[0].0, "prod");
+        assert_eq!(imported[0].1.access_key, "AK\"1");
+        assert!(imported[0].1.path_style);
+        assert!(imported[0].1.request_payer);
+        assert_eq!(imported[0].1.region, "eu-west-1");
+        let redacted = aliases_to_json(&cfg, false);
+        assert!(!redacted.contains("secret"));
+        assert!(aliases_from_json("[{\"name\": }]").is_err());
+
+        // A --no-secrets export re-imported over the same alias keeps its keys...
+        let mut keyless = aliases_from_json(&redacted).expect("keyless export should parse");
+        assert_eq!(keyless[0].1.access_key, "");
+        adopt_existing_keys(&mut keyless, &cfg).expect("keys come from the existing alias");
+        assert_eq!(keyless[0].1.access_key, "AK\"1");
+        assert_eq!(keyless[0].1.secret_key, "secret");
+        // ...but cannot create a new alias without keys.
+        let mut keyless = aliases_from_json(&redacted).unwrap();
+        let err = adopt_existing_keys(&mut keyless, &AppConfig::default()).unwrap_err();
+        assert!(
+            err.contains("prod") && err.contains("--no-secrets"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn alias_json_rejects_duplicate_names() {
+        let duplicate_member = "[{\"name\":\"a\",\"endpoint\":\"http://e\",\"name\":\"b\"}]";
+        assert!(
+            parse_json_object_array(duplicate_member)
+                .unwrap_err()
+                .contains("duplicate name")
+        );
+        let entry =
+            "{\"name\":\"a\",\"endpoint\":\"http://e\",\"access_key\":\"k\",\"secret_key\":\"s\"}";
+        let twice = format!("[{entry},{entry}]");
+        assert!(
+            aliases_from_json(&twice)
+                .unwrap_err()
+                .contains("more than once")
+        );
+    }
+
+    #[test]
+    fn alias_json_decodes_escapes_and_rejects_control_characters() {
+        let escaped = r#"[{"s":"a\/b\"c\\dé😀","c":"\b\f"}]"#;
+        let obj = &parse_json_object_array(escaped).expect("escapes should decode")[0];
+        assert_eq!(
+            obj["s"],
+            JsonScalar::Str("a/b\"c\\d\u{e9}\u{1f600}".to_string())
+        );
+        assert_eq!(obj["c"], JsonScalar::Str("\u{8}\u{c}".to_string()));
+        assert!(parse_json_object_array(r#"[{"s":"\x"}]"#).is_err());
+        assert!(parse_json_object_array(r#"[{"s":"\ud83d"}]"#).is_err());
+        assert!(parse_json_object_array(r#"[{"s":"\u12"}]"#).is_err());
+        assert!(parse_json_object_array("[{\"s\":\"a\tb\"}]").is_err());
+
+        let secret =
+            r#"[{"name":"a","endpoint":"http://e","access_key":"k","secret_key":"s\tx\ny"}]"#;
+        let err = aliases_from_json(secret).unwrap_err();
+        assert!(
+            err.contains("control character") && err.contains("secret_key"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn roundtrip_config() {
+        let mut aliases = BTreeMap::new();
+        aliases.insert(
+            "local".to_string(),
+            AliasConfig {
+                endpoint: "http://127.0.0.1:9000".to_string(),
+                access_key: "minio".to_string(),
+                secret_key: "minio123".to_string(),
+                region: "us-east-1".to_string(),
+                path_style: true,
+                request_payer: false,
+                aws_profile: None,
+                session_token: None,
+            },
+        );
+        let cfg = AppConfig {
+            aliases,
+            default_alias: Some("local".to_string()),
+        };
+
+        let text = serialize_config(&cfg);
+        let parsed = parse_config(&text).expect("config should parse");
+        assert_eq!(parsed.aliases.len(), 1);
+        assert_eq!(parsed.default_alias.as_deref(), Some("local"));
+        let alias = parsed.aliases.get("local").expect("alias exists");
+        assert!(alias.path_style);
+        assert!(!alias.request_payer);
+        let payer = parse_config("rp\thttp://h\tak\tsk\tus-east-1\t1\trequest_payer=1\n")
+            .expect("config with options should parse");
+        assert!(payer.aliases["rp"].request_payer);
+        assert!(serialize_config(&payer).ends_with("\trequest_payer=1\n"));
+        assert!(parse_config("rp\thttp://h\tak\tsk\tus-east-1\t1\tbogus\n").is_err());
+        assert_eq!(alias.region, "us-east-1");
+    }
+
+    #[test]
+    fn parse_aws_profile_merges_credentials_and_config() {
+        let credentials = "[default]\naws_access_key_id = AKDEF\naws_secret_access_key = skdef\n\n\
+            [work]\naws_access_key_id=AKWORK\naws_secret_access_key=skwork\naws_session_token = tok\n";
+        let config = "[default]\nregion = us-east-1\n[profile work]\nregion = eu-central-1\n\
+            endpoint_url = https://minio.example.com\n[profile keyless]\nregion = us-west-2\n";
+        let work = parse_aws_profile(credentials, config, "work").expect("work profile");
+        assert_eq!(work.access_key, "AKWORK");
+        assert_eq!(work.secret_key, "skwork");
+        assert_eq!(work.session_token.as_deref(), Some("tok"));
+        assert_eq!(work.region.as_deref(), Some("eu-central-1"));
+        assert_eq!(
+            work.endpoint_url.as_deref(),
+            Some("https://minio.example.com")
+        );
+        let default = parse_aws_profile(credentials, config, "default").expect("default");
+        assert_eq!(default.access_key, "AKDEF");
+        assert_eq!(default.region.as_deref(), Some("us-east-1"));
+        assert!(parse_aws_profile(credentials, config, "keyless").is_err());
+        assert!(parse_aws_profile(credentials, config, "missing").is_err());
+
+        let mapped =
+            parse_config("aws\thttps://s3.amazonaws.com\t\t\tus-east-1\t0\taws_profile=work\n")
+                .expect("config with aws_profile should parse");
+        assert_eq!(mapped.aliases["aws"].aws_profile.as_deref(), Some("work"));
+        assert!(serialize_config(&mapped).ends_with("\taws_profile=work\n"));
+    }
+
+    #[test]
+    fn parse_rename_args_requires_same_bucket() {
+        let args = |a: &str, b: &str| -> Vec<String> {
+            vec!["rename".to_string(), a.to_string(), b.to_string()]
+        };
+        let (from, to) =
+            parse_rename_args(&args("s3/b/old.txt", "s3/b/dir/new.txt")).expect("same bucket");
+        assert_eq!(from.key.as_deref(), Some("old.txt"));
+        assert_eq!(to.key.as_deref(), Some("dir/new.txt"));
+        let err = parse_rename_args(&args("s3/b/old.txt", "s3/other/old.txt")).unwrap_err();
+        assert!(err.contains("s4 mv"), "{err}");
+        assert!(parse_rename_args(&args("s3/b/old.txt", "s3/b/old.txt")).is_err());
+        assert!(parse_rename_args(&args("s3/b/old.txt", "s3/b")).is_err());
+    }
+
+    #[test]
+    fn request_target_keeps_base_path_in_both_styles() {
+        let gw = parse_endpoint("https://gw.example.com/s3/").expect("endpoint");
+        assert_eq!(gw.base_path, "/s3");
+        assert_eq!(
+            gw.request_target(true, "bucket", Some("dir/a b.txt")),
+            (
+                "gw.example.com".to_string(),
+                "/s3/bucket/dir/a%20b.txt".to_string()
+            )
+        );
+        assert_eq!(
+            gw.request_target(false, "bucket", Some("dir/a b.txt")),
+            (
+                "bucket.gw.example.com".to_string(),
+                "/s3/dir/a%20b.txt".to_string()
+            )
+        );
+        assert_eq!(
+            gw.request_target(false, "bucket", None),
+            ("bucket.gw.example.com".to_string(), "/s3/".to_string())
+        );
+        assert_eq!(
+            gw.request_target(false, "", None),
+            ("gw.example.com".to_string(), "/s3".to_string())
+        );
+
+        // IP literals and localhost have no virtual hosts: path-style either way.
+        let plain = parse_endpoint("http://127.0.0.1:9000").expect("endpoint");
+        assert_eq!(
+            plain.request_target(false, "bucket", Some("k")),
+            ("127.0.0.1:9000".to_string(), "/bucket/k".to_string())
+        );
+        assert_eq!(plain.request_target(true, "", None).1, "/");
+        assert_eq!(plain.request_target(false, "bucket", None).1, "/bucket");
+        for endpoint in [
+            "http://localhost:9000",
+            "http://[::1]:9000",
+            "http://10.0.0.5",
+        ] {
+            let local = parse_endpoint(endpoint).expect("endpoint");
+            assert_eq!(
+                local.request_target(false, "bucket", Some("k")).1,
+                "/bucket/k",
+                "{endpoint}"
+            );
+        }
+        // Dotted buckets would break the https wildcard certificate; plain http is fine.
+        assert_eq!(
+            gw.request_target(false, "my.bucket", Some("k")),
+            ("gw.example.com".to_string(), "/s3/my.bucket/k".to_string())
+        );
+        let http = parse_endpoint("http://s3.example.com").expect("endpoint");
+        assert_eq!(
+            http.request_target(false, "my.bucket", Some("k")).0,
+            "my.bucket.s3.example.com"
+        );
+    }
+
+    #[test]
+    fn uri_encode_works() {
+        assert_eq!(uri_encode_path("a b/c"), "a%20b/c");
+    }
+
+    #[test]
+    fn extract_tag_blocks_works() {
+        let xml =
+            "<Root><Version><Key>a.txt</Key></Version><Version><Key>b.txt</Key></Version></Root>";
+        let blocks = extract_tag_blocks(xml, "Version");
+        assert_eq!(blocks.len(), 2);
+        assert!(blocks[0].contains("<Key>a.txt</Key>"));
+        assert!(blocks[1].contains("<Key>b.txt</Key>"));
+    }
+
+    #[test]
+    fn extract_version_entries_works_for_versions_and_delete_markers() {
+        let xml = "<ListVersionsResult><Version><Key>k1</Key><VersionId>v1</VersionId></Version><DeleteMarker><Key>k2</Key><VersionId>v2</VersionId></DeleteMarker></ListVersionsResult>";
+        let versions = extract_version_entries(xml, "Version");
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions[0].key, "k1");
+        assert_eq!(versions[0].version_id, "v1");
+
+        let delete_markers = extract_version_entries(xml, "DeleteMarker");
+        assert_eq!(delete_markers.len(), 1);
+        assert_eq!(delete_markers[0].key, "k2");
+        assert_eq!(delete_markers[0].version_id, "v2");
+    }
+
+    #[test]
+    fn extract_xml_keys() {
+        let xml = "<ListBucketResult><Contents><Key>a.txt</Key></Contents><Contents><Key>dir/b.txt</Key></Contents></ListBucketResult>";
+        let keys = extract_tag_values(xml, "Key");
+        assert_eq!(keys, vec!["a.txt".to_string(), "dir/b.txt".to_string()]);
+    }
+
+    #[test]
+    fn parse_put_args_supports_recursive() {
+        let args: Vec<String> = [
+            "put",
+            "--recursive",
+            "--content-md5",
+            "./site",
+            "s3/web/static",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let (opts, source, target) = parse_put_args(&args).expect("put args should parse");
+        assert!(opts.recursive);
+        assert!(opts.upload.content_md5);
+        assert_eq!(source, std::path::PathBuf::from("./site"));
+        assert_eq!(target.key.as_deref(), Some("static"));
+        assert!(parse_put_args(&args[..4]).is_err());
+    }
+
+    #[test]
+    fn put_glob_matches_file_names_like_a_shell() {
+        let dir = std::env::temp_dir().join(format!("s4-put-glob-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub.gz")).expect("create glob dir");
+        for name in ["a.gz", "b.gz", "c.txt", ".hidden.gz"] {
+            std::fs::write(dir.join(name), name).expect("write glob file");
+        }
+        let names = |pattern: &str| -> Vec<String> {
+            expand_put_glob(&dir.join(pattern))
+                .expect("glob should expand")
+                .into_iter()
+                .map(|(_, name)| name)
+                .collect()
+        };
+        assert_eq!(names("*.gz"), vec!["a.gz", "b.gz"]);
+        assert_eq!(names(".*.gz"), vec![".hidden.gz"]);
+        assert_eq!(names("?.txt"), vec!["c.txt"]);
+        assert!(names("*.zip").is_empty());
+        assert!(expand_put_glob(&dir.join("*").join("a.gz")).is_err());
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert!(has_wildcard("logs/*.gz") && has_wildcard("a?.txt"));
+        assert!(!has_wildcard("logs/a.gz"));
+        let args: Vec<String> = ["put", "--allow-empty", "logs/*.gz", "s3/b/logs/"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let (opts, source, _) = parse_put_args(&args).expect("put args should parse");
+        assert!(opts.allow_empty);
+        assert_eq!(source, std::path::PathBuf::from("logs/*.gz"));
+    }
+
+    #[test]
+    fn parse_common_prefixes_skips_top_level_prefix() {
+        let body = "<ListBucketResult><Prefix>logs/</Prefix>\
+            <Contents><Key>logs/a.txt</Key></Contents>\
+            <CommonPrefixes><Prefix>logs/2024/</Prefix></CommonPrefixes>\
+            <CommonPrefixes><Prefix>logs/a&amp;b/</Prefix></CommonPrefixes>\
+            </ListBucketResult>";
+        assert_eq!(
+            parse_common_prefixes(body),
+            vec!["logs/2024/".to_string(), "logs/a&b/".to_string()]
+        );
+        let args: Vec<String> = ["ls", "-d", "s3/b/logs/"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let (opts, target) = parse_ls_args(&args).expect("ls -d should parse");
+        assert!(opts.folders);
+        assert_eq!(target.key.as_deref(), Some("logs/"));
+        let no_bucket: Vec<String> = ["ls", "--folders", "s3"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert!(parse_ls_args(&no_bucket).is_err());
+    }
+
+    #[test]
+    fn list_v1_pages_by_marker() {
+        let page = ListQuery {
+            prefix: "logs/",
+            fetch_owner: true,
+            ..ListQuery::default()
+        };
+        assert_eq!(
+            page.render(false, Some("tok")),
+            "list-type=2&fetch-owner=true&prefix=logs/&continuation-token=tok"
+        );
+        assert_eq!(
+            page.render(true, Some("logs/b")),
+            "prefix=logs/&marker=logs/b"
+        );
+        assert_eq!(ListQuery::default().render(true, None), "");
+
+        let keys = "<ListBucketResult><IsTruncated>true</IsTruncated>\
+            <Contents><Key>a</Key></Contents><Contents><Key>b&amp;c</Key></Contents>\
+            </ListBucketResult>";
+        assert_eq!(list_v1_next_marker(keys).as_deref(), Some("b&c"));
+        let folders = "<ListBucketResult><Contents><Key>a</Key></Contents>\
+            <CommonPrefixes><Prefix>z/</Prefix></CommonPrefixes></ListBucketResult>";
+        assert_eq!(list_v1_next_marker(folders).as_deref(), Some("z/"));
+        let explicit = "<ListBucketResult><NextMarker>m</NextMarker>\
+            <Contents><Key>a</Key></Contents></ListBucketResult>";
+        assert_eq!(list_v1_next_marker(explicit).as_deref(), Some("m"));
+
+        let rejected = |status, body: &str| S4Error::Http {
+            status,
+            body: body.to_string(),
+        };
+        assert!(rejects_list_v2(&rejected(501, "")));
+        assert!(rejects_list_v2(&rejected(
+            400,
+            "<Error><Code>InvalidArgument</Code></Error>"
+        )));
+        assert!(!rejects_list_v2(&rejected(
+            403,
+            "<Error><Code>AccessDenied</Code></Error>"
+        )));
+        assert!(!rejects_list_v2(&rejected(
+            400,
+            "<Error><Code>InvalidBucketName</Code></Error>"
+        )));
+        assert!(!rejects_list_v2(&rejected(400, "")));
+        assert!(!rejects_list_v2(&rejected(400, "<html>Bad Request</html>")));
+    }
+
+    #[test]
+    fn list_v1_fallback_is_tracked_per_endpoint() {
+        let old = "http://old-gateway.test:9000";
+        let new = "http://new-gateway.test:9000";
+        assert!(!lists_with_v1(old));
+        fall_back_to_list_v1(old, "test");
+        assert!(lists_with_v1(old));
+        assert!(!lists_with_v1(new));
+    }
+
+    #[test]
+    fn du_rollup_groups_by_prefix_depth() {
+        let obj = |key: &str, size: u64| ListedObject {
+            key: key.to_string(),
+            size,
+            ..ListedObject::default()
+        };
+        let objects = vec![
+            obj("logs/2024/a", 10),
+            obj("logs/2024/b", 5),
+            obj("logs/2025/c", 100),
+            obj("logs/top", 1),
+            obj("img/x", 50),
+        ];
+        let entries = du_rollup(&objects, "", 1, false);
+        let summary: Vec<(&str, u64, u64)> = entries
+            .iter()
+            .map(|e| (e.prefix.as_str(), e.objects, e.bytes))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![("logs/", 4, 116), ("img/", 1, 50), ("", 5, 166)]
+        );
+
+        let entries = du_rollup(&objects[..4], "logs/", 2, true);
+        let prefixes: Vec<&str> = entries.iter().map(|e| e.prefix.as_str()).collect();
+        assert_eq!(prefixes, vec!["logs/2024/", "logs/2025/", "logs/"]);
+        assert_eq!(entries[2].bytes, 116);
+
+        let entries = du_rollup(&objects, "", 0, false);
+        assert_eq!(entries.len(), 1);
+        let row = ["logs/".to_string(), "3".to_string(), "116".to_string()];
+        assert_eq!(
+            json_record(&["prefix", "objects", "bytes"], &row),
+            "{\"prefix\":\"logs/\",\"objects\":3,\"bytes\":116}"
+        );
+
+        let args = |list: &[&str]| list.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        let (opts, target) =
+            parse_du_args(&args(&["du", "--depth", "2", "s3/b/logs/"])).expect("du should parse");
+        assert_eq!(opts.depth, 2);
+        assert_eq!(target.key.as_deref(), Some("logs/"));
+        // `-d` is `ls --folders`; du spells the depth out.
+        assert!(parse_du_args(&args(&["du", "-d", "2", "s3/b"])).is_err());
+    }
+
+    #[test]
+    fn object_lock_status_reads_head_headers() {
+        let head = "HTTP/1.1 200 OK\r\nx-amz-object-lock-mode: GOVERNANCE\r\n\
+            X-Amz-Object-Lock-Retain-Until-Date: 2030-01-01T00:00:00Z\r\n\
+            x-amz-object-lock-legal-hold: ON\r\n";
+        assert_eq!(
+            object_lock_status(head),
+            ObjectLockStatus {
+                mode: "GOVERNANCE".to_string(),
+                retain_until: "2030-01-01T00:00:00Z".to_string(),
+                legal_hold: "ON".to_string(),
+            }
+        );
+        assert_eq!(
+            object_lock_status("HTTP/1.1 200 OK\r\n"),
+            ObjectLockStatus::default()
+        );
+        let args: Vec<String> = ["ls", "--lock-status", "--owner", "s3/b"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert!(parse_ls_args(&args).is_err());
+    }
+
+    #[test]
+    fn diff_listings_reports_missing_and_changed_keys() {
+        let obj = |key: &str, size: u64, etag: &str| ListedObject {
+            key: key.to_string(),
+            size,
+            etag: etag.to_string(),
+            ..ListedObject::default()
+        };
+        let source = vec![
+            obj("src/a.txt", 1, "\"e1\""),
+            obj("src/b.txt", 2, "\"e2\""),
+            obj("src/c.txt", 3, "\"e3\""),
+            obj("src/d.txt", 4, "\"e4\""),
+        ];
+        let target = vec![
+            obj("dst/a.txt", 1, "e1"),
+            obj("dst/b.txt", 5, "\"e2\""),
+            obj("dst/c.txt", 3, "\"other\""),
+            obj("dst/z.txt", 9, "\"e9\""),
+        ];
+        let diff = diff_listings(source, "src", target, "dst");
+        assert_eq!(diff.only_in_source, vec!["d.txt".to_string()]);
+        assert_eq!(diff.only_in_target, vec!["z.txt".to_string()]);
+        let changed: Vec<&str> = diff.different.iter().map(|(k, _, _)| k.as_str()).collect();
+        assert_eq!(changed, vec!["b.txt", "c.txt"]);
+        assert_eq!(diff.same, 1);
+    }
+
+    #[test]
+    fn parse_put_args_accepts_from_url() {
+        let args: Vec<String> = [
+            "put",
+            "--from-url",
+            "https://example.com/f.bin",
+            "s3/b/f.bin",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let (opts, _, target) = parse_put_args(&args).expect("put --from-url should parse");
+        assert_eq!(opts.from_url.as_deref(), Some("https://example.com/f.bin"));
+        assert_eq!(target.key.as_deref(), Some("f.bin"));
+        assert!(parse_source_url("ftp://example.com/f.bin").is_err());
+        assert!(parse_source_url("file:///etc/passwd").is_err());
+        assert!(parse_source_url("http://").is_err());
+        let extra: Vec<String> = ["put", "--from-url", "http://h/x", "./local", "s3/b/k"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert!(parse_put_args(&extra).is_err());
+    }
+
+    #[test]
+    fn collect_local_files_returns_sorted_relative_paths() {
+        let root = std::env::temp_dir().join(format!("s4-collect-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("nested/deep")).unwrap();
+        std::fs::write(root.join("b.txt"), "b").unwrap();
+        std::fs::write(root.join("nested/deep/a.txt"), "a").unwrap();
+        let files = collect_local_files(&root, false).expect("walk should succeed");
+        let relative: Vec<&str> = files.iter().map(|(_, r)| r.as_str()).collect();
+        assert_eq!(relative, vec!["b.txt", "nested/deep/a.txt"]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn collect_local_files_follows_symlinks_only_on_request() {
+        use std::os::unix::fs::symlink;
+        let root = std::env::temp_dir().join(format!("s4-symlinks-{}", std::process::id()));
+        let outside = root.with_extension("outside");
+        std::fs::create_dir_all(root.join("dir")).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(root.join("dir/a.txt"), "a").unwrap();
+        std::fs::write(outside.join("o.txt"), "o").unwrap();
+        symlink(root.join("dir/a.txt"), root.join("link.txt")).unwrap();
+        symlink(&outside, root.join("ext")).unwrap();
+        symlink(root.join("missing"), root.join("broken")).unwrap();
+        symlink(&root, root.join("dir/loop")).unwrap();
+
+        let relative = |follow| -> Vec<String> {
+            collect_local_files(&root, follow)
+                .expect("walk should succeed")
+                .into_iter()
+                .map(|(_, r)| r)
+                .collect()
+        };
+        assert_eq!(relative(false), vec!["dir/a.txt"]);
+        assert_eq!(relative(true), vec!["dir/a.txt", "ext/o.txt", "link.txt"]);
+        std::fs::remove_dir_all(&root).unwrap();
+        std::fs::remove_dir_all(&outside).unwrap();
+    }
+
+    #[test]
+    fn object_header_flags_fill_upload_headers() {
+        let args: Vec<String> = [
+            "put",
+            "--content-disposition",
+            "attachment; filename=\"r.csv\"",
+            "--cache-control",
+            "max-age=3600",
+            "--content-language",
+            "de",
+            "r.csv",
+            "s3/b/r.csv",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let (opts, _, _) = parse_put_args(&args).expect("put args should parse");
+        assert_eq!(
+            opts.upload.headers,
+            vec![
+                "Content-Disposition: attachment; filename=\"r.csv\"".to_string(),
+                "Cache-Control: max-age=3600".to_string(),
+                "Content-Language: de".to_string(),
+            ]
+        );
+        let cp: Vec<String> = ["cp", "--cache-control", "no-cache", "s3/b/a", "s3/b/c"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let (opts, _, _) = parse_cp_args(&cp).expect("cp args should parse");
+        assert_eq!(
+            opts.upload.headers,
+            vec!["Cache-Control: no-cache".to_string()]
+        );
+        assert!(object_header("--cache-control", "a\r\nx-amz-acl: public-read").is_err());
+        assert!(object_header("--content-language", " ").is_err());
+    }
+
+    #[test]
+    fn conditional_write_flags_build_headers() {
+        let args: Vec<String> = ["cp", "--if-match", "abc123", "a.txt", "s3/b/a.txt"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let (opts, src, dst) = parse_cp_args(&args).expect("cp args should parse");
+        assert_eq!(
+            opts.upload.preconditions,
+            vec!["If-Match: \"abc123\"".to_string()]
+        );
+        assert_eq!((src.as_str(), dst.as_str()), ("a.txt", "s3/b/a.txt"));
+        assert_eq!(
+            precondition_header("--if-none-match", "*").unwrap(),
+            "If-None-Match: *"
+        );
+        assert!(precondition_header("--if-none-match", "abc").is_err());
+
+        let err = explain_precondition_failure(
+            http_error("412", "<Error><Code>PreconditionFailed</Code></Error>"),
+            "b",
+            "k",
+            &["If-None-Match: *".to_string()],
+        );
+        assert_eq!(err.to_string(), "object already exists: b/k");
+    }
+
+    #[test]
+    fn parse_cp_args_accepts_recursive_only_for_mv() {
+        let args = |cmd: &str, extra: &[&str]| -> Vec<String> {
+            std::iter::once(cmd)
+                .chain(extra.iter().copied())
+                .chain(["s3/b/old", "s3/b/new"])
+                .map(|s| s.to_string())
+                .collect()
+        };
+        let (opts, _, _) =
+            parse_cp_args(&args("mv", &["--recursive", "--dry-run"])).expect("mv -r parses");
+        assert!(opts.recursive && opts.dry_run);
+        assert!(
+            parse_cp_args(&args("mv", &["-r"]))
+                .expect("mv -r")
+                .0
+                .recursive
+        );
+        assert!(parse_cp_args(&args("cp", &["--recursive"])).is_err());
+        assert!(parse_cp_args(&args("mv", &["-r", "--if-size-differs"])).is_err());
+    }
+
+    #[test]
+    fn sync_destination_key_respects_prefixes() {
+        assert_eq!(
+            sync_destination_key("images/cat.jpg", "images", "backup"),
+            "backup/cat.jpg"
+        );
+        assert_eq!(
+            sync_destination_key("images/nested/cat.jpg", "", "archive"),
+            "archive/images/nested/cat.jpg"
+        );
+        assert_eq!(sync_destination_key("a.txt", "", ""), "a.txt");
+    }
+
+    #[test]
+    fn governance_bypass_retry_matches_worm_and_retention_errors() {
+        assert!(should_retry_with_governance_bypass("AccessDenied"));
+        assert!(should_retry_with_governance_bypass("retention policy"));
+        assert!(should_retry_with_governance_bypass("governance mode"));
+        assert!(should_retry_with_governance_bypass(
+            "InvalidRequest: Object is WORM protected and cannot be overwritten"
+        ));
+        assert!(!should_retry_with_governance_bypass("NoSuchBucket"));
+    }
+
+    #[test]
+    fn xml_unescape_works() {
+        assert_eq!(xml_unescape("a&amp;b&quot;c"), "a&b\"c");
+    }
+
+    #[test]
+    fn whoami_reads_owner_and_redacts_secret() {
+        let body = "<ListAllMyBucketsResult><Owner><ID>abc123</ID><DisplayName>team-a</DisplayName></Owner>\
+            <Buckets><Bucket><Name>b</Name></Bucket></Buckets></ListAllMyBucketsResult>";
+        assert_eq!(
+            list_buckets_owner(body),
+            ("abc123".to_string(), "team-a".to_string())
+        );
+        assert_eq!(
+            list_buckets_owner("<ListAllMyBucketsResult/>"),
+            (String::new(), String::new())
+        );
+        assert_eq!(
+            redact_secret("wJalrXUtnFEMI/K7MDENG/bPxRfiCYEXAMPLEKEY"),
+            "****EKEY"
+        );
+        assert_eq!(redact_secret("minio123"), "****");
+        assert_eq!(redact_secret(""), "");
+    }
+
+    #[test]
+    fn looks_ready_xml_accepts_known_payloads() {
+        assert!(looks_ready_xml(
+            "<ListAllMyBucketsResult></ListAllMyBucketsResult>"
+        ));
+        assert!(looks_ready_xml("<Error><Code>AccessDenied</Code></Error>"));
+        assert!(!looks_ready_xml("not-xml"));
+    }
+
+    #[test]
+    fn is_bucket_owned_error_matches_only_owned_code() {
+        assert!(is_bucket_owned_error(
+            "request failed with status 409: body='<Error><Code>BucketAlreadyOwnedByYou</Code></Error>' stderr=''"
+        ));
+        assert!(!is_bucket_owned_error(
+            "request failed with status 409: body='<Error><Code>BucketAlreadyExists</Code></Error>' stderr=''"
+        ));
+        assert!(!is_bucket_owned_error("request execution failed: timeout"));
+    }
+
+    #[test]
+    fn build_create_bucket_xml_skips_us_east_1() {
+        assert_eq!(build_create_bucket_xml("us-east-1"), None);
+        assert_eq!(build_create_bucket_xml(""), None);
+        assert_eq!(
+            build_create_bucket_xml("eu-west-1").as_deref(),
+            Some(
+                "<CreateBucketConfiguration><LocationConstraint>eu-west-1</LocationConstraint></CreateBucketConfiguration>"
+            )
+        );
+    }
+
+    #[test]
+    fn parse_location_constraint_handles_empty_and_namespaced() {
+        assert_eq!(
+            parse_location_constraint(
+                "<LocationConstraint xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\">eu-central-1</LocationConstraint>"
+            ),
+            "eu-central-1"
+        );
+        assert_eq!(
+            parse_location_constraint(
+                "<LocationConstraint xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\"/>"
+            ),
+            "us-east-1"
+        );
+        assert_eq!(
+            parse_location_constraint("<LocationConstraint></LocationConstraint>"),
+            "us-east-1"
+        );
+        assert_eq!(
+            parse_location_constraint("<LocationConstraint>EU</LocationConstraint>"),
+            "eu-west-1"
+        );
+    }
+
+    #[test]
+    fn parse_s3_error_extracts_code_message_and_region() {
+        let body = "<?xml version=\"1.0\" encoding=\"UTF-8\"?><Error><Code>AuthorizationHeaderMalformed</Code><Message>the region &apos;us-east-1&apos; is wrong</Message><Region>eu-west-1</Region></Error>";
+        let err = parse_s3_error(body).expect("error body should parse");
+        assert_eq!(err.code, "AuthorizationHeaderMalformed");
+        assert_eq!(err.message, "the region 'us-east-1' is wrong");
+        assert_eq!(err.region.as_deref(), Some("eu-west-1"));
+        assert!(parse_s3_error("<ListBucketResult></ListBucketResult>").is_none());
+    }
+
+    #[test]
+    fn wrong_region_hint_uses_body_then_header() {
+        let malformed =
+            "<Error><Code>AuthorizationHeaderMalformed</Code><Region>eu-west-1</Region></Error>";
+        assert_eq!(
+            wrong_region_hint("400", malformed, "").as_deref(),
+            Some("eu-west-1")
+        );
+        let redirect = "<Error><Code>PermanentRedirect</Code><Endpoint>b.s3.eu-central-1.amazonaws.com</Endpoint></Error>";
+        assert_eq!(
+            wrong_region_hint("301", redirect, "eu-central-1").as_deref(),
+            Some("eu-central-1")
+        );
+        assert_eq!(wrong_region_hint("301", "", "").as_deref(), None);
+        assert_eq!(
+            wrong_region_hint(
+                "403",
+                "<Error><Code>AccessDenied</Code></Error>",
+                "eu-west-1"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn s4_error_http_keeps_status_and_exit_code() {
+        let err = http_error("404", " <Error><Code>NoSuchKey</Code></Error>\n");
+        assert!(matches!(err, S4Error::Http { status: 404, .. }));
+        assert_eq!(
+            err.to_string(),
+            "request failed with status 404: body='<Error><Code>NoSuchKey</Code></Error>'"
+        );
+        assert_eq!(err.exit_code(), 4);
+        assert_eq!(http_error("403", "").exit_code(), 5);
+        assert_eq!(http_error("500", "").exit_code(), 3);
+        assert_eq!(S4Error::Network("curl: (7)".to_string()).exit_code(), 7);
+        assert_eq!(S4Error::Usage("usage: s4 ls".to_string()).exit_code(), 2);
+        assert_eq!(S4Error::from("boom").exit_code(), 1);
+    }
+
+    #[test]
+    fn run_tool_reports_missing_binary() {
+        let err = run_tool(&mut Command::new("s4-definitely-missing-binary"))
+            .expect_err("missing binary must fail");
+        assert_eq!(
+            err.to_string(),
+            "the `s4-definitely-missing-binary` binary is required but was not found in PATH"
+        );
+    }
+
+    #[test]
+    fn rfc3339_converts_to_http_date() {
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z").unwrap(), 0);
+        let secs = parse_rfc3339("1994-11-06T08:49:37Z").unwrap();
+        assert_eq!(http_date(secs), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(
+            parse_rfc3339("1994-11-06T10:49:37.250+02:00").unwrap(),
+            secs
+        );
+        assert_eq!(
+            parse_rfc3339("2024-02-29").unwrap(),
+            parse_rfc3339("2024-02-29T00:00:00Z").unwrap()
+        );
+        assert!(parse_rfc3339("yesterday").is_err());
+        for impossible in [
+            "2023-02-29",
+            "1900-02-29",
+            "2024-02-30",
+            "2024-04-31",
+            "2024-00-10",
+            "2024-06-00",
+            "2024-06-01T24:00:00Z",
+            "2024-06-01T12:60:00Z",
+        ] {
+            assert!(parse_rfc3339(impossible).is_err(), "{impossible}");
+        }
+        assert!(parse_rfc3339("2000-02-29").is_ok());
+        assert!(parse_rfc3339("2024-12-31T23:59:60Z").is_ok());
+    }
+
+    #[test]
+    fn ranged_download_splits_only_range_capable_objects() {
+        let head = "HTTP/1.1 200 OK\r\nAccept-Ranges: bytes\r\nContent-Length: 5242880\r\n";
+        assert_eq!(ranged_download_size(head), Some(5 * 1024 * 1024));
+        assert_eq!(
+            ranged_download_size("HTTP/1.1 200 OK\r\nContent-Length: 10\r\n"),
+            None
+        );
+        let mib = 1024 * 1024;
+        assert_eq!(
+            split_download_ranges(5 * mib, 2),
+            vec![(0, 5 * mib / 2 - 1), (5 * mib / 2, 5 * mib - 1)]
+        );
+        // Never more ranges than whole MiBs, so small objects stay a single stream.
+        assert_eq!(split_download_ranges(3 * mib, 8).len(), 3);
+        assert!(split_download_ranges(2 * mib - 1, 8).is_empty());
+        assert!(split_download_ranges(10, 4).is_empty());
+        assert!(split_download_ranges(0, 4).is_empty());
+        // Large objects get more ranges than workers so each stays bounded in memory.
+        let big = split_download_ranges(1024 * mib, 4);
+        assert_eq!(big.len(), 16);
+        assert_eq!(big.last(), Some(&(1024 * mib - 64 * mib, 1024 * mib - 1)));
+        assert!(parse_download_concurrency("0").is_err());
+    }
+
+    #[test]
+    fn cat_line_options_number_and_slice() {
+        let args: Vec<String> = ["cat", "-n", "--tail", "2", "s3/b/log.txt"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let (opts, _, dest) = parse_get_args(&args).expect("cat args should parse");
+        assert!(dest.is_none());
+        assert_eq!(opts.lines.apply("a\nb\nc\n"), "     2\tb\n     3\tc\n");
+        let head = CatLines {
+            head: Some(1),
+            ..CatLines::default()
+        };
+        assert_eq!(head.apply("a\nb"), "a\n");
+        assert!(!CatLines::default().is_active());
+        let both: Vec<String> = ["cat", "--head", "1", "--tail", "1", "s3/b/k"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert!(parse_get_args(&both).is_err());
+    }
+
+    #[test]
+    fn parse_ready_args_accepts_health_endpoint() {
+        let args: Vec<String> = ["ready", "--health-endpoint", "local"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let (health, target) = parse_ready_args(&args).expect("ready args should parse");
+        assert!(health);
+        assert_eq!(target.alias, "local");
+        assert!(
+            !parse_ready_args(&[args[0].clone(), args[2].clone()])
+                .unwrap()
+                .0
+        );
+        assert!(parse_ready_args(&args[..2]).is_err());
+    }
+
+    #[test]
+    fn run_ordered_emits_in_index_order() {
+        let mut seen = Vec::new();
+        run_ordered(
+            6,
+            3,
+            |idx| {
+                std::thread::sleep(std::time::Duration::from_millis(((6 - idx) * 5) as u64));
+                Ok(idx * 10)
+            },
+            |idx, value| {
+                seen.push((idx, value));
+                Ok(())
+            },
+        )
+        .expect("all work succeeds");
+        assert_eq!(seen, (0..6).map(|i| (i, i * 10)).collect::<Vec<_>>());
+
+        let mut emitted = Vec::new();
+        let err = run_ordered(
+            5,
+            2,
+            |idx| {
+                if idx == 2 {
+                    Err(S4Error::Other("boom".to_string()))
+                } else {
+                    Ok(idx)
+                }
+            },
+            |idx, _| {
+                emitted.push(idx);
+                Ok(())
+            },
+        );
+        assert!(err.is_err());
+        assert_eq!(emitted, vec![0, 1]);
+    }
+
+    #[test]
+    fn not_found_errors_are_friendly() {
+        let missing_key = S4Error::Http {
+            status: 404,
+            body: "<Error><Code>NoSuchKey</Code><Message>gone</Message></Error>".to_string(),
+        };
+        let err = not_found_error(missing_key, "local", "b", "dir/k.txt");
+        assert_eq!(err.to_string(), "no such object: local/b/dir/k.txt");
+        assert_eq!(err.exit_code(), 6);
+        let missing_bucket = S4Error::Http {
+            status: 404,
+            body: "<Error><Code>NoSuchBucket</Code></Error>".to_string(),
+        };
+        assert_eq!(
+            not_found_error(missing_bucket, "local", "b", "k").to_string(),
+            "no such bucket: local/b"
+        );
+        let head_404 = S4Error::Http {
+            status: 404,
+            body: String::new(),
+        };
+        assert!(matches!(
+            not_found_error(head_404, "local", "b", "k"),
+            S4Error::NotFound(_)
+        ));
+        let denied = S4Error::Http {
+            status: 403,
+            body: String::new(),
+        };
+        assert_eq!(not_found_error(denied, "local", "b", "k").exit_code(), 5);
+    }
+
+    #[test]
+    fn fingerprints_compare_size_and_plain_etags() {
+        let head = "HTTP/1.1 200 OK\r\nETag: \"5d41402abc4b2a76b9719d911017c592\"\r\nContent-Length: 5\r\n";
+        let remote = head_fingerprint(head, true).expect("head has a size");
+        let local = TransferFingerprint {
+            size: 5,
+            md5: Some("5D41402ABC4B2A76B9719D911017C592".to_string()),
+        };
+        assert!(local.matches(&remote));
+        let changed = TransferFingerprint {
+            size: 5,
+            md5: Some("00000000000000000000000000000000".to_string()),
+        };
+        assert!(!changed.matches(&remote));
+        // Multipart ETags carry no MD5, so only the size is compared.
+        let multipart = "Content-Length: 5\r\nETag: \"abc-2\"\r\n";
+        assert!(changed.matches(&head_fingerprint(multipart, true).unwrap()));
+        assert!(
+            !head_fingerprint("Content-Length: 6\r\n", false)
+                .unwrap()
+                .matches(&local)
+        );
+        assert_eq!(head_fingerprint(head, false).unwrap().md5, None);
+    }
+
+    #[test]
+    fn head_args_and_line_boundaries() {
+        let args: Vec<String> = ["head", "--bytes", "16", "a/b/k"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let (mode, decompress, target) = parse_head_args(&args).expect("head args should parse");
+        assert_eq!(mode, HeadMode::Bytes(16));
+        assert!(!decompress);
+        assert_eq!(target.key.as_deref(), Some("k"));
+        let args: Vec<String> = ["head", "a/b/k", "3"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(parse_head_args(&args).unwrap().0, HeadMode::Lines(3));
+        assert_eq!(parse_head_args(&args[..2]).unwrap().0, HeadMode::Lines(10));
+        assert!(parse_head_args(&args[..1]).is_err());
+
+        assert_eq!(head_line_end(b"a\nbb\nccc\n", 2), Some(5));
+        assert_eq!(head_line_end(b"a\nbb", 2), None);
+        assert_eq!(head_line_end(b"\xff\x00\n", 1), Some(3));
+        assert_eq!(head_line_end(b"abc", 0), Some(0));
+    }
+
+    #[test]
+    fn ping_count_parses_and_summarizes() {
+        let args: Vec<String> = ["ping", "--count", "5", "local"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let (count, target) = parse_ping_args(&args).expect("ping args should parse");
+        assert_eq!((count, target.alias.as_str()), (Some(5), "local"));
+        assert!(parse_ping_args(&args[..3]).is_err());
+        assert_eq!(ping_stats(&[30, 10, 20, 40]), Some((10, 25, 40, 40)));
+        let many: Vec<u128> = (1..=100).collect();
+        assert_eq!(ping_stats(&many).map(|s| s.3), Some(95));
+        assert_eq!(ping_stats(&[]), None);
+    }
+
+    #[test]
+    fn parse_get_args_collects_conditions() {
+        let args: Vec<String> = [
+            "get",
+            "--if-none-match",
+            "abc",
+            "--if-modified-since",
+            "1994-11-06T08:49:37Z",
+            "s3/b/k",
+            "out.bin",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let (opts, targets, dest) = parse_get_args(&args).expect("get args should parse");
+        assert_eq!(
+            opts.conditions,
+            vec![
+                "If-None-Match: \"abc\"".to_string(),
+                "If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT".to_string()
+            ]
+        );
+        assert_eq!(targets[0].key.as_deref(), Some("k"));
+        assert_eq!(dest, Some(std::path::PathBuf::from("out.bin")));
+        let cat: Vec<String> = vec!["cat".to_string(), "s3/b/k".to_string()];
+        assert!(parse_get_args(&cat).expect("cat args").2.is_none());
+    }
+
+    #[test]
+    fn accept_encoding_parses_and_picks_text_types() {
+        let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let (opts, _, _) =
+            parse_get_args(&args(&["cat", "--accept-encoding", "auto", "s3/b/k"])).unwrap();
+        assert_eq!(opts.accept_encoding, Some(AcceptEncoding::Auto));
+        assert!(parse_get_args(&args(&["cat", "--accept-encoding", "br", "s3/b/k"])).is_err());
+        assert!(
+            parse_get_args(&args(&[
+                "cat",
+                "--decompress",
+                "--accept-encoding",
+                "gzip",
+                "s3/b/k"
+            ]))
+            .is_err()
+        );
+        assert!(is_text_like_content_type("text/plain; charset=utf-8"));
+        assert!(is_text_like_content_type("application/vnd.api+json"));
+        assert!(is_text_like_content_type("Application/JSON"));
+        assert!(!is_text_like_content_type("image/png"));
+        assert!(!is_text_like_content_type("application/gzip"));
+        assert!(!is_text_like_content_type(""));
+    }
+
+    #[test]
+    fn append_detects_native_support_and_keeps_object_headers() {
+        let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let (native, target) = parse_append_args(&args(&["append", "s3/b/log"])).unwrap();
+        assert!(!native);
+        assert_eq!(target.key.as_deref(), Some("log"));
+        assert!(
+            parse_append_args(&args(&["append", "--native", "s3/b/log"]))
+                .unwrap()
+                .0
+        );
+        assert!(parse_append_args(&args(&["append"])).is_err());
+        assert!(supports_native_append("logs--use1-az4--x-s3"));
+        assert!(!supports_native_append("logs"));
+        let head = "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 3\r\n\
+            ETag: \"abc\"\r\nx-amz-meta-owner: ops\r\nCache-Control: no-cache\r\n";
+        assert_eq!(
+            append_carry_headers(head),
+            vec![
+                "Content-Type: text/plain".to_string(),
+                "x-amz-meta-owner: ops".to_string(),
+                "Cache-Control: no-cache".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn copy_replace_headers_are_signed() {
+        let head = "HTTP/1.1 200 OK\r\nContent-Type: text/csv\r\nx-amz-meta-owner: ops\r\n\
+            Cache-Control: no-cache\r\n";
+        let overrides = vec![
+            "Content-Disposition: attachment; filename=\"r.csv\"".to_string(),
+            "Cache-Control: max-age=60".to_string(),
+            "Content-Language: ru".to_string(),
+        ];
+        let headers = copy_replace_headers(head, &overrides);
+        let (signed, unsigned) = partition_signed_headers(&headers);
+        assert!(unsigned.is_empty());
+        let names: Vec<&str> = signed.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "x-amz-metadata-directive",
+                "content-type",
+                "x-amz-meta-owner",
+                "content-disposition",
+                "cache-control",
+                "content-language",
+            ]
+        );
+        assert!(signed.contains(&("cache-control".to_string(), "max-age=60".to_string())));
+    }
+
+    #[test]
+    fn append_write_offset_and_carried_metadata_are_signed() {
+        let head = "HTTP/1.1 200 OK\r\nContent-Length: 3\r\nx-amz-meta-owner: ops\r\n";
+        let mut headers = append_carry_headers(head);
+        headers.push("x-amz-write-offset-bytes: 3".to_string());
+        let (signed, unsigned) = partition_signed_headers(&headers);
+        assert!(unsigned.is_empty());
+        assert_eq!(
+            signed,
+            vec![
+                ("x-amz-meta-owner".to_string(), "ops".to_string()),
+                ("x-amz-write-offset-bytes".to_string(), "3".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn get_accepts_several_sources_before_the_directory() {
+        let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let (_, targets, dest) =
+            parse_get_args(&args(&["get", "s3/b/logs/a.log", "s3/c/b.log", "out/"])).unwrap();
+        let keys: Vec<_> = targets.iter().map(|t| t.key.clone().unwrap()).collect();
+        assert_eq!(keys, ["logs/a.log", "b.log"]);
+        assert_eq!(targets[1].bucket.as_deref(), Some("c"));
+        assert_eq!(dest, Some(std::path::PathBuf::from("out/")));
+        assert!(parse_get_args(&args(&["get", "s3/b/k"])).is_err());
+        assert!(parse_get_args(&args(&["cat", "s3/b/k", "s3/b/j"])).is_err());
+        assert_eq!(key_basename("logs/2024/app.log").unwrap(), "app.log");
+        assert!(key_basename("logs/").is_err());
+    }
+
+    #[test]
+    fn bucket_list_rows_pairs_names_with_creation_dates() {
+        let xml = "<ListAllMyBucketsResult><Buckets>\
+            <Bucket><Name>logs</Name><CreationDate>2024-01-02T03:04:05.000Z</CreationDate></Bucket>\
+            <Bucket><Name>media</Name><CreationDate>2024-02-03T04:05:06.000Z</CreationDate></Bucket>\
+            </Buckets></ListAllMyBucketsResult>";
+        let rows = bucket_list_rows(xml);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1], vec!["media", "2024-02-03T04:05:06.000Z"]);
+    }
+
+    #[test]
+    fn ranged_get_as_head_reports_full_object_size() {
+        let headers = "HTTP/1.1 206 Partial Content\r\nETag: \"abc\"\r\nContent-Length: 1\r\nContent-Range: bytes 0-0/5120\r\n\r\n";
+        let head = ranged_get_as_head(headers);
+        assert!(head.contains("ETag: \"abc\""));
+        assert!(head.contains("Content-Length: 5120"));
+        assert!(!head.contains("Content-Length: 1\n"));
+        assert!(!head.contains("Content-Range"));
+    }
+
+    #[test]
+    fn parse_listed_objects_keeps_fields_per_entry() {
+        let xml = "<ListBucketResult>\
+            <Contents><Key>a.txt</Key><LastModified>2024-01-02T03:04:05.000Z</LastModified>\
+            <ETag>&quot;e1&quot;</ETag><Size>12</Size><StorageClass>STANDARD</StorageClass></Contents>\
+            <Contents><Key>b&amp;c.txt</Key><Size>7</Size></Contents>\
+            </ListBucketResult>";
+        let objects = parse_listed_objects(xml);
+        assert_eq!(objects.len(), 2);
+        assert_eq!(objects[0].etag, "e1");
+        assert_eq!(objects[0].size, 12);
+        assert_eq!(objects[0].storage_class, "STANDARD");
+        assert_eq!(objects[1].key, "b&c.txt");
+        assert_eq!(objects[1].size, 7);
+        assert!(objects[1].last_modified.is_empty());
+    }
+
+    #[test]
+    fn sync_ages_come_from_the_listing() {
+        let xml = "<ListBucketResult>\
+            <Contents><Key>old.log</Key><LastModified>2024-01-01T00:00:00.000Z</LastModified><Size>1</Size></Contents>\
+            <Contents><Key>new.log</Key><LastModified>2024-01-10T00:00:00.000Z</LastModified><Size>1</Size></Contents>\
+            <Contents><Key>dir/</Key><LastModified>2024-01-01T00:00:00.000Z</LastModified><Size>0</Size></Contents>\
+            <Contents><Key>undated.log</Key><Size>1</Size></Contents>\
+            </ListBucketResult>";
+        let jan_1 = parse_rfc3339("2024-01-01T00:00:00Z").unwrap();
+        let listed = listed_modified_times(parse_listed_objects(xml), DirMarkers::Skip);
+        assert_eq!(
+            listed,
+            vec![
+                ("old.log".to_string(), Some(jan_1)),
+                ("new.log".to_string(), Some(jan_1 + 9 * 86_400)),
+                ("undated.log".to_string(), None),
+            ]
+        );
+
+        let now = jan_1 + 10 * 86_400;
+        assert_eq!(object_age_secs(jan_1, now), 10 * 86_400);
+        assert_eq!(object_age_secs(now + 60, now), 0);
+        let older = SyncOptions {
+            older_than: Some(7 * 86_400),
+            ..SyncOptions::default()
+        };
+        let kept: Vec<&str> = listed
+            .iter()
+            .filter_map(|(key, modified)| Some((key, (*modified)?)))
+            .filter(|(_, modified)| sync_time_filter_matches(&older, *modified, now))
+            .map(|(key, _)| key.as_str())
+            .collect();
+        assert_eq!(kept, vec!["old.log"]);
+    }
+
+    #[test]
+    fn upload_records_round_trip_and_filter_by_bucket() {
+        let record = UploadRecord {
+            endpoint: "http://127.0.0.1:9000".to_string(),
+            bucket: "b".to_string(),
+            key: "dir/a <&> b.bin".to_string(),
+            upload_id: "2~xYz.abc".to_string(),
+            pid: 4242,
+            initiated: 1_700_000_000,
+        };
+        assert_eq!(UploadRecord::parse(&record.render()), Some(record.clone()));
+        assert_eq!(
+            UploadRecord::parse("<Upload><Bucket>b</Bucket></Upload>"),
+            None
+        );
+
+        let dir = std::env::temp_dir().join(format!("s4-upload-records-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create record dir");
+        let other = UploadRecord {
+            bucket: "other".to_string(),
+            ..record.clone()
+        };
+        std::fs::write(dir.join("1.xml"), record.render()).expect("write record");
+        std::fs::write(dir.join("2.xml"), other.render()).expect("write record");
+        std::fs::write(dir.join("3.xml"), "garbage").expect("write record");
+        let found = load_upload_records(&dir, "http://127.0.0.1:9000", "b");
+        std::fs::remove_dir_all(&dir).ok();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].1, record);
+
+        let args: Vec<String> = ["multipart", "recover", "--dry-run", "a/b"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let (dry_run, target) = parse_multipart_args(&args).expect("recover should parse");
+        assert!(dry_run);
+        assert_eq!(target.bucket.as_deref(), Some("b"));
+        let with_key: Vec<String> = ["multipart", "recover", "a/b/k"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert!(parse_multipart_args(&with_key).is_err());
+        assert!(parse_multipart_args(&["multipart".to_string(), "ls".to_string()]).is_err());
+    }
+
+    #[test]
+    fn sign_v4_scopes_credentials_to_the_given_service() {
+        let sign = |service| {
+            sign_v4(&CanonicalRequest { method: "GET", uri_path: "/minio/admin/v3/info", query: "", host: "127.0.0.1:9000", payload_hash: "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855", headers: &[] }, "eu-west-1", service, "AKID", "secret")
+            .expect("signing should succeed")
+        };
+        let sts = sign("sts");
+        let date = &sts.amz_date[..8];
+        assert!(
+            sts.authorization.starts_with(&format!(
+                "AWS4-HMAC-SHA256 Credential=AKID/{date}/eu-west-1/sts/aws4_request, "
+            )),
+            "{}",
+            sts.authorization
+        );
+        assert!(
+            sts.authorization
+                .contains("SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature=")
+        );
+        let s3 = sign(S3_SIGNING_SERVICE);
+        assert!(s3.authorization.contains("/eu-west-1/s3/aws4_request"));
+        if s3.amz_date == sts.amz_date {
+            // Same inputs apart from the scope: the signing key must differ.
+            assert_ne!(
+                s3.authorization.rsplit('=').next(),
+                sts.authorization.rsplit('=').next()
+            );
+        }
+    }
+
+    #[test]
+    fn sync_journal_skips_recorded_keys_and_is_removed_when_done() {
+        let root = std::env::temp_dir().join(format!("s4-journal-test-{}", std::process::id()));
+        let (src, dst) = (root.join("src"), root.join("dst"));
+        std::fs::create_dir_all(&src).unwrap();
+        std::fs::write(src.join("a.txt"), "a").unwrap();
+        std::fs::write(src.join("b%0A.txt"), "b").unwrap();
+        let (source, destination) = (SyncEndpoint::Local(src), SyncEndpoint::Local(dst.clone()));
+        let header = format!(
+            "# s4 sync journal {} -> {}",
+            source.entry_target(""),
+            destination.entry_target("")
+        );
+        let journal = root.join("sync.journal");
+        std::fs::write(&journal, format!("{header}\na.txt\nb%250A.t")).unwrap();
+        let opts = SyncOptions {
+            journal: Some(journal.clone()),
+            ..SyncOptions::default()
+        };
+        let pass = cmd_sync_once(&source, &destination, &opts, None, false, false)
+            .expect("sync should run");
+        let journal_left = journal.exists();
+        let copied_a = dst.join("a.txt").exists();
+        std::fs::remove_dir_all(&root).ok();
+        assert_eq!((pass.copied, pass.resumed), (1, 1));
+        assert!(
+            !copied_a,
+            "a.txt was journaled and must not be copied again"
+        );
+        assert!(!journal_left);
+
+        let keys = parse_sync_journal(
+            &format!("{header}\n{}\n", encode_journal_key("x\ny%0A")),
+            &header,
+        )
+        .unwrap();
+        assert!(keys.contains("x\ny%0A"));
+        assert!(parse_sync_journal("# s4 sync journal a -> b\n", &header).is_err());
+    }
+
+    #[test]
+    fn usage_parses_minio_quota_and_args() {
+        assert_eq!(
+            parse_minio_quota(r#"{"quota":1073741824,"size":1073741824,"quotatype":"hard"}"#),
+            Ok(Some(BucketQuota {
+                bytes: 1 << 30,
+                kind: "hard".to_string()
+            }))
+        );
+        assert_eq!(parse_minio_quota(r#"{"quota":0}"#), Ok(None));
+        assert!(parse_minio_quota("<Error/>").is_err());
+
+        let args: Vec<String> = ["usage", "--quota", "a/b/logs/"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let (quota, target) = parse_usage_args(&args).expect("usage should parse");
+        assert!(quota);
+        assert_eq!(target.key.as_deref(), Some("logs/"));
+        assert!(parse_usage_args(&["usage".to_string()]).is_err());
+    }
+
+    #[test]
+    fn stat_checksum_compares_only_recomputable_digests() {
+        let single = "ETag: \"40C53C58FDAFACC83CFFF6EE3D2F6D69\"\r\n\
+            x-amz-checksum-crc32: 2xkmWQ==\r\n";
+        let (digests, notes) = comparable_digests(single);
+        assert_eq!(
+            digests,
+            vec![
+                (
+                    StoredDigest::Md5,
+                    "40c53c58fdafacc83cfff6ee3d2f6d69".to_string()
+                ),
+                (
+                    StoredDigest::Additive(ChecksumAlgorithm::Crc32),
+                    "2xkmWQ==".to_string()
+                ),
+            ]
+        );
+        assert!(notes.is_empty());
+
+        let multipart = "ETag: \"d41d8cd98f00b204e9800998ecf8427e-3\"\r\n\
+            x-amz-checksum-sha256: n4bQgYhMfWWaL+qgxVrQFaO/TxsrC4Is0V1sFbDwCgg=-3\r\n";
+        let (digests, notes) = comparable_digests(multipart);
+        assert!(digests.is_empty());
+        assert_eq!(notes.len(), 2);
+
+        for sse in [
+            "x-amz-server-side-encryption: aws:kms\r\n",
+            "x-amz-server-side-encryption: aws:kms:dsse\r\n",
+            "x-amz-server-side-encryption-customer-algorithm: AES256\r\n",
+        ] {
+            let head = format!("{single}{sse}");
+            let (digests, notes) = comparable_digests(&head);
+            assert_eq!(
+                digests,
+                vec![(
+                    StoredDigest::Additive(ChecksumAlgorithm::Crc32),
+                    "2xkmWQ==".to_string()
+                )]
+            );
+            assert!(notes[0].contains("is unverifiable"), "{notes:?}");
+        }
+        let sse_s3 = format!("{single}x-amz-server-side-encryption: AES256\r\n");
+        assert_eq!(comparable_digests(&sse_s3).0.len(), 2);
+
+        let args: Vec<String> = ["stat", "--checksum", "./f.bin", "a/b/k"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let (local, target) = parse_stat_args(&args).expect("stat should parse");
+        assert_eq!(local, Some(PathBuf::from("./f.bin")));
+        assert_eq!(target.key.as_deref(), Some("k"));
+        assert!(parse_stat_args(&["stat".to_string(), "--checksum".to_string()]).is_err());
+    }
+
+    #[test]
+    fn shell_resolves_targets_against_current_directory() {
+        let known: std::collections::HashSet<String> =
+            ["m".to_string(), "other".to_string()].into();
+        let mut cwd = ShellCwd {
+            alias: "m".to_string(),
+            path: Vec::new(),
+        };
+        cwd.cd("photos/2024", &known);
+        assert_eq!(cwd.display(), "m/photos/2024/");
+        assert_eq!(cwd.resolve("img.png", &known), "m/photos/2024/img.png");
+        assert_eq!(cwd.resolve(".", &known), "m/photos/2024/");
+        assert_eq!(cwd.resolve("../2023/a.png", &known), "m/photos/2023/a.png");
+        assert_eq!(cwd.resolve("..", &known), "m/photos");
+        assert_eq!(cwd.resolve("/logs/x", &known), "m/logs/x");
+        assert_eq!(cwd.resolve("other/b/k", &known), "other/b/k");
+        cwd.cd("../../..", &known);
+        assert_eq!(cwd.display(), "m/");
+        cwd.cd("other/b", &known);
+        assert_eq!(cwd.display(), "other/b/");
+
+        assert_eq!(
+            split_shell_words(r#"  put 'my file.txt' "a \"b\"" c\ d "#).unwrap(),
+            vec!["put", "my file.txt", "a \"b\"", "c d"]
+        );
+        assert_eq!(split_shell_words("ls ''").unwrap(), vec!["ls", ""]);
+        assert!(split_shell_words("cat 'open").is_err());
+    }
+
+    #[test]
+    fn status_trailer_split_keeps_binary_body() {
+        let mut stdout = vec![0x89, b'P', b'N', b'G', 0xff, 0x00];
+        stdout.extend_from_slice(b"\nHTTPSTATUS:");
+        stdout.extend_from_slice(
+            b"\nHTTPSTATUS:200\nBUCKETREGION:\nCONTENTENCODING:\nSIZEDOWNLOAD:6",
+        );
+        let (body, trailer) = split_status_trailer(&stdout).expect("trailer present");
+        assert_eq!(body, b"\x89PNG\xff\x00\nHTTPSTATUS:");
+        assert!(trailer.starts_with("200\nBUCKETREGION:"));
+        assert!(split_status_trailer(b"no trailer").is_none());
+    }
+
+    #[test]
+    fn restore_header_and_wait_args_are_parsed() {
+        let done = "HTTP/1.1 200 OK\r\nx-amz-restore: ongoing-request=\"false\", \
+            expiry-date=\"Fri, 21 Dec 2012 00:00:00 GMT\"\r\n";
+        assert_eq!(
+            parse_restore_header(done),
+            RestoreState::Done {
+                expiry: Some("Fri, 21 Dec 2012 00:00:00 GMT".to_string())
+            }
+        );
+        let ongoing = "x-amz-restore: ongoing-request=\"true\"\r\nx-amz-storage-class: GLACIER\r\n";
+        assert_eq!(parse_restore_header(ongoing), RestoreState::Ongoing);
+        let archived = "x-amz-storage-class: DEEP_ARCHIVE\r\n";
+        assert_eq!(parse_restore_header(archived), RestoreState::None);
+        assert!(is_archive_storage_class(archived));
+        assert!(!is_archive_storage_class(
+            "x-amz-storage-class: STANDARD\r\n"
+        ));
+
+        let args: Vec<String> = [
+            "restore",
+            "wait",
+            "--timeout",
+            "2h",
+            "--interval",
+            "10s",
+            "a/b/k",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let (wait, target) = parse_restore_args(&args).expect("wait should parse");
+        assert_eq!(
+            wait,
+            RestoreWait {
+                timeout: 7200,
+                interval: 10
+            }
+        );
+        assert_eq!(target.key.as_deref(), Some("k"));
+        assert!(parse_restore_args(&["restore".to_string(), "start".to_string()]).is_err());
+        assert!(parse_restore_args(&["restore".to_string(), "wait".to_string()]).is_err());
+    }
+
+    #[test]
+    fn copy_result_is_parsed_and_reported() {
+        let body = "<CopyObjectResult><LastModified>2024-05-01T10:00:00.000Z</LastModified>\
+            <ETag>&quot;9b2cf535f27731c974343645a3985328&quot;</ETag></CopyObjectResult>";
+        let copied = parse_copy_result(body).expect("copy result should parse");
+        assert_eq!(copied.etag, "9b2cf535f27731c974343645a3985328");
+        assert_eq!(copied.last_modified, "2024-05-01T10:00:00.000Z");
+        assert_eq!(
+            copy_result_json(&copied),
+            ",\"etag\":\"9b2cf535f27731c974343645a3985328\",\"last_modified\":\"2024-05-01T10:00:00.000Z\""
+        );
+        assert_eq!(copy_result_json(&CopyResult::default()), "");
+        let failed = parse_copy_result("<Error><Code>InternalError</Code></Error>").unwrap_err();
+        assert!(is_transient_error(&failed));
+    }
+
+    #[test]
+    fn complete_multipart_response_is_validated() {
+        let ok = "<CompleteMultipartUploadResult><ETag>&quot;abc-3&quot;</ETag></CompleteMultipartUploadResult>";
+        assert!(check_complete_multipart_response(ok, 3).is_ok());
+        assert!(check_complete_multipart_response(ok, 2).is_err());
+        let err = "<Error><Code>InternalError</Code><Message>try again</Message></Error>";
+        let failed = check_complete_multipart_response(err, 3).unwrap_err();
+        assert!(is_transient_error(&failed));
+        assert!(!is_transient_error(&S4Error::Http {
+            status: 403,
+            body: String::new()
+        }));
+    }
+
+    #[test]
+    fn rm_older_than_needs_recursive_and_force() {
+        let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let (opts, target) = parse_rm_args(&args(&[
+            "rm",
+            "-r",
+            "--older-than",
+            "30d",
+            "--force",
+            "s3/b/logs/",
+        ]))
+        .unwrap();
+        assert_eq!(opts.older_than, Some(30 * 86400));
+        assert_eq!(target.key.as_deref(), Some("logs/"));
+        assert!(parse_rm_args(&args(&["rm", "-r", "--older-than", "30d", "s3/b"])).is_err());
+        assert!(
+            parse_rm_args(&args(&[
+                "rm",
+                "-r",
+                "--older-than",
+                "30d",
+                "--dry-run",
+                "s3/b"
+            ]))
+            .is_ok()
+        );
+        assert!(parse_rm_args(&args(&["rm", "--older-than", "1h", "--force", "s3/b"])).is_err());
+        assert!(parse_rm_args(&args(&["rm", "-r", "--force", "s3/b"])).is_err());
+
+        let object = |key: &str, last_modified: &str| ListedObject {
+            key: key.to_string(),
+            last_modified: last_modified.to_string(),
+            ..ListedObject::default()
+        };
+        let now = parse_rfc3339("2024-03-31T00:00:00Z").unwrap();
+        let kept = objects_older_than(
+            vec![
+                object("old", "2024-01-01T00:00:00.000Z"),
+                object("new", "2024-03-30T00:00:00.000Z"),
+                object("undated", ""),
+            ],
+            30 * 86400,
+            now,
+        );
+        assert_eq!(kept, vec![object("old", "2024-01-01T00:00:00.000Z")]);
+    }
+
+    #[test]
+    fn dry_run_flags_parse_for_rm_and_cp() {
+        let rm: Vec<String> = ["rm", "--dry-run", "s3/b/k"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let (opts, target) = parse_rm_args(&rm).expect("rm args should parse");
+        assert!(opts.dry_run);
+        assert!(!opts.bypass_governance);
+        let bypass: Vec<String> = ["rm", "--bypass-governance", "s3/b/k"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert!(
+            parse_rm_args(&bypass)
+                .expect("rm args should parse")
+                .0
+                .bypass_governance
+        );
+        assert_eq!(target.key.as_deref(), Some("k"));
+        assert!(parse_rm_args(&rm[..2]).is_err());
+        let cp: Vec<String> = ["mv", "--dry-run", "s3/b/k", "s3/b/k2"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert!(parse_cp_args(&cp).expect("mv args should parse").0.dry_run);
+    }
+
+    #[test]
+    fn object_lock_copy_headers_picks_lock_headers_only() {
+        let head = "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nX-Amz-Object-Lock-Mode: GOVERNANCE\r\nx-amz-object-lock-retain-until-date: 2030-01-01T00:00:00Z\r\nx-amz-object-lock-legal-hold: ON\r\nx-amz-tagging-count: 2\r\n";
+        assert_eq!(
+            object_lock_copy_headers(head),
+            vec![
+                "x-amz-object-lock-mode: GOVERNANCE".to_string(),
+                "x-amz-object-lock-retain-until-date: 2030-01-01T00:00:00Z".to_string(),
+                "x-amz-object-lock-legal-hold: ON".to_string(),
+            ]
+        );
+        assert!(object_lock_copy_headers("HTTP/1.1 200 OK\r\n").is_empty());
+    }
+
+    #[test]
+    fn lock_and_checksum_copy_headers_are_signed() {
+        let head = "HTTP/1.1 200 OK\r\nx-amz-object-lock-mode: GOVERNANCE\r\nx-amz-object-lock-retain-until-date: 2030-01-01T00:00:00Z\r\nx-amz-object-lock-legal-hold: ON\r\n";
+        let mut headers = object_lock_copy_headers(head);
+        headers.extend(checksum_headers(ChecksumAlgorithm::Crc32, "2xkmWQ=="));
+        let (signed, unsigned) = partition_signed_headers(&headers);
+        assert!(unsigned.is_empty());
+        let names: Vec<&str> = signed.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "x-amz-object-lock-mode",
+                "x-amz-object-lock-retain-until-date",
+                "x-amz-object-lock-legal-hold",
+                "x-amz-checksum-crc32",
+                "x-amz-sdk-checksum-algorithm",
+            ]
+        );
+    }
+
+    #[test]
+    fn curl_trace_redacts_credentials() {
+        assert_eq!(
+            redact_curl_trace_line("> Authorization: AWS4-HMAC-SHA256 Credential=AKIA/x"),
+            "> Authorization: [REDACTED]"
+        );
+        assert_eq!(
+            redact_curl_trace_line("> x-amz-date: 20240101T000000Z"),
+            "> x-amz-date: 20240101T000000Z"
+        );
+        assert_eq!(
+            redact_curl_trace_line("> x-amz-security-token: IQoJb3JpZ2luX2VjEXAMPLE"),
+            "> x-amz-security-token: [REDACTED]"
+        );
+        assert!(is_curl_trace_line("< HTTP/1.1 200 OK"));
+        assert!(!is_curl_trace_line("curl: (7) Failed to connect"));
+    }
+
+    #[test]
+    fn show_headers_keeps_only_header_lines() {
+        let stderr = "*   Trying 127.0.0.1:9000...\n> GET /b HTTP/1.1\n\
+            > Authorization: AWS4-HMAC-SHA256 Credential=AKIA/x\n>\n\
+            < HTTP/1.1 200 OK\n< ETag: \"abc\"\n{ [5 bytes data]\ncurl: (18) partial file\n";
+        let (shown, rest) = split_curl_trace(stderr, true);
+        assert_eq!(
+            shown,
+            vec![
+                "> GET /b HTTP/1.1",
+                "> Authorization: [REDACTED]",
+                ">",
+                "< HTTP/1.1 200 OK",
+                "< ETag: \"abc\"",
+            ]
+        );
+        assert_eq!(rest, "curl: (18) partial file\n");
+        let (traced, _) = split_curl_trace(stderr, false);
+        assert_eq!(traced.len(), 7);
+    }
+
+    #[test]
+    fn gzip_file_roundtrips_through_gunzip() {
+        let dir = std::env::temp_dir();
+        let src = dir.join(format!("s4-gzip-src-{}", std::process::id()));
+        let gz = dir.join(format!("s4-gzip-dst-{}", std::process::id()));
+        std::fs::write(&src, b"repeated repeated repeated").expect("write source");
+        gzip_file(&src, &gz).expect("gzip should succeed");
+        let mut out = Vec::new();
+        gunzip_file(&gz, &mut out).expect("gunzip should succeed");
+        let _ = std::fs::remove_file(&src);
+        let _ = std::fs::remove_file(&gz);
+        assert_eq!(out, b"repeated repeated repeated");
+        let args: Vec<String> = ["pipe", "--compress", "gzip", "s3/logs/app.log.gz"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let (upload, target) = parse_pipe_args(&args).expect("pipe args should parse");
+        assert!(upload.compress);
+        assert_eq!(target.key.as_deref(), Some("app.log.gz"));
+        assert!(parse_pipe_args(&args[..3]).is_err());
+    }
+
+    #[test]
+    fn gunzip_file_inflates_gzip_bodies() {
+        use std::io::Write;
+        let path = std::env::temp_dir().join(format!("s4-gunzip-test-{}", std::process::id()));
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"hello log line\n").expect("compress");
+        std::fs::write(&path, encoder.finish().expect("finish")).expect("write gz");
+        let mut out = Vec::new();
+        gunzip_file(&path, &mut out).expect("gunzip should succeed");
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(out, b"hello log line\n");
+        assert!(is_gzip_encoding("GZIP"));
+        assert!(!is_gzip_encoding("br"));
+    }
+
+    #[test]
+    fn format_bytes_uses_binary_units() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(17 * 1024 * 1024), "17.0 MiB");
+    }
+
+    #[test]
+    fn transfer_summary_line_reports_rate_and_duration() {
+        let line = transfer_summary_line(34, 1_288_490_189, std::time::Duration::from_secs(301));
+        assert_eq!(
+            line,
+            "Transferred 1.2 GiB in 34 object(s) (4.1 MiB/s, 00:05:01)"
+        );
+        assert_eq!(
+            transfer_summary_line(0, 0, std::time::Duration::ZERO),
+            "Transferred 0 B in 0 object(s) (0 B/s, 00:00:00)"
+        );
+    }
+
+    #[test]
+    fn build_complete_multipart_xml_contains_parts() {
+        let xml = build_complete_multipart_xml(
+            &[
+                (1, "etag-1".to_string(), None),
+                (2, "etag-2".to_string(), None),
+            ],
+            None,
+        );
+        assert!(xml.contains("<PartNumber>1</PartNumber>"));
+        assert!(xml.contains("<ETag>\"etag-2\"</ETag>"));
+    }
+
+    #[test]
+    fn build_complete_multipart_xml_includes_part_checksums() {
+        let xml = build_complete_multipart_xml(
+            &[(1, "etag-1".to_string(), Some("AAAAAA==".to_string()))],
+            Some(ChecksumAlgorithm::Crc32),
+        );
+        assert!(xml.contains("<ChecksumCRC32>AAAAAA==</ChecksumCRC32>"));
+        assert_eq!(
+            ChecksumAlgorithm::parse("sha256"),
+            Ok(ChecksumAlgorithm::Sha256)
+        );
+        assert!(ChecksumAlgorithm::parse("md5").is_err());
+    }
+
+    #[test]
+    fn normalize_sigv4_query_adds_empty_values_for_subresources() {
+        assert_eq!(normalize_sigv4_query("cors"), "cors=");
+        assert_eq!(normalize_sigv4_query("uploads"), "uploads=");
+        assert_eq!(
+            normalize_sigv4_query("list-type=2&prefix=a"),
+            "list-type=2&prefix=a"
+        );
+    }
+
+    #[test]
+    fn normalize_resolve_entry_supports_equals_and_colon_formats() {
+        assert_eq!(
+            normalize_resolve_entry("minio.local:9000=127.0.0.1"),
+            "minio.local:9000:127.0.0.1"
+        );
+        assert_eq!(
+            normalize_resolve_entry("minio.local:9000:127.0.0.1"),
+            "minio.local:9000:127.0.0.1"
+        );
+    }
+
+    #[test]
+    fn uri_encode_query_component_works() {
+        assert_eq!(uri_encode_query_component("a b/+"), "a%20b%2F%2B");
+    }
+
+    #[test]
+    fn wildcard_match_works() {
+        assert!(wildcard_match("*.tmp", "a.tmp"));
+        assert!(wildcard_match("foo/*/bar", "foo/x/bar"));
+        assert!(!wildcard_match("*.tmp", "a.txt"));
+    }
+
+    #[test]
+    fn parse_sync_args_with_flags() {
+        let args = vec![
+            "mirror".to_string(),
+            "--dry-run".to_string(),
+            "--remove".to_string(),
+            "-w".to_string(),
+            "--exclude".to_string(),
+            "*.tmp".to_string(),
+            "a/src/prefix".to_string(),
+            "b/dst/prefix".to_string(),
+        ];
+        let (opts, src, dst) = parse_sync_args(&args).expect("sync args should parse");
+        assert!(opts.dry_run);
+        assert!(opts.remove);
+        assert!(opts.watch);
+        assert_eq!(opts.excludes, vec!["*.tmp".to_string()]);
+        assert_eq!(opts.newer_than, None);
+        assert_eq!(opts.older_than, None);
+        assert_eq!(src, "a/src/prefix");
+        assert_eq!(dst, "b/dst/prefix");
+        assert!(is_excluded("x.tmp", &opts.excludes));
+    }
+
+    #[test]
+    fn sync_remove_skips_when_source_is_empty() {
+        let root = std::env::temp_dir().join(format!("s4-sync-empty-{}", std::process::id()));
+        let (src, dst) = (root.join("src"), root.join("dst"));
+        std::fs::create_dir_all(&src).unwrap();
+        std::fs::create_dir_all(&dst).unwrap();
+        std::fs::write(dst.join("keep.txt"), "keep").unwrap();
+        let (source, destination) = (SyncEndpoint::Local(src), SyncEndpoint::Local(dst.clone()));
+        let mut opts = SyncOptions {
+            remove: true,
+            ..SyncOptions::default()
+        };
+
+        let pass = cmd_sync_once(&source, &destination, &opts, None, true, false)
+            .expect("sync should run");
+        assert_eq!((pass.copied, pass.removed), (0, 0));
+        assert!(dst.join("keep.txt").exists());
+
+        opts.allow_empty_source = true;
+        opts.dry_run = true;
+        let pass = cmd_sync_once(&source, &destination, &opts, None, true, false)
+            .expect("sync should run");
+        assert_eq!(
+            sync_actions_json(&pass.planned),
+            format!(
+                "[{{\"op\":\"remove\",\"dst\":\"{}\"}}]",
+                dst.join("keep.txt").display()
+            )
+        );
+        assert!(dst.join("keep.txt").exists());
+        opts.dry_run = false;
+        let pass = cmd_sync_once(&source, &destination, &opts, None, true, false)
+            .expect("sync should run");
+        assert_eq!(pass.removed, 1);
+        assert!(!dst.join("keep.txt").exists());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn sync_verify_counts_matching_copies() {
+        let root = std::env::temp_dir().join(format!("s4-sync-verify-{}", std::process::id()));
+        let (src, dst) = (root.join("src"), root.join("dst"));
+        std::fs::create_dir_all(src.join("nested")).unwrap();
+        std::fs::write(src.join("a.txt"), "a").unwrap();
+        std::fs::write(src.join("nested/b.txt"), "bb").unwrap();
+        let (source, destination) = (SyncEndpoint::Local(src), SyncEndpoint::Local(dst.clone()));
+        let opts = SyncOptions {
+            verify: true,
+            ..SyncOptions::default()
+        };
+        let pass = cmd_sync_once(&source, &destination, &opts, None, false, false)
+            .expect("sync should run");
+        assert_eq!((pass.copied, pass.verified), (2, 2));
+        assert!(pass.mismatched.is_empty());
+
+        let mut pass = SyncPass::default();
+        std::fs::write(dst.join("a.txt"), "changed").unwrap();
+        let keys = vec!["a.txt".to_string(), "nested/b.txt".to_string()];
+        verify_sync_pass(&source, &destination, &keys, false, &mut pass, false)
+            .expect("verify should run");
+        assert_eq!(pass.verified, 1);
+        assert_eq!(
+            pass.mismatched,
+            vec![dst.join("a.txt").display().to_string()]
+        );
+        assert_eq!(
+            etag_md5("\"0cc175b9c0f1b6a831c399e269772661\"").as_deref(),
+            Some("0cc175b9c0f1b6a831c399e269772661")
+        );
+        assert_eq!(etag_md5("\"0cc175b9c0f1b6a831c399e269772661-3\""), None);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn sync_metrics_render_textfile_and_count_errors() {
+        let source = SyncEndpoint::Local(std::path::PathBuf::from("/data/\"in\""));
+        let destination = SyncEndpoint::Local(std::path::PathBuf::from("/backup"));
+        let mut metrics = SyncMetrics::start(&source, &destination);
+        metrics.record(&Ok(SyncPass {
+            copied: 3,
+            bytes: 2048,
+            mismatched: vec!["x".to_string()],
+            ..SyncPass::default()
+        }));
+        metrics.record(&Err(S4Error::Other("boom".to_string())));
+        let text = metrics.render();
+        let labels = r#"{src="/data/\"in\"",dst="/backup"}"#;
+        assert!(text.contains(&format!("s4_sync_objects_copied{labels} 3\n")));
+        assert!(text.contains("# TYPE s4_sync_objects_copied gauge\n"));
+        assert!(text.contains(&format!("s4_sync_bytes_total{labels} 2048\n")));
+        assert!(text.contains(&format!("s4_sync_errors_total{labels} 2\n")));
+        assert!(text.contains("# TYPE s4_sync_duration_seconds gauge\n"));
+
+        let path = std::env::temp_dir().join(format!("s4-metrics-{}.prom", std::process::id()));
+        metrics.write(&path);
+        assert!(
+            std::fs::read_to_string(&path)
+                .unwrap()
+                .contains("s4_sync_objects_removed")
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn sync_stops_at_deadline_between_keys() {
+        let root = std::env::temp_dir().join(format!("s4-sync-deadline-{}", std::process::id()));
+        let (src, dst) = (root.join("src"), root.join("dst"));
+        std::fs::create_dir_all(&src).unwrap();
+        std::fs::create_dir_all(&dst).unwrap();
+        std::fs::write(src.join("a.txt"), "a").unwrap();
+        std::fs::write(src.join("b.txt"), "b").unwrap();
+        std::fs::write(dst.join("stale.txt"), "x").unwrap();
+        let (source, destination) = (
+            SyncEndpoint::Local(src.clone()),
+            SyncEndpoint::Local(dst.clone()),
+        );
+        let opts = SyncOptions {
+            remove: true,
+            ..SyncOptions::default()
+        };
+
+        let expired = Some(std::time::Instant::now());
+        let pass = cmd_sync_once(&source, &destination, &opts, expired, false, false)
+            .expect("sync should run");
+        assert_eq!((pass.copied, pass.remaining, pass.removed), (0, 2, 0));
+        assert!(!dst.join("a.txt").exists());
+        assert!(dst.join("stale.txt").exists());
+
+        let (src, dst) = (src.to_string_lossy(), dst.to_string_lossy());
+        let config = AppConfig::default();
+        let once = SyncOptions {
+            deadline: Some(0),
+            ..SyncOptions::default()
+        };
+        assert!(cmd_sync(&config, &src, &dst, &once, true, false).is_err());
+        let watch = SyncOptions {
+            watch: true,
+            ..once
+        };
+        cmd_sync(&config, &src, &dst, &watch, true, false)
+            .expect("--watch should stop normally at the deadline");
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn parse_human_duration_works() {
+        assert_eq!(parse_human_duration("10d").expect("duration"), 864000);
+        assert_eq!(
+            parse_human_duration("7d10h30m5s").expect("duration"),
+            642605
+        );
+        assert!(parse_human_duration("10").is_err());
+    }
+
+    #[test]
+    fn parse_sync_args_with_time_filters() {
+        let args = vec![
+            "sync".to_string(),
+            "--newer-than".to_string(),
+            "10d".to_string(),
+            "--older-than".to_string(),
+            "1h".to_string(),
+            "a/src".to_string(),
+            "b/dst".to_string(),
+        ];
+        let (opts, _, _) = parse_sync_args(&args).expect("sync args should parse");
+        assert!(!opts.watch);
+        assert_eq!(opts.newer_than, Some(864000));
+        assert_eq!(opts.older_than, Some(3600));
+    }
+
+    #[test]
+    fn sync_absolute_time_filters() {
+        let args: Vec<String> = [
+            "sync",
+            "--modified-after",
+            "2024-01-01T00:00:00Z",
+            "--modified-before",
+            "2024-02-01T00:00:00Z",
+            "a/src",
+            "b/dst",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let (opts, _, _) = parse_sync_args(&args).expect("sync args should parse");
+        let jan_1 = parse_rfc3339("2024-01-01T00:00:00Z").unwrap();
+        assert_eq!(opts.modified_after, Some(jan_1));
+        let now = jan_1 + 90 * 86_400;
+        assert!(!sync_time_filter_matches(&opts, jan_1, now));
+        assert!(sync_time_filter_matches(&opts, jan_1 + 1, now));
+        assert!(!sync_time_filter_matches(&opts, jan_1 + 31 * 86_400, now));
+        let relative = SyncOptions {
+            newer_than: Some(60),
+            ..SyncOptions::default()
+        };
+        assert!(sync_time_filter_matches(&relative, now - 60, now));
+        assert!(!sync_time_filter_matches(&relative, now - 61, now));
+        assert!(
+            parse_sync_args(&["sync".into(), "--modified-after".into(), "yesterday".into()])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn parse_cors_args_set_works() {
+        let args = vec![
+            "cors".to_string(),
+            "set".to_string(),
+            "a/bucket".to_string(),
+            "cors.xml".to_string(),
+        ];
+        let parsed = parse_cors_args(&args).expect("cors args should parse");
+        match parsed {
+            CorsCommand::Set {
+                target,
+                file,
+                validate_only,
+            } => {
+                assert!(!validate_only);
+                assert_eq!(target.alias, "a");
+                assert_eq!(target.bucket.as_deref(), Some("bucket"));
+                assert_eq!(file.to_string_lossy(), "cors.xml");
+            }
+            _ => panic!("expected cors set"),
+        }
+    }
+
+    #[test]
+    fn parse_cors_args_get_works() {
+        let args = vec![
+            "cors".to_string(),
+            "get".to_string(),
+            "a/bucket".to_string(),
+        ];
+        let parsed = parse_cors_args(&args).expect("cors args should parse");
+        match parsed {
+            CorsCommand::Get { target, raw } => {
+                assert!(!raw);
+                assert_eq!(target.alias, "a");
+                assert_eq!(target.bucket.as_deref(), Some("bucket"));
+            }
+            _ => panic!("expected cors get"),
+        }
+    }
+
+    #[test]
+    fn xml_well_formedness_reports_lines() {
+        let ok = "<?xml version=\"1.0\"?>\n<CORSConfiguration xmlns=\"x\">\n<!-- c -->\n\
+            <CORSRule><AllowedOrigin>*</AllowedOrigin><ID/></CORSRule>\n</CORSConfiguration>\n";
+        assert_eq!(check_xml_well_formed(ok, "CORSConfiguration"), Ok(()));
+        let mismatched = "<CORSConfiguration>\n<CORSRule>\n<AllowedOrigin>*</AllowedMethod>\n";
+        assert_eq!(
+            check_xml_well_formed(mismatched, "CORSConfiguration"),
+            Err(
+                "line 3: </AllowedMethod> does not match <AllowedOrigin> opened on line 3"
+                    .to_string()
+            )
+        );
+        let unclosed = "<CORSConfiguration>\n  <CORSRule>\n</CORSConfiguration>";
+        assert!(
+            check_xml_well_formed(unclosed, "CORSConfiguration")
+                .unwrap_err()
+                .starts_with("line 3:")
+        );
+        assert_eq!(
+            check_xml_well_formed("<Rules></Rules>", "CORSConfiguration"),
+            Err("line 1: expected root element <CORSConfiguration>, found <Rules>".to_string())
+        );
+        assert!(check_xml_well_formed("<A>\n<B</A>", "A").is_err());
+        assert!(check_xml_well_formed("<A/> trailing", "A").is_err());
+        assert!(check_xml_well_formed("", "A").is_err());
+    }
+
+    #[test]
+    fn bucket_config_xml_converts_to_json() {
+        let cors = "<CORSConfiguration><CORSRule><AllowedOrigin>https://a.example</AllowedOrigin>\
+            <AllowedMethod>GET</AllowedMethod><AllowedMethod>PUT</AllowedMethod>\
+            <MaxAgeSeconds>300</MaxAgeSeconds></CORSRule></CORSConfiguration>";
+        assert_eq!(
+            cors_rules_json(cors),
+            "[{\"id\":null,\"allowed_origins\":[\"https://a.example\"],\"allowed_methods\":[\"GET\",\"PUT\"],\"allowed_headers\":[],\"expose_headers\":[],\"max_age_seconds\":300}]"
+        );
+        let enc = "<ServerSideEncryptionConfiguration><Rule><ApplyServerSideEncryptionByDefault>\
+            <SSEAlgorithm>AES256</SSEAlgorithm></ApplyServerSideEncryptionByDefault></Rule>\
+            </ServerSideEncryptionConfiguration>";
+        assert_eq!(
+            encryption_rules_json(enc),
+            "[{\"sse_algorithm\":\"AES256\",\"kms_master_key_id\":null,\"bucket_key_enabled\":null}]"
+        );
+        let events = "<NotificationConfiguration><QueueConfiguration><Id>1</Id>\
+            <Queue>arn:minio:sqs::1:webhook</Queue><Event>s3:ObjectCreated:*</Event>\
+            <Filter><S3Key><FilterRule><Name>suffix</Name><Value>.jpg</Value></FilterRule>\
+            </S3Key></Filter></QueueConfiguration></NotificationConfiguration>";
+        assert_eq!(
+            notification_configs_json(events),
+            "[{\"type\":\"queue\",\"id\":\"1\",\"arn\":\"arn:minio:sqs::1:webhook\",\"events\":[\"s3:ObjectCreated:*\"],\"filters\":[{\"name\":\"suffix\",\"value\":\".jpg\"}]}]"
+        );
+        assert_eq!(cors_rules_json("<CORSConfiguration/>"), "[]");
+    }
+
+    #[test]
+    fn parse_encrypt_args_set_works() {
+        let args = vec![
+            "encrypt".to_string(),
+            "set".to_string(),
+            "a/bucket".to_string(),
+            "enc.xml".to_string(),
+        ];
+        let parsed = parse_encrypt_args(&args).expect("encrypt args should parse");
+        match parsed {
+            EncryptCommand::Set {
+                target,
+                file,
+                validate_only,
+            } => {
+                assert!(!validate_only);
+                assert_eq!(target.alias, "a");
+                assert_eq!(target.bucket.as_deref(), Some("bucket"));
+                assert_eq!(file.to_string_lossy(), "enc.xml");
+            }
+            _ => panic!("expected encrypt set"),
+        }
+    }
+
+    #[test]
+    fn parse_encrypt_args_info_works() {
+        let args = vec![
+            "encrypt".to_string(),
+            "info".to_string(),
+            "a/bucket".to_string(),
+        ];
+        let parsed = parse_encrypt_args(&args).expect("encrypt args should parse");
+        match parsed {
+            EncryptCommand::Info { target, raw } => {
+                assert!(!raw);
+                assert_eq!(target.alias, "a");
+                assert_eq!(target.bucket.as_deref(), Some("bucket"));
+            }
+            _ => panic!("expected encrypt info"),
+        }
+    }
+
+    #[test]
+    fn parse_event_args_add_works() {
+        let args = vec![
+            "event".to_string(),
+            "add".to_string(),
+            "a/bucket".to_string(),
+            "event.xml".to_string(),
+        ];
+        let parsed = parse_event_args(&args).expect("event args should parse");
+        match parsed {
+            EventCommand::Add {
+                target,
+                file,
+                validate_only,
+            } => {
+                assert!(!validate_only);
+                assert_eq!(target.alias, "a");
+                assert_eq!(target.bucket.as_deref(), Some("bucket"));
+                assert_eq!(file.to_string_lossy(), "event.xml");
+            }
+            _ => panic!("expected event add"),
+        }
+    }
+
+    #[test]
+    fn parse_event_args_remove_force_works() {
+        let args = vec![
+            "event".to_string(),
+            "rm".to_string(),
+            "a/bucket".to_string(),
+            "--force".to_string(),
+        ];
+        let parsed = parse_event_args(&args).expect("event args should parse");
+        match parsed {
+            EventCommand::Remove { target, force } => {
+                assert_eq!(target.alias, "a");
+                assert_eq!(target.bucket.as_deref(), Some("bucket"));
+                assert!(force);
+            }
+            _ => panic!("expected event remove"),
+        }
+    }
+
+    #[test]
+    fn parse_idp_args_openid_works() {
+        let args = vec!["idp".to_string(), "openid".to_string()];
+        let parsed = parse_idp_args(&args).expect("idp args should parse");
+        match parsed.kind {
+            IdpKind::OpenId => {}
+            _ => panic!("expected openid"),
+        }
+    }
+
+    #[test]
+    fn parse_idp_args_ldap_works() {
+        let args = vec!["idp".to_string(), "ldap".to_string()];
+        let parsed = parse_idp_args(&args).expect("idp args should parse");
+        match parsed.kind {
+            IdpKind::Ldap => {}
+            _ => panic!("expected ldap"),
+        }
+    }
+
+    #[test]
+    fn parse_ilm_args_rule_works() {
+        let args = vec!["ilm".to_string(), "rule".to_string()];
+        let parsed = parse_ilm_args(&args).expect("ilm args should parse");
+        match parsed.kind {
+            IlmKind::Rule => {}
+            _ => panic!("expected rule"),
+        }
+    }
+
+    #[test]
+    fn parse_ilm_args_restore_works() {
+        let args = vec!["ilm".to_string(), "restore".to_string()];
+        let parsed = parse_ilm_args(&args).expect("ilm args should parse");
+        match parsed.kind {
+            IlmKind::Restore => {}
+            _ => panic!("expected restore"),
+        }
+    }
+
+    #[test]
+    fn parse_legalhold_args_set_works() {
+        let args = vec![
+            "legalhold".to_string(),
+            "set".to_string(),
+            "a/b/k".to_string(),
+        ];
+        let parsed = parse_legalhold_args(&args).expect("legalhold args should parse");
+        match parsed {
+            LegalHoldCommand::Set { target } => {
+                assert_eq!(target.alias, "a");
+                assert_eq!(target.bucket.as_deref(), Some("b"));
+                assert_eq!(target.key.as_deref(), Some("k"));
+            }
+            _ => panic!("expected legalhold set"),
+        }
+    }
+
+    #[test]
+    fn parse_legalhold_args_info_works() {
+        let args = vec![
+            "legalhold".to_string(),
+            "info".to_string(),
+            "a/b/k".to_string(),
+        ];
+        let parsed = parse_legalhold_args(&args).expect("legalhold args should parse");
+        match parsed {
+            LegalHoldCommand::Info { target } => {
+                assert_eq!(target.alias, "a");
+                assert_eq!(target.bucket.as_deref(), Some("b"));
+                assert_eq!(target.key.as_deref(), Some("k"));
+            }
+            _ => panic!("expected legalhold info"),
+        }
+    }
+
+    #[test]
+    fn parse_replicate_args_list_alias_works() {
+        let args = vec![
+            "replicate".to_string(),
+            "ls".to_string(),
+            "a/bucket".to_string(),
+        ];
+        let parsed = parse_replicate_args(&args).expect("replicate args should parse");
+        match parsed.subcommand {
+            ReplicateSubcommand::List => {}
+            _ => panic!("expected list"),
+        }
+        let target = parsed.target.expect("target expected");
+        assert_eq!(target.alias, "a");
+        assert_eq!(target.bucket.as_deref(), Some("bucket"));
+    }
+
+    #[test]
+    fn parse_replicate_args_backlog_works() {
+        let args = vec!["replicate".to_string(), "backlog".to_string()];
+        let parsed = parse_replicate_args(&args).expect("replicate args should parse");
+        match parsed.subcommand {
+            ReplicateSubcommand::Backlog => {}
+            _ => panic!("expected backlog"),
+        }
+    }
+
+    #[test]
+    fn parse_retention_args_set_works() {
+        let args = vec![
+            "retention".to_string(),
+            "set".to_string(),
+            "a/b/k".to_string(),
+            "--mode".to_string(),
+            "GOVERNANCE".to_string(),
+            "--retain-until".to_string(),
+            "2030-01-01T00:00:00Z".to_string(),
+        ];
+        let parsed = parse_retention_args(&args).expect("retention args should parse");
+        match parsed {
+            RetentionCommand::Set {
+                target,
+                mode,
+                retain_until,
+            } => {
+                assert_eq!(target.alias, "a");
+                assert_eq!(target.bucket.as_deref(), Some("b"));
+                assert_eq!(target.key.as_deref(), Some("k"));
+                assert_eq!(mode, "GOVERNANCE");
+                assert_eq!(retain_until, "2030-01-01T00:00:00Z");
+            }
+            _ => panic!("expected retention set"),
+        }
+    }
 
-NOTE:
-  mb supports --with-lock for object-lock buckets (used by legalhold tests)"
-    );
-}
+    #[test]
+    fn parse_retention_args_set_default_builds_lock_config() {
+        let args: Vec<String> = [
+            "retention",
+            "set-default",
+            "a/b",
+            "--mode",
+            "governance",
+            "--days",
+            "30",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        match parse_retention_args(&args).expect("retention args should parse") {
+            RetentionCommand::SetDefault {
+                target,
+                mode,
+                period,
+            } => {
+                assert_eq!(target.bucket.as_deref(), Some("b"));
+                assert_eq!(period, RetentionPeriod::Days(30));
+                assert_eq!(
+                    build_object_lock_config_xml(Some((&mode, period))),
+                    "<ObjectLockConfiguration><ObjectLockEnabled>Enabled</ObjectLockEnabled><Rule><DefaultRetention><Mode>GOVERNANCE</Mode><Days>30</Days></DefaultRetention></Rule></ObjectLockConfiguration>"
+                );
+            }
+            _ => panic!("expected retention set-default"),
+        }
+        assert!(!build_object_lock_config_xml(None).contains("<Rule>"));
+        let mut both = args.clone();
+        both.extend(["--years".to_string(), "1".to_string()]);
+        assert!(parse_retention_args(&both).is_err());
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::{
-        AliasConfig, AppConfig, CorsCommand, EncryptCommand, EventCommand, IdpKind, IlmKind,
-        LegalHoldCommand, ReplicateSubcommand, RetentionCommand, build_complete_multipart_xml,
-        build_select_request_xml, extract_tag_blocks, extract_tag_values, extract_version_entries,
-        is_excluded, looks_ready_xml, normalize_resolve_entry, normalize_sigv4_query, parse_config,
-        parse_cors_args, parse_encrypt_args, parse_event_args, parse_event_stream_records,
-        parse_globals, parse_human_duration, parse_idp_args, parse_ilm_args, parse_legalhold_args,
-        parse_replicate_args, parse_retention_args, parse_sql_args, parse_sync_args, parse_target,
-        serialize_config, should_retry_with_governance_bypass, sync_destination_key,
-        uri_encode_path, uri_encode_query_component, wildcard_match, xml_unescape,
-    };
-    use std::collections::BTreeMap;
+    #[test]
+    fn parse_retention_args_info_works() {
+        let args = vec![
+            "retention".to_string(),
+            "info".to_string(),
+            "a/b/k".to_string(),
+        ];
+        let parsed = parse_retention_args(&args).expect("retention args should parse");
+        match parsed {
+            RetentionCommand::Info { target } => {
+                assert_eq!(target.alias, "a");
+                assert_eq!(target.bucket.as_deref(), Some("b"));
+                assert_eq!(target.key.as_deref(), Some("k"));
+            }
+            _ => panic!("expected retention info"),
+        }
+    }
 
     #[test]
-    fn parse_target_with_key() {
-        let t = parse_target("local/bucket/folder/file.txt").expect("target should parse");
-        assert_eq!(t.alias, "local");
-        assert_eq!(t.bucket.as_deref(), Some("bucket"));
-        assert_eq!(t.key.as_deref(), Some("folder/file.txt"));
+    fn parse_sql_args_defaults_and_targets() {
+        let args = vec!["sql".to_string(), "a/bucket/path.csv".to_string()];
+        let (opts, targets) = parse_sql_args(&args).expect("sql args should parse");
+        assert_eq!(opts.query, "select * from S3Object");
+        assert!(!opts.recursive);
+        assert_eq!(targets.len(), 1);
+        assert_eq!(targets[0].alias, "a");
+        assert_eq!(targets[0].bucket.as_deref(), Some("bucket"));
+        assert_eq!(targets[0].key.as_deref(), Some("path.csv"));
     }
 
     #[test]
-    fn roundtrip_config() {
-        let mut aliases = BTreeMap::new();
-        aliases.insert(
-            "local".to_string(),
+    fn parse_sql_args_full_flags() {
+        let args = vec![
+            "sql".to_string(),
+            "--query".to_string(),
+            "select count(*) from S3Object".to_string(),
+            "-r".to_string(),
+            "--csv-input".to_string(),
+            "fh=USE,fd=;".to_string(),
+            "--compression".to_string(),
+            "GZIP".to_string(),
+            "--csv-output".to_string(),
+            "fd=;".to_string(),
+            "--csv-output-header".to_string(),
+            "c1,c2".to_string(),
+            "--enc-c".to_string(),
+            "a/bucket=Zm9v".to_string(),
+            "a/bucket/prefix".to_string(),
+        ];
+        let (opts, targets) = parse_sql_args(&args).expect("sql args should parse");
+        assert_eq!(opts.query, "select count(*) from S3Object");
+        assert!(opts.recursive);
+        assert_eq!(opts.csv_input.as_deref(), Some("fh=USE,fd=;"));
+        assert_eq!(opts.compression.as_deref(), Some("GZIP"));
+        assert_eq!(opts.csv_output.as_deref(), Some("fd=;"));
+        assert_eq!(opts.csv_output_header.as_deref(), Some("c1,c2"));
+        assert_eq!(opts.enc_c, vec!["a/bucket=Zm9v".to_string()]);
+        assert_eq!(targets[0].key.as_deref(), Some("prefix"));
+    }
+
+    #[test]
+    fn build_select_request_xml_contains_query_and_serialization() {
+        let args = vec![
+            "sql".to_string(),
+            "--query".to_string(),
+            "select * from S3Object".to_string(),
+            "--json-output".to_string(),
+            "rd=\n".to_string(),
+            "a/b/k".to_string(),
+        ];
+        let (opts, _) = parse_sql_args(&args).expect("sql args should parse");
+        let xml = build_select_request_xml(&opts);
+        assert!(xml.contains("<Expression>select * from S3Object</Expression>"));
+        assert!(xml.contains("<ExpressionType>SQL</ExpressionType>"));
+        assert!(xml.contains("<JSON>"));
+    }
+
+    #[test]
+    fn parse_event_stream_records_returns_payload_for_records_event() {
+        fn mk_header(name: &str, value: &str) -> Vec<u8> {
+            let mut h = Vec::new();
+            h.push(name.len() as u8);
+            h.extend_from_slice(name.as_bytes());
+            h.push(7);
+            h.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            h.extend_from_slice(value.as_bytes());
+            h
+        }
+        let payload = b"row1,row2\n";
+        let mut headers = Vec::new();
+        headers.extend_from_slice(&mk_header(":message-type", "event"));
+        headers.extend_from_slice(&mk_header(":event-type", "Records"));
+
+        let total_len = 12 + headers.len() + payload.len() + 4;
+        let mut msg = Vec::new();
+        msg.extend_from_slice(&(total_len as u32).to_be_bytes());
+        msg.extend_from_slice(&(headers.len() as u32).to_be_bytes());
+        msg.extend_from_slice(&[0, 0, 0, 0]);
+        msg.extend_from_slice(&headers);
+        msg.extend_from_slice(payload);
+        msg.extend_from_slice(&[0, 0, 0, 0]);
+
+        let out = parse_event_stream_records(&msg).expect("records event should parse");
+        assert_eq!(out, payload);
+    }
+
+    fn event_stream_message(headers: &[(&str, &str)], payload: &[u8]) -> Vec<u8> {
+        let mut h = Vec::new();
+        for (name, value) in headers {
+            h.push(name.len() as u8);
+            h.extend_from_slice(name.as_bytes());
+            h.push(7);
+            h.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            h.extend_from_slice(value.as_bytes());
+        }
+        let total_len = 12 + h.len() + payload.len() + 4;
+        let mut msg = Vec::new();
+        msg.extend_from_slice(&(total_len as u32).to_be_bytes());
+        msg.extend_from_slice(&(h.len() as u32).to_be_bytes());
+        msg.extend_from_slice(&[0, 0, 0, 0]);
+        msg.extend_from_slice(&h);
+        msg.extend_from_slice(payload);
+        msg.extend_from_slice(&[0, 0, 0, 0]);
+        msg
+    }
+
+    /// Serves `body` with a 200 to the first request on a local port; returns the endpoint.
+    fn serve_once(body: Vec<u8>) -> String {
+        use std::io::{BufRead, BufReader, Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind test server");
+        let addr = listener.local_addr().expect("test server address");
+        std::thread::spawn(move || {
+            let Ok((stream, _)) = listener.accept() else {
+                return;
+            };
+            let mut reader = BufReader::new(stream);
+            let mut content_length = 0usize;
+            let mut line = String::new();
+            while reader.read_line(&mut line).is_ok_and(|n| n > 0) && line != "\r\n" {
+                if let Some((name, value)) = line.split_once(':')
+                    && name.eq_ignore_ascii_case("content-length")
+                {
+                    content_length = value.trim().parse().unwrap_or(0);
+                }
+                line.clear();
+            }
+            let mut request_body = vec![0; content_length];
+            let _ = reader.read_exact(&mut request_body);
+            let mut stream = reader.into_inner();
+            let _ = write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            let _ = stream.write_all(&body);
+        });
+        format!("http://{addr}")
+    }
+
+    #[test]
+    fn sql_error_event_fails_the_select() {
+        let mut stream = event_stream_message(
+            &[(":message-type", "event"), (":event-type", "Records")],
+            b"partial,row\n",
+        );
+        stream.extend(event_stream_message(
+            &[
+                (":message-type", "error"),
+                (":error-code", "CSVParsingError"),
+                (":error-message", "bad row 2"),
+            ],
+            b"",
+        ));
+        let err = parse_event_stream_records(&stream).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "sql select failed: CSVParsingError: bad row 2"
+        );
+        assert_eq!(
+            parse_event_stream_records(b"plain text").unwrap(),
+            b"plain text"
+        );
+
+        let mut config = AppConfig::default();
+        config.aliases.insert(
+            "t".to_string(),
             AliasConfig {
-                endpoint: "http://127.0.0.1:9000".to_string(),
-                access_key: "minio".to_string(),
-                secret_key: "minio123".to_string(),
+                endpoint: serve_once(stream),
+                access_key: "ak".to_string(),
+                secret_key: "sk".to_string(),
                 region: "us-east-1".to_string(),
                 path_style: true,
+                request_payer: false,
+                aws_profile: None,
+                session_token: None,
             },
         );
-        let cfg = AppConfig { aliases };
+        let args: Vec<String> = ["sql", "t/bucket/data.csv"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let (opts, targets) = parse_sql_args(&args).expect("sql args should parse");
+        let err = cmd_sql(&config, &opts, &targets, false, false).unwrap_err();
+        assert!(err.to_string().contains("CSVParsingError"), "{err}");
+        assert_ne!(err.exit_code(), 0);
+    }
 
-        let text = serialize_config(&cfg);
-        let parsed = parse_config(&text).expect("config should parse");
-        assert_eq!(parsed.aliases.len(), 1);
-        let alias = parsed.aliases.get("local").expect("alias exists");
-        assert!(alias.path_style);
-        assert_eq!(alias.region, "us-east-1");
+    #[test]
+    fn parse_globals_output_format() {
+        let (opts, _) = parse_globals(vec![
+            "--output".to_string(),
+            "csv".to_string(),
+            "ls".to_string(),
+        ])
+        .expect("parse globals should succeed");
+        assert_eq!(opts.output, OutputFormat::Csv);
+        assert!(parse_globals(vec!["-o".to_string(), "yaml".to_string()]).is_err());
     }
 
     #[test]
-    fn uri_encode_works() {
-        assert_eq!(uri_encode_path("a b/c"), "a%20b/c");
+    fn listed_objects_carry_owner_when_present() {
+        let xml = "<ListBucketResult><Contents><Key>a</Key><Size>1</Size>\
+            <Owner><ID>abc123</ID><DisplayName>team-a</DisplayName></Owner></Contents>\
+            <Contents><Key>b</Key><Size>2</Size></Contents></ListBucketResult>";
+        let objects = parse_listed_objects(xml);
+        assert_eq!(
+            objects[0].owner,
+            Some(ListedOwner {
+                id: "abc123".to_string(),
+                display_name: "team-a".to_string(),
+            })
+        );
+        assert_eq!(objects[1].owner, None);
+        let args: Vec<String> = ["ls", "--owner", "a/b"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let (opts, target) = parse_ls_args(&args).expect("ls args should parse");
+        assert!(opts.owner);
+        assert_eq!(target.bucket.as_deref(), Some("b"));
     }
 
     #[test]
-    fn extract_tag_blocks_works() {
-        let xml =
-            "<Root><Version><Key>a.txt</Key></Version><Version><Key>b.txt</Key></Version></Root>";
-        let blocks = extract_tag_blocks(xml, "Version");
-        assert_eq!(blocks.len(), 2);
-        assert!(blocks[0].contains("<Key>a.txt</Key>"));
-        assert!(blocks[1].contains("<Key>b.txt</Key>"));
+    fn object_list_rows_and_csv_escaping() {
+        let xml = "<ListBucketResult><Contents><Key>a,b &amp; c</Key><LastModified>2024-01-01T00:00:00.000Z</LastModified><ETag>&quot;abc&quot;</ETag><Size>3</Size></Contents></ListBucketResult>";
+        let rows = object_list_rows(xml, DirMarkers::Keep);
+        assert_eq!(
+            rows,
+            vec![vec![
+                "a,b & c".to_string(),
+                "3".to_string(),
+                "2024-01-01T00:00:00.000Z".to_string(),
+                "abc".to_string()
+            ]]
+        );
+        assert_eq!(csv_field(&rows[0][0]), "\"a,b & c\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(
+            json_record(&["key", "size"], &rows[0]),
+            "{\"key\":\"a,b & c\",\"size\":3}"
+        );
     }
 
     #[test]
-    fn extract_version_entries_works_for_versions_and_delete_markers() {
-        let xml = "<ListVersionsResult><Version><Key>k1</Key><VersionId>v1</VersionId></Version><DeleteMarker><Key>k2</Key><VersionId>v2</VersionId></DeleteMarker></ListVersionsResult>";
-        let versions = extract_version_entries(xml, "Version");
-        assert_eq!(versions.len(), 1);
-        assert_eq!(versions[0].key, "k1");
-        assert_eq!(versions[0].version_id, "v1");
+    fn dir_markers_filter_zero_byte_slash_keys() {
+        let xml = "<ListBucketResult>\
+            <Contents><Key>photos/</Key><Size>0</Size></Contents>\
+            <Contents><Key>photos/a.jpg</Key><Size>7</Size></Contents>\
+            <Contents><Key>odd/</Key><Size>4</Size></Contents>\
+            <Contents><Key>empty.txt</Key><Size>0</Size></Contents></ListBucketResult>";
+        let keys = |mode| -> Vec<String> {
+            object_list_rows(xml, mode)
+                .into_iter()
+                .map(|row| row[0].clone())
+                .collect()
+        };
+        assert_eq!(keys(DirMarkers::Keep).len(), 4);
+        assert_eq!(
+            keys(DirMarkers::Skip),
+            ["photos/a.jpg", "odd/", "empty.txt"]
+        );
+        assert_eq!(keys(DirMarkers::Only), ["photos/"]);
 
-        let delete_markers = extract_version_entries(xml, "DeleteMarker");
-        assert_eq!(delete_markers.len(), 1);
-        assert_eq!(delete_markers[0].key, "k2");
-        assert_eq!(delete_markers[0].version_id, "v2");
+        let mut mode = DirMarkers::Keep;
+        assert!(!mode.parse_flag("--owner").unwrap());
+        assert!(mode.parse_flag("--only-dir-markers").unwrap());
+        assert_eq!(mode, DirMarkers::Only);
+        assert!(mode.parse_flag("--skip-dir-markers").is_err());
+    }
+
+    #[test]
+    fn parse_globals_quiet_flag() {
+        let (opts, rest) = parse_globals(vec![
+            "-q".to_string(),
+            "--json".to_string(),
+            "put".to_string(),
+        ])
+        .expect("parse globals should succeed");
+        assert!(opts.quiet);
+        assert_eq!(opts.output, OutputFormat::Json);
+        assert_eq!(rest, vec!["put".to_string()]);
+    }
+
+    #[test]
+    fn take_command_limit_strips_flag_and_value() {
+        let mut args: Vec<String> = ["get", "--limit", "5M", "a/b/c", "out"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(
+            take_command_limit(&mut args).expect("limit should parse"),
+            Some("5M".to_string())
+        );
+        assert_eq!(args, vec!["get", "a/b/c", "out"]);
+        assert_eq!(take_command_limit(&mut args).unwrap(), None);
+        let mut missing = vec!["get".to_string(), "--limit".to_string()];
+        assert!(take_command_limit(&mut missing).is_err());
+        let mut args: Vec<String> = ["cp", "--if-match", "--limit", "--limit", "1M", "a", "b"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(
+            take_command_limit(&mut args).unwrap(),
+            Some("1M".to_string())
+        );
+        assert_eq!(args, vec!["cp", "--if-match", "--limit", "a", "b"]);
+        for operands in [
+            &["put", "./f", "a/b/k", "--limit", "1M"][..],
+            &["put", "--", "--limit", "1M"],
+        ] {
+            let mut args: Vec<String> = operands.iter().map(|s| s.to_string()).collect();
+            assert_eq!(take_command_limit(&mut args).unwrap(), None);
+            assert_eq!(args.len(), operands.len());
+        }
+        assert!(is_unlimited_rate("0") && is_unlimited_rate("Unlimited"));
+        assert!(!is_unlimited_rate("1M"));
     }
 
     #[test]
-    fn extract_xml_keys() {
-        let xml = "<ListBucketResult><Contents><Key>a.txt</Key></Contents><Contents><Key>dir/b.txt</Key></Contents></ListBucketResult>";
-        let keys = extract_tag_values(xml, "Key");
-        assert_eq!(keys, vec!["a.txt".to_string(), "dir/b.txt".to_string()]);
+    fn profile_selects_config_file_in_config_dir() {
+        let (opts, _) = parse_globals(vec![
+            "-C".to_string(),
+            "/tmp/cfg".to_string(),
+            "--profile".to_string(),
+            "staging".to_string(),
+            "ls".to_string(),
+        ])
+        .expect("globals should parse");
+        let path = resolve_config_path(opts.config_dir.as_deref(), opts.profile.as_deref(), None)
+            .expect("path should resolve");
+        assert_eq!(path, PathBuf::from("/tmp/cfg/config-staging.toml"));
+        assert_eq!(
+            resolve_config_path(Some(Path::new("/tmp/cfg")), None, None)
+                .expect("path should resolve"),
+            PathBuf::from("/tmp/cfg/config.toml")
+        );
+        assert!(parse_profile_name("../prod").is_err());
+        assert!(parse_profile_name("").is_err());
     }
 
     #[test]
-    fn sync_destination_key_respects_prefixes() {
+    fn s4_config_env_sits_between_config_dir_and_home() {
+        let env_file = Some(Path::new("/mnt/s4/prod.toml"));
         assert_eq!(
-            sync_destination_key("images/cat.jpg", "images", "backup"),
-            "backup/cat.jpg"
+            resolve_config_path(None, None, env_file).expect("path should resolve"),
+            PathBuf::from("/mnt/s4/prod.toml")
         );
         assert_eq!(
-            sync_destination_key("images/nested/cat.jpg", "", "archive"),
-            "archive/images/nested/cat.jpg"
+            resolve_config_path(Some(Path::new("/tmp/cfg")), None, env_file)
+                .expect("path should resolve"),
+            PathBuf::from("/tmp/cfg/config.toml")
         );
-        assert_eq!(sync_destination_key("a.txt", "", ""), "a.txt");
+        let profile =
+            resolve_config_path(None, Some("staging"), env_file).expect("path should resolve");
+        assert!(profile.ends_with(".s4/config-staging.toml"));
     }
 
     #[test]
-    fn governance_bypass_retry_matches_worm_and_retention_errors() {
-        assert!(should_retry_with_governance_bypass("AccessDenied"));
-        assert!(should_retry_with_governance_bypass("retention policy"));
-        assert!(should_retry_with_governance_bypass("governance mode"));
-        assert!(should_retry_with_governance_bypass(
-            "InvalidRequest: Object is WORM protected and cannot be overwritten"
-        ));
-        assert!(!should_retry_with_governance_bypass("NoSuchBucket"));
+    fn parse_globals_max_concurrent() {
+        let (opts, _) = parse_globals(vec!["--max-concurrent".to_string(), "2".to_string()])
+            .expect("parse globals should succeed");
+        assert_eq!(opts.max_concurrent, Some(2));
+        assert!(parse_globals(vec!["--max-concurrent".to_string(), "0".to_string()]).is_err());
     }
 
     #[test]
-    fn xml_unescape_works() {
-        assert_eq!(xml_unescape("a&amp;b&quot;c"), "a&b\"c");
+    fn parse_globals_extended_flags() {
+        let (opts, rest) = parse_globals(vec![
+            "--insecure".to_string(),
+            "--resolve".to_string(),
+            "minio.local:9000=127.0.0.1".to_string(),
+            "--limit-upload".to_string(),
+            "1M".to_string(),
+            "--limit-download".to_string(),
+            "2M".to_string(),
+            "-H".to_string(),
+            "x-test: one".to_string(),
+            "--custom-header".to_string(),
+            "x-test2: two".to_string(),
+            "ls".to_string(),
+            "a/b".to_string(),
+        ])
+        .expect("parse globals should succeed");
+        assert!(opts.insecure);
+        assert_eq!(opts.resolve, vec!["minio.local:9000=127.0.0.1".to_string()]);
+        assert_eq!(opts.limit_upload.as_deref(), Some("1M"));
+        assert_eq!(opts.limit_download.as_deref(), Some("2M"));
+        assert_eq!(
+            opts.custom_headers,
+            vec!["x-test: one".to_string(), "x-test2: two".to_string()]
+        );
+        assert_eq!(rest, vec!["ls".to_string(), "a/b".to_string()]);
     }
 
     #[test]
-    fn looks_ready_xml_accepts_known_payloads() {
-        assert!(looks_ready_xml(
-            "<ListAllMyBucketsResult></ListAllMyBucketsResult>"
-        ));
-        assert!(looks_ready_xml("<Error><Code>AccessDenied</Code></Error>"));
-        assert!(!looks_ready_xml("not-xml"));
+    fn parse_globals_tmp_dir() {
+        let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let (opts, rest) = parse_globals(args(&["--tmp-dir", "/var/tmp/s4", "put"]))
+            .expect("parse globals should succeed");
+        assert_eq!(opts.tmp_dir, Some(PathBuf::from("/var/tmp/s4")));
+        assert_eq!(rest, vec!["put".to_string()]);
+        assert!(parse_globals(args(&["--tmp-dir"])).is_err());
+        assert!(check_tmp_dir(Path::new("/nonexistent/s4-tmp")).is_err());
+        assert!(check_tmp_dir(&std::env::temp_dir()).is_ok());
     }
 
     #[test]
-    fn build_complete_multipart_xml_contains_parts() {
-        let xml =
-            build_complete_multipart_xml(&[(1, "etag-1".to_string()), (2, "etag-2".to_string())]);
-        assert!(xml.contains("<PartNumber>1</PartNumber>"));
-        assert!(xml.contains("<ETag>\"etag-2\"</ETag>"));
+    fn parse_globals_unsigned_payload() {
+        let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let (opts, rest) = parse_globals(args(&["--unsigned-payload", "put", "f", "a/b/k"]))
+            .expect("parse globals should succeed");
+        assert!(opts.unsigned_payload);
+        assert_eq!(rest, args(&["put", "f", "a/b/k"]));
+        let (opts, _) = parse_globals(args(&["put"])).unwrap();
+        assert!(!opts.unsigned_payload);
     }
 
     #[test]
-    fn normalize_sigv4_query_adds_empty_values_for_subresources() {
-        assert_eq!(normalize_sigv4_query("cors"), "cors=");
-        assert_eq!(normalize_sigv4_query("uploads"), "uploads=");
-        assert_eq!(
-            normalize_sigv4_query("list-type=2&prefix=a"),
-            "list-type=2&prefix=a"
-        );
+    fn parse_globals_ca_bundle() {
+        let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let (opts, _) = parse_globals(args(&["--ca-bundle", "/etc/s4/ca.pem", "ls"]))
+            .expect("parse globals should succeed");
+        assert_eq!(opts.ca_bundle, Some(PathBuf::from("/etc/s4/ca.pem")));
+        assert!(!opts.insecure);
+        assert!(parse_globals(args(&["--ca-bundle"])).is_err());
     }
 
     #[test]
-    fn normalize_resolve_entry_supports_equals_and_colon_formats() {
-        assert_eq!(
-            normalize_resolve_entry("minio.local:9000=127.0.0.1"),
-            "minio.local:9000:127.0.0.1"
-        );
+    fn parse_globals_client_cert() {
+        let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let (opts, _) = parse_globals(args(&[
+            "--client-cert",
+            "/etc/s4/client.pem",
+            "--client-key",
+            "/etc/s4/client.key",
+            "ls",
+        ]))
+        .expect("parse globals should succeed");
+        assert_eq!(opts.client_cert, Some(PathBuf::from("/etc/s4/client.pem")));
+        assert_eq!(opts.client_key, Some(PathBuf::from("/etc/s4/client.key")));
+        let (opts, _) = parse_globals(args(&["--client-cert", "/etc/s4/both.pem", "ls"])).unwrap();
+        assert_eq!(opts.client_key, None);
+        assert!(parse_globals(args(&["--client-key"])).is_err());
+    }
+
+    #[test]
+    fn parse_globals_signed_header() {
+        let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let (opts, _) = parse_globals(args(&[
+            "--signed-header",
+            "X-Amz-Meta-Owner:  team   a ",
+            "-H",
+            "x-trace: 1",
+            "ls",
+        ]))
+        .expect("parse globals should succeed");
         assert_eq!(
-            normalize_resolve_entry("minio.local:9000:127.0.0.1"),
-            "minio.local:9000:127.0.0.1"
+            opts.signed_headers,
+            vec![("x-amz-meta-owner".to_string(), "team a".to_string())]
         );
+        assert_eq!(opts.custom_headers, vec!["x-trace: 1".to_string()]);
+        assert!(parse_globals(args(&["--signed-header", "no-colon", "ls"])).is_err());
+        assert!(parse_signed_header("x-amz-date:20240101T000000Z").is_err());
+        assert!(parse_signed_header("Host:example.com").is_err());
+        assert!(parse_signed_header("bad name:v").is_err());
     }
 
     #[test]
-    fn uri_encode_query_component_works() {
-        assert_eq!(uri_encode_query_component("a b/+"), "a%20b%2F%2B");
+    fn repeated_signed_headers_are_merged() {
+        let headers = vec![
+            ("x-amz-meta-tag".to_string(), "a".to_string()),
+            ("x-amz-meta-owner".to_string(), "ops".to_string()),
+            ("x-amz-meta-tag".to_string(), "b c".to_string()),
+        ];
+        assert_eq!(
+            merge_signed_headers(&headers),
+            vec![
+                ("x-amz-meta-tag".to_string(), "a,b c".to_string()),
+                ("x-amz-meta-owner".to_string(), "ops".to_string()),
+            ]
+        );
+        let sign = sign_v4(
+            &CanonicalRequest {
+                method: "PUT",
+                uri_path: "/bucket/f.txt",
+                query: "",
+                host: "127.0.0.1:9000",
+                payload_hash: "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+                headers: &headers,
+            },
+            "us-east-1",
+            S3_SIGNING_SERVICE,
+            "AKID",
+            "secret",
+        )
+        .expect("signing should succeed");
+        assert!(
+            sign.authorization.contains(
+                "SignedHeaders=host;x-amz-content-sha256;x-amz-date;x-amz-meta-owner;x-amz-meta-tag, "
+            ),
+            "{}",
+            sign.authorization
+        );
     }
 
     #[test]
-    fn wildcard_match_works() {
-        assert!(wildcard_match("*.tmp", "a.tmp"));
-        assert!(wildcard_match("foo/*/bar", "foo/x/bar"));
-        assert!(!wildcard_match("*.tmp", "a.txt"));
+    fn parse_globals_user_agent() {
+        let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let (opts, rest) = parse_globals(args(&["--user-agent", "backup-job/1", "ls", "a/b"]))
+            .expect("parse globals should succeed");
+        assert_eq!(opts.user_agent.as_deref(), Some("backup-job/1"));
+        assert_eq!(rest, args(&["ls", "a/b"]));
+        assert!(parse_globals(args(&["--user-agent"])).is_err());
     }
 
     #[test]
-    fn parse_sync_args_with_flags() {
-        let args = vec![
-            "mirror".to_string(),
-            "--dry-run".to_string(),
-            "--remove".to_string(),
-            "-w".to_string(),
-            "--exclude".to_string(),
-            "*.tmp".to_string(),
-            "a/src/prefix".to_string(),
-            "b/dst/prefix".to_string(),
-        ];
-        let (opts, src, dst) = parse_sync_args(&args).expect("sync args should parse");
-        assert!(opts.dry_run);
-        assert!(opts.remove);
-        assert!(opts.watch);
-        assert_eq!(opts.excludes, vec!["*.tmp".to_string()]);
-        assert_eq!(opts.newer_than, None);
-        assert_eq!(opts.older_than, None);
-        assert_eq!(src.alias, "a");
-        assert_eq!(dst.alias, "b");
-        assert!(is_excluded("x.tmp", &opts.excludes));
+    fn expect_continue_only_for_large_bodies_unless_overridden() {
+        assert!(!wants_expect_continue(1024, &[]));
+        assert!(wants_expect_continue(EXPECT_CONTINUE_THRESHOLD_BYTES, &[]));
+        assert!(wants_expect_continue(5 << 30, &["x-trace: 1".to_string()]));
+        assert!(!wants_expect_continue(5 << 30, &["Expect:".to_string()]));
+        assert!(!wants_expect_continue(
+            5 << 30,
+            &["expect: 100-continue".to_string()]
+        ));
     }
 
     #[test]
-    fn parse_human_duration_works() {
-        assert_eq!(parse_human_duration("10d").expect("duration"), 864000);
+    fn parse_globals_endpoint_group_builds_ephemeral_alias() {
+        let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let (opts, rest) = parse_globals(args(&[
+            "--endpoint",
+            "http://127.0.0.1:9000",
+            "--access-key",
+            "minio",
+            "--secret-key",
+            "minio123",
+            "--path-style",
+            "ls",
+            "_/bucket",
+        ]))
+        .expect("endpoint group should parse");
+        assert_eq!(rest, args(&["ls", "_/bucket"]));
+        let alias = ephemeral_alias(&opts).expect("all three keys given");
+        assert_eq!(alias.endpoint, "http://127.0.0.1:9000");
         assert_eq!(
-            parse_human_duration("7d10h30m5s").expect("duration"),
-            642605
+            (alias.access_key.as_str(), alias.secret_key.as_str()),
+            ("minio", "minio123")
         );
-        assert!(parse_human_duration("10").is_err());
+        assert_eq!(alias.region, "us-east-1");
+        assert!(alias.path_style);
+
+        assert!(ephemeral_alias(&GlobalOpts::default()).is_none());
+        assert!(parse_globals(args(&["--endpoint", "http://h", "ls"])).is_err());
+        assert!(parse_globals(args(&["--region", "eu-west-1", "ls"])).is_err());
+        assert!(parse_globals(args(&["--access-key", "a", "--secret-key", "s", "ls"])).is_err());
     }
 
     #[test]
-    fn parse_sync_args_with_time_filters() {
-        let args = vec![
-            "sync".to_string(),
-            "--newer-than".to_string(),
-            "10d".to_string(),
-            "--older-than".to_string(),
-            "1h".to_string(),
-            "a/src".to_string(),
-            "b/dst".to_string(),
+    fn listing_colors_are_opt_out_and_keep_alignment() {
+        assert!(color_wanted(true, false, None));
+        assert!(color_wanted(true, false, Some(std::ffi::OsStr::new(""))));
+        assert!(!color_wanted(false, false, None));
+        assert!(!color_wanted(true, true, None));
+        assert!(!color_wanted(true, false, Some(std::ffi::OsStr::new("1"))));
+
+        let rows = vec![
+            vec!["dir/".to_string(), "0".to_string(), "x".to_string()],
+            vec!["a.txt".to_string(), "1234".to_string(), "y".to_string()],
         ];
-        let (opts, _, _) = parse_sync_args(&args).expect("sync args should parse");
-        assert!(!opts.watch);
-        assert_eq!(opts.newer_than, Some(864000));
-        assert_eq!(opts.older_than, Some(3600));
+        let widths = column_widths(&rows);
+        assert_eq!(widths, vec![5, 4, 1]);
+        let lines: Vec<String> = rows
+            .iter()
+            .map(|row| {
+                table_row(row, Some(&widths), &[1], false, |i, cell| {
+                    (i == 0).then(|| key_style(cell)).flatten()
+                })
+            })
+            .collect();
+        assert_eq!(lines, vec!["dir/      0  x", "a.txt  1234  y"]);
+        let painted = table_row(&rows[0], Some(&widths), &[1], true, |i, cell| {
+            (i == 0).then(|| key_style(cell)).flatten()
+        });
+        assert_eq!(painted, "\x1b[34mdir/ \x1b[0m     0  x");
+        let piped = table_row(&rows[1], None, &[1], false, |_, _| Some(STYLE_DIM));
+        assert_eq!(piped, "a.txt\t1234\ty");
     }
 
     #[test]
-    fn parse_cors_args_set_works() {
-        let args = vec![
-            "cors".to_string(),
-            "set".to_string(),
-            "a/bucket".to_string(),
-            "cors.xml".to_string(),
-        ];
-        let parsed = parse_cors_args(&args).expect("cors args should parse");
-        match parsed {
-            CorsCommand::Set { target, file } => {
-                assert_eq!(target.alias, "a");
-                assert_eq!(target.bucket.as_deref(), Some("bucket"));
-                assert_eq!(file.to_string_lossy(), "cors.xml");
-            }
-            _ => panic!("expected cors set"),
-        }
+    fn expiration_header_gives_date_and_decoded_rule() {
+        let head = "HTTP/1.1 200 OK\r\nx-amz-expiration: expiry-date=\"Fri, 23 Dec 2012 00:00:00 GMT\", rule-id=\"picture%20deletion\"\r\n\r\n";
+        assert_eq!(
+            parse_expiration_header(head),
+            Some(ObjectExpiration {
+                expiry_date: "Fri, 23 Dec 2012 00:00:00 GMT".to_string(),
+                rule_id: "picture deletion".to_string(),
+            })
+        );
+        assert_eq!(parse_expiration_header("HTTP/1.1 200 OK\r\n\r\n"), None);
     }
 
     #[test]
-    fn parse_cors_args_get_works() {
-        let args = vec![
-            "cors".to_string(),
-            "get".to_string(),
-            "a/bucket".to_string(),
-        ];
-        let parsed = parse_cors_args(&args).expect("cors args should parse");
-        match parsed {
-            CorsCommand::Get { target } => {
-                assert_eq!(target.alias, "a");
-                assert_eq!(target.bucket.as_deref(), Some("bucket"));
-            }
-            _ => panic!("expected cors get"),
-        }
+    fn page_size_becomes_max_keys() {
+        let (opts, _) = parse_globals(vec!["--page-size".to_string(), "250".to_string()])
+            .expect("parse globals should succeed");
+        assert_eq!(opts.page_size, Some(250));
+        assert!(parse_globals(vec!["--page-size".to_string(), "0".to_string()]).is_err());
+        assert!(parse_globals(vec!["--page-size".to_string(), "1001".to_string()]).is_err());
+
+        let page = ListQuery {
+            prefix: "logs/",
+            max_keys: 250,
+            ..ListQuery::default()
+        };
+        assert_eq!(
+            page.render(false, Some("tok")),
+            "list-type=2&prefix=logs/&max-keys=250&continuation-token=tok"
+        );
+        assert_eq!(page.render(true, None), "prefix=logs/&max-keys=250");
     }
 
     #[test]
-    fn parse_encrypt_args_set_works() {
-        let args = vec![
-            "encrypt".to_string(),
-            "set".to_string(),
-            "a/bucket".to_string(),
-            "enc.xml".to_string(),
-        ];
-        let parsed = parse_encrypt_args(&args).expect("encrypt args should parse");
-        match parsed {
-            EncryptCommand::Set { target, file } => {
-                assert_eq!(target.alias, "a");
-                assert_eq!(target.bucket.as_deref(), Some("bucket"));
-                assert_eq!(file.to_string_lossy(), "enc.xml");
-            }
-            _ => panic!("expected encrypt set"),
-        }
+    fn api_args_build_a_raw_request() {
+        let args: Vec<String> = [
+            "api",
+            "--method",
+            "put",
+            "--query",
+            "?tagging",
+            "--body",
+            "t.xml",
+            "-H",
+            "Content-MD5: abc",
+            "m/b/k",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let request = parse_api_args(&args).expect("api args should parse");
+        assert_eq!(request.method, "PUT");
+        assert_eq!(request.query, "tagging");
+        assert_eq!(request.body, Some(PathBuf::from("t.xml")));
+        assert_eq!(request.headers, vec!["Content-MD5: abc".to_string()]);
+        assert_eq!(request.target.key.as_deref(), Some("k"));
+
+        let bad = |list: &[&str]| {
+            let args: Vec<String> = list.iter().map(|s| s.to_string()).collect();
+            parse_api_args(&args).is_err()
+        };
+        assert!(bad(&["api", "--method", "PATCH", "m/b"]));
+        assert!(bad(&["api", "--body", "f", "m/b/k"]));
+        assert!(bad(&["api", "--header", "novalue", "m/b"]));
+        assert!(bad(&["api"]));
     }
 
     #[test]
-    fn parse_encrypt_args_info_works() {
-        let args = vec![
-            "encrypt".to_string(),
-            "info".to_string(),
-            "a/bucket".to_string(),
-        ];
-        let parsed = parse_encrypt_args(&args).expect("encrypt args should parse");
-        match parsed {
-            EncryptCommand::Info { target } => {
-                assert_eq!(target.alias, "a");
-                assert_eq!(target.bucket.as_deref(), Some("bucket"));
-            }
-            _ => panic!("expected encrypt info"),
+    fn local_entry_path_stays_under_the_root() {
+        let root = Path::new("/data/mirror");
+        assert_eq!(
+            local_entry_path(root, "photos/a.jpg").unwrap(),
+            PathBuf::from("/data/mirror/photos/a.jpg")
+        );
+        assert_eq!(
+            local_entry_path(root, "./a.txt").unwrap(),
+            PathBuf::from("/data/mirror/a.txt")
+        );
+        for key in [
+            "../../.ssh/authorized_keys",
+            "/etc/passwd",
+            "a/../../b",
+            "a/..",
+            "",
+        ] {
+            assert!(local_entry_path(root, key).is_err(), "{key}");
         }
     }
 
     #[test]
-    fn parse_event_args_add_works() {
-        let args = vec![
-            "event".to_string(),
-            "add".to_string(),
-            "a/bucket".to_string(),
-            "event.xml".to_string(),
-        ];
-        let parsed = parse_event_args(&args).expect("event args should parse");
-        match parsed {
-            EventCommand::Add { target, file } => {
-                assert_eq!(target.alias, "a");
-                assert_eq!(target.bucket.as_deref(), Some("bucket"));
-                assert_eq!(file.to_string_lossy(), "event.xml");
-            }
-            _ => panic!("expected event add"),
+    fn sync_endpoint_resolves_aliases_before_local_paths() {
+        let mut config = AppConfig::default();
+        config.aliases.insert(
+            "m".to_string(),
+            AliasConfig {
+                endpoint: "http://127.0.0.1:9000".to_string(),
+                access_key: "ak".to_string(),
+                secret_key: "sk".to_string(),
+                region: "us-east-1".to_string(),
+                path_style: true,
+                request_payer: false,
+                aws_profile: None,
+                session_token: None,
+            },
+        );
+        match SyncEndpoint::resolve(&config, "m/bucket/logs/").unwrap() {
+            SyncEndpoint::S3 {
+                alias_name,
+                bucket,
+                prefix,
+                ..
+            } => assert_eq!(
+                (alias_name.as_str(), bucket.as_str(), prefix.as_str()),
+                ("m", "bucket", "logs/")
+            ),
+            SyncEndpoint::Local(path) => panic!("expected S3, got {}", path.display()),
+        }
+        for local in ["./site", "/tmp/site", "../backup"] {
+            assert!(
+                matches!(SyncEndpoint::resolve(&config, local).unwrap(), SyncEndpoint::Local(ref p) if p == Path::new(local)),
+                "{local}"
+            );
         }
+        assert!(SyncEndpoint::resolve(&config, "nope/bucket").is_err());
+        assert!(SyncEndpoint::resolve(&config, "m").is_err());
     }
 
     #[test]
-    fn parse_event_args_remove_force_works() {
-        let args = vec![
-            "event".to_string(),
-            "rm".to_string(),
-            "a/bucket".to_string(),
-            "--force".to_string(),
-        ];
-        let parsed = parse_event_args(&args).expect("event args should parse");
-        match parsed {
-            EventCommand::Remove { target, force } => {
-                assert_eq!(target.alias, "a");
-                assert_eq!(target.bucket.as_deref(), Some("bucket"));
-                assert!(force);
-            }
-            _ => panic!("expected event remove"),
-        }
+    fn sync_endpoint_lists_local_files_with_sizes_and_mtimes() {
+        let root = std::env::temp_dir().join(format!("s4-sync-local-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::write(root.join("a.txt"), "abc").unwrap();
+        std::fs::write(root.join("sub/b.txt"), "hello").unwrap();
+        let endpoint = SyncEndpoint::Local(root.clone());
+        let listed = endpoint.list(DirMarkers::Keep, false, false).unwrap();
+        let keys: Vec<&str> = listed.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(keys, vec!["a.txt", "sub/b.txt"]);
+        assert!(listed.iter().all(|(_, modified)| modified.is_some()));
+        let prints = endpoint.fingerprints(false, false).unwrap();
+        assert_eq!(prints["sub/b.txt"].size, 5);
+        assert_eq!(
+            endpoint.local_md5("a.txt").unwrap().as_deref(),
+            Some("900150983cd24fb0d6963f7d28e17f72")
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+        assert!(
+            SyncEndpoint::Local(root)
+                .list(DirMarkers::Keep, false, false)
+                .unwrap()
+                .is_empty()
+        );
     }
 
     #[test]
-    fn parse_idp_args_openid_works() {
-        let args = vec!["idp".to_string(), "openid".to_string()];
-        let parsed = parse_idp_args(&args).expect("idp args should parse");
-        match parsed.kind {
-            IdpKind::OpenId => {}
-            _ => panic!("expected openid"),
-        }
+    fn mv_refuses_a_destination_inside_the_source() {
+        assert!(prefix_inside_folder("photos/sub", "photos/"));
+        assert!(prefix_inside_folder("photos/", "photos/"));
+        assert!(prefix_inside_folder("photos", "photos/"));
+        assert!(prefix_inside_folder("anything", ""));
+        assert!(!prefix_inside_folder("photos2", "photos/"));
+        assert!(!prefix_inside_folder("archive/photos", "photos/"));
+
+        let body = "<DeleteResult><Deleted><Key>a</Key></Deleted>\
+            <Error><Key>b&amp;c</Key><Code>AccessDenied</Code><Message>Access Denied</Message></Error>\
+            </DeleteResult>";
+        assert_eq!(
+            parse_delete_errors(body),
+            vec![(
+                "b&c".to_string(),
+                "AccessDenied".to_string(),
+                "Access Denied".to_string()
+            )]
+        );
+        assert!(parse_delete_errors("<DeleteResult></DeleteResult>").is_empty());
     }
 
     #[test]
-    fn parse_idp_args_ldap_works() {
-        let args = vec!["idp".to_string(), "ldap".to_string()];
-        let parsed = parse_idp_args(&args).expect("idp args should parse");
-        match parsed.kind {
-            IdpKind::Ldap => {}
-            _ => panic!("expected ldap"),
-        }
+    fn aws_profiles_resolve_only_for_referenced_aliases() {
+        let alias = |profile: Option<&str>| AliasConfig {
+            endpoint: "https://s3.example.com".to_string(),
+            access_key: "ak".to_string(),
+            secret_key: "sk".to_string(),
+            region: "us-east-1".to_string(),
+            path_style: false,
+            request_payer: false,
+            aws_profile: profile.map(str::to_string),
+            session_token: None,
+        };
+        let mut config = AppConfig::default();
+        config.aliases.insert("good".to_string(), alias(None));
+        config
+            .aliases
+            .insert("broken".to_string(), alias(Some("s4-test-no-such-profile")));
+        let args: Vec<String> = ["ls", "good/bucket", "./broken"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let used = referenced_aliases(&config, &args);
+        assert_eq!(used, std::collections::HashSet::from(["good".to_string()]));
+        resolve_aws_aliases(&mut config, None, &used).expect("unrelated broken profile");
+        let broken = std::collections::HashSet::from(["broken".to_string()]);
+        let err = resolve_aws_aliases(&mut config, None, &broken).unwrap_err();
+        assert!(err.to_string().contains("alias 'broken'"), "{err}");
     }
 
     #[test]
-    fn parse_ilm_args_rule_works() {
-        let args = vec!["ilm".to_string(), "rule".to_string()];
-        let parsed = parse_ilm_args(&args).expect("ilm args should parse");
-        match parsed.kind {
-            IlmKind::Rule => {}
-            _ => panic!("expected rule"),
-        }
+    fn remove_temp_and_untrack_temp_drop_registered_paths() {
+        let dir = std::env::temp_dir().join(format!("s4-untrack-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let removed = dir.join("removed");
+        let renamed = dir.join("renamed");
+        std::fs::write(&removed, b"x").unwrap();
+        track_temp(&removed);
+        track_temp(&renamed);
+        remove_temp(&removed);
+        untrack_temp(&renamed);
+        assert!(!removed.exists());
+        let registry = cleanup_registry().lock().unwrap();
+        assert!(!registry.temp_paths.contains(&removed));
+        assert!(!registry.temp_paths.contains(&renamed));
+        drop(registry);
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn parse_ilm_args_restore_works() {
-        let args = vec!["ilm".to_string(), "restore".to_string()];
-        let parsed = parse_ilm_args(&args).expect("ilm args should parse");
-        match parsed.kind {
-            IlmKind::Restore => {}
-            _ => panic!("expected restore"),
-        }
+    fn run_tool_captures_output_of_the_tracked_child() {
+        let output = run_tool(Command::new("sh").args(["-c", "echo out; echo err >&2"])).unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"out\n");
+        assert_eq!(output.stderr, b"err\n");
     }
 
     #[test]
-    fn parse_legalhold_args_set_works() {
-        let args = vec![
-            "legalhold".to_string(),
-            "set".to_string(),
-            "a/b/k".to_string(),
+    fn request_headers_are_signed_except_removals() {
+        let headers = vec![
+            "Content-MD5: abc==".to_string(),
+            "If-None-Match: *".to_string(),
+            "if-match:\"etag\"".to_string(),
+            "Content-Type: text/plain".to_string(),
+            "x-amz-meta-a:  one   two ".to_string(),
+            "Expect:".to_string(),
         ];
-        let parsed = parse_legalhold_args(&args).expect("legalhold args should parse");
-        match parsed {
-            LegalHoldCommand::Set { target } => {
-                assert_eq!(target.alias, "a");
-                assert_eq!(target.bucket.as_deref(), Some("b"));
-                assert_eq!(target.key.as_deref(), Some("k"));
-            }
-            _ => panic!("expected legalhold set"),
-        }
+        let (signed, unsigned) = partition_signed_headers(&headers);
+        assert_eq!(
+            signed,
+            vec![
+                ("content-md5".to_string(), "abc==".to_string()),
+                ("if-none-match".to_string(), "*".to_string()),
+                ("if-match".to_string(), "\"etag\"".to_string()),
+                ("content-type".to_string(), "text/plain".to_string()),
+                ("x-amz-meta-a".to_string(), "one two".to_string()),
+            ]
+        );
+        assert_eq!(unsigned, vec![&headers[5]]);
     }
 
     #[test]
-    fn parse_legalhold_args_info_works() {
-        let args = vec![
-            "legalhold".to_string(),
-            "info".to_string(),
-            "a/b/k".to_string(),
-        ];
-        let parsed = parse_legalhold_args(&args).expect("legalhold args should parse");
-        match parsed {
-            LegalHoldCommand::Info { target } => {
-                assert_eq!(target.alias, "a");
-                assert_eq!(target.bucket.as_deref(), Some("b"));
-                assert_eq!(target.key.as_deref(), Some("k"));
-            }
-            _ => panic!("expected legalhold info"),
-        }
+    fn governance_bypass_delete_signs_the_bypass_header() {
+        let headers = vec!["x-amz-bypass-governance-retention: true".to_string()];
+        let (signed, unsigned) = partition_signed_headers(&headers);
+        assert!(unsigned.is_empty());
+        let sign = sign_v4(
+            &CanonicalRequest {
+                method: "DELETE",
+                uri_path: "/bucket/locked.txt",
+                query: "",
+                host: "127.0.0.1:9000",
+                payload_hash: "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+                headers: &signed,
+            },
+            "us-east-1",
+            S3_SIGNING_SERVICE,
+            "AKID",
+            "secret",
+        )
+        .expect("signing should succeed");
+        assert!(
+            sign.authorization.contains(
+                "SignedHeaders=host;x-amz-bypass-governance-retention;x-amz-content-sha256;x-amz-date, "
+            ),
+            "{}",
+            sign.authorization
+        );
     }
 
     #[test]
-    fn parse_replicate_args_list_alias_works() {
-        let args = vec![
-            "replicate".to_string(),
-            "ls".to_string(),
-            "a/bucket".to_string(),
-        ];
-        let parsed = parse_replicate_args(&args).expect("replicate args should parse");
-        match parsed.subcommand {
-            ReplicateSubcommand::List => {}
-            _ => panic!("expected list"),
+    fn from_url_fetches_skip_endpoint_only_curl_flags() {
+        if let Ok(mut opts) = curl_global_opts().lock() {
+            opts.custom_headers = vec!["X-Tenant: secret".to_string()];
+            opts.client_cert = Some(PathBuf::from("/etc/s4/client.pem"));
         }
-        let target = parsed.target.expect("target expected");
-        assert_eq!(target.alias, "a");
-        assert_eq!(target.bucket.as_deref(), Some("bucket"));
+        let mut endpoint = Command::new("curl");
+        apply_curl_global_flags(&mut endpoint, false, true);
+        let mut fetch = Command::new("curl");
+        apply_curl_transport_flags(&mut fetch, false, true);
+        if let Ok(mut opts) = curl_global_opts().lock() {
+            opts.custom_headers.clear();
+            opts.client_cert = None;
+        }
+        let args = |cmd: &Command| -> Vec<String> {
+            cmd.get_args()
+                .map(|a| a.to_string_lossy().into_owned())
+                .collect()
+        };
+        assert!(args(&endpoint).contains(&"X-Tenant: secret".to_string()));
+        assert!(args(&endpoint).contains(&"--cert".to_string()));
+        assert!(!args(&fetch).contains(&"X-Tenant: secret".to_string()));
+        assert!(!args(&fetch).contains(&"--cert".to_string()));
+        assert!(args(&fetch).contains(&"--user-agent".to_string()));
     }
 
     #[test]
-    fn parse_replicate_args_backlog_works() {
-        let args = vec!["replicate".to_string(), "backlog".to_string()];
-        let parsed = parse_replicate_args(&args).expect("replicate args should parse");
-        match parsed.subcommand {
-            ReplicateSubcommand::Backlog => {}
-            _ => panic!("expected backlog"),
-        }
+    fn upload_state_dirs_fall_back_past_a_read_only_config_dir() {
+        let dirs = upload_state_dirs(
+            Path::new("/run/secrets/s4/config.toml"),
+            Some(Path::new("/home/ci")),
+            Path::new("/tmp"),
+            1000,
+        );
+        assert_eq!(
+            dirs,
+            vec![
+                PathBuf::from("/run/secrets/s4/multipart"),
+                PathBuf::from("/home/ci/.s4/multipart"),
+                PathBuf::from("/tmp/s4-multipart-1000"),
+            ]
+        );
+        let dirs = upload_state_dirs(
+            Path::new("/home/ci/.s4/config.toml"),
+            Some(Path::new("/home/ci")),
+            Path::new("/tmp"),
+            0,
+        );
+        assert_eq!(dirs.len(), 2);
     }
 
     #[test]
-    fn parse_retention_args_set_works() {
-        let args = vec![
-            "retention".to_string(),
-            "set".to_string(),
-            "a/b/k".to_string(),
-            "--mode".to_string(),
-            "GOVERNANCE".to_string(),
-            "--retain-until".to_string(),
-            "2030-01-01T00:00:00Z".to_string(),
+    fn recover_plan_partitions_running_stale_and_abortable_uploads() {
+        let record = |key: &str, upload_id: &str, pid: u32| {
+            (
+                PathBuf::from(format!("/state/{key}.xml")),
+                UploadRecord {
+                    endpoint: "http://e".to_string(),
+                    bucket: "b".to_string(),
+                    key: key.to_string(),
+                    upload_id: upload_id.to_string(),
+                    pid,
+                    initiated: 0,
+                },
+            )
+        };
+        let records = vec![
+            record("live", "u1", 10),
+            record("gone", "u2", 20),
+            record("dead", "u3", 30),
+            // A live process wins even when its upload is still listed.
+            record("live-listed", "u4", 10),
         ];
-        let parsed = parse_retention_args(&args).expect("retention args should parse");
-        match parsed {
-            RetentionCommand::Set {
-                target,
-                mode,
-                retain_until,
-            } => {
-                assert_eq!(target.alias, "a");
-                assert_eq!(target.bucket.as_deref(), Some("b"));
-                assert_eq!(target.key.as_deref(), Some("k"));
-                assert_eq!(mode, "GOVERNANCE");
-                assert_eq!(retain_until, "2030-01-01T00:00:00Z");
-            }
-            _ => panic!("expected retention set"),
-        }
+        let listed: std::collections::HashSet<String> =
+            ["u3", "u4"].iter().map(|s| s.to_string()).collect();
+        let plan = RecoverPlan::new(&records, &listed, |pid| pid == 10);
+        let keys =
+            |items: Vec<&UploadRecord>| items.iter().map(|r| r.key.clone()).collect::<Vec<_>>();
+        assert_eq!(keys(plan.running), vec!["live", "live-listed"]);
+        assert_eq!(
+            keys(plan.stale.iter().map(|(_, r)| *r).collect()),
+            vec!["gone"]
+        );
+        assert_eq!(
+            keys(plan.abort.iter().map(|(_, r)| *r).collect()),
+            vec!["dead"]
+        );
+        assert_eq!(plan.abort[0].0, Path::new("/state/dead.xml"));
     }
 
     #[test]
-    fn parse_retention_args_info_works() {
-        let args = vec![
-            "retention".to_string(),
-            "info".to_string(),
-            "a/b/k".to_string(),
-        ];
-        let parsed = parse_retention_args(&args).expect("retention args should parse");
-        match parsed {
-            RetentionCommand::Info { target } => {
-                assert_eq!(target.alias, "a");
-                assert_eq!(target.bucket.as_deref(), Some("b"));
-                assert_eq!(target.key.as_deref(), Some("k"));
-            }
-            _ => panic!("expected retention info"),
-        }
+    fn process_running_fails_closed() {
+        assert!(process_running(std::process::id()));
+        // pid 0 and pids beyond i32 would address process groups, not a process.
+        assert!(process_running(0));
+        assert!(process_running(u32::MAX));
+        let mut child = Command::new("true").spawn().expect("spawn true");
+        let pid = child.id();
+        child.wait().expect("wait for true");
+        assert!(!process_running(pid));
     }
 
     #[test]
-    fn parse_sql_args_defaults_and_targets() {
-        let args = vec!["sql".to_string(), "a/bucket/path.csv".to_string()];
-        let (opts, targets) = parse_sql_args(&args).expect("sql args should parse");
-        assert_eq!(opts.query, "select * from S3Object");
-        assert!(!opts.recursive);
-        assert_eq!(targets.len(), 1);
-        assert_eq!(targets[0].alias, "a");
-        assert_eq!(targets[0].bucket.as_deref(), Some("bucket"));
-        assert_eq!(targets[0].key.as_deref(), Some("path.csv"));
+    fn shared_temp_state_dir_must_be_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("s4-state-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        create_state_dir(&dir).expect("a fresh directory is created private");
+        assert!(is_private_dir(&dir));
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(create_state_dir(&dir).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn parse_sql_args_full_flags() {
-        let args = vec![
-            "sql".to_string(),
-            "--query".to_string(),
-            "select count(*) from S3Object".to_string(),
-            "-r".to_string(),
-            "--csv-input".to_string(),
-            "fh=USE,fd=;".to_string(),
-            "--compression".to_string(),
-            "GZIP".to_string(),
-            "--csv-output".to_string(),
-            "fd=;".to_string(),
-            "--csv-output-header".to_string(),
-            "c1,c2".to_string(),
-            "--enc-c".to_string(),
-            "a/bucket=Zm9v".to_string(),
-            "a/bucket/prefix".to_string(),
-        ];
-        let (opts, targets) = parse_sql_args(&args).expect("sql args should parse");
-        assert_eq!(opts.query, "select count(*) from S3Object");
-        assert!(opts.recursive);
-        assert_eq!(opts.csv_input.as_deref(), Some("fh=USE,fd=;"));
-        assert_eq!(opts.compression.as_deref(), Some("GZIP"));
-        assert_eq!(opts.csv_output.as_deref(), Some("fd=;"));
-        assert_eq!(opts.csv_output_header.as_deref(), Some("c1,c2"));
-        assert_eq!(opts.enc_c, vec!["a/bucket=Zm9v".to_string()]);
-        assert_eq!(targets[0].key.as_deref(), Some("prefix"));
+    fn curl_version_gates_header_writeout() {
+        let parse = parse_curl_version;
+        assert_eq!(
+            parse("curl 7.88.1 (x86_64-pc-linux-gnu) libcurl/7.88.1 OpenSSL/3.0.19"),
+            Some((7, 88))
+        );
+        assert_eq!(parse("curl 8.5.0 (aarch64-apple-darwin23.0)"), Some((8, 5)));
+        assert_eq!(parse("not curl"), None);
+        assert!(parse("curl 7.68.0 (x86_64-pc-linux-gnu)").unwrap() < CURL_HEADER_WRITEOUT_VERSION);
+        assert!(
+            parse("curl 7.84.0 (x86_64-pc-linux-gnu)").unwrap() >= CURL_HEADER_WRITEOUT_VERSION
+        );
     }
 
     #[test]
-    fn build_select_request_xml_contains_query_and_serialization() {
-        let args = vec![
-            "sql".to_string(),
-            "--query".to_string(),
-            "select * from S3Object".to_string(),
-            "--json-output".to_string(),
-            "rd=\n".to_string(),
-            "a/b/k".to_string(),
-        ];
-        let (opts, _) = parse_sql_args(&args).expect("sql args should parse");
-        let xml = build_select_request_xml(&opts);
-        assert!(xml.contains("<Expression>select * from S3Object</Expression>"));
-        assert!(xml.contains("<ExpressionType>SQL</ExpressionType>"));
-        assert!(xml.contains("<JSON>"));
+    fn folder_prefix_keeps_sibling_prefixes_out() {
+        assert_eq!(folder_prefix(""), "");
+        assert_eq!(folder_prefix("/"), "");
+        assert_eq!(folder_prefix("photos"), "photos/");
+        assert_eq!(folder_prefix("photos/"), "photos/");
+        assert!(!"photos2/a.jpg".starts_with(&folder_prefix("photos")));
     }
 
     #[test]
-    fn parse_event_stream_records_returns_payload_for_records_event() {
-        fn mk_header(name: &str, value: &str) -> Vec<u8> {
-            let mut h = Vec::new();
-            h.push(name.len() as u8);
-            h.extend_from_slice(name.as_bytes());
-            h.push(7);
-            h.extend_from_slice(&(value.len() as u16).to_be_bytes());
-            h.extend_from_slice(value.as_bytes());
-            h
-        }
-        let payload = b"row1,row2\n";
-        let mut headers = Vec::new();
-        headers.extend_from_slice(&mk_header(":message-type", "event"));
-        headers.extend_from_slice(&mk_header(":event-type", "Records"));
-
-        let total_len = 12 + headers.len() + payload.len() + 4;
-        let mut msg = Vec::new();
-        msg.extend_from_slice(&(total_len as u32).to_be_bytes());
-        msg.extend_from_slice(&(headers.len() as u32).to_be_bytes());
-        msg.extend_from_slice(&[0, 0, 0, 0]);
-        msg.extend_from_slice(&headers);
-        msg.extend_from_slice(payload);
-        msg.extend_from_slice(&[0, 0, 0, 0]);
+    fn tree_lines_nest_directories_once() {
+        let mut keys: Vec<String> = [
+            "top.txt",
+            "logs/",
+            "logs/2024/jan.log",
+            "logs/2024/feb.log",
+            "logs/2025/jan.log",
+            "logs-old/x.log",
+            "logs/readme",
+        ]
+        .iter()
+        .map(|k| k.to_string())
+        .collect();
+        keys.sort();
+        assert_eq!(
+            tree_lines(&keys, false),
+            vec![
+                "  logs-old/",
+                "    x.log",
+                "  logs/",
+                "    2024/",
+                "      feb.log",
+                "      jan.log",
+                "    2025/",
+                "      jan.log",
+                "    readme",
+                "  top.txt",
+            ]
+        );
+    }
 
-        let out = parse_event_stream_records(&msg);
-        assert_eq!(out, payload);
+    #[test]
+    fn tree_lines_keep_same_named_dirs_apart_and_paint_only_dirs() {
+        let keys: Vec<String> = ["a/x/1", "b/x/2", "b/x/y/"]
+            .iter()
+            .map(|k| k.to_string())
+            .collect();
+        assert_eq!(
+            tree_lines(&keys, false),
+            vec![
+                "  a/", "    x/", "      1", "  b/", "    x/", "      2", "      y/"
+            ]
+        );
+        let painted = tree_lines(&keys[..1], true);
+        assert_eq!(painted[0], "  \x1b[34ma/\x1b[0m");
+        assert_eq!(painted[2], "      1");
     }
+
     #[test]
-    fn parse_globals_extended_flags() {
-        let (opts, rest) = parse_globals(vec![
-            "--insecure".to_string(),
-            "--resolve".to_string(),
-            "minio.local:9000=127.0.0.1".to_string(),
-            "--limit-upload".to_string(),
-            "1M".to_string(),
-            "--limit-download".to_string(),
-            "2M".to_string(),
-            "-H".to_string(),
-            "x-test: one".to_string(),
-            "--custom-header".to_string(),
-            "x-test2: two".to_string(),
-            "ls".to_string(),
-            "a/b".to_string(),
-        ])
-        .expect("parse globals should succeed");
-        assert!(opts.insecure);
-        assert_eq!(opts.resolve, vec!["minio.local:9000=127.0.0.1".to_string()]);
-        assert_eq!(opts.limit_upload.as_deref(), Some("1M"));
-        assert_eq!(opts.limit_download.as_deref(), Some("2M"));
+    fn content_md5_is_the_base64_of_the_hex_digest() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"foob"), "Zm9vYg==");
+        assert_eq!(base64_encode(b"fooba"), "Zm9vYmE=");
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+        assert_eq!(hex_digest_base64("666f6f").unwrap(), "Zm9v");
+        assert!(hex_digest_base64("6x").is_err());
+        let path = std::env::temp_dir().join(format!("s4-md5-{}", std::process::id()));
+        std::fs::write(&path, b"hello").unwrap();
         assert_eq!(
-            opts.custom_headers,
-            vec!["x-test: one".to_string(), "x-test2: two".to_string()]
+            file_md5_hex(&path).unwrap(),
+            "5d41402abc4b2a76b9719d911017c592"
         );
-        assert_eq!(rest, vec!["ls".to_string(), "a/b".to_string()]);
+        assert_eq!(
+            content_md5_header(&path).unwrap(),
+            "XUFAKrxLKna5cZ2REBfFkg=="
+        );
+        assert_eq!(
+            checksum_value(&path, ChecksumAlgorithm::Sha256).unwrap(),
+            "LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ="
+        );
+        assert_eq!(
+            checksum_value(&path, ChecksumAlgorithm::Crc32).unwrap(),
+            "NhCmhg=="
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn find_flags_are_only_read_before_the_target() {
+        let args = |list: &[&str]| list.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        let (opts, target) = parse_find_args(&args(&[
+            "find",
+            "--owner",
+            "--skip-dir-markers",
+            "s3/b/logs",
+            "err",
+        ]))
+        .unwrap();
+        assert!(opts.owner);
+        assert_eq!(opts.dir_markers, DirMarkers::Skip);
+        assert_eq!(target.key.as_deref(), Some("logs"));
+        assert_eq!(opts.needle.as_deref(), Some("err"));
+
+        let (opts, _) = parse_find_args(&args(&["find", "s3/b", "--owner"])).unwrap();
+        assert!(!opts.owner);
+        assert_eq!(opts.dir_markers, DirMarkers::Keep);
+        assert_eq!(opts.needle.as_deref(), Some("--owner"));
+
+        assert!(parse_find_args(&args(&["find", "--bogus", "s3/b"])).is_err());
+        assert!(parse_find_args(&args(&["find", "--owner"])).is_err());
+        assert!(parse_find_args(&args(&["find", "s3/b", "a", "b"])).is_err());
+    }
+
+    #[test]
+    fn head_decompress_counts_inflated_bytes() {
+        let args: Vec<String> = ["head", "--decompress", "a/b/k", "2"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let (mode, decompress, _) = parse_head_args(&args).unwrap();
+        assert_eq!(mode, HeadMode::Lines(2));
+        assert!(decompress);
+
+        let text: String = (0..2000).map(|i| format!("line {i}\n")).collect();
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, text.as_bytes()).unwrap();
+        let gz = encoder.finish().unwrap();
+        let mut body = HeadBody::new(true);
+        body.push(&gz[..gz.len() / 2]).unwrap();
+        assert_eq!(body.fetched, (gz.len() / 2) as u64);
+        assert_eq!(head_end(body.bytes(), mode), Some(14));
+        assert!(text.as_bytes().starts_with(body.bytes()));
+        body.push(&gz[gz.len() / 2..]).unwrap();
+        assert_eq!(body.into_bytes(), text.as_bytes());
+
+        let mut plain = HeadBody::new(false);
+        plain.push(b"abc").unwrap();
+        assert_eq!(head_end(plain.bytes(), HeadMode::Bytes(2)), Some(2));
+        assert_eq!(head_end(plain.bytes(), HeadMode::Bytes(4)), None);
     }
 }